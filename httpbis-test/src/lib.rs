@@ -34,3 +34,29 @@ pub fn init_logger() {
         env_logger::init();
     });
 }
+
+/// Deterministic pseudo-random bytes (xorshift) for feeding garbage to peer
+pub fn garbage(seed: u32, len: usize) -> Vec<u8> {
+    let mut x = seed.wrapping_mul(2654435761) | 1;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect()
+}
+
+/// Frames of known types with random flags, stream ids and payloads
+pub fn garbage_frames(seed: u32, count: usize) -> Vec<u8> {
+    let mut bytes = garbage(seed, count * 300).into_iter();
+    let mut r = Vec::new();
+    for _ in 0..count {
+        let len = bytes.next().unwrap() as usize;
+        r.extend(&[0, 0, len as u8]);
+        // DATA..CONTINUATION
+        r.push(bytes.next().unwrap() % 10);
+        r.extend(bytes.by_ref().take(5 + len));
+    }
+    r
+}
@@ -117,6 +117,16 @@ impl ServerOneConn {
         let conn = g.as_ref().expect("conn");
         conn.dump_state().wait().expect("dump_status")
     }
+
+    pub fn pause_reads(&self) {
+        let g = self.conn.lock().expect("lock");
+        g.as_ref().expect("conn").pause_reads();
+    }
+
+    pub fn resume_reads(&self) {
+        let g = self.conn.lock().expect("lock");
+        g.as_ref().expect("conn").resume_reads();
+    }
 }
 
 impl Drop for ServerOneConn {
@@ -7,6 +7,7 @@ use std::io::Write;
 use std::net;
 use std::net::ToSocketAddrs;
 use std::str;
+use std::time::Duration;
 
 use bytes::Bytes;
 
@@ -19,6 +20,9 @@ use httpbis::for_test::solicit::frame::data::DataFrame;
 use httpbis::for_test::solicit::frame::goaway::GoawayFrame;
 use httpbis::for_test::solicit::frame::headers::HeadersFlag;
 use httpbis::for_test::solicit::frame::headers::HeadersFrame;
+use httpbis::for_test::solicit::frame::flags::Flags;
+use httpbis::for_test::solicit::frame::push_promise::PushPromiseFlag;
+use httpbis::for_test::solicit::frame::push_promise::PushPromiseFrame;
 use httpbis::for_test::solicit::frame::rst_stream::RstStreamFrame;
 use httpbis::for_test::solicit::frame::settings::SettingsFrame;
 use httpbis::for_test::solicit::frame::window_update::WindowUpdateFrame;
@@ -151,6 +155,34 @@ impl HttpConnTester {
         }
     }
 
+    /// Read and discard everything until peer closes the connection,
+    /// panic if connection is not closed in time
+    pub fn recv_until_eof(&mut self) {
+        self.tcp
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .expect("set_read_timeout");
+        let mut buf = [0; 1000];
+        loop {
+            match self.tcp.read(&mut buf) {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => return,
+                Err(e) => panic!("expecting EOF: {:?}", e),
+            }
+        }
+    }
+
+    /// Send arbitrary bytes, error is returned because peer
+    /// may close the connection before all bytes are sent
+    pub fn send_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.tcp.write_all(data)
+    }
+
+    /// Close write side of the connection, peer reads EOF
+    pub fn shutdown_write(&mut self) {
+        self.tcp.shutdown(net::Shutdown::Write).expect("shutdown");
+    }
+
     pub fn send_preface(&mut self) {
         self.tcp.write(PREFACE).expect("send");
     }
@@ -185,6 +217,37 @@ impl HttpConnTester {
         self.send_frame(headers_frame);
     }
 
+    /// Send PUSH_PROMISE frame with END_HEADERS flag
+    /// promising `promised_stream_id` on `stream_id`
+    pub fn send_push_promise(
+        &mut self,
+        stream_id: StreamId,
+        promised_stream_id: StreamId,
+        headers: Headers,
+    ) {
+        let fragment = self
+            .encoder
+            .encode_for_test(headers.0.iter().map(|h| (h.name(), h.value())));
+        let mut flags = Flags::new(0);
+        flags.set(PushPromiseFlag::EndHeaders);
+        self.send_frame(PushPromiseFrame {
+            flags,
+            stream_id,
+            promised_stream_id,
+            header_fragment: Bytes::from(fragment),
+            padding_len: 0,
+        });
+    }
+
+    /// Send HEADERS frame without END_HEADERS flag,
+    /// so peer must expect CONTINUATION frame next
+    pub fn send_headers_without_end_headers(&mut self, stream_id: StreamId, headers: Headers) {
+        let fragment = self
+            .encoder
+            .encode_for_test(headers.0.iter().map(|h| (h.name(), h.value())));
+        self.send_frame(HeadersFrame::new_conv(fragment, stream_id));
+    }
+
     pub fn send_get(&mut self, stream_id: StreamId, path: &str) {
         let mut headers = Headers::new();
         headers.add(":method", "GET");
@@ -368,6 +431,25 @@ impl HttpConnTester {
         }
     }
 
+    pub fn recv_frame_push_promise_decode(&mut self) -> (PushPromiseFrame, Headers) {
+        let frame = match self.recv_frame() {
+            HttpFrame::PushPromise(frame) => frame,
+            f => panic!("expecting PUSH_PROMISE, got: {:?}", f),
+        };
+        assert!(frame.flags.is_set(PushPromiseFlag::EndHeaders));
+        let headers = self
+            .decoder
+            .decode(&frame.header_fragment)
+            .expect("decode");
+        let headers = Headers(
+            headers
+                .into_iter()
+                .map(|(n, v)| Header::new(n, v))
+                .collect(),
+        );
+        (frame, headers)
+    }
+
     pub fn recv_frame_headers_decode(&mut self) -> (HeadersFrame, Headers, u32) {
         let (frame, cont_count) = self.recv_frame_headers_continuation();
         let headers = self
@@ -1,6 +1,8 @@
 //! Tests for client.
 
+use std::net::ToSocketAddrs;
 use std::sync::mpsc;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
@@ -10,6 +12,8 @@ extern crate futures;
 extern crate httpbis;
 extern crate log;
 extern crate regex;
+extern crate tls_api;
+extern crate tls_api_openssl;
 extern crate tokio_core;
 
 extern crate httpbis_test;
@@ -23,9 +27,18 @@ use futures::sync::oneshot;
 
 use tokio_core::reactor;
 
+use httpbis::for_test::solicit::frame::headers::HeadersFlag;
+use httpbis::for_test::solicit::frame::headers::HeadersFrame;
+use httpbis::for_test::solicit::frame::settings::HttpSetting;
+use httpbis::for_test::solicit::frame::settings::SettingsFrame;
+use httpbis::for_test::solicit::frame::GoawayFrame;
+use httpbis::for_test::solicit::frame::HttpFrame;
+use httpbis::for_test::solicit::frame::PingFrame;
+use httpbis::for_test::solicit::frame::WindowUpdateFrame;
 use httpbis::for_test::solicit::DEFAULT_SETTINGS;
 use httpbis::for_test::*;
 use httpbis::ErrorCode;
+use tls_api::TlsConnectorBuilder;
 use httpbis::*;
 
 #[test]
@@ -85,6 +98,133 @@ fn rst_is_error() {
     assert_eq!(0, state.streams.len(), "{:?}", state);
 }
 
+#[test]
+fn rst_no_error_after_complete_response_is_ok() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    // request body never completes
+    let body = futures::stream::once(Ok(Bytes::from(&b"aa"[..])))
+        .chain(futures::future::empty().into_stream());
+    let req = client
+        .start_request(headers, HttpStreamAfterHeaders::bytes(body))
+        .collect();
+
+    server_tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"aa", &server_tester.recv_frame_data_check(1, false)[..]);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"done", true);
+    server_tester.send_rst(1, ErrorCode::NoError);
+
+    let message = req.wait().expect("Should be OK");
+    assert_eq!(b"done", &message.body[..]);
+
+    let state: ConnStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len(), "{:?}", state);
+}
+
+#[test]
+fn request_body_with_trailers() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    let mut trailers = Headers::new();
+    trailers.add("x-checksum", "abcd");
+
+    let (body_tx, body_rx) = futures::sync::mpsc::unbounded();
+    let body = body_rx.map_err(|()| unreachable!());
+    let req = client
+        .start_request(
+            headers,
+            HttpStreamAfterHeaders::bytes_with_trailers(body, trailers),
+        ).collect();
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    body_tx.unbounded_send(Bytes::from(&b"aa"[..])).unwrap();
+    assert_eq!(b"aa", &server_tester.recv_frame_data_check(1, false)[..]);
+    body_tx.unbounded_send(Bytes::from(&b"bb"[..])).unwrap();
+    assert_eq!(b"bb", &server_tester.recv_frame_data_check(1, false)[..]);
+    drop(body_tx);
+
+    let trailers = server_tester.recv_frame_headers_check(1, true);
+    assert_eq!("abcd", trailers.get("x-checksum"));
+
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    req.wait().expect("response");
+}
+
+#[test]
+fn request_body_with_pseudo_header_trailers() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let mut trailers = Headers::new();
+    trailers.add(":status", "200");
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    let body = futures::stream::once(Ok(Bytes::from(&b"aa"[..])));
+    let _req = client.start_request(
+        headers,
+        HttpStreamAfterHeaders::bytes_with_trailers(body, trailers),
+    );
+
+    server_tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"aa", &server_tester.recv_frame_data_check(1, false)[..]);
+    // malformed trailers are not sent
+    server_tester.recv_rst_frame_check(1, ErrorCode::InternalError);
+}
+
+#[test]
+fn request_body_from_read() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let content: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+    let body = HttpStreamAfterHeaders::from_read(std::io::Cursor::new(content.clone()), 1000);
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    let _req = client.start_request(headers, body);
+
+    server_tester.recv_frame_headers_check(1, false);
+    let mut received = Vec::new();
+    loop {
+        let frame = server_tester.recv_frame_data();
+        assert!(frame.data.len() <= 1000);
+        received.extend_from_slice(&frame.data);
+        if frame.is_end_of_stream() {
+            break;
+        }
+    }
+    assert_eq!(content, received);
+}
+
 #[test]
 fn handle_1xx_headers() {
     init_logger();
@@ -122,7 +262,11 @@ fn client_call_dropped() {
 
         drop(req);
 
-        server_tester.send_headers(1, Headers::ok_200(), true);
+        server_tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+
+        // frames sent before RST_STREAM is received are ignored
+        server_tester.send_headers(1, Headers::ok_200(), false);
+        server_tester.send_data(1, b"aabb", true);
     }
 
     {
@@ -137,6 +281,35 @@ fn client_call_dropped() {
     assert_eq!(0, state.streams.len(), "{:?}", state);
 }
 
+#[test]
+fn client_call_dropped_after_complete() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    {
+        let req = client.start_get("/fgfg", "localhost");
+        server_tester.recv_message(1);
+        server_tester.send_headers(1, Headers::ok_200(), true);
+
+        while client.dump_state().wait().expect("state").streams.len() != 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // response is not read, but stream is already closed
+        req.cancel();
+    }
+
+    {
+        let req = client.start_get("/fgfg", "localhost").collect();
+        // no RST_STREAM before the next request
+        server_tester.recv_message(3);
+        server_tester.send_headers(3, Headers::ok_200(), true);
+        let resp = req.wait().expect("OK");
+        assert_eq!(200, resp.headers.status());
+    }
+}
+
 #[test]
 fn reconnect_on_disconnect() {
     init_logger();
@@ -211,6 +384,136 @@ fn reconnect_on_goaway() {
     }
 }
 
+#[test]
+fn retry_request_refused_by_goaway() {
+    init_logger();
+
+    let (server, client) = HttpServerTester::new_with_client();
+
+    let mut old_conn = server.accept_xchg();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    assert_eq!("/1", old_conn.recv_message(1).headers.path());
+    let req2 = client.start_get("/2", "localhost").collect();
+    assert_eq!("/2", old_conn.recv_message(3).headers.path());
+
+    // request is resent when response future is polled
+    let req2 = thread::spawn(move || req2.wait());
+
+    // only the first request is processed by server
+    old_conn.send_goaway(1);
+
+    // the second request is resent over new connection
+    let mut new_conn = server.accept();
+    new_conn.recv_preface();
+    new_conn.settings_xchg_but_ack();
+    assert_eq!("/2", new_conn.recv_message(1).headers.path());
+    new_conn.send_headers(1, Headers::ok_200(), true);
+    let resp2 = req2.join().unwrap().expect("req2");
+    assert_eq!(200, resp2.headers.status());
+
+    old_conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req1.wait().expect("req1").headers.status());
+    old_conn.recv_eof();
+}
+
+#[test]
+fn refused_by_goaway_debug_data() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.max_stream_refused_retries = Some(0);
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept_xchg();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    conn.recv_message(1);
+    let req2 = client.start_get("/2", "localhost").collect();
+    conn.recv_message(3);
+
+    conn.send_frame(GoawayFrame::with_debug_data(
+        1,
+        ErrorCode::NoError,
+        Bytes::from("maintenance"),
+    ));
+
+    match req2.wait() {
+        Err(httpbis::Error::RefusedByGoaway(error_code, debug_data)) => {
+            assert_eq!(ErrorCode::NoError, error_code);
+            assert_eq!(Bytes::from("maintenance"), debug_data);
+        }
+        r => panic!("expecting RefusedByGoaway, got {:?}", r.map(|_| ())),
+    }
+
+    conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req1.wait().expect("req1").headers.status());
+}
+
+#[test]
+fn retry_request_refused_stream() {
+    init_logger();
+
+    let (server, client) = HttpServerTester::new_with_client();
+
+    let mut conn = server.accept_xchg();
+
+    let req = client.start_post("/a", "localhost", Bytes::from("abc")).collect();
+    // request is resent when response future is polled
+    let req = thread::spawn(move || req.wait());
+    let message = conn.recv_message(1);
+    assert_eq!("/a", message.headers.path());
+    assert_eq!(&b"abc"[..], &message.body[..]);
+    conn.send_rst(1, ErrorCode::RefusedStream);
+
+    // resent with the same body
+    let message = conn.recv_message(3);
+    assert_eq!("/a", message.headers.path());
+    assert_eq!(&b"abc"[..], &message.body[..]);
+    conn.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(200, req.join().unwrap().expect("req").headers.status());
+
+    // body is not marked retryable
+    let mut headers = Headers::new_post("/b");
+    headers.add(":authority", "localhost");
+    headers.add(":scheme", "http");
+    let body = HttpStreamAfterHeaders::once_bytes("abc");
+    assert!(!body.is_retryable());
+    let req = client.start_request(headers, body).collect();
+    assert_eq!("/b", conn.recv_message(5).headers.path());
+    conn.send_rst(5, ErrorCode::RefusedStream);
+    match req.wait() {
+        Err(httpbis::Error::CodeError(ErrorCode::RefusedStream)) => {}
+        r => panic!("expecting REFUSED_STREAM, got {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn max_stream_refused_retries() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+    let mut conf = ClientConf::new();
+    conf.max_stream_refused_retries = Some(1);
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept_xchg();
+
+    let req = client.start_get("/a", "localhost").collect();
+    let req = thread::spawn(move || req.wait());
+    assert_eq!("/a", conn.recv_message(1).headers.path());
+    conn.send_rst(1, ErrorCode::RefusedStream);
+    assert_eq!("/a", conn.recv_message(3).headers.path());
+    conn.send_rst(3, ErrorCode::RefusedStream);
+
+    match req.join().unwrap() {
+        Err(httpbis::Error::CodeError(ErrorCode::RefusedStream)) => {}
+        r => panic!("expecting REFUSED_STREAM, got {:?}", r.map(|_| ())),
+    }
+}
+
 #[test]
 pub fn issue_89() {
     init_logger();
@@ -291,3 +594,1370 @@ fn external_event_loop() {
 
     t.join().expect("join");
 }
+
+#[test]
+fn new_from_url() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |headers, _| {
+        Response::found_200_plain_text(headers.get(":authority"))
+    });
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().unwrap();
+
+    let url = format!("http://[::1]:{}/", port);
+    let client =
+        Client::new_from_url::<tls_api_openssl::TlsConnector>(&url, ClientConf::new())
+            .expect("client");
+
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    let message = client
+        .start_request_simple(headers, Bytes::new())
+        .collect()
+        .wait()
+        .expect("wait");
+    assert_eq!(format!("[::1]:{}", port).as_bytes(), &message.body[..]);
+}
+
+#[test]
+fn new_from_url_unsupported_scheme() {
+    init_logger();
+
+    match Client::new_from_url::<tls_api_openssl::TlsConnector>("ftp://localhost", ClientConf::new())
+    {
+        Err(Error::InvalidUrl(..)) => {}
+        Err(e) => panic!("wrong error: {:?}", e),
+        Ok(..) => panic!("expected error"),
+    }
+}
+
+#[test]
+fn pause_reads() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    client.pause_reads();
+    // make sure pause is processed
+    client.dump_state().wait().expect("state");
+
+    let req = client.start_get("/fgfg", "localhost").collect();
+
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"hello", true);
+
+    thread::sleep(Duration::from_millis(100));
+
+    // response is not read
+    let state: ConnStateSnapshot = client.dump_state().wait().expect("state");
+    assert_eq!(1, state.streams.len(), "{:?}", state);
+
+    client.resume_reads();
+
+    let message = req.wait().expect("Should be OK");
+    assert_eq!(b"hello", &message.body[..]);
+}
+
+#[test]
+fn close_with_debug_data() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    client.close(ErrorCode::NoError, Bytes::from("shutting down"));
+
+    let goaway = server_tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(&b"shutting down"[..], &goaway.debug_data()[..]);
+}
+
+#[test]
+fn inspect_body() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let req = client
+        .start_get("/fgfg", "localhost")
+        .inspect_body(move |data| {
+            tx.lock().unwrap().send(data.clone()).unwrap();
+        }).collect();
+
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"hello", false);
+    server_tester.send_data(1, b"world", true);
+
+    let message = req.wait().expect("Should be OK");
+    assert_eq!(b"helloworld", &message.body[..]);
+
+    let chunks: Vec<Bytes> = rx.try_iter().collect();
+    assert_eq!(
+        vec![Bytes::from(&b"hello"[..]), Bytes::from(&b"world"[..])],
+        chunks
+    );
+}
+
+#[test]
+fn write_queue_soft_limit() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.write_queue_soft_limit = Some(100);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+    server_tester.send_recv_settings(SettingsFrame::from_settings(vec![
+        HttpSetting::InitialWindowSize(0),
+    ]));
+
+    let _r1 = client.start_post("/r1", "localhost", Bytes::from(vec![17; 1000]));
+    server_tester.recv_frame_headers_check(1, false);
+
+    // body is queued until the server opens the window
+    while client.dump_state().wait().unwrap().write_queue_bytes() < 1000 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let r2 = client.start_get("/r2", "localhost").collect();
+    match r2.wait() {
+        Err(Error::WriteQueueFull) => {}
+        Err(e) => panic!("expecting WriteQueueFull, got: {:?}", e),
+        Ok(_) => panic!("expecting WriteQueueFull, got OK"),
+    }
+}
+
+#[test]
+fn http_scheme_override() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.http_scheme = Some(HttpScheme::Https);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let _r1 = client.start_get("/r1", "localhost");
+    let headers = server_tester.recv_frame_headers_check(1, false);
+    assert_eq!("https", headers.get(":scheme"));
+}
+
+#[test]
+fn auto_priority() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.auto_priority = Some(AutoPriority::PriorityHeader);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":path", "/r1"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+        Header::new("priority", "u=0"),
+    ]);
+    let _r1 = client.start_request_simple(headers, Bytes::new());
+    let (frame, _, _) = server_tester.recv_frame_headers_decode();
+    assert_eq!(1, frame.stream_id);
+    assert_eq!(
+        Urgency::HIGHEST.weight(),
+        frame.stream_dep.expect("stream_dep").weight
+    );
+
+    server_tester.recv_frame_data_check_empty_end(1);
+
+    // requests without priority header get default urgency
+    let _r3 = client.start_get("/r3", "localhost");
+    let (frame, _, _) = server_tester.recv_frame_headers_decode();
+    assert_eq!(3, frame.stream_id);
+    assert_eq!(
+        Urgency::DEFAULT.weight(),
+        frame.stream_dep.expect("stream_dep").weight
+    );
+}
+
+#[test]
+fn conn_window_after_many_closed_streams() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let w = DEFAULT_SETTINGS.initial_window_size;
+
+    // total data is much larger than connection window,
+    // tester panics if connection window is not returned
+    for i in 0..20 {
+        let stream_id = 1 + 2 * i;
+
+        let req = client.start_get("/fgfg", "localhost").collect();
+
+        server_tester.recv_frame_headers_check(stream_id, false);
+        server_tester.recv_frame_data_check_empty_end(stream_id);
+
+        server_tester.send_headers(stream_id, Headers::ok_200(), false);
+        server_tester.send_data(stream_id, &[17; 10000], true);
+
+        assert_eq!(10000, req.wait().expect("response").body.len());
+    }
+
+    let state = client.dump_state().wait().expect("state");
+    assert_eq!(0, state.streams.len());
+    assert!(state.in_window_size >= (w / 2) as i32);
+}
+
+#[test]
+fn nat_keepalive() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.nat_keepalive_interval = Some(Duration::from_millis(100));
+    conf.nat_keepalive_grace = Some(Duration::from_millis(300));
+
+    let _client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let recv_ping = |server_tester: &mut HttpConnTester| match server_tester.recv_frame() {
+        HttpFrame::Ping(ping) => {
+            assert!(!ping.is_ack());
+            ping
+        }
+        f => panic!("expecting PING, got: {:?}", f),
+    };
+
+    // idle connection is pinged periodically
+    for _ in 0..3 {
+        let ping = recv_ping(&mut server_tester);
+        server_tester.send_frame(PingFrame::new_ack(ping.opaque_data()));
+    }
+
+    // connection is closed when PING is not acknowledged within grace
+    recv_ping(&mut server_tester);
+    server_tester.recv_eof();
+}
+
+#[test]
+fn keepalive_ping() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.keepalive_interval = Some(Duration::from_millis(100));
+    conf.keepalive_timeout = Some(Duration::from_millis(200));
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let recv_ping = |server_tester: &mut HttpConnTester| match server_tester.recv_frame() {
+        HttpFrame::Ping(ping) => {
+            assert!(!ping.is_ack());
+            ping
+        }
+        f => panic!("expecting PING, got: {:?}", f),
+    };
+
+    assert_eq!(None, client.dump_state().wait().expect("state").ping_rtt);
+
+    let mut opaque_data = Vec::new();
+    for _ in 0..3 {
+        let ping = recv_ping(&mut server_tester);
+        opaque_data.push(ping.opaque_data());
+        server_tester.send_frame(PingFrame::new_ack(ping.opaque_data()));
+    }
+    // each PING has fresh payload
+    assert_eq!(vec![1, 2, 3], opaque_data);
+
+    thread::sleep(Duration::from_millis(20));
+    let ping_rtt = client.dump_state().wait().expect("state").ping_rtt;
+    assert!(ping_rtt.expect("ping_rtt") < Duration::from_secs(1));
+
+    // connection is closed when PING is not acknowledged in time
+    recv_ping(&mut server_tester);
+    server_tester.recv_eof();
+}
+
+#[test]
+fn max_buffered_response_size() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |headers, _| {
+        let len = if headers.path() == "/large" { 10000 } else { 100 };
+        Response::headers_and_bytes(Headers::ok_200(), vec![17; len])
+    });
+
+    let mut conf = ClientConf::new();
+    conf.max_buffered_response_size = Some(1000);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let message = client.start_get("/small", "localhost").collect().wait();
+    assert_eq!(100, message.expect("small").body.len());
+
+    match client.start_get("/large", "localhost").collect().wait() {
+        Err(Error::BodyTooLarge) => {}
+        Err(e) => panic!("expecting BodyTooLarge, got: {:?}", e),
+        Ok(_) => panic!("expecting BodyTooLarge, got OK"),
+    }
+
+    // response body stream fails too
+    let body = client
+        .start_get("/large", "localhost")
+        .0
+        .and_then(|(_, body)| body.filter_data().concat2())
+        .wait();
+    match body {
+        Err(Error::BodyTooLarge) => {}
+        Err(e) => panic!("expecting BodyTooLarge, got: {:?}", e),
+        Ok(_) => panic!("expecting BodyTooLarge, got OK"),
+    }
+}
+
+#[test]
+fn hpack_dynamic_table_stats() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| Response::found_200_plain_text("hi"));
+
+    let client =
+        Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    // encoder indexes headers with new names,
+    // and these headers do not fit into default 4096 bytes table
+    for i in 0..20 {
+        let mut headers = Headers::new_get("/");
+        headers.add(":authority", "localhost");
+        headers.add(":scheme", "http");
+        headers.add(&format!("x-unique-{}", i), &"x".repeat(500));
+        let message = client
+            .start_request_simple(headers, Bytes::new())
+            .collect()
+            .wait()
+            .expect("response");
+        assert_eq!(200, message.headers.status());
+    }
+
+    let client_stats = client.dump_state().wait().unwrap().hpack_encoder_stats;
+    let server_stats = server.dump_state().hpack_decoder_stats;
+    assert!(client_stats.insertions >= 20, "{:?}", client_stats);
+    assert!(client_stats.evictions > 0, "{:?}", client_stats);
+    assert_eq!(client_stats, server_stats);
+}
+
+#[test]
+fn set_tls_configured() {
+    init_logger();
+
+    let mut client = ClientBuilder::<tls_api_openssl::TlsConnector>::new();
+    client
+        .set_tls_configured("localhost", |builder| {
+            builder
+                .underlying_mut()
+                .set_cipher_list("ECDHE+AESGCM")
+                .map_err(tls_api::Error::new)
+        }).expect("tls");
+
+    // configuration error is returned to the caller
+    let mut client = ClientBuilder::<tls_api_openssl::TlsConnector>::new();
+    let r = client.set_tls_configured("localhost", |builder| {
+        builder
+            .underlying_mut()
+            .set_cipher_list("NO-SUCH-CIPHER")
+            .map_err(tls_api::Error::new)
+    });
+    assert!(r.is_err());
+}
+
+#[test]
+fn rotate_connection_after_max_requests() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.max_connection_requests = Some(2);
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut old_conn = server.accept_xchg();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    old_conn.recv_message(1);
+    let req2 = client.start_get("/2", "localhost").collect();
+    old_conn.recv_message(3);
+
+    // third request is sent over new connection
+    let req3 = client.start_get("/3", "localhost").collect();
+    let mut new_conn = server.accept();
+    new_conn.recv_preface();
+    new_conn.settings_xchg_but_ack();
+    new_conn.recv_message(1);
+    new_conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req3.wait().expect("req3").headers.status());
+
+    // old connection is drained
+    let goaway = old_conn.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(0, goaway.last_stream_id());
+    old_conn.send_headers(1, Headers::ok_200(), true);
+    old_conn.send_headers(3, Headers::ok_200(), true);
+    assert_eq!(200, req1.wait().expect("req1").headers.status());
+    assert_eq!(200, req2.wait().expect("req2").headers.status());
+    old_conn.recv_eof();
+}
+
+#[test]
+fn client_idle_timeout() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.pool_idle_timeout = Some(Duration::from_millis(200));
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut old_conn = server.accept_xchg();
+
+    let req = client.start_get("/1", "localhost").collect();
+    old_conn.recv_message(1);
+    old_conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req.wait().expect("req").headers.status());
+
+    // idle connection is closed
+    let goaway = old_conn.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    old_conn.recv_eof();
+
+    // new connection is opened on request
+    let req = client.start_get("/2", "localhost").collect();
+    let mut new_conn = server.accept();
+    new_conn.recv_preface();
+    new_conn.settings_xchg_but_ack();
+    new_conn.recv_message(1);
+    new_conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req.wait().expect("req").headers.status());
+}
+
+#[test]
+fn response_callbacks() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        Response::headers_and_bytes_stream(
+            Headers::ok_200(),
+            futures::stream::iter_ok(vec![Bytes::from("ab"), Bytes::from("cd")]),
+        )
+    });
+
+    let client = Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    let mut lp = reactor::Core::new().expect("core");
+
+    // events stream is complete when consumer is dropped
+    let (tx, rx) = futures::sync::mpsc::unbounded();
+    let (chunk_tx, complete_tx, error_tx) = (tx.clone(), tx.clone(), tx);
+    client
+        .start_get("/", "localhost")
+        .for_each_chunk(move |chunk| {
+            chunk_tx.unbounded_send(format!("chunk {:?}", chunk)).unwrap();
+        }).on_headers(|headers| assert_eq!(200, headers.status()))
+        .on_complete(move || complete_tx.unbounded_send("complete".to_owned()).unwrap())
+        .on_error(move |e| error_tx.unbounded_send(format!("error {:?}", e)).unwrap())
+        .spawn(&lp.handle());
+
+    let events: Vec<String> = lp.run(rx.collect()).unwrap();
+    assert_eq!(
+        vec!["chunk b\"ab\"", "chunk b\"cd\"", "complete"],
+        events
+    );
+
+    let (tx, rx) = futures::sync::mpsc::unbounded();
+    let complete_tx = tx.clone();
+    Response::err(Error::Other("test"))
+        .for_each_chunk(|_| panic!("no chunks expected"))
+        .on_complete(move || complete_tx.unbounded_send("complete".to_owned()).unwrap())
+        .on_error(move |e| tx.unbounded_send(format!("error {:?}", e)).unwrap())
+        .spawn(&lp.handle());
+    let events: Vec<String> = lp.run(rx.collect()).unwrap();
+    assert_eq!(vec!["error Other(\"test\")"], events);
+
+    // panic in callback is reported once to `on_error`
+    let (tx, rx) = futures::sync::mpsc::unbounded();
+    let complete_tx = tx.clone();
+    client
+        .start_get("/", "localhost")
+        .for_each_chunk(|_| panic!("chunk"))
+        .on_complete(move || complete_tx.unbounded_send("complete".to_owned()).unwrap())
+        .on_error(move |e| tx.unbounded_send(format!("error {:?}", e)).unwrap())
+        .spawn(&lp.handle());
+    let events: Vec<String> = lp.run(rx.collect()).unwrap();
+    assert_eq!(vec!["error HandlerPanicked(\"chunk\")"], events);
+}
+
+#[test]
+fn initial_settings_for_test() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.common.initial_settings_for_test = Some(
+        RawSettingsFrame::new()
+            .setting(HttpSetting::MaxConcurrentStreams(100))
+            .param(0xf0f0, 1)
+            .setting(HttpSetting::MaxConcurrentStreams(7)),
+    );
+    let _client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept();
+    conn.recv_preface();
+    let settings = conn.recv_frame_settings_set();
+    assert_eq!(
+        vec![
+            HttpSetting::MaxConcurrentStreams(100),
+            HttpSetting::MaxConcurrentStreams(7),
+        ],
+        settings.settings
+    );
+    assert_eq!(7, conn.peer_settings.max_concurrent_streams);
+}
+
+#[test]
+fn settings_updated_callback() {
+    init_logger();
+
+    struct Callbacks(Mutex<mpsc::Sender<HttpSettings>>);
+
+    impl ClientConnCallbacks for Callbacks {
+        fn goaway(&self, _stream_id: u32, _raw_error_code: u32) {}
+
+        fn settings_updated(&self, settings: &HttpSettings) {
+            self.0.lock().unwrap().send(*settings).unwrap();
+        }
+    }
+
+    let server = HttpServerTester::new();
+
+    let (tx, rx) = mpsc::channel();
+    let mut conf = ClientConf::new();
+    conf.callbacks = Some(ClientCallbacks::new(Callbacks(Mutex::new(tx))));
+    let _client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept_xchg();
+    assert_eq!(DEFAULT_SETTINGS, rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+    conn.send_recv_settings(SettingsFrame::from_settings(vec![
+        HttpSetting::MaxConcurrentStreams(3),
+    ]));
+    let settings = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(3, settings.max_concurrent_streams);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size, settings.initial_window_size);
+}
+
+#[test]
+fn goaway_with_debug_callback() {
+    init_logger();
+
+    struct Callbacks(Mutex<mpsc::Sender<(u32, u32, Bytes)>>);
+
+    impl ClientConnCallbacks for Callbacks {
+        fn goaway(&self, _stream_id: u32, _raw_error_code: u32) {
+            panic!("goaway_with_debug must be called instead");
+        }
+
+        fn goaway_with_debug(&self, stream_id: u32, raw_error_code: u32, debug_data: Bytes) {
+            let goaway = (stream_id, raw_error_code, debug_data);
+            self.0.lock().unwrap().send(goaway).unwrap();
+        }
+    }
+
+    let server = HttpServerTester::new();
+
+    let (tx, rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let addr = (BIND_HOST, server.port()).to_socket_addrs().unwrap().next().unwrap();
+    thread::spawn(move || {
+        let mut core = reactor::Core::new().expect("core");
+        let _conn = ClientConn::spawn_plain(
+            core.handle(),
+            Box::new(addr),
+            ClientConf::new(),
+            Callbacks(Mutex::new(tx)),
+        );
+        drop(core.run(shutdown_rx));
+    });
+
+    let mut conn = server.accept();
+    conn.recv_preface();
+    conn.settings_xchg();
+
+    conn.send_frame(GoawayFrame::with_debug_data(
+        0,
+        ErrorCode::EnhanceYourCalm,
+        Bytes::from("maintenance"),
+    ));
+    let (stream_id, raw_error_code, debug_data) =
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(0, stream_id);
+    assert_eq!(ErrorCode::EnhanceYourCalm as u32, raw_error_code);
+    assert_eq!(Bytes::from("maintenance"), debug_data);
+
+    drop(shutdown_tx);
+}
+
+#[test]
+fn request_timeout() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let client = Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    let mut conn = server.accept_xchg();
+
+    let get = |path: &str| {
+        Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", path.to_owned()),
+            Header::new(":authority", "localhost"),
+            Header::new(":scheme", "http"),
+        ])
+    };
+
+    // no response headers in time
+    let req = client.start_request_with_timeout(
+        get("/slow"),
+        HttpStreamAfterHeaders::once_bytes(Bytes::new()),
+        Duration::from_millis(100),
+    );
+    conn.recv_message(1);
+    conn.recv_rst_frame_check(1, ErrorCode::Cancel);
+    match req.collect().wait() {
+        Err(Error::RequestTimeout) => {}
+        r => panic!("expecting request timeout: {:?}", r.map(|m| m.headers)),
+    }
+    assert!(client.dump_state().wait().expect("state").streams.is_empty());
+
+    // response headers disarm the timeout
+    let req = client.start_request_with_timeout(
+        get("/fast"),
+        HttpStreamAfterHeaders::once_bytes(Bytes::new()),
+        Duration::from_millis(100),
+    );
+    conn.recv_message(3);
+    conn.send_headers(3, Headers::ok_200(), false);
+    thread::sleep(Duration::from_millis(300));
+    conn.send_data(3, b"done", true);
+    let message = req.collect().wait().expect("response");
+    assert_eq!(200, message.headers.status());
+    assert_eq!(&b"done"[..], &message.body[..]);
+}
+
+#[test]
+fn error_events() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let (events_tx, events_rx) = futures::sync::mpsc::unbounded();
+
+    let mut conf = ClientConf::new();
+    conf.common.error_events = Some(events_tx);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let req = client.start_get("/a", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_rst(1, ErrorCode::InadequateSecurity);
+    assert!(req.wait().is_err());
+
+    // RST_STREAM(NO_ERROR) is not reported
+    let req = client.start_get("/b", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_headers(3, Headers::ok_200(), true);
+    server_tester.send_rst(3, ErrorCode::NoError);
+    req.wait().expect("response");
+
+    // window overflow is a connection error
+    server_tester.send_frame(WindowUpdateFrame::for_connection(0x7fffffff));
+    server_tester.recv_goaway_frame_check(ErrorCode::FlowControlError);
+
+    let events: Vec<ConnErrorEvent> = events_rx.take(2).collect().wait().expect("events");
+    assert_eq!(
+        vec![
+            ConnErrorEvent::RstStreamReceived(1, ErrorCode::InadequateSecurity),
+            ConnErrorEvent::GoawaySent(ErrorCode::FlowControlError, Bytes::new()),
+        ],
+        events
+    );
+}
+
+#[test]
+fn not_http_2() {
+    init_logger();
+
+    let (server, client) = HttpServerTester::new_with_client();
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.recv_frame_settings_set();
+
+    // make sure request is sent on this connection
+    let req = client.start_get("/a", "localhost").collect();
+    server_tester.recv_frame_headers_check(1, false);
+
+    let response = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
+    server_tester.send_raw(response).unwrap();
+
+    match req.wait() {
+        Err(httpbis::Error::ClientDied(Some(ref e))) => match **e {
+            // error contains bytes buffered so far, which may be only part of the response
+            httpbis::Error::NotHttp2(ref bytes) => {
+                assert!(bytes.starts_with(b"HTTP/1.1 400"), "{:?}", bytes)
+            }
+            ref e => panic!("wrong error: {:?}", e),
+        },
+        r => panic!("expecting NotHttp2, got {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn response_content_length_mismatch() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let client = Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    let mut conn = server.accept_xchg();
+
+    let headers_with_content_length = |len: &str| {
+        let mut headers = Headers::ok_200();
+        headers.add("content-length", len);
+        headers
+    };
+
+    let expect_protocol_error = |response: Response| match response.collect().wait() {
+        Err(Error::CodeError(ErrorCode::ProtocolError)) => {}
+        r => panic!("expecting PROTOCOL_ERROR: {:?}", r.map(|m| m.body)),
+    };
+
+    // more data than declared
+    let req = client.start_get("/overrun", "localhost");
+    conn.recv_message(1);
+    conn.send_headers(1, headers_with_content_length("3"), false);
+    conn.send_data(1, b"abcdef", true);
+    conn.recv_rst_frame_check(1, ErrorCode::ProtocolError);
+    expect_protocol_error(req);
+
+    // less data than declared
+    let req = client.start_get("/underrun", "localhost");
+    conn.recv_message(3);
+    conn.send_headers(3, headers_with_content_length("10"), false);
+    conn.send_data(3, b"abc", true);
+    conn.recv_rst_frame_check(3, ErrorCode::ProtocolError);
+    expect_protocol_error(req);
+
+    // response to HEAD has no body
+    let req = client.start_request_simple(
+        Headers(vec![
+            Header::new(":method", "HEAD"),
+            Header::new(":path", "/head"),
+            Header::new(":authority", "localhost"),
+            Header::new(":scheme", "http"),
+        ]),
+        Bytes::new(),
+    );
+    conn.recv_message(5);
+    conn.send_headers(5, headers_with_content_length("10"), false);
+    conn.send_data(5, b"", true);
+    let message = req.collect().wait().expect("head");
+    assert_eq!(Some("10"), message.headers.get_opt("content-length"));
+
+    // no body at all
+    let req = client.start_get("/empty", "localhost");
+    conn.recv_message(7);
+    conn.send_headers(7, headers_with_content_length("10"), true);
+    conn.recv_rst_frame_check(7, ErrorCode::ProtocolError);
+    expect_protocol_error(req);
+}
+
+#[test]
+fn conn_control() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let client =
+        Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    client.control(ConnControl::UpdateSettings(vec![HttpSetting::MaxConcurrentStreams(5)]));
+    let settings = server_tester.recv_frame_settings_set();
+    assert_eq!(vec![HttpSetting::MaxConcurrentStreams(5)], settings.settings);
+    server_tester.send_frame(SettingsFrame::new_ack());
+
+    client.control(ConnControl::Ping);
+    let ping = match server_tester.recv_frame() {
+        HttpFrame::Ping(ping) => ping,
+        f => panic!("expecting PING, got: {:?}", f),
+    };
+    assert!(!ping.is_ack());
+    server_tester.send_frame(PingFrame::new_ack(ping.opaque_data()));
+
+    thread::sleep(Duration::from_millis(20));
+    assert!(client.dump_state().wait().expect("state").ping_rtt.is_some());
+
+    client.control(ConnControl::GoawayGraceful);
+    match server_tester.recv_frame() {
+        HttpFrame::Goaway(..) => {}
+        f => panic!("expecting GOAWAY, got: {:?}", f),
+    }
+}
+
+#[test]
+fn update_settings_acked() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let client =
+        Client::new_plain(BIND_HOST, server.port(), ClientConf::new()).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let first = client.update_settings(vec![HttpSetting::InitialWindowSize(100000)]);
+    // several SETTINGS may be outstanding
+    let second = client.update_settings(vec![HttpSetting::MaxConcurrentStreams(5)]);
+
+    let settings = server_tester.recv_frame_settings_set();
+    assert_eq!(vec![HttpSetting::InitialWindowSize(100000)], settings.settings);
+    let settings = server_tester.recv_frame_settings_set();
+    assert_eq!(vec![HttpSetting::MaxConcurrentStreams(5)], settings.settings);
+
+    // SETTINGS are acknowledged in order
+    server_tester.send_frame(SettingsFrame::new_ack());
+    first.wait().expect("first acked");
+    assert!(client.dump_state().wait().expect("state").settings_ack_pending);
+
+    server_tester.send_frame(SettingsFrame::new_ack());
+    second.wait().expect("second acked");
+    assert!(!client.dump_state().wait().expect("state").settings_ack_pending);
+}
+
+#[test]
+fn header_table_size() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.header_table_size = Some(8192);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.send_settings(SettingsFrame::new());
+    let settings = server_tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::HeaderTableSize(8192)));
+    server_tester.send_frame(SettingsFrame::new_ack());
+    server_tester.recv_frame_settings_ack();
+
+    server_tester.send_recv_settings(SettingsFrame::from_settings(vec![
+        HttpSetting::HeaderTableSize(0),
+    ]));
+
+    let _req = client.start_get("/a", "localhost");
+
+    // dynamic table size update is the first instruction of the next header block
+    match server_tester.fn_recv_frame_no_check_ack() {
+        HttpFrame::Headers(headers) => assert_eq!(0x20, headers.header_fragment()[0]),
+        f => panic!("expecting HEADERS, got: {:?}", f),
+    }
+}
+
+#[test]
+fn header_table_size_lowered_on_settings_ack() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.header_table_size = Some(100);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept();
+    server_tester.recv_preface();
+    server_tester.send_settings(SettingsFrame::new());
+    let settings = server_tester.recv_frame_settings_set();
+    assert!(settings.settings.contains(&HttpSetting::HeaderTableSize(100)));
+    server_tester.recv_frame_settings_ack();
+
+    // dynamic table size update to 4096, then indexed `:status: 200`
+    let fragment: &[u8] = &[0x3f, 0xe1, 0x1f, 0x88];
+    let send_response = |server_tester: &mut HttpConnTester, stream_id| {
+        let mut headers = HeadersFrame::new_conv(fragment, stream_id);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        headers.set_flag(HeadersFlag::EndStream);
+        server_tester.send_frame(headers);
+    };
+
+    // SETTINGS is not acknowledged yet, peer may use default table size
+    let req = client.start_get("/a", "localhost");
+    server_tester.recv_message(1);
+    send_response(&mut server_tester, 1);
+    assert_eq!(200, req.collect().wait().expect("response").headers.status());
+
+    server_tester.send_frame(SettingsFrame::new_ack());
+
+    let req = client.start_get("/b", "localhost");
+    server_tester.recv_message(3);
+    send_response(&mut server_tester, 3);
+    server_tester.recv_goaway_frame_check(ErrorCode::CompressionError);
+    assert!(req.collect().wait().is_err());
+}
+
+fn push_get(path: &str) -> Headers {
+    Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":path", path.to_owned()),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ])
+}
+
+#[test]
+fn client_push() {
+    init_logger();
+
+    struct Callbacks(Mutex<mpsc::Sender<(u32, u32, Headers, Response)>>);
+
+    impl ClientConnCallbacks for Callbacks {
+        fn goaway(&self, _stream_id: u32, _raw_error_code: u32) {}
+
+        fn on_push(&self, associated: u32, promised: u32, headers: Headers, resp: Response) {
+            self.0
+                .lock()
+                .unwrap()
+                .send((associated, promised, headers, resp))
+                .unwrap();
+        }
+    }
+
+    let server = HttpServerTester::new();
+
+    let (push_tx, push_rx) = mpsc::channel();
+    let mut conf = ClientConf::new();
+    conf.enable_push = Some(true);
+    conf.callbacks = Some(ClientCallbacks::new(Callbacks(Mutex::new(push_tx))));
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept_xchg();
+    assert!(conn.peer_settings.enable_push);
+
+    let resp = client.start_get("/", "localhost");
+    conn.recv_message(1);
+
+    conn.send_push_promise(1, 2, push_get("/style.css"));
+    conn.send_headers(1, Headers::ok_200(), false);
+    conn.send_data(1, b"main", true);
+    conn.send_headers(2, Headers::ok_200(), false);
+    conn.send_data(2, b"pushed", true);
+
+    let (associated, promised, headers, pushed) =
+        push_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!((1, 2), (associated, promised));
+    assert_eq!("/style.css", headers.path());
+    assert_eq!(&b"pushed"[..], &pushed.collect().wait().expect("pushed").body[..]);
+    assert_eq!(&b"main"[..], &resp.collect().wait().expect("resp").body[..]);
+}
+
+#[test]
+fn client_push_rejected() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.enable_push = Some(true);
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut conn = server.accept_xchg();
+
+    let resp = client.start_get("/", "localhost");
+    conn.recv_message(1);
+
+    // push is not consumed
+    conn.send_push_promise(1, 2, push_get("/style.css"));
+    conn.recv_rst_frame_check(2, ErrorCode::Cancel);
+
+    // unsafe method cannot be pushed
+    let mut post = push_get("/form");
+    post.0[0] = Header::new(":method", "POST");
+    conn.send_push_promise(1, 4, post);
+    conn.recv_rst_frame_check(4, ErrorCode::ProtocolError);
+
+    conn.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, resp.collect().wait().expect("resp").headers.status());
+}
+
+#[test]
+fn client_push_disabled() {
+    init_logger();
+
+    let (mut conn, client) = HttpConnTester::new_server_with_client_xchg();
+    assert!(!conn.peer_settings.enable_push);
+
+    let _resp = client.start_get("/", "localhost");
+    conn.recv_message(1);
+
+    conn.send_push_promise(1, 2, push_get("/style.css"));
+    conn.recv_goaway_frame_check(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn garbage_from_server() {
+    init_logger();
+
+    for seed in 0..20 {
+        for garbage in &[garbage(seed, 0x10000), garbage_frames(seed, 200)] {
+            let (server, client) = HttpServerTester::new_with_client();
+
+            let mut server_tester = server.accept();
+            server_tester.recv_preface();
+            server_tester.recv_frame_settings_set();
+
+            // make sure request is sent on this connection
+            // and not queued for the next connection
+            let req = client.start_get("/a", "localhost").collect();
+            server_tester.recv_frame_headers_check(1, false);
+
+            server_tester.send_raw(garbage).ok();
+
+            assert!(req.wait().is_err());
+            server_tester.recv_until_eof();
+        }
+    }
+}
+
+#[test]
+fn on_read_eof_finish_writes() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientConf::new();
+    conf.on_read_eof = Some(OnReadEof::FinishWrites);
+
+    let client = Client::new_plain(BIND_HOST, server.port(), conf).expect("client");
+
+    let mut server_tester = server.accept_xchg();
+
+    let headers = Headers(vec![
+        Header::new(":method", "POST"),
+        Header::new(":path", "/upload"),
+        Header::new(":authority", "localhost"),
+        Header::new(":scheme", "http"),
+    ]);
+    let (body_tx, body_rx) = futures::sync::mpsc::unbounded();
+    let post = client
+        .start_request(
+            headers,
+            HttpStreamAfterHeaders::bytes(body_rx.map_err(|()| unreachable!())),
+        )
+        .collect();
+
+    server_tester.recv_frame_headers_check(1, false);
+
+    body_tx.unbounded_send(Bytes::from(&b"aa"[..])).unwrap();
+    assert_eq!(b"aa", &server_tester.recv_frame_data_check(1, false)[..]);
+
+    let get = client.start_get("/get", "localhost").collect();
+    server_tester.recv_message(3);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+    server_tester.send_data(1, b"done", true);
+    server_tester.shutdown_write();
+
+    // response was complete before EOF
+    let message = post.wait().expect("post");
+    assert_eq!(b"done", &message.body[..]);
+
+    // response was not received before EOF
+    match get.wait() {
+        Err(Error::EofFromPeer) => {}
+        Err(e) => panic!("expecting EofFromPeer: {:?}", e),
+        Ok(_) => panic!("expecting error"),
+    }
+    server_tester.recv_rst_frame_check(3, ErrorCode::Cancel);
+
+    // request body is still sent after EOF
+    body_tx.unbounded_send(Bytes::from(&b"bb"[..])).unwrap();
+    drop(body_tx);
+    assert_eq!(b"bb", &server_tester.recv_frame_data_tail(1)[..]);
+
+    server_tester.recv_until_eof();
+}
+
+#[test]
+fn client_pool_skips_full_conn() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientPoolConf::new();
+    conf.pool_size = Some(2);
+    let pool = ClientPool::new_plain(BIND_HOST, server.port(), conf).expect("pool");
+
+    let mut testers: Vec<HttpConnTester> = (0..2)
+        .map(|_| {
+            let mut tester = server.accept();
+            tester.recv_preface();
+            tester.send_settings(SettingsFrame::from_settings(vec![
+                HttpSetting::MaxConcurrentStreams(1),
+            ]));
+            tester.recv_frame_settings_set();
+            tester.send_frame(SettingsFrame::new_ack());
+            tester.recv_frame_settings_ack();
+            tester
+        }).collect();
+
+    let req1 = pool.start_get("/1", "localhost").collect();
+    let req2 = pool.start_get("/2", "localhost").collect();
+
+    // each connection gets one request
+    let path_a = testers[0].recv_message(1).headers.path().to_owned();
+    let path_b = testers[1].recv_message(1).headers.path().to_owned();
+    let index2 = match (&path_a[..], &path_b[..]) {
+        ("/1", "/2") => 1,
+        ("/2", "/1") => 0,
+        paths => panic!("unexpected paths: {:?}", paths),
+    };
+
+    testers[index2].send_headers(1, Headers::ok_200(), false);
+    testers[index2].send_data(1, b"two", true);
+    assert_eq!(b"two", &req2.wait().expect("req2").body[..]);
+
+    // round-robin points to connection with `/1` in flight, it is skipped
+    let req3 = pool.start_get("/3", "localhost").collect();
+    assert_eq!("/3", testers[index2].recv_message(3).headers.path());
+
+    testers[index2].send_headers(3, Headers::ok_200(), true);
+    req3.wait().expect("req3");
+
+    testers[1 - index2].send_headers(1, Headers::ok_200(), true);
+    req1.wait().expect("req1");
+}
+
+#[test]
+fn client_pool_replaces_conn_after_goaway() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientPoolConf::new();
+    conf.pool_size = Some(1);
+    conf.health_check_interval = Some(Duration::from_millis(20));
+    let pool = ClientPool::new_plain(BIND_HOST, server.port(), conf).expect("pool");
+
+    let mut tester1 = server.accept_xchg();
+    tester1.send_goaway(0);
+
+    // replacement connection is opened by health check
+    let mut tester2 = server.accept_xchg();
+
+    let req = pool.start_get("/a", "localhost").collect();
+    assert_eq!("/a", tester2.recv_message(1).headers.path());
+    tester2.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req.wait().expect("req").headers.status());
+
+    tester1.recv_until_eof();
+}
+
+#[test]
+fn client_pool_idle_timeout() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientPoolConf::new();
+    conf.pool_size = Some(1);
+    conf.health_check_interval = Some(Duration::from_millis(20));
+    conf.client.pool_idle_timeout = Some(Duration::from_millis(200));
+    let pool = ClientPool::new_plain(BIND_HOST, server.port(), conf).expect("pool");
+
+    let mut tester1 = server.accept_xchg();
+
+    let req = pool.start_get("/a", "localhost").collect();
+    tester1.recv_message(1);
+    tester1.send_headers(1, Headers::ok_200(), true);
+    req.wait().expect("req");
+
+    // idle connection is closed and not reopened by health check
+    assert_eq!(ErrorCode::NoError, tester1.recv_goaway_frame().error_code());
+    tester1.recv_until_eof();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(0, pool.stats().wait().expect("stats").conns);
+
+    // connection is reopened on request
+    let _req = pool.start_get("/b", "localhost");
+    let mut tester2 = server.accept();
+    tester2.recv_preface();
+    assert_eq!(1, pool.stats().wait().expect("stats").conns);
+}
+
+#[test]
+fn client_pool_reconnect_backoff() {
+    init_logger();
+
+    // server accepts connections and closes them immediately
+    let listener = std::net::TcpListener::bind((BIND_HOST, 0)).expect("bind");
+    let port = listener.local_addr().unwrap().port();
+    listener.set_nonblocking(true).expect("set_nonblocking");
+
+    let mut conf = ClientPoolConf::new();
+    conf.pool_size = Some(1);
+    conf.health_check_interval = Some(Duration::from_millis(10));
+    conf.client.reconnect_backoff = Some(BackoffConfig {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(10),
+        multiplier: 2.0,
+        jitter: 0.0,
+    });
+    let pool = ClientPool::new_plain(BIND_HOST, port, conf).expect("pool");
+
+    // attempts at about 0, 100, 300, 700 ms
+    let mut accepted = 0;
+    let deadline = std::time::Instant::now() + Duration::from_millis(1000);
+    while std::time::Instant::now() < deadline {
+        match listener.accept() {
+            Ok(_) => accepted += 1,
+            Err(_) => thread::sleep(Duration::from_millis(5)),
+        }
+    }
+    assert!(accepted >= 2 && accepted <= 6, "{}", accepted);
+
+    let stats = pool.stats().wait().expect("stats");
+    assert!(stats.consecutive_failures >= 2, "{:?}", stats);
+    assert!(stats.reconnect_delay.is_some(), "{:?}", stats);
+
+    match pool.start_get("/a", "localhost").collect().wait() {
+        Err(_) => {}
+        Ok(_) => panic!("expecting error"),
+    }
+}
+
+#[test]
+fn client_pool_fail_fast() {
+    init_logger();
+
+    let server = HttpServerTester::new();
+
+    let mut conf = ClientPoolConf::new();
+    conf.pool_size = Some(1);
+    conf.client.fail_fast = Some(true);
+    let pool = ClientPool::new_plain(BIND_HOST, server.port(), conf).expect("pool");
+
+    // server did not send SETTINGS yet
+    let mut tester = server.accept();
+    tester.recv_preface();
+
+    match pool.start_get("/a", "localhost").collect().wait() {
+        Err(httpbis::Error::NoConnectionAvailable) => {}
+        r => panic!("expecting NoConnectionAvailable: {:?}", r.map(|m| m.headers)),
+    }
+
+    tester.settings_xchg();
+
+    let req = pool.start_get("/b", "localhost").collect();
+    assert_eq!("/b", tester.recv_message(1).headers.path());
+    tester.send_headers(1, Headers::ok_200(), true);
+    assert_eq!(200, req.wait().expect("req").headers.status());
+}
+
+#[test]
+fn dump_state_streams() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let _req1 = client.start_get("/1", "localhost");
+    server_tester.recv_message(1);
+    let _req3 = client.start_get("/3", "localhost");
+    server_tester.recv_message(3);
+
+    server_tester.send_headers(1, Headers::ok_200(), false);
+
+    // wait for HEADERS to be processed
+    let state = loop {
+        let state = client.dump_state().wait().expect("state");
+        if state.streams[&1].in_message_stage == InMessageStage::AfterInitialHeaders {
+            break state;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+
+    let streams = state.streams_by_id();
+    assert_eq!(vec![1, 3], streams.iter().map(|&(id, _)| id).collect::<Vec<_>>());
+    assert_eq!(StreamState::HalfClosedLocal, streams[0].1.state);
+    assert_eq!(InMessageStage::Initial, streams[1].1.in_message_stage);
+    assert_eq!(0, streams[1].1.out_data_size);
+}
+
+#[test]
+fn conn_metrics() {
+    init_logger();
+
+    let (mut server_tester, client) = HttpConnTester::new_server_with_client_xchg();
+
+    let req1 = client.start_get("/1", "localhost").collect();
+    server_tester.recv_message(1);
+    server_tester.send_headers(1, Headers::ok_200(), true);
+    req1.wait().expect("req1");
+
+    let req3 = client.start_get("/3", "localhost").collect();
+    server_tester.recv_message(3);
+    server_tester.send_rst(3, ErrorCode::Cancel);
+    assert!(req3.wait().is_err());
+
+    // counters are published after connection event loop iteration
+    let mut metrics = client.metrics();
+    while metrics.rst_received == 0 {
+        thread::sleep(Duration::from_millis(1));
+        metrics = client.metrics();
+    }
+
+    assert_eq!(2, metrics.streams_opened);
+    assert_eq!(2, metrics.streams_closed);
+    assert_eq!(0, metrics.open_streams);
+    assert_eq!(1, metrics.rst_received);
+    assert_eq!(0, metrics.rst_sent);
+    assert!(metrics.bytes_sent > 0);
+    assert!(metrics.bytes_received > 0);
+}
@@ -2,6 +2,7 @@
 
 extern crate bytes;
 extern crate env_logger;
+extern crate flate2;
 extern crate futures;
 extern crate httpbis;
 extern crate log;
@@ -12,9 +13,13 @@ extern crate tokio_tls_api;
 extern crate httpbis_test;
 use httpbis_test::*;
 
+use std::collections::HashMap;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 
@@ -24,6 +29,7 @@ use std::io::Read as _Read;
 use std::io::Write as _Write;
 use std::thread;
 
+use futures::future;
 use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
@@ -31,17 +37,24 @@ use futures::sync::oneshot;
 use futures::Async;
 use futures::Poll;
 
+use httpbis::for_test::solicit::frame::continuation::ContinuationFlag;
+use httpbis::for_test::solicit::frame::continuation::ContinuationFrame;
 use httpbis::for_test::solicit::frame::headers::*;
 use httpbis::for_test::solicit::frame::settings::HttpSetting;
 use httpbis::for_test::solicit::frame::settings::SettingsFrame;
+use httpbis::for_test::solicit::frame::FrameIR;
+use httpbis::for_test::solicit::frame::HttpFrame;
+use httpbis::for_test::solicit::frame::PriorityFrame;
+use httpbis::for_test::solicit::frame::WindowUpdateFrame;
+use httpbis::for_test::solicit::frame::PingFrame;
 use httpbis::for_test::solicit::DEFAULT_SETTINGS;
 use httpbis::*;
 
+use std::fs;
 use std::iter::FromIterator;
 use std::net::TcpStream;
 use std::sync::mpsc;
 
-#[cfg(unix)]
 extern crate tempdir;
 #[cfg(unix)]
 extern crate unix_socket;
@@ -76,6 +89,101 @@ fn simple_new() {
     assert_eq!(0, server.dump_state().streams.len());
 }
 
+#[test]
+fn conn_metrics() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.get(1, "/blocks/1/1");
+
+    // request body is not complete when stream is reset
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/echo");
+    headers.add(":scheme", "http");
+    tester.send_headers(3, headers, false);
+    tester.send_rst(3, ErrorCode::Cancel);
+
+    let metrics = loop {
+        let conns = server.server.conn_metrics();
+        assert_eq!(1, conns.len());
+        let metrics = *conns.values().next().unwrap();
+        if metrics.rst_received == 1 && metrics.open_streams == 0 {
+            break metrics;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(2, metrics.streams_opened);
+    assert_eq!(2, metrics.streams_closed);
+    assert!(metrics.bytes_received > 0);
+}
+
+#[test]
+fn max_concurrent_streams_violation() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_concurrent_streams = Some(1);
+    server.service.set_service_fn("/", |_, _| {
+        let body = future::empty::<Bytes, Error>().into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    tester.recv_frame_headers_check(1, false);
+
+    tester.send_get(3, "/");
+    tester.recv_rst_frame_check(3, ErrorCode::RefusedStream);
+    tester.send_get(5, "/");
+    tester.recv_rst_frame_check(5, ErrorCode::RefusedStream);
+
+    let metrics = loop {
+        let conns = server.conn_metrics();
+        assert_eq!(1, conns.len());
+        let metrics = *conns.values().next().unwrap();
+        if metrics.max_concurrent_streams_violations == 2 {
+            break metrics;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(1, metrics.open_streams);
+}
+
+#[test]
+fn close_conn_with_debug_data() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.get(1, "/blocks/1/1");
+
+    let conn_id = *server.server.conn_metrics().keys().next().unwrap();
+    assert!(server.server.close_conn(conn_id, ErrorCode::NoError, Bytes::from("restart")));
+    // second call is ignored
+    server.server.close_conn(conn_id, ErrorCode::InternalError, Bytes::from("again"));
+
+    let goaway = tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(1, goaway.last_stream_id());
+    assert_eq!(&b"restart"[..], &goaway.debug_data()[..]);
+    tester.recv_eof();
+}
+
 #[test]
 fn panic_in_handler() {
     init_logger();
@@ -197,14 +305,71 @@ fn rst_stream_on_data_without_stream() {
     tester.send_preface();
     tester.settings_xchg();
 
-    // DATA frame without open stream
+    // DATA frame on idle stream
     tester.send_data(11, &[10, 20, 30], false);
 
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+
+    tester.recv_eof();
+}
+
+#[test]
+fn rst_stream_on_data_on_closed_stream() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.get(5, "/blocks/1/1");
+
+    // stream 3 is implicitly closed when stream 5 is opened
+    tester.send_data(3, &[10, 20, 30], false);
+    tester.recv_rst_frame_check(3, ErrorCode::StreamClosed);
+
+    // stream 5 is closed by END_STREAM from client
+    tester.send_data(5, &[10, 20, 30], false);
     tester.recv_goaway_frame_check(ErrorCode::StreamClosed);
 
     tester.recv_eof();
 }
 
+#[test]
+fn rst_stream_flood() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_rst_stream_rate = Some((5, Duration::from_secs(60)));
+    server.service.set_service_fn("/", |_, _| {
+        let body = future::empty::<Bytes, Error>().into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // open and immediately reset streams
+    for stream_id in (1..=11).step_by(2) {
+        tester.send_get(stream_id, "/");
+        tester.send_rst(stream_id, ErrorCode::Cancel);
+    }
+
+    let goaway = loop {
+        match tester.recv_frame() {
+            HttpFrame::Goaway(goaway) => break goaway,
+            HttpFrame::Headers(..) | HttpFrame::Data(..) | HttpFrame::RstStream(..) => {}
+            f => panic!("unexpected frame: {:?}", f),
+        }
+    };
+    assert_eq!(ErrorCode::EnhanceYourCalm, goaway.error_code());
+    tester.recv_eof();
+}
+
 #[test]
 fn exceed_max_frame_size() {
     init_logger();
@@ -246,6 +411,53 @@ fn increase_frame_size() {
     assert_eq!(10000, tester.recv_frame_data_tail(1).len());
 }
 
+#[test]
+fn out_data_frame_max_size() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.out_data_frame_max_size = Some(10000);
+    server.service.set_service_fn("/", |_, _| {
+        Response::headers_and_bytes(Headers::ok_200(), vec![1; 30000])
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // option is smaller than default 16384
+    tester.send_get(1, "/");
+    assert_eq!(200, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(10000, tester.recv_frame_data_check(1, false).len());
+    assert_eq!(10000, tester.recv_frame_data_check(1, false).len());
+    assert_eq!(10000, tester.recv_frame_data_tail(1).len());
+}
+
+#[test]
+fn out_data_frame_max_size_above_peer_max_frame_size() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.out_data_frame_max_size = Some(100000);
+    server.service.set_service_fn("/", |_, _| {
+        Response::headers_and_bytes(Headers::ok_200(), vec![1; 30000])
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // peer SETTINGS_MAX_FRAME_SIZE is not exceeded
+    tester.send_get(1, "/");
+    assert_eq!(200, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(16384, tester.recv_frame_data_check(1, false).len());
+    assert_eq!(30000 - 16384, tester.recv_frame_data_tail(1).len());
+}
+
 #[test]
 fn exceed_window_size() {
     init_logger();
@@ -402,6 +614,75 @@ pub fn server_sends_continuation_frame() {
     assert_eq!(&b"there"[..], &tester.recv_frame_data_tail(1)[..]);
 }
 
+#[test]
+fn data_between_headers_and_continuation() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "POST");
+    headers.add(":path", "/echo");
+    headers.add(":scheme", "http");
+    tester.send_headers_without_end_headers(1, headers);
+
+    tester.send_data(1, b"abcd", true);
+
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+    tester.recv_eof();
+}
+
+#[test]
+fn continuation_for_different_stream() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/echo");
+    tester.send_headers_without_end_headers(1, headers);
+
+    let mut continuation = ContinuationFrame::new_conv(Vec::new(), 3);
+    continuation.set_flag(ContinuationFlag::EndHeaders);
+    tester.send_frame(continuation);
+
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+    tester.recv_eof();
+}
+
+#[test]
+fn continuation_flood() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/echo");
+    tester.send_headers_without_end_headers(1, headers);
+
+    // default limit is 128 frames
+    for _ in 0..200 {
+        tester.send_frame(ContinuationFrame::new_conv(Vec::new(), 1));
+    }
+
+    tester.recv_goaway_frame_check(ErrorCode::EnhanceYourCalm);
+    tester.recv_eof();
+}
+
 #[test]
 pub fn http_1_1() {
     init_logger();
@@ -421,6 +702,102 @@ pub fn http_1_1() {
     );
 }
 
+#[test]
+fn h2c_upgrade() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.allow_h2c_upgrade = Some(true);
+    server.service.set_service_fn("/", |headers, _| {
+        let body = format!("{} {}", headers.path(), headers.get("x-test"));
+        Response::headers_and_bytes(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let port = server.local_addr().port().unwrap();
+    let mut tcp_stream = TcpStream::connect((BIND_HOST, port)).expect("connect");
+    // HTTP2-Settings is SETTINGS_INITIAL_WINDOW_SIZE = 5
+    tcp_stream
+        .write_all(
+            b"GET /upgraded HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAQAAAAF\r\n\
+            X-Test: abc\r\n\
+            \r\n",
+        ).expect("write");
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut buf = [0];
+        tcp_stream.read_exact(&mut buf).expect("read");
+        head.push(buf[0]);
+    }
+    assert!(
+        head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"),
+        "{:?}",
+        BsDebug(&head)
+    );
+
+    let mut tester = HttpConnTester::with_tcp(tcp_stream);
+    tester.send_preface();
+    tester.settings_xchg_but_ack();
+
+    // request is served as stream 1 with window from HTTP2-Settings
+    tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"/upgr", &tester.recv_frame_data_check(1, false)[..]);
+    tester.send_frame(WindowUpdateFrame::for_stream(1, 100));
+    assert_eq!(b"aded abc", &tester.recv_frame_data_tail(1)[..]);
+}
+
+#[test]
+fn h2c_upgrade_long_request_line() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.allow_h2c_upgrade = Some(true);
+    server.service.set_service_fn("/", |headers, _| {
+        Response::headers_and_bytes(Headers::ok_200(), headers.path().to_owned())
+    });
+    let server = server.build().expect("server");
+
+    let port = server.local_addr().port().unwrap();
+    let mut tcp_stream = TcpStream::connect((BIND_HOST, port)).expect("connect");
+    // request line is longer than HTTP/2 preface,
+    // HTTP2-Settings is SETTINGS_MAX_CONCURRENT_STREAMS = 100
+    tcp_stream
+        .write_all(
+            b"GET /upgraded1 HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAMAAABk\r\n\
+            \r\n",
+        ).expect("write");
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut buf = [0];
+        tcp_stream.read_exact(&mut buf).expect("read");
+        head.push(buf[0]);
+    }
+    assert!(
+        head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"),
+        "{:?}",
+        BsDebug(&head)
+    );
+
+    let mut tester = HttpConnTester::with_tcp(tcp_stream);
+    tester.send_preface();
+    tester.settings_xchg_but_ack();
+
+    tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"/upgraded1", &tester.recv_frame_data_tail(1)[..]);
+}
+
 #[cfg(unix)]
 #[test]
 pub fn http_1_1_unix() {
@@ -490,3 +867,2090 @@ fn external_event_loop() {
 
     t.join().expect("thread join");
 }
+
+#[test]
+fn handler_aborted_callback() {
+    init_logger();
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.on_handler_aborted = Some(HandlerAbortedCallback::new(move |path| {
+        tx.lock().unwrap().send(path.to_owned()).expect("send");
+    }));
+    server.service.set_service_fn("/", |_, _| {
+        // never completes
+        let body = futures::future::empty().into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/forever");
+    tester.recv_frame_headers_check(1, false);
+
+    drop(tester);
+
+    let path = rx.recv_timeout(Duration::from_secs(10)).expect("callback");
+    assert_eq!("/forever", path);
+}
+
+#[test]
+fn max_connection_bytes() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_connection_bytes = Some(1000);
+    server.service.set_service_fn("/", |_, _| {
+        Response::headers_and_bytes(Headers::ok_200(), Bytes::from(vec![17u8; 2000]))
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/big");
+
+    loop {
+        match tester.recv_frame() {
+            HttpFrame::Goaway(goaway) => {
+                assert_eq!(ErrorCode::EnhanceYourCalm, goaway.error_code());
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn max_connection_memory() {
+    init_logger();
+
+    // request bodies are kept unread
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let bodies_copy = bodies.clone();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_connection_memory = Some(10000);
+    server.service.set_service_fn("/", move |_, body| {
+        bodies_copy.lock().unwrap().push(body);
+        Response::new(future::empty())
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+    tester.send_data(1, &[17; 8000], false);
+    tester.send_data(1, &[17; 8000], false);
+
+    loop {
+        match tester.recv_frame() {
+            HttpFrame::Goaway(goaway) => {
+                assert_eq!(ErrorCode::EnhanceYourCalm, goaway.error_code());
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn ping_interval() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.ping_interval = Some(Duration::from_millis(100));
+    server.conf.ping_timeout = Some(Duration::from_millis(200));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let recv_ping = |tester: &mut HttpConnTester| match tester.recv_frame() {
+        HttpFrame::Ping(ping) => {
+            assert!(!ping.is_ack());
+            ping
+        }
+        f => panic!("expecting PING, got: {:?}", f),
+    };
+
+    let state = server.dump_state().wait().expect("state");
+    assert_eq!(None, state.single_conn().1.ping_rtt);
+
+    // idle connection is pinged periodically
+    for _ in 0..2 {
+        let ping = recv_ping(&mut tester);
+        tester.send_frame(PingFrame::new_ack(ping.opaque_data()));
+    }
+
+    thread::sleep(Duration::from_millis(20));
+    let state = server.dump_state().wait().expect("state");
+    let ping_rtt = state.single_conn().1.ping_rtt;
+    assert!(ping_rtt.expect("ping_rtt") < Duration::from_secs(1));
+
+    // connection is closed when PING is not acknowledged in time
+    recv_ping(&mut tester);
+    tester.recv_eof();
+}
+
+#[test]
+fn unsolicited_ping_ack() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_frame(PingFrame::new_ack(17));
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+}
+
+#[test]
+fn discard_request_body() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |req, _| match req.path() {
+        // handler stream error is not confused with discarded request body
+        "/error" => Response::headers_and_bytes_stream(
+            Headers::ok_200(),
+            stream::iter_result(vec![
+                Ok(Bytes::from("done")),
+                Err(Error::CodeError(ErrorCode::NoError)),
+            ]),
+        ),
+        _ => Response::headers_and_bytes(Headers::ok_200(), "done").discard_request_body(),
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let post = |path: &str| {
+        let mut headers = Headers::new();
+        headers.add(":method", "POST");
+        headers.add(":path", path);
+        headers.add(":scheme", "http");
+        headers
+    };
+
+    tester.send_headers(1, post("/upload"), false);
+    tester.send_data(1, b"aa", false);
+
+    tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"done", &tester.recv_frame_data_tail(1)[..]);
+    tester.recv_rst_frame_check(1, ErrorCode::NoError);
+    // DATA sent before RST_STREAM is received is ignored, not answered with STREAM_CLOSED
+    tester.send_data(1, b"bb", false);
+
+    tester.send_headers(3, post("/error"), false);
+    tester.send_data(3, b"aa", false);
+
+    tester.recv_frame_headers_check(3, false);
+    assert_eq!(b"done", &tester.recv_frame_data_check(3, false)[..]);
+    tester.recv_rst_frame_check(3, ErrorCode::InternalError);
+}
+
+#[test]
+fn respond_before_request_body_drained() {
+    init_logger();
+
+    let (drained_tx, drained_rx) = mpsc::channel();
+    let drained_tx = Mutex::new(drained_tx);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", move |_, req| {
+        let drained_tx = drained_tx.lock().unwrap().clone();
+        thread::spawn(move || drained_tx.send(req.drain().wait()).unwrap());
+        Response::headers_and_bytes(
+            Headers::from_status_code(StatusCode::ACCEPTED),
+            "accepted",
+        )
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+
+    assert_eq!(202, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(b"accepted", &tester.recv_frame_data_tail(1)[..]);
+
+    // more than initial window: draining task must keep flow control moving
+    let w = DEFAULT_SETTINGS.initial_window_size as i32;
+    let mut stream_window = w;
+    let mut sent = 0;
+    while sent < w * 3 {
+        while stream_window < 10000 || tester.out_window_size.size() < 10000 {
+            match tester.fn_recv_frame_no_check_ack() {
+                HttpFrame::WindowUpdate(ref f) if f.stream_id == 0 => {
+                    tester.out_window_size.try_increase(f.increment).unwrap();
+                }
+                HttpFrame::WindowUpdate(ref f) if f.stream_id == 1 => {
+                    stream_window += f.increment as i32;
+                }
+                f => panic!("expecting WINDOW_UPDATE, got: {:?}", f),
+            }
+        }
+        tester.send_data(1, &[17; 10000], false);
+        stream_window -= 10000;
+        sent += 10000;
+    }
+    tester.send_data(1, b"", true);
+
+    let drained = drained_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(sent as u64, drained.unwrap());
+
+    assert_eq!(0, server.dump_state().wait().expect("state").single_conn().1.streams.len());
+}
+
+#[test]
+fn initial_settings_from_conf() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.header_table_size = Some(1000);
+    server.conf.max_concurrent_streams = Some(10);
+    server.conf.initial_window_size = Some(100000);
+    server.conf.max_frame_size = Some(20000);
+    server.conf.max_header_list_size = Some(5000);
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+
+    let settings_frame = tester.recv_frame_settings_set();
+    let mut settings = DEFAULT_SETTINGS;
+    settings.apply_from_frame(&settings_frame);
+    assert_eq!(1000, settings.header_table_size);
+    assert_eq!(false, settings.enable_push);
+    assert_eq!(10, settings.max_concurrent_streams);
+    assert_eq!(100000, settings.initial_window_size);
+    assert_eq!(20000, settings.max_frame_size);
+    assert_eq!(5000, settings.max_header_list_size);
+}
+
+#[test]
+fn max_header_list_size_boundary() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_header_list_size = Some(4096);
+    server
+        .service
+        .set_service_fn("/", |_, _| Response::headers(Headers::ok_200()));
+    let server = server.build().expect("server");
+
+    // request headers with `x-pad` making header list size `size`
+    let headers_of_size = |size: usize| {
+        let mut headers = Headers::new_get("/");
+        headers.add(":scheme", "http");
+        headers.add("x-pad", "");
+        let base: usize = headers
+            .0
+            .iter()
+            .map(|h| h.name().len() + h.value().len() + 32)
+            .sum();
+        headers.0.pop();
+        headers.add("x-pad", &"a".repeat(size - base));
+        headers
+    };
+
+    // header list of exactly the limit is accepted
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+    tester.send_headers(1, headers_of_size(4096), true);
+    let (frame, headers, _) = tester.recv_frame_headers_decode();
+    assert_eq!(1, frame.stream_id);
+    assert_eq!(200, headers.status());
+
+    // one octet over the limit
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+    tester.send_headers(1, headers_of_size(4097), true);
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+    tester.recv_eof();
+}
+
+#[test]
+fn max_header_list_size_exceeded_during_decode() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_header_list_size = Some(4096);
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // literal with incremental indexing: name "x", value of 1000 octets
+    let mut fragment = vec![0x40, 0x01, b'x', 0x7f, 0xe9, 0x06];
+    fragment.extend(vec![b'a'; 1000]);
+    // one octet per reference to the new dynamic table entry, 100 KB when decoded
+    fragment.extend(vec![0xbe; 100]);
+
+    let mut headers_frame = HeadersFrame::new_conv(fragment, 1);
+    headers_frame.set_flag(HeadersFlag::EndHeaders);
+    headers_frame.set_flag(HeadersFlag::EndStream);
+    tester.send_frame(headers_frame);
+
+    tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+    tester.recv_eof();
+}
+
+#[test]
+fn hpack_context_per_connection() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |req, _| {
+        let mut headers = Headers::ok_200();
+        headers.add("x-pattern", req.get("x-pattern"));
+        Response::headers(headers)
+    });
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().unwrap();
+
+    let connect = || {
+        let mut tester = HttpConnTester::connect(port);
+        tester.send_preface();
+        tester.settings_xchg();
+        tester
+    };
+
+    let request = |tester: &mut HttpConnTester, stream_id, pattern: &str| {
+        let mut headers = Headers::new_get("/");
+        headers.add(":scheme", "http");
+        headers.add("x-pattern", pattern);
+        tester.send_headers(stream_id, headers, true);
+    };
+
+    let mut a = connect();
+    let mut b = connect();
+
+    // both connections index different values under the same names
+    // in their dynamic tables, requests are interleaved
+    for i in 0..5 {
+        let stream_id = 1 + 2 * i;
+        let pattern_a = format!("a{}", "x".repeat(100 * (i % 2 + 1) as usize));
+        let pattern_b = format!("b{}", i % 3);
+        request(&mut a, stream_id, &pattern_a);
+        request(&mut b, stream_id, &pattern_b);
+        assert_eq!(pattern_b, b.recv_message(stream_id).headers.get("x-pattern"));
+        assert_eq!(pattern_a, a.recv_message(stream_id).headers.get("x-pattern"));
+    }
+
+    // reference to dynamic table entry 1151 which does not exist
+    let mut headers_frame = HeadersFrame::new_conv(vec![0xff, 0x80, 0x08], 11);
+    headers_frame.set_flag(HeadersFlag::EndHeaders);
+    headers_frame.set_flag(HeadersFlag::EndStream);
+    a.send_frame(headers_frame);
+    a.recv_goaway_frame_check(ErrorCode::CompressionError);
+
+    // compression error does not affect another connection
+    for i in 5..8 {
+        let stream_id = 1 + 2 * i;
+        let pattern_b = format!("b{}", i % 3);
+        request(&mut b, stream_id, &pattern_b);
+        assert_eq!(pattern_b, b.recv_message(stream_id).headers.get("x-pattern"));
+    }
+}
+
+#[test]
+fn iter_raw_received_order() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |req, _| {
+        let fields: Vec<String> = req
+            .iter_raw()
+            .filter(|&(name, _)| !name.starts_with(b":"))
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    String::from_utf8_lossy(name),
+                    String::from_utf8_lossy(value)
+                )
+            }).collect();
+        Response::headers_and_bytes(Headers::ok_200(), fields.join(" "))
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // duplicate fields interleaved with other fields
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    headers.add("x-b", "1");
+    headers.add("x-a", "2");
+    headers.add("x-b", "3");
+    headers.add("accept", "*/*");
+    headers.add("x-a", "4");
+    tester.send_headers(1, headers, true);
+
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    assert_eq!(
+        &b"x-b=1 x-a=2 x-b=3 accept=*/* x-a=4"[..],
+        &message.body[..]
+    );
+}
+
+fn initial_settings_out_of_range() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_frame_size = Some(100);
+    assert!(server.build().is_err());
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.initial_window_size = Some(0x8000_0000);
+    assert!(server.build().is_err());
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.enable_push = Some(true);
+    assert!(server.build().is_err());
+}
+
+#[test]
+fn settings_initial_window_size_too_large() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_frame(SettingsFrame::from_settings(vec![
+        HttpSetting::InitialWindowSize(0x8000_0000),
+    ]));
+    tester.recv_goaway_frame_check(ErrorCode::FlowControlError);
+    tester.recv_eof();
+}
+
+#[test]
+fn settings_max_frame_size_out_of_range() {
+    init_logger();
+
+    for &max_frame_size in &[0x3fff, 0x100_0000] {
+        let server = ServerTest::new();
+
+        let mut tester = HttpConnTester::connect(server.port);
+        tester.send_preface();
+        tester.settings_xchg();
+
+        tester.send_frame(SettingsFrame::from_settings(vec![
+            HttpSetting::MaxFrameSize(max_frame_size),
+        ]));
+        tester.recv_goaway_frame_check(ErrorCode::ProtocolError);
+        tester.recv_eof();
+    }
+}
+
+#[test]
+fn settings_duplicate_identifiers_last_wins() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let w = DEFAULT_SETTINGS.initial_window_size;
+    tester.send_recv_settings(SettingsFrame::from_settings(vec![
+        HttpSetting::InitialWindowSize(10),
+        HttpSetting::InitialWindowSize(w * 2),
+        HttpSetting::InitialWindowSize(20),
+    ]));
+
+    tester.send_get(1, "/blocks/100/1");
+
+    assert_eq!(200, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(20, tester.recv_frame_data_check(1, false).len());
+
+    let server_sn = server.server.dump_state().wait().expect("state");
+    assert_eq!(0, server_sn.single_conn().1.single_stream().1.out_window_size);
+}
+
+#[test]
+fn pause_reads() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| Response::found_200_plain_text("hello"));
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    server.pause_reads();
+    // make sure pause is processed
+    server.dump_state();
+
+    tester.send_get(1, "/pause");
+
+    thread::sleep(Duration::from_millis(100));
+
+    // request is not read
+    assert_eq!(0, server.dump_state().streams.len());
+
+    server.resume_reads();
+
+    let message = tester.recv_message(1);
+    assert_eq!(b"hello", &message.body[..]);
+}
+
+#[test]
+fn response_from_future() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |req, _| match req.path() {
+        "/fail" => Response::from_future(
+            future::err(Error::Other("db is down")),
+            future::ok(stream::empty()),
+        ),
+        "/custom" => Response::from_future_or_else(
+            future::err(Error::Other("db is down")),
+            future::ok(stream::empty()),
+            |_| Response::headers(Headers::from_status(503)),
+        ),
+        _ => {
+            let (tx, rx) = oneshot::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                tx.send(Headers::ok_200()).unwrap();
+            });
+            Response::from_future(
+                rx.map_err(|_| Error::Other("canceled")),
+                future::lazy(|| Ok(stream::iter_ok(vec![Bytes::from("ab"), Bytes::from("cd")]))),
+            )
+        }
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let message = tester.get(1, "/deferred");
+    assert_eq!(200, message.headers.status());
+    assert_eq!(&b"abcd"[..], &message.body[..]);
+
+    let message = tester.get(3, "/fail");
+    assert_eq!(500, message.headers.status());
+
+    let message = tester.get(5, "/custom");
+    assert_eq!(503, message.headers.status());
+}
+
+#[test]
+fn response_with_trailers() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |req, _| {
+        let trailers = future::lazy(|| {
+            let mut trailers = Headers::new();
+            trailers.add("grpc-status", "0");
+            Ok(trailers)
+        });
+        let response = match req.path() {
+            // body stream sets END_STREAM on the last DATA frame
+            "/once" => Response::headers_and_stream(
+                Headers::ok_200(),
+                HttpStreamAfterHeaders::once_bytes("abcd"),
+            ),
+            _ => Response::headers_and_bytes_stream(
+                Headers::ok_200(),
+                stream::iter_ok(vec![Bytes::from("ab"), Bytes::from("cd")]),
+            ),
+        };
+        response.with_trailers(trailers)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/stream");
+    assert_eq!(200, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(b"ab", &tester.recv_frame_data_check(1, false)[..]);
+    assert_eq!(b"cd", &tester.recv_frame_data_check(1, false)[..]);
+    let trailers = tester.recv_frame_headers_check(1, true);
+    assert_eq!(Some("0"), trailers.get_opt("grpc-status"));
+
+    tester.send_get(3, "/once");
+    assert_eq!(200, tester.recv_frame_headers_check(3, false).status());
+    assert_eq!(b"abcd", &tester.recv_frame_data_check(3, false)[..]);
+    let trailers = tester.recv_frame_headers_check(3, true);
+    assert_eq!(Some("0"), trailers.get_opt("grpc-status"));
+}
+
+#[test]
+fn event_stream() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        let mut event = SseEvent::data("first\nsecond");
+        event.id = Some("1".to_owned());
+        event.event = Some("update".to_owned());
+        event.retry = Some(1000);
+        Response::event_stream(stream::iter_ok(vec![event, SseEvent::data("bye")]))
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let message = tester.get(1, "/events");
+    assert_eq!("text/event-stream", message.headers.get("content-type"));
+    assert_eq!(
+        &b"id: 1\nevent: update\nretry: 1000\ndata: first\ndata: second\n\ndata: bye\n\n"[..],
+        &message.body[..]
+    );
+}
+
+#[test]
+fn response_from_receiver() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        let (tx, rx) = futures::sync::mpsc::unbounded();
+        thread::spawn(move || {
+            for chunk in &["ab", "cd"] {
+                tx.unbounded_send(Bytes::from(*chunk)).expect("send");
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        Response::from_receiver(Headers::ok_200(), rx)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let message = tester.get(1, "/");
+    assert_eq!(200, message.headers.status());
+    assert_eq!(b"abcd", &message.body[..]);
+}
+
+#[test]
+fn response_from_result_receiver_error() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        let (tx, rx) = futures::sync::mpsc::unbounded();
+        thread::spawn(move || {
+            tx.unbounded_send(Ok(Bytes::from("ab"))).expect("send");
+            thread::sleep(Duration::from_millis(10));
+            tx.unbounded_send(Err(Error::Other("producer failed")))
+                .expect("send");
+        });
+        Response::from_result_receiver(Headers::ok_200(), rx)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    assert_eq!(200, tester.recv_frame_headers_check(1, false).status());
+    assert_eq!(b"ab", &tester.recv_frame_data_check(1, false)[..]);
+    tester.recv_rst_frame_check(1, ErrorCode::InternalError);
+}
+
+#[test]
+fn garbage_after_preface() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    for seed in 0..20 {
+        for garbage in &[garbage(seed, 0x10000), garbage_frames(seed, 200)] {
+            let mut tester = HttpConnTester::connect(server.port);
+            tester.send_preface();
+            tester.settings_xchg();
+            tester.send_raw(garbage).ok();
+            tester.recv_until_eof();
+        }
+    }
+
+    // server is still alive
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+    let message = tester.get(1, "/blocks/1/1");
+    assert_eq!(200, message.headers.status());
+}
+
+#[test]
+fn response_sink_flush() {
+    init_logger();
+
+    let (flushed_tx, flushed_rx) = mpsc::channel();
+    let flushed_tx = Mutex::new(flushed_tx);
+    let (finish_tx, finish_rx) = mpsc::channel::<()>();
+    let finish_rx = Mutex::new(Some(finish_rx));
+
+    let server = ServerOneConn::new_fn(0, move |_, _| {
+        let (resp, mut sink) = Response::sink(Headers::ok_200());
+        let flushed_tx = flushed_tx.lock().unwrap().clone();
+        let finish_rx = finish_rx.lock().unwrap().take().unwrap();
+        thread::spawn(move || {
+            sink.write(b"progress").expect("write");
+            sink.flush().expect("flush");
+            flushed_tx.send(()).unwrap();
+            finish_rx.recv().unwrap();
+            sink.write(b"done").expect("write");
+        });
+        resp
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    flushed_rx.recv().unwrap();
+    tester.recv_frame_headers_check(1, false);
+    // flushed chunk arrives while response is still open
+    assert_eq!(&b"progress"[..], &tester.recv_frame_data_check(1, false)[..]);
+
+    finish_tx.send(()).unwrap();
+    // last chunk might be sent with END_STREAM
+    let mut rem = Vec::new();
+    loop {
+        let data = tester.recv_frame_data();
+        assert_eq!(1, data.stream_id);
+        rem.extend_from_slice(&data.data);
+        if data.is_end_of_stream() {
+            break;
+        }
+    }
+    assert_eq!(&b"done"[..], &rem[..]);
+}
+
+#[test]
+fn missing_pseudo_headers() {
+    init_logger();
+
+    let server = ServerTest::new();
+
+    let mut tester = HttpConnTester::connect(server.port);
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let all = vec![
+        Header::new(":method", "GET"),
+        Header::new(":scheme", "http"),
+        Header::new(":path", "/blocks/1/1"),
+    ];
+
+    for i in 0..all.len() {
+        let stream_id = 1 + 2 * i as u32;
+        let mut headers = all.clone();
+        headers.remove(i);
+        tester.send_headers(stream_id, Headers(headers), true);
+        tester.recv_rst_frame_check(stream_id, ErrorCode::ProtocolError);
+    }
+
+    // CONNECT request must not contain :scheme and :path
+    let headers = Headers(vec![
+        Header::new(":method", "CONNECT"),
+        Header::new(":authority", "localhost:443"),
+        Header::new(":path", "/"),
+    ]);
+    tester.send_headers(7, headers, true);
+    tester.recv_rst_frame_check(7, ErrorCode::ProtocolError);
+}
+
+#[test]
+fn headers_with_end_stream_empty_body() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, req| {
+        Response::new(req.filter_data().concat2().map(|body| {
+            let body = format!("body len: {}", body.len());
+            (
+                Headers::ok_200(),
+                HttpStreamAfterHeaders::once_bytes(body.into_bytes()),
+            )
+        }))
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // single HEADERS frame with END_HEADERS and END_STREAM
+    tester.send_get(1, "/empty");
+
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    assert_eq!(b"body len: 0", &message.body[..]);
+}
+
+#[test]
+fn error_in_stream() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        let body = stream::iter_result(vec![
+            Ok(Bytes::from("first")),
+            Err(Error::Other("body failed")),
+        ]);
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/error");
+    tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"first", &tester.recv_frame_data_check(1, false)[..]);
+    tester.recv_rst_frame_check(1, ErrorCode::InternalError);
+
+    assert_eq!(0, server.dump_state().streams.len());
+}
+
+#[test]
+fn https_scheme_on_plain_connection() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |req, _| {
+        let scheme = req.get_opt(":scheme").unwrap_or("").to_owned();
+        Response::found_200_plain_text(&scheme)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // e. g. TLS is terminated by a proxy in front of the server
+    let headers = Headers(vec![
+        Header::new(":method", "GET"),
+        Header::new(":scheme", "https"),
+        Header::new(":path", "/"),
+    ]);
+    tester.send_headers(1, headers, true);
+
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    assert_eq!(&b"https"[..], &message.body[..]);
+}
+
+#[test]
+fn response_compression() {
+    init_logger();
+
+    let body = "hello world ".repeat(1000);
+    let body_copy = body.clone();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.response_compression = Some(CompressionPolicy::default());
+    server.service.set_service_fn("/", move |_, _| {
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/plain");
+        let body = Bytes::from(body_copy.clone());
+        // split into chunks to check stream is compressed incrementally
+        Response::headers_and_bytes_stream(
+            headers,
+            stream::iter_ok(vec![body.slice_to(100), body.slice_from(100)]),
+        )
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    headers.add("accept-encoding", "gzip, deflate");
+    tester.send_headers(1, headers, true);
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    assert_eq!("gzip", message.headers.get("content-encoding"));
+    assert!(message.body.len() < body.len());
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&message.body[..])
+        .read_to_string(&mut decoded)
+        .expect("gzip");
+    assert_eq!(body, decoded);
+
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    headers.add("accept-encoding", "deflate");
+    tester.send_headers(3, headers, true);
+    let message = tester.recv_message(3);
+    assert_eq!("deflate", message.headers.get("content-encoding"));
+    let mut decoded = String::new();
+    flate2::read::ZlibDecoder::new(&message.body[..])
+        .read_to_string(&mut decoded)
+        .expect("deflate");
+    assert_eq!(body, decoded);
+
+    // client does not accept compression
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(5, headers, true);
+    let message = tester.recv_message(5);
+    assert_eq!(None, message.headers.get_opt("content-encoding"));
+    assert_eq!(body.as_bytes(), &message.body[..]);
+}
+
+#[test]
+fn shutdown_gracefully_with_timeout() {
+    init_logger();
+
+    let (body_tx, body_rx) = oneshot::channel::<Bytes>();
+    let body_rx = Mutex::new(Some(body_rx));
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", move |_, _| {
+        let body_rx = body_rx.lock().unwrap().take().expect("single request");
+        let body = body_rx.map_err(|_| Error::Other("canceled")).into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    tester.recv_frame_headers_check(1, false);
+
+    let shutdown = server.shutdown_gracefully_with_timeout(Duration::from_secs(30));
+
+    let goaway = tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(1, goaway.last_stream_id());
+
+    // started stream is completed
+    body_tx.send(Bytes::from("done")).unwrap();
+    assert_eq!(b"done", &tester.recv_frame_data_tail(1)[..]);
+
+    shutdown.wait().expect("shutdown");
+    tester.recv_eof();
+
+    // new connections are not accepted
+    let port = server.local_addr().port().unwrap();
+    assert!(TcpStream::connect((BIND_HOST, port)).is_err());
+}
+
+#[test]
+fn shutdown_gracefully() {
+    init_logger();
+
+    let (body_tx, body_rx) = oneshot::channel::<Bytes>();
+    let body_rx = Mutex::new(Some(body_rx));
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.shutdown_timeout = Some(Duration::from_secs(30));
+    server.service.set_service_fn("/", move |_, _| {
+        let body_rx = body_rx.lock().unwrap().take().expect("single request");
+        let body = body_rx.map_err(|_| Error::Other("canceled")).into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    tester.recv_frame_headers_check(1, false);
+
+    let shutdown = server.shutdown_gracefully();
+
+    let goaway = tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+    assert_eq!(1, goaway.last_stream_id());
+
+    // second call does not send GOAWAY again
+    let shutdown2 = server.shutdown_gracefully();
+
+    // stream opened after GOAWAY is refused
+    tester.send_get(3, "/");
+    tester.recv_rst_frame_check(3, ErrorCode::RefusedStream);
+
+    // started stream is completed
+    body_tx.send(Bytes::from("done")).unwrap();
+    assert_eq!(b"done", &tester.recv_frame_data_tail(1)[..]);
+
+    shutdown.wait().expect("shutdown");
+    shutdown2.wait().expect("shutdown");
+    tester.recv_eof();
+}
+
+#[test]
+fn shutdown_gracefully_timed_out() {
+    init_logger();
+
+    let (req_body_tx, req_body_rx) = mpsc::channel();
+    let req_body_tx = Mutex::new(req_body_tx);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", move |_, req| {
+        let req_body_tx = req_body_tx.lock().unwrap().clone();
+        thread::spawn(move || req_body_tx.send(req.collect().wait()).unwrap());
+        // response body is never completed
+        let body = future::empty::<Bytes, Error>().into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    // request body is never completed too
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+    tester.recv_frame_headers_check(1, false);
+
+    let shutdown = server.shutdown_gracefully_with_timeout(Duration::from_millis(100));
+
+    tester.recv_goaway_frame_check(ErrorCode::NoError);
+    tester.recv_rst_frame_check(1, ErrorCode::Cancel);
+    tester.recv_goaway_frame_check(ErrorCode::NoError);
+
+    shutdown.wait().expect("shutdown");
+    tester.recv_eof();
+
+    // stream is removed and handler is notified
+    match req_body_rx.recv().expect("recv") {
+        Err(Error::CodeError(ErrorCode::Cancel)) => {}
+        r => panic!("unexpected request body: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn shutdown_gracefully_drain_max_streams() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.drain_max_streams = Some(2);
+    server.service.set_service_fn("/", |_, _| {
+        // response body is never completed
+        let body = future::empty::<Bytes, Error>().into_stream();
+        Response::headers_and_bytes_stream(Headers::ok_200(), body)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    for stream_id in (1..=15).step_by(2) {
+        tester.send_get(stream_id, "/");
+        tester.recv_frame_headers_check(stream_id, false);
+    }
+
+    // 8 streams are open, so timeout is reduced to 2 / 8 of 8 seconds
+    let start = Instant::now();
+    let shutdown = server.shutdown_gracefully_with_timeout(Duration::from_secs(8));
+
+    tester.recv_goaway_frame_check(ErrorCode::NoError);
+    for _ in 0..8 {
+        assert_eq!(ErrorCode::Cancel, tester.recv_rst_frame().error_code());
+    }
+    tester.recv_goaway_frame_check(ErrorCode::NoError);
+
+    shutdown.wait().expect("shutdown");
+    tester.recv_eof();
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(6), "{:?}", elapsed);
+}
+
+#[test]
+fn priority_exclusive_dependency() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.enable_priority = Some(true);
+    server.service.set_service_fn("/", |_, _| {
+        Response::headers_and_bytes(Headers::ok_200(), vec![b'x'; 20000])
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    // responses are queued, but not sent until stream windows are increased
+    tester.send_settings(SettingsFrame::from_settings(vec![
+        HttpSetting::InitialWindowSize(0),
+    ]));
+    tester.recv_frame_settings_set();
+    tester.send_frame(SettingsFrame::new_ack());
+    tester.recv_frame_settings_ack();
+
+    for stream_id in (1..=5).step_by(2) {
+        tester.send_get(stream_id, "/");
+        tester.recv_frame_headers_check(stream_id, false);
+    }
+    // stream 5 becomes the only dependency of root, streams 1 and 3 depend on 5
+    tester.send_frame(PriorityFrame::new(5, 0, 15, true));
+
+    thread::sleep(Duration::from_millis(100));
+
+    let mut window_updates = Vec::new();
+    for stream_id in (1..=5).step_by(2) {
+        let frame = WindowUpdateFrame::for_stream(stream_id, 100000);
+        window_updates.extend(frame.serialize_into_vec());
+    }
+    tester.send_raw(&window_updates).expect("send");
+
+    let mut order = Vec::new();
+    for _ in 0..6 {
+        let data = tester.recv_frame_data();
+        order.push(data.stream_id);
+    }
+    assert_eq!(vec![5, 5], &order[..2]);
+}
+
+#[test]
+fn panic_response() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.panic_response = Some(PanicResponseCallback::new(|req, message| {
+        let mut headers = Headers::from_status(503);
+        headers.add("content-type", "application/json");
+        let body = format!(
+            "{{\"error\":\"{}\",\"request_id\":\"{}\"}}",
+            message,
+            req.get("x-request-id")
+        );
+        Response::headers_and_bytes(headers, body)
+    }));
+    server.service.set_service_fn("/", |_, _| panic!("requested"));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    headers.add("x-request-id", "abc");
+    tester.send_headers(1, headers, true);
+
+    let message = tester.recv_message(1);
+    assert_eq!(503, message.headers.status());
+    assert_eq!("application/json", message.headers.get("content-type"));
+    assert_eq!(
+        &b"{\"error\":\"requested\",\"request_id\":\"abc\"}"[..],
+        &message.body[..]
+    );
+}
+
+#[test]
+fn new_stream_rate_limit() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.new_stream_rate_limit = Some((1, 3));
+    server
+        .service
+        .set_service_fn("/", |_, _| Response::found_200_plain_text("hi"));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    for stream_id in (1..).step_by(2).take(3) {
+        tester.send_get(stream_id, "/");
+        assert_eq!(200, tester.recv_message(stream_id).headers.status());
+    }
+
+    // burst of stream opens
+    for stream_id in (7..).step_by(2).take(4) {
+        tester.send_get(stream_id, "/");
+    }
+
+    for stream_id in (7..).step_by(2).take(3) {
+        tester.recv_rst_frame_check(stream_id, ErrorCode::RefusedStream);
+    }
+    let goaway = tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::EnhanceYourCalm, goaway.error_code());
+    assert_eq!(11, goaway.last_stream_id());
+}
+
+#[test]
+fn preface_timeout() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.preface_timeout = Some(Duration::from_millis(300));
+    server
+        .service
+        .set_service_fn("/", |_, _| Response::found_200_plain_text("hi"));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().unwrap();
+
+    // nothing sent
+    let mut tester = HttpConnTester::connect(port);
+    tester.recv_eof();
+
+    // preface without initial SETTINGS
+    let mut tester = HttpConnTester::connect(port);
+    tester.send_preface();
+    tester.recv_frame_settings_set();
+    tester.recv_eof();
+
+    // timer is cancelled after handshake
+    let mut tester = HttpConnTester::connect(port);
+    tester.send_preface();
+    tester.settings_xchg();
+    thread::sleep(Duration::from_millis(600));
+    tester.send_get(1, "/");
+    assert_eq!(200, tester.recv_message(1).headers.status());
+}
+
+#[test]
+fn max_content_length() {
+    init_logger();
+
+    let handler_calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls_copy = handler_calls.clone();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_content_length = Some(10);
+    server.service.set_service_fn("/", move |_, req| {
+        handler_calls_copy.fetch_add(1, Ordering::SeqCst);
+        Response::new(req.filter_data().concat2().map(|body| {
+            (Headers::ok_200(), HttpStreamAfterHeaders::once_bytes(body))
+        }))
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let post = |content_length: Option<&str>| {
+        let mut headers = Headers::new();
+        headers.add(":method", "POST");
+        headers.add(":path", "/");
+        headers.add(":scheme", "http");
+        if let Some(content_length) = content_length {
+            headers.add("content-length", content_length);
+        }
+        headers
+    };
+
+    // rejected immediately after headers
+    tester.send_headers(1, post(Some("1000000")), false);
+    assert_eq!(413, tester.recv_frame_headers_check(1, true).status());
+    // client is told to stop sending request body
+    tester.recv_rst_frame_check(1, ErrorCode::NoError);
+    assert_eq!(0, handler_calls.load(Ordering::SeqCst));
+
+    // body within limit
+    tester.send_headers(3, post(Some("5")), false);
+    tester.send_data(3, b"hello", true);
+    let message = tester.recv_message(3);
+    assert_eq!(b"hello", &message.body[..]);
+
+    // body of unknown length is limited while it is received
+    tester.send_headers(5, post(None), false);
+    tester.send_data(5, b"12345678", false);
+    tester.send_data(5, b"12345678", false);
+    tester.recv_rst_frame_check(5, ErrorCode::Cancel);
+    assert_eq!(2, handler_calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn request_content_length_mismatch() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |_, req| {
+        Response::new(req.filter_data().concat2().map(|body| {
+            (Headers::ok_200(), HttpStreamAfterHeaders::once_bytes(body))
+        }))
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let post = |content_length: &str| {
+        let mut headers = Headers::new();
+        headers.add(":method", "POST");
+        headers.add(":path", "/");
+        headers.add(":scheme", "http");
+        headers.add("content-length", content_length);
+        headers
+    };
+
+    // more data than declared
+    tester.send_headers(1, post("3"), false);
+    tester.send_data(1, b"abcdef", true);
+    tester.recv_rst_frame_check(1, ErrorCode::ProtocolError);
+
+    // less data than declared
+    tester.send_headers(3, post("10"), false);
+    tester.send_data(3, b"abc", true);
+    tester.recv_rst_frame_check(3, ErrorCode::ProtocolError);
+
+    // stream ended by trailers before declared length
+    tester.send_headers(5, post("10"), false);
+    tester.send_data(5, b"abc", false);
+    tester.send_headers(5, Headers::new(), true);
+    tester.recv_rst_frame_check(5, ErrorCode::ProtocolError);
+
+    // no body at all
+    tester.send_headers(7, post("10"), true);
+    tester.recv_rst_frame_check(7, ErrorCode::ProtocolError);
+
+    // exact length is fine
+    tester.send_headers(9, post("3"), false);
+    tester.send_data(9, b"abc", true);
+    let message = tester.recv_message(9);
+    assert_eq!(b"abc", &message.body[..]);
+}
+
+#[test]
+fn request_id_header() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.request_id_header = Some("X-Request-Id".to_owned());
+    server.service.set_service_fn("/", |req, _| {
+        // handler sees generated id too
+        let id = req.get_opt("x-request-id").unwrap_or("").to_owned();
+        Response::found_200_plain_text(&id)
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/");
+    headers.add(":scheme", "http");
+    headers.add("x-request-id", "abc-123");
+    tester.send_headers(1, headers, true);
+    let message = tester.recv_message(1);
+    assert_eq!(Some("abc-123"), message.headers.get_opt("x-request-id"));
+    assert_eq!(&b"abc-123"[..], &message.body[..]);
+
+    tester.send_get(3, "/");
+    let message = tester.recv_message(3);
+    let id = message.headers.get_opt("x-request-id").expect("request id").to_owned();
+    assert_eq!(36, id.len());
+    assert_eq!(id.as_bytes(), &message.body[..]);
+}
+
+#[test]
+fn request_trailers() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |_, body| {
+        Response::new(body.collect_with_trailers().map(|(body, trailers)| {
+            let checksum = trailers
+                .as_ref()
+                .and_then(|t| t.get_opt("x-checksum"))
+                .unwrap_or("")
+                .to_owned();
+            let body = format!("{} {}", String::from_utf8_lossy(&body), checksum);
+            (Headers::ok_200(), HttpStreamAfterHeaders::once_bytes(body))
+        }))
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+    tester.send_data(1, b"abc", false);
+    let mut trailers = Headers::new();
+    trailers.add("x-checksum", "123");
+    tester.send_headers(1, trailers, true);
+
+    let message = tester.recv_message(1);
+    assert_eq!(&b"abc 123"[..], &message.body[..]);
+}
+
+struct RecordingTracer(Arc<Mutex<Vec<String>>>);
+
+struct RecordingSpan(Arc<Mutex<Vec<String>>>);
+
+impl RequestTracer for RecordingTracer {
+    fn start_client_span(&self, _headers: &Headers, _parent: Option<TraceParent>) -> Box<RequestSpan> {
+        unreachable!()
+    }
+
+    fn start_server_span(&self, headers: &Headers, parent: Option<TraceParent>) -> Box<RequestSpan> {
+        let parent = parent.map(|p| p.to_string()).unwrap_or_default();
+        self.0.lock().unwrap().push(format!("start {} {}", headers.path(), parent));
+        Box::new(RecordingSpan(self.0.clone()))
+    }
+}
+
+impl RequestSpan for RecordingSpan {
+    fn stream_id(&mut self, stream_id: u32) {
+        self.0.lock().unwrap().push(format!("stream {}", stream_id));
+    }
+
+    fn response_headers(&mut self, headers: &Headers) {
+        self.0.lock().unwrap().push(format!("status {}", headers.status()));
+    }
+
+    fn end(&mut self, error: Option<&Error>) {
+        self.0.lock().unwrap().push(format!("end {}", error.is_none()));
+    }
+}
+
+#[test]
+fn request_tracing() {
+    init_logger();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.request_tracing = Some(RequestTracing::new(RecordingTracer(events.clone())));
+    server.service.set_service_fn("/", |_, _| Response::found_200_plain_text("hi"));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", "/a");
+    headers.add(":scheme", "http");
+    headers.add("traceparent", traceparent);
+    tester.send_headers(1, headers, true);
+    assert_eq!(200, tester.recv_message(1).headers.status());
+
+    // span is ended after the last frame is queued
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while events.lock().unwrap().len() < 4 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(
+        vec![
+            format!("start /a {}", traceparent),
+            "stream 1".to_owned(),
+            "status 200".to_owned(),
+            "end true".to_owned(),
+        ],
+        *events.lock().unwrap()
+    );
+}
+
+#[test]
+fn response_etag() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service_fn("/", |req, _| {
+        let mut headers = Headers::ok_200();
+        headers.add("cache-control", "max-age=60");
+        Response::headers_and_bytes_with_etag(&req, headers, "hello")
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    let etag = message.headers.get("etag").to_owned();
+
+    let mut headers = Headers::new_get("/");
+    headers.add(":scheme", "http");
+    headers.add("if-none-match", &format!("W/\"other\", {}", etag));
+    tester.send_headers(3, headers, true);
+    let message = tester.recv_message(3);
+    assert_eq!(304, message.headers.status());
+    assert_eq!(etag, message.headers.get("etag"));
+    assert_eq!("max-age=60", message.headers.get("cache-control"));
+    assert!(message.body.is_empty());
+}
+
+#[test]
+fn static_files() {
+    init_logger();
+
+    let tempdir = tempdir::TempDir::new("rust_http2_test").unwrap();
+    fs::write(tempdir.path().join("index.html"), "<p>index</p>").unwrap();
+    fs::create_dir(tempdir.path().join("sub")).unwrap();
+    fs::write(tempdir.path().join("sub/data.txt"), "0123456789").unwrap();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server
+        .service
+        .set_service("/", Arc::new(StaticFiles::new(tempdir.path())));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let get = |path: &str, extra: &[(&str, &str)]| {
+        let mut headers = Headers::new_get(path);
+        headers.add(":scheme", "http");
+        for &(name, value) in extra {
+            headers.add(name, value);
+        }
+        headers
+    };
+
+    tester.send_headers(1, get("/", &[]), true);
+    let message = tester.recv_message(1);
+    assert_eq!(200, message.headers.status());
+    assert_eq!(Some("text/html; charset=utf-8"), message.headers.get_opt("content-type"));
+    assert_eq!(&b"<p>index</p>"[..], &message.body[..]);
+
+    tester.send_headers(3, get("/sub/data.txt", &[]), true);
+    let message = tester.recv_message(3);
+    assert_eq!(&b"0123456789"[..], &message.body[..]);
+    let etag = message.headers.get("etag").to_owned();
+    let last_modified = message.headers.get("last-modified").to_owned();
+
+    tester.send_headers(5, get("/sub/data.txt", &[("range", "bytes=2-4")]), true);
+    let message = tester.recv_message(5);
+    assert_eq!(206, message.headers.status());
+    assert_eq!(Some("bytes 2-4/10"), message.headers.get_opt("content-range"));
+    assert_eq!(&b"234"[..], &message.body[..]);
+
+    tester.send_headers(7, get("/sub/data.txt", &[("range", "bytes=20-")]), true);
+    assert_eq!(416, tester.recv_message(7).headers.status());
+
+    tester.send_headers(9, get("/sub/data.txt", &[("if-none-match", &etag)]), true);
+    assert_eq!(304, tester.recv_message(9).headers.status());
+
+    let if_modified_since = [("if-modified-since", &last_modified[..])];
+    tester.send_headers(11, get("/sub/data.txt", &if_modified_since), true);
+    assert_eq!(304, tester.recv_message(11).headers.status());
+
+    tester.send_headers(13, get("/sub/missing.txt", &[]), true);
+    assert_eq!(404, tester.recv_message(13).headers.status());
+
+    tester.send_headers(15, get("/sub/../../etc/passwd", &[]), true);
+    assert_eq!(403, tester.recv_message(15).headers.status());
+
+    tester.send_headers(17, get("/sub/data.txt", &[("range", "bytes=0-1,8-")]), true);
+    let message = tester.recv_message(17);
+    assert_eq!(206, message.headers.status());
+    assert!(message.headers.get("content-type").starts_with("multipart/byteranges"));
+    let body = String::from_utf8(message.body.to_vec()).unwrap();
+    assert!(body.contains("content-range: bytes 0-1/10\r\n\r\n01\r\n"));
+    assert!(body.contains("content-range: bytes 8-9/10\r\n\r\n89\r\n"));
+}
+
+#[test]
+fn method_not_allowed() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_method_service_fn("GET", "/items", |_, _| {
+        Response::found_200_plain_text("list")
+    });
+    server.service.set_method_service_fn("POST", "/items", |_, _| {
+        Response::found_200_plain_text("created")
+    });
+    server.service.set_service_fn("/any", |_, _| Response::found_200_plain_text("any"));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let request = |method: &str, path: &str| {
+        Headers(vec![
+            Header::new(":method", method.to_owned()),
+            Header::new(":path", path.to_owned()),
+            Header::new(":scheme", "http"),
+        ])
+    };
+
+    tester.send_headers(1, request("GET", "/items"), true);
+    assert_eq!(&b"list"[..], &tester.recv_message(1).body[..]);
+
+    tester.send_headers(3, request("POST", "/items"), true);
+    assert_eq!(&b"created"[..], &tester.recv_message(3).body[..]);
+
+    tester.send_headers(5, request("DELETE", "/items"), true);
+    let message = tester.recv_message(5);
+    assert_eq!(405, message.headers.status());
+    assert_eq!(Some("GET, POST"), message.headers.get_opt("allow"));
+
+    // service without method matches any method
+    tester.send_headers(7, request("DELETE", "/any"), true);
+    assert_eq!(&b"any"[..], &tester.recv_message(7).body[..]);
+}
+
+#[test]
+fn method_not_allowed_service() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_method_service_fn("GET", "/items", |_, _| {
+        Response::found_200_plain_text("list")
+    });
+    server
+        .service
+        .set_method_not_allowed_service(Arc::new(|_: Headers, _: HttpStreamAfterHeaders| {
+            Response::headers_and_bytes(Headers::from_status(405), "use GET")
+        }));
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_post("/items");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, true);
+    let message = tester.recv_message(1);
+    assert_eq!(405, message.headers.status());
+    assert_eq!(Some("GET"), message.headers.get_opt("allow"));
+    assert_eq!(&b"use GET"[..], &message.body[..]);
+}
+
+#[test]
+fn trailers_only() {
+    init_logger();
+
+    let server = ServerOneConn::new_fn(0, |_, _| {
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "application/grpc");
+        headers.add("grpc-status", "5");
+        headers.add("grpc-message", "not found");
+        Response::trailers_only(headers)
+    });
+
+    let mut tester = HttpConnTester::connect(server.port());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    let headers = tester.recv_frame_headers_check(1, true);
+    assert_eq!(200, headers.status());
+    assert_eq!("5", headers.get("grpc-status"));
+    assert_eq!("not found", headers.get("grpc-message"));
+
+    // no separate DATA or trailing HEADERS frame
+    tester.send_get(3, "/");
+    tester.recv_frame_headers_check(3, true);
+
+    assert_eq!(0, server.dump_state().streams.len());
+}
+
+#[test]
+fn stream_queue_high_water() {
+    init_logger();
+
+    let (body_tx, body_rx) = mpsc::channel();
+    let body_tx = Mutex::new(body_tx);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.common.stream_queue_high_water = Some(1000);
+    server.conf.common.stream_queue_low_water = Some(100);
+    server.service.set_service_fn("/", move |_, body| {
+        body_tx.lock().unwrap().send(body).unwrap();
+        Response::new(future::empty())
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+    for _ in 0..6 {
+        tester.send_data(1, &[17; 10000], false);
+    }
+
+    let mut body = body_rx.recv().unwrap().wait();
+    // let server receive all DATA frames
+    thread::sleep(Duration::from_millis(100));
+    let deferred = || {
+        let state = server.dump_state().wait().expect("state");
+        state.single_conn().1.deferred_window_update_bytes
+    };
+
+    for _ in 0..5 {
+        body.next().unwrap().unwrap();
+    }
+    // window is low, but consumer is slow
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size as usize, deferred());
+
+    // consumer drained the queue
+    body.next().unwrap().unwrap();
+    assert_eq!(0, deferred());
+    loop {
+        if let HttpFrame::WindowUpdate(f) = tester.fn_recv_frame_no_check_ack() {
+            if f.stream_id == 1 {
+                assert_eq!(DEFAULT_SETTINGS.initial_window_size, f.increment);
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn window_update_threshold() {
+    init_logger();
+
+    let (body_tx, body_rx) = mpsc::channel();
+    let body_tx = Mutex::new(body_tx);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.common.conn_initial_window_size = Some(1 << 20);
+    server.conf.common.window_update_strategy = Some(WindowUpdateStrategy::Threshold(20000));
+    server.service.set_service_fn("/", move |_, body| {
+        body_tx.lock().unwrap().send(body).unwrap();
+        Response::new(future::empty())
+    });
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg_but_ack();
+
+    // connection window is enlarged right after SETTINGS
+    match tester.fn_recv_frame_no_check_ack() {
+        HttpFrame::WindowUpdate(f) => {
+            assert_eq!(0, f.stream_id);
+            assert_eq!((1 << 20) - DEFAULT_SETTINGS.initial_window_size, f.increment);
+        }
+        f => panic!("expecting WINDOW_UPDATE, got: {:?}", f),
+    }
+    tester.recv_frame_settings_ack();
+
+    let state = server.dump_state().wait().expect("state");
+    let conn = state.single_conn().1;
+    assert_eq!(1 << 20, conn.in_window_size);
+    assert_eq!(1 << 20, conn.in_window_size_target);
+    assert_eq!(DEFAULT_SETTINGS.initial_window_size, conn.stream_in_window_size);
+    assert_eq!(
+        WindowUpdateStrategy::Threshold(20000),
+        conn.window_update_strategy
+    );
+
+    let mut headers = Headers::new_post("/");
+    headers.add(":scheme", "http");
+    tester.send_headers(1, headers, false);
+    tester.send_data(1, &[17; 10000], false);
+
+    let mut body = body_rx.recv().unwrap().wait();
+    body.next().unwrap().unwrap();
+
+    // below threshold
+    tester.send_data(1, &[17; 10000], false);
+    body.next().unwrap().unwrap();
+
+    let mut conn_increment = None;
+    let mut stream_increment = None;
+    while conn_increment.is_none() || stream_increment.is_none() {
+        match tester.fn_recv_frame_no_check_ack() {
+            HttpFrame::WindowUpdate(ref f) if f.stream_id == 0 => {
+                assert_eq!(None, conn_increment);
+                conn_increment = Some(f.increment);
+            }
+            HttpFrame::WindowUpdate(ref f) if f.stream_id == 1 => {
+                assert_eq!(None, stream_increment);
+                stream_increment = Some(f.increment);
+            }
+            f => panic!("expecting WINDOW_UPDATE, got: {:?}", f),
+        }
+    }
+    assert_eq!(Some(20000), conn_increment);
+    assert_eq!(Some(20000), stream_increment);
+}
+
+struct PushServiceFn<F>(F);
+
+impl<F> Service for PushServiceFn<F>
+where
+    F: Fn(Headers, ServerPush) -> Response + Send + Sync + 'static,
+{
+    fn start_request(&self, _headers: Headers, _req: HttpStreamAfterHeaders) -> Response {
+        unreachable!()
+    }
+
+    fn start_request_with_push(
+        &self,
+        headers: Headers,
+        _req: HttpStreamAfterHeaders,
+        push: ServerPush,
+    ) -> Response {
+        (self.0)(headers, push)
+    }
+}
+
+fn push_server<F>(f: F) -> Server
+where
+    F: Fn(Headers, ServerPush) -> Response + Send + Sync + 'static,
+{
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.service.set_service("/", Arc::new(PushServiceFn(f)));
+    server.build().expect("server")
+}
+
+fn pushed_get(path: &str) -> Headers {
+    let mut headers = Headers::new();
+    headers.add(":method", "GET");
+    headers.add(":path", path);
+    headers.add(":scheme", "http");
+    headers
+}
+
+#[test]
+fn push_promise() {
+    init_logger();
+
+    let server = push_server(|_, push| {
+        for path in &["/style.css", "/app.js"] {
+            let sink = push.push_request(pushed_get(path)).expect("push");
+            sink.send(Response::found_200_plain_text(*path))
+                .expect("send");
+        }
+        Response::found_200_plain_text("main")
+    });
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+
+    // promised stream ids are even and increasing
+    let (frame, headers) = tester.recv_frame_push_promise_decode();
+    assert_eq!(1, frame.stream_id);
+    assert_eq!(2, frame.promised_stream_id);
+    assert_eq!("/style.css", headers.path());
+    let (frame, headers) = tester.recv_frame_push_promise_decode();
+    assert_eq!(1, frame.stream_id);
+    assert_eq!(4, frame.promised_stream_id);
+    assert_eq!("/app.js", headers.path());
+
+    // responses are interleaved, decode header blocks in arrival order
+    let mut bodies: HashMap<u32, Vec<u8>> = HashMap::new();
+    let mut open = 3;
+    while open > 0 {
+        match tester.recv_frame() {
+            HttpFrame::Headers(frame) => {
+                assert!(frame.flags.is_set(HeadersFlag::EndHeaders));
+                let headers = tester.decoder.decode(frame.header_fragment()).expect("decode");
+                assert_eq!(
+                    (Bytes::from(":status"), Bytes::from("200")),
+                    headers[0],
+                    "stream {}",
+                    frame.stream_id
+                );
+                if frame.is_end_of_stream() {
+                    open -= 1;
+                }
+            }
+            HttpFrame::Data(frame) => {
+                bodies
+                    .entry(frame.stream_id)
+                    .or_insert_with(Vec::new)
+                    .extend_from_slice(&frame.data);
+                if frame.is_end_of_stream() {
+                    open -= 1;
+                }
+            }
+            f => panic!("unexpected frame: {:?}", f),
+        }
+    }
+
+    assert_eq!(&b"main"[..], &bodies[&1][..]);
+    assert_eq!(&b"/style.css"[..], &bodies[&2][..]);
+    assert_eq!(&b"/app.js"[..], &bodies[&4][..]);
+}
+
+#[test]
+fn pushed_streams_not_counted_in_max_concurrent_streams() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_port(0);
+    server.conf.max_concurrent_streams = Some(2);
+    server.service.set_service(
+        "/",
+        Arc::new(PushServiceFn(|headers: Headers, push: ServerPush| {
+            if headers.path() == "/" {
+                // pushed response is never completed
+                let body = future::empty::<Bytes, Error>().into_stream();
+                let sink = push.push_request(pushed_get("/events")).expect("push");
+                sink.send(Response::headers_and_bytes_stream(Headers::ok_200(), body))
+                    .expect("send");
+            }
+            let body = future::empty::<Bytes, Error>().into_stream();
+            Response::headers_and_bytes_stream(Headers::ok_200(), body)
+        })),
+    );
+    let server = server.build().expect("server");
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    let (frame, _) = tester.recv_frame_push_promise_decode();
+    assert_eq!(2, frame.promised_stream_id);
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        let (frame, _, _) = tester.recv_frame_headers_decode();
+        assert!(!frame.is_end_of_stream());
+        streams.push(frame.stream_id);
+    }
+    streams.sort();
+    assert_eq!(vec![1, 2], streams);
+
+    // pushed stream 2 is open, but only client streams are limited
+    tester.send_get(3, "/other");
+    tester.recv_frame_headers_check(3, false);
+
+    tester.send_get(5, "/other");
+    tester.recv_rst_frame_check(5, ErrorCode::RefusedStream);
+}
+
+#[test]
+fn push_disabled_by_client() {
+    init_logger();
+
+    let server = push_server(|_, push| {
+        match push.push_request(pushed_get("/style.css")) {
+            Err(Error::PushNotAllowed) => Response::found_200_plain_text("rejected"),
+            r => panic!("push must fail: {:?}", r.map(|_| ())),
+        }
+    });
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    let mut settings = SettingsFrame::new();
+    settings.add_setting(HttpSetting::EnablePush(false));
+    tester.send_settings(settings);
+    tester.recv_frame_settings_set();
+    tester.send_frame(SettingsFrame::new_ack());
+    tester.recv_frame_settings_ack();
+
+    tester.send_get(1, "/");
+    tester.recv_frame_headers_check(1, false);
+    assert_eq!(b"rejected", &tester.recv_frame_data_tail(1)[..]);
+}
+
+#[test]
+fn push_rejected_after_goaway() {
+    init_logger();
+
+    let (push_tx, push_rx) = mpsc::channel::<()>();
+    let push_rx = Mutex::new(Some(push_rx));
+    let (result_tx, result_rx) = mpsc::channel();
+    let result_tx = Mutex::new(result_tx);
+
+    let server = push_server(move |_, push| {
+        let (resp, sink) = Response::sink(Headers::ok_200());
+        let push_rx = push_rx.lock().unwrap().take().expect("single request");
+        let result_tx = result_tx.lock().unwrap().clone();
+        thread::spawn(move || {
+            push_rx.recv().unwrap();
+            let rejected = match push.push_request(pushed_get("/style.css")) {
+                Err(Error::PushNotAllowed) => true,
+                _ => false,
+            };
+            result_tx.send(rejected).unwrap();
+            drop(sink);
+        });
+        resp
+    });
+
+    let mut tester = HttpConnTester::connect(server.local_addr().port().unwrap());
+    tester.send_preface();
+    tester.settings_xchg();
+
+    tester.send_get(1, "/");
+    tester.recv_frame_headers_check(1, false);
+
+    let _shutdown = server.shutdown_gracefully_with_timeout(Duration::from_secs(30));
+    let goaway = tester.recv_goaway_frame();
+    assert_eq!(ErrorCode::NoError, goaway.error_code());
+
+    push_tx.send(()).unwrap();
+    assert!(result_rx.recv().unwrap());
+    tester.recv_frame_data_tail(1);
+}
@@ -0,0 +1,391 @@
+//! Byte range requests (RFC 7233)
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use bytes::Bytes;
+
+use futures::future::Future;
+use futures::stream;
+use futures::stream::Stream;
+use futures::Async;
+use futures::Poll;
+
+use data_or_trailers::HttpStreamAfterHeaders;
+use error::Error;
+use resp::Response;
+use server::request_id::generate_request_id;
+use solicit::header::Headers;
+
+/// More ranges than this are ignored and whole content is sent
+const MAX_RANGES: usize = 32;
+
+/// Size of `DATA` chunks read from source
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Single byte range, positions are inclusive
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ByteRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.last - self.first + 1
+    }
+
+    /// Always `false`: both ends of the range are inclusive
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// `content-range` header value
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.first, self.last, total)
+    }
+}
+
+/// `range` header resolved against content length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No range requested, or range is ignored: whole content is sent
+    Full,
+    /// Satisfiable ranges, sorted, overlapping and adjacent ranges are coalesced
+    Ranges(Vec<ByteRange>),
+    /// None of the ranges is satisfiable, `416` should be sent
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Parse `range` header value for content of given length.
+    ///
+    /// Invalid header, unknown range unit or too many ranges
+    /// result in `Full` as allowed by RFC 7233 section 3.1.
+    pub fn parse(value: &str, len: u64) -> RangeRequest {
+        let value = value.trim();
+        if !value.starts_with("bytes=") {
+            return RangeRequest::Full;
+        }
+
+        let mut ranges = Vec::new();
+        let mut specs = 0;
+        for spec in value["bytes=".len()..].split(',') {
+            let spec = spec.trim();
+            if spec.is_empty() {
+                continue;
+            }
+            specs += 1;
+            if specs > MAX_RANGES {
+                return RangeRequest::Full;
+            }
+            match parse_range_spec(spec, len) {
+                Err(()) => return RangeRequest::Full,
+                Ok(Some(range)) => ranges.push(range),
+                Ok(None) => {}
+            }
+        }
+
+        if specs == 0 {
+            return RangeRequest::Full;
+        }
+        if ranges.is_empty() {
+            return RangeRequest::Unsatisfiable;
+        }
+
+        ranges.sort_by_key(|r| r.first);
+        let mut coalesced: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match coalesced.last_mut() {
+                Some(ref mut prev) if range.first <= prev.last + 1 => {
+                    prev.last = cmp::max(prev.last, range.last);
+                    continue;
+                }
+                _ => {}
+            }
+            coalesced.push(range);
+        }
+        RangeRequest::Ranges(coalesced)
+    }
+}
+
+/// `Err` if spec is invalid, `None` if spec is not satisfiable
+fn parse_range_spec(spec: &str, len: u64) -> Result<Option<ByteRange>, ()> {
+    let dash = spec.find('-').ok_or(())?;
+    let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+    if first.is_empty() {
+        // suffix range: last N bytes
+        let suffix = last.parse::<u64>().map_err(|_| ())?;
+        if suffix == 0 || len == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(ByteRange {
+            first: len.saturating_sub(suffix),
+            last: len - 1,
+        }));
+    }
+
+    let first = first.parse::<u64>().map_err(|_| ())?;
+    let last = match last {
+        "" => u64::MAX,
+        last => last.parse::<u64>().map_err(|_| ())?,
+    };
+    if last < first {
+        return Err(());
+    }
+    if first >= len {
+        return Ok(None);
+    }
+    Ok(Some(ByteRange {
+        first,
+        last: cmp::min(last, len - 1),
+    }))
+}
+
+enum Segment {
+    Bytes(Bytes),
+    Read { pos: u64, remaining: u64 },
+}
+
+/// Body of range response: fragments of source interleaved with multipart delimiters
+struct RangeStream<R> {
+    source: R,
+    segments: VecDeque<Segment>,
+}
+
+impl<R: Read + Seek> Stream for RangeStream<R> {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        let (pos, remaining) = match self.segments.pop_front() {
+            None => return Ok(Async::Ready(None)),
+            Some(Segment::Bytes(bytes)) => return Ok(Async::Ready(Some(bytes))),
+            Some(Segment::Read { pos, remaining }) => (pos, remaining),
+        };
+
+        let mut buf = vec![0; cmp::min(remaining, CHUNK_SIZE) as usize];
+        self.source.seek(SeekFrom::Start(pos))?;
+        // fails if source is truncated after headers are sent
+        self.source.read_exact(&mut buf)?;
+
+        let read = buf.len() as u64;
+        if read < remaining {
+            self.segments.push_front(Segment::Read {
+                pos: pos + read,
+                remaining: remaining - read,
+            });
+        }
+        Ok(Async::Ready(Some(Bytes::from(buf))))
+    }
+}
+
+/// Implementation of `Response::byte_ranges`
+pub(crate) fn range_response<R>(
+    headers: Headers,
+    source: R,
+    len: u64,
+    range: RangeRequest,
+) -> Response
+where
+    R: Read + Seek + Send + 'static,
+{
+    let mut headers = headers;
+    headers.0.retain(|h| {
+        h.name() != b":status" && h.name() != b"content-length" && h.name() != b"content-range"
+    });
+
+    let ranges = match range {
+        RangeRequest::Full => {
+            let segment = Segment::Read {
+                pos: 0,
+                remaining: len,
+            };
+            return body_response(200, headers, source, len, vec![segment]);
+        }
+        RangeRequest::Unsatisfiable => {
+            // there is no content
+            headers.0.retain(|h| h.name() != b"content-type");
+            let mut r = Headers::from_status(416);
+            r.add("content-range", &format!("bytes */{}", len));
+            r.extend(headers);
+            return Response::headers(r);
+        }
+        RangeRequest::Ranges(ranges) => ranges,
+    };
+
+    if ranges.len() == 1 {
+        let range = ranges[0];
+        headers.add("content-range", &range.content_range(len));
+        let segment = Segment::Read {
+            pos: range.first,
+            remaining: range.len(),
+        };
+        return body_response(206, headers, source, range.len(), vec![segment]);
+    }
+
+    // content type is sent in each part
+    let content_type = headers.get_opt("content-type").map(|c| c.to_owned());
+    headers.0.retain(|h| h.name() != b"content-type");
+
+    let boundary = generate_request_id();
+    headers.add(
+        "content-type",
+        &format!("multipart/byteranges; boundary={}", boundary),
+    );
+
+    let mut segments = Vec::new();
+    for (i, range) in ranges.iter().enumerate() {
+        let mut part_headers = String::new();
+        if i != 0 {
+            part_headers.push_str("\r\n");
+        }
+        part_headers.push_str(&format!("--{}\r\n", boundary));
+        if let Some(ref content_type) = content_type {
+            part_headers.push_str(&format!("content-type: {}\r\n", content_type));
+        }
+        part_headers.push_str(&format!("content-range: {}\r\n\r\n", range.content_range(len)));
+        segments.push(Segment::Bytes(Bytes::from(part_headers)));
+        segments.push(Segment::Read {
+            pos: range.first,
+            remaining: range.len(),
+        });
+    }
+    segments.push(Segment::Bytes(Bytes::from(format!("\r\n--{}--\r\n", boundary))));
+
+    let content_length = segments
+        .iter()
+        .map(|s| match *s {
+            Segment::Bytes(ref bytes) => bytes.len() as u64,
+            Segment::Read { remaining, .. } => remaining,
+        }).sum();
+    body_response(206, headers, source, content_length, segments)
+}
+
+fn body_response<R>(
+    status: u32,
+    headers: Headers,
+    source: R,
+    content_length: u64,
+    segments: Vec<Segment>,
+) -> Response
+where
+    R: Read + Seek + Send + 'static,
+{
+    let mut r = Headers::from_status(status);
+    r.add("content-length", &content_length.to_string());
+    r.add("accept-ranges", "bytes");
+    r.extend(headers);
+
+    // no reads for empty content
+    let segments = segments.into_iter().filter(|s| match *s {
+        Segment::Read { remaining, .. } => remaining != 0,
+        Segment::Bytes(..) => true,
+    });
+    let stream = RangeStream {
+        source,
+        segments: segments.collect(),
+    };
+    Response::headers_and_stream(r, HttpStreamAfterHeaders::bytes(stream))
+}
+
+/// Drop body of the response, e. g. to answer `HEAD` request
+pub(crate) fn without_body(response: Response) -> Response {
+    Response::new(
+        response
+            .0
+            .map(|(headers, _)| (headers, HttpStreamAfterHeaders::bytes(stream::empty()))),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn range(first: u64, last: u64) -> ByteRange {
+        ByteRange { first, last }
+    }
+
+    #[test]
+    fn parse() {
+        let parse = |value| RangeRequest::parse(value, 100);
+        let ranges = |r: &[ByteRange]| RangeRequest::Ranges(r.to_vec());
+
+        assert_eq!(ranges(&[range(0, 9)]), parse("bytes=0-9"));
+        assert_eq!(ranges(&[range(90, 99)]), parse("bytes=90-"));
+        assert_eq!(ranges(&[range(90, 99)]), parse("bytes=90-1000"));
+        assert_eq!(ranges(&[range(80, 99)]), parse("bytes=-20"));
+        assert_eq!(ranges(&[range(0, 99)]), parse("bytes=-200"));
+        assert_eq!(RangeRequest::Unsatisfiable, parse("bytes=100-"));
+        assert_eq!(RangeRequest::Unsatisfiable, parse("bytes=-0"));
+        assert_eq!(RangeRequest::Unsatisfiable, RangeRequest::parse("bytes=-5", 0));
+        assert_eq!(RangeRequest::Full, parse("bytes=9-0"));
+        assert_eq!(RangeRequest::Full, parse("items=0-9"));
+        assert_eq!(RangeRequest::Full, parse("bytes=x-9"));
+        assert_eq!(RangeRequest::Full, parse("bytes="));
+
+        // multiple ranges are sorted and coalesced, unsatisfiable are dropped
+        assert_eq!(
+            ranges(&[range(0, 9), range(20, 29)]),
+            parse("bytes=20-29, 0-9, 200-300")
+        );
+        assert_eq!(ranges(&[range(0, 14)]), parse("bytes=5-14,0-9"));
+        assert_eq!(ranges(&[range(0, 19)]), parse("bytes=0-9,10-19"));
+
+        let many: Vec<String> = (0..MAX_RANGES + 1)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect();
+        assert_eq!(RangeRequest::Full, parse(&format!("bytes={}", many.join(","))));
+    }
+
+    fn body(response: Response) -> (Headers, Vec<u8>) {
+        let message = response.collect().wait().unwrap();
+        (message.headers, message.body.to_vec())
+    }
+
+    #[test]
+    fn response() {
+        let source = || Cursor::new(b"0123456789".to_vec());
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/plain");
+
+        let (h, b) = body(range_response(headers.clone(), source(), 10, RangeRequest::Full));
+        assert_eq!(200, h.status());
+        assert_eq!(Some("10"), h.get_opt("content-length"));
+        assert_eq!(b"0123456789".to_vec(), b);
+
+        let r = RangeRequest::parse("bytes=2-4", 10);
+        let (h, b) = body(range_response(headers.clone(), source(), 10, r));
+        assert_eq!(206, h.status());
+        assert_eq!(Some("bytes 2-4/10"), h.get_opt("content-range"));
+        assert_eq!(Some("text/plain"), h.get_opt("content-type"));
+        assert_eq!(b"234".to_vec(), b);
+
+        let r = RangeRequest::parse("bytes=20-", 10);
+        let (h, b) = body(range_response(headers.clone(), source(), 10, r));
+        assert_eq!(416, h.status());
+        assert_eq!(Some("bytes */10"), h.get_opt("content-range"));
+        assert!(b.is_empty());
+
+        let r = RangeRequest::parse("bytes=0-1,-2", 10);
+        let (h, b) = body(range_response(headers.clone(), source(), 10, r));
+        assert_eq!(206, h.status());
+        let content_type = h.get("content-type");
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let boundary = &content_type["multipart/byteranges; boundary=".len()..];
+        let expected = format!(
+            "--{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 8-9/10\r\n\r\n89\r\n\
+             --{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(expected.as_bytes(), &b[..]);
+        assert_eq!(Some(&b.len().to_string()[..]), h.get_opt("content-length"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Reconnect backoff
+
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::time::Duration;
+
+/// Exponential backoff with jitter.
+///
+/// Delay after `n` consecutive failures is
+/// `initial_delay * multiplier ^ (n - 1)` capped by `max_delay`,
+/// randomly reduced by up to `jitter` fraction.
+#[derive(Debug, Clone)]
+pub struct BackoffConfig {
+    /// Delay after the first failure
+    pub initial_delay: Duration,
+    /// Upper bound of the delay
+    pub max_delay: Duration,
+    /// Delay is multiplied by this after each failure
+    pub multiplier: f64,
+    /// Fraction of the delay which is randomized, from 0 to 1
+    pub jitter: f64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> BackoffConfig {
+        BackoffConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+fn duration_to_millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
+
+/// Random number without pulling `rand` crate: `RandomState` is randomly seeded
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+impl BackoffConfig {
+    /// Delay without jitter after given number of consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::from_secs(0);
+        }
+        let max = duration_to_millis(self.max_delay);
+        let exp = cmp::min(failures - 1, i32::MAX as u32) as i32;
+        let millis = duration_to_millis(self.initial_delay) * self.multiplier.powi(exp);
+        // NaN or infinity is capped too
+        let millis = if millis < max { millis } else { max };
+        Duration::from_millis(millis as u64)
+    }
+
+    fn delay_with_random(&self, failures: u32, random: u64) -> Duration {
+        let delay = duration_to_millis(self.delay(failures));
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let fraction = random as f64 / u64::MAX as f64;
+        Duration::from_millis((delay * (1.0 - jitter * fraction)) as u64)
+    }
+
+    /// Delay with jitter applied
+    pub fn delay_with_jitter(&self, failures: u32) -> Duration {
+        self.delay_with_random(failures, random_u64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn conf() -> BackoffConfig {
+        BackoffConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn delay() {
+        let conf = conf();
+        assert_eq!(Duration::from_millis(0), conf.delay(0));
+        assert_eq!(Duration::from_millis(100), conf.delay(1));
+        assert_eq!(Duration::from_millis(200), conf.delay(2));
+        assert_eq!(Duration::from_millis(800), conf.delay(4));
+        assert_eq!(Duration::from_secs(1), conf.delay(5));
+        assert_eq!(Duration::from_secs(1), conf.delay(u32::max_value()));
+    }
+
+    #[test]
+    fn jitter() {
+        let conf = conf();
+        assert_eq!(Duration::from_millis(200), conf.delay_with_random(2, 0));
+        assert_eq!(Duration::from_millis(100), conf.delay_with_random(2, u64::MAX));
+        for _ in 0..100 {
+            let delay = conf.delay_with_jitter(2);
+            assert!(delay >= Duration::from_millis(100), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(200), "{:?}", delay);
+        }
+    }
+}
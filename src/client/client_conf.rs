@@ -1,6 +1,35 @@
 use std::time::Duration;
 
+use client::backoff::BackoffConfig;
+use client::client_conn::ClientCallbacks;
+use client::client_priority::AutoPriority;
 use common::CommonConf;
+use common::OnReadEof;
+use error::Error;
+use result::Result;
+use solicit::HttpScheme;
+use trace::RequestTracing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAlpn {
+    // Do not check negotiated ALPN, e. g. for servers speaking h2 without ALPN
+    Ignore,
+    // Fail connection with `Error::AlpnMismatch` unless "h2" is negotiated
+    Require,
+}
+
+impl ClientAlpn {
+    /// Check protocol negotiated by TLS handshake
+    pub(crate) fn check(&self, negotiated: Option<&[u8]>) -> Result<()> {
+        match (*self, negotiated) {
+            (ClientAlpn::Ignore, _) => Ok(()),
+            (ClientAlpn::Require, Some(b"h2")) => Ok(()),
+            (ClientAlpn::Require, negotiated) => Err(Error::AlpnMismatch(
+                negotiated.map(|p| String::from_utf8_lossy(p).into_owned()),
+            )),
+        }
+    }
+}
 
 #[derive(Default, Debug, Clone)]
 pub struct ClientConf {
@@ -8,6 +37,81 @@ pub struct ClientConf {
     pub no_delay: Option<bool>,
     pub thread_name: Option<String>,
     pub connection_timeout: Option<Duration>,
+    /// Soft limit of bytes waiting to be sent on connection
+    /// (serialized frames and request bodies waiting for flow control window).
+    /// While limit is exceeded, new requests fail with `Error::WriteQueueFull`.
+    pub write_queue_soft_limit: Option<usize>,
+    /// `:scheme` of requests created with `start_get` and `start_post`.
+    /// Defaults to `https` for TLS connections and `http` otherwise,
+    /// override is useful e. g. when proxy terminates TLS.
+    /// Requests started with explicit headers are sent with their own `:scheme`.
+    pub http_scheme: Option<HttpScheme>,
+    /// Assign priority to each request: sent to the server in HEADERS
+    /// and used to order request bodies waiting to be sent.
+    /// By default requests are sent without priority.
+    pub auto_priority: Option<AutoPriority>,
+    /// Send PING when connection was idle for this interval,
+    /// so NAT and load balancers do not drop the connection state.
+    /// Disabled by default.
+    pub nat_keepalive_interval: Option<Duration>,
+    /// Close connection if keepalive PING is not acknowledged within this time,
+    /// default is three keepalive intervals.
+    pub nat_keepalive_grace: Option<Duration>,
+    /// Send PING at this interval regardless of connection activity,
+    /// round-trip time is available in connection state dump.
+    /// Disabled by default.
+    pub keepalive_interval: Option<Duration>,
+    /// Close connection with `Error::KeepaliveTimeout`
+    /// if keepalive PING is not acknowledged within this time
+    /// (checked on keepalive interval ticks), default is keepalive interval.
+    pub keepalive_timeout: Option<Duration>,
+    /// Maximum response body size, larger responses fail with `Error::BodyTooLarge`
+    /// when body size exceeds the limit, both in `Response::collect`
+    /// and in the response body stream.
+    pub max_buffered_response_size: Option<usize>,
+    /// Open a new connection for requests when current connection is older than this.
+    /// Old connection is drained: it is sent GOAWAY, requests in flight complete,
+    /// and then it is closed.
+    pub max_connection_age: Option<Duration>,
+    /// Open a new connection after this number of requests was started
+    /// on current connection, old connection is drained like with `max_connection_age`.
+    pub max_connection_requests: Option<u64>,
+    /// SETTINGS_HEADER_TABLE_SIZE: maximum size of HPACK dynamic table
+    /// used to decode response headers, protocol default is 4096.
+    pub header_table_size: Option<u32>,
+    /// Gracefully close connection without requests in flight for this time,
+    /// new connection is opened when next request is started.
+    /// `ClientPool` checks its connections on health check interval.
+    /// By default idle connections are kept open.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Advertise SETTINGS_ENABLE_PUSH, pushed responses are passed
+    /// to `ClientConnCallbacks::on_push`. Default is `false`.
+    pub enable_push: Option<bool>,
+    /// Check ALPN protocol negotiated on TLS connections,
+    /// default is `Require` when TLS implementation supports ALPN.
+    pub alpn: Option<ClientAlpn>,
+    /// What to do when server closes its side of TCP connection,
+    /// default is `CloseImmediately`.
+    pub on_read_eof: Option<OnReadEof>,
+    /// Delay opening new connection after failed connection attempts.
+    /// Used by `ClientPool`, by default connections are reopened immediately.
+    pub reconnect_backoff: Option<BackoffConfig>,
+    /// How many times a request with `HttpStreamAfterHeaders::retryable` body
+    /// is resent when the server refuses it without processing: GOAWAY with lower
+    /// last stream id or RST_STREAM(REFUSED_STREAM) before response headers.
+    /// Requests started with `start_get` and `start_post` are retryable.
+    /// Default is 3, 0 disables retries.
+    pub max_stream_refused_retries: Option<u32>,
+    /// Fail requests with `Error::NoConnectionAvailable` when no connection
+    /// is established yet instead of queuing them until handshake completes.
+    /// Used by `ClientPool`, default is `false`.
+    pub fail_fast: Option<bool>,
+    /// Start a span for each request and send its context
+    /// in `traceparent` header. Disabled by default.
+    pub request_tracing: Option<RequestTracing>,
+    /// Connection events of `Client`: GOAWAY, peer SETTINGS, pushed responses.
+    /// Callbacks are shared by connections opened after reconnect.
+    pub callbacks: Option<ClientCallbacks>,
 
     pub common: CommonConf,
 }
@@ -17,3 +121,23 @@ impl ClientConf {
         Default::default()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alpn_check() {
+        assert!(ClientAlpn::Require.check(Some(b"h2")).is_ok());
+        match ClientAlpn::Require.check(Some(b"http/1.1")) {
+            Err(Error::AlpnMismatch(Some(ref p))) if p == "http/1.1" => {}
+            r => panic!("{:?}", r),
+        }
+        match ClientAlpn::Require.check(None) {
+            Err(Error::AlpnMismatch(None)) => {}
+            r => panic!("{:?}", r),
+        }
+        assert!(ClientAlpn::Ignore.check(None).is_ok());
+        assert!(ClientAlpn::Ignore.check(Some(b"http/1.1")).is_ok());
+    }
+}
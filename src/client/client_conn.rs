@@ -1,8 +1,10 @@
 //! Single client connection
 
+use std::fmt;
 use std::io;
 use std::result::Result as std_Result;
 use std::sync::Arc;
+use std::time::Duration;
 
 use error;
 use error::Error;
@@ -11,18 +13,24 @@ use result;
 use exec::CpuPoolOption;
 
 use solicit::end_stream::EndStream;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
 use solicit::frame::settings::*;
+use solicit::session::StreamState;
 use solicit::header::*;
 use solicit::StreamId;
 use solicit::DEFAULT_SETTINGS;
 
 use service::Service;
 
+use bytes::Bytes;
+
+use futures::future;
 use futures::future::Future;
 use futures::stream::Stream;
 use futures::sync::mpsc::unbounded;
 use futures::sync::mpsc::UnboundedSender;
 use futures::sync::oneshot;
+use futures::Async;
 
 use tls_api::TlsConnector;
 
@@ -37,15 +45,19 @@ use solicit_async::*;
 use common::*;
 use data_or_trailers::*;
 use socket::*;
+use trace::SpanHandle;
 
+use client::client_priority::AutoPriority;
 use client_died_error_holder::ClientDiedErrorHolder;
 use common::client_or_server::ClientOrServer;
+use common::init_where::InitWhere;
 use data_or_headers::DataOrHeaders;
 use data_or_headers_with_flag::DataOrHeadersWithFlag;
 use headers_place::HeadersPlace;
 use req_resp::RequestOrResponse;
 use result_or_eof::ResultOrEof;
 use std::marker;
+use ClientAlpn;
 use ClientConf;
 use ClientTlsOption;
 use ErrorCode;
@@ -67,7 +79,12 @@ where
     const CLIENT_OR_SERVER: ClientOrServer = ClientOrServer::Client;
 }
 
-pub struct ClientStreamData {}
+pub struct ClientStreamData {
+    // cancel request if response headers are not received in time
+    request_timeout: Option<reactor::Timeout>,
+    // response to HEAD declares content-length, but has no body
+    head: bool,
+}
 
 impl HttpStreamDataSpecific for ClientStreamData {}
 
@@ -81,13 +98,16 @@ where
 }
 
 pub struct ClientConnData {
-    _callbacks: Box<ClientConnCallbacks>,
+    callbacks: Box<ClientConnCallbacks>,
+    write_queue_soft_limit: Option<usize>,
+    auto_priority: Option<AutoPriority>,
 }
 
 impl ConnSpecific for ClientConnData {}
 
 pub struct ClientConn {
     write_tx: UnboundedSender<ClientToWriteMessage>,
+    metrics: Arc<ConnMetricsCounters>,
 }
 
 unsafe impl Sync for ClientConn {}
@@ -96,11 +116,17 @@ pub struct StartRequestMessage {
     pub headers: Headers,
     pub body: HttpStreamAfterHeaders,
     pub resp_tx: oneshot::Sender<Response>,
+    /// Reset stream if response headers are not received within given time
+    pub timeout: Option<Duration>,
+    /// Span of the request, see `ClientConf::request_tracing`
+    pub(crate) span: Option<SpanHandle>,
 }
 
 enum ClientToWriteMessage {
     Start(StartRequestMessage),
     WaitForHandshake(oneshot::Sender<result::Result<()>>),
+    // response stream is dropped before completion
+    Cancel(StreamId),
     Common(CommonToWriteMessage),
 }
 
@@ -119,6 +145,7 @@ where
     fn process_message(&mut self, message: ClientToWriteMessage) -> result::Result<()> {
         match message {
             ClientToWriteMessage::Start(start) => self.process_start(start),
+            ClientToWriteMessage::Cancel(stream_id) => self.process_cancel(stream_id),
             ClientToWriteMessage::Common(common) => self.process_common_message(common),
             ClientToWriteMessage::WaitForHandshake(tx) => {
                 // ignore error
@@ -127,6 +154,32 @@ where
             }
         }
     }
+
+    fn poll_timers(&mut self) -> result::Result<()> {
+        let mut expired = Vec::new();
+        for (&stream_id, stream) in &mut self.streams.map {
+            if let Some(ref mut timeout) = stream.specific.request_timeout {
+                if timeout.poll()? != Async::NotReady {
+                    expired.push(stream_id);
+                }
+            }
+        }
+
+        for stream_id in expired {
+            debug!("request timeout on stream {}, resetting", stream_id);
+            if let Some(mut stream) = self.streams.get_mut(stream_id) {
+                if let Some(peer_tx) = stream.stream().peer_tx.take() {
+                    peer_tx.send_error(Error::RequestTimeout).ok();
+                }
+            }
+            self.send_rst_stream(stream_id, ErrorCode::Cancel)?;
+        }
+        Ok(())
+    }
+
+    fn metrics_updated(&mut self, metrics: &ConnMetrics) {
+        self.specific.callbacks.metrics_updated(metrics);
+    }
 }
 
 impl<I> Conn<ClientTypes<I>>
@@ -138,22 +191,78 @@ where
             headers,
             body,
             resp_tx,
+            timeout,
+            span,
         } = start;
 
+        if let Some(ref goaway) = self.goaway_received {
+            debug!("rejecting request, GOAWAY received from server");
+            let e = Error::RefusedByGoaway(goaway.error_code(), goaway.debug_data.clone());
+            if resp_tx.send(Response::err(e)).is_err() {
+                warn!("caller died");
+            }
+            return Ok(());
+        }
+
+        if self.read_eof {
+            debug!("rejecting request, EOF received from server");
+            if resp_tx.send(Response::err(Error::EofFromPeer)).is_err() {
+                warn!("caller died");
+            }
+            return Ok(());
+        }
+
+        if let Some(write_queue_soft_limit) = self.specific.write_queue_soft_limit {
+            let write_queue_bytes = self.write_queue_bytes();
+            if write_queue_bytes > write_queue_soft_limit {
+                debug!(
+                    "rejecting request, write queue size {} exceeds limit {}",
+                    write_queue_bytes, write_queue_soft_limit
+                );
+                if resp_tx.send(Response::err(Error::WriteQueueFull)).is_err() {
+                    warn!("caller died");
+                }
+                return Ok(());
+            }
+        }
+
+        let request_timeout = match timeout {
+            Some(timeout) => Some(reactor::Timeout::new(timeout, &self.loop_handle)?),
+            None => None,
+        };
+
         let stream_id = self.next_local_stream_id();
 
+        if let Some(span) = span {
+            span.stream_id(stream_id);
+        }
+
+        let head = headers.get_opt(":method") == Some("HEAD");
+
+        let weight = self
+            .specific
+            .auto_priority
+            .as_ref()
+            .map(|p| p.urgency(&headers).weight());
+
         let out_window = {
-            let (mut http_stream, resp_stream, out_window) = self.new_stream_data(
+            let (mut http_stream, mut resp_stream, out_window) = self.new_stream_data(
                 stream_id,
                 None,
                 InMessageStage::Initial,
-                ClientStreamData {},
+                ClientStreamData {
+                    request_timeout,
+                    head,
+                },
             );
 
+            resp_stream.cancel_on_drop = Some(ClientToWriteMessage::Cancel(stream_id));
+
             if let Err(_) = resp_tx.send(Response::from_stream(resp_stream)) {
                 warn!("caller died");
             }
 
+            http_stream.stream().weight = weight;
             http_stream.push_back(DataOrHeaders::Headers(headers));
 
             out_window
@@ -165,11 +274,72 @@ where
         self.buffer_outg_conn()?;
         Ok(())
     }
+
+    fn process_cancel(&mut self, stream_id: StreamId) -> result::Result<()> {
+        let headers_sent = match self.streams.get_mut(stream_id) {
+            Some(mut stream) => stream.stream().headers_sent,
+            // stream is complete or already reset
+            None => return Ok(()),
+        };
+
+        if !headers_sent {
+            debug!("response dropped before stream {} is started", stream_id);
+            self.streams.remove_stream(stream_id);
+            return Ok(());
+        }
+
+        debug!("response dropped, cancelling stream {}", stream_id);
+        self.cancelled_streams.add(stream_id);
+        self.send_rst_stream(stream_id, ErrorCode::Cancel)
+    }
 }
 
 pub trait ClientConnCallbacks: 'static {
     // called at most once
     fn goaway(&self, stream_id: StreamId, raw_error_code: u32);
+
+    /// Same as `goaway`, also with GOAWAY opaque debug data, often a human-readable reason.
+    ///
+    /// Called instead of `goaway`, default implementation forwards to `goaway`.
+    fn goaway_with_debug(&self, stream_id: StreamId, raw_error_code: u32, _debug_data: Bytes) {
+        self.goaway(stream_id, raw_error_code);
+    }
+
+    /// Called when peer SETTINGS frame is applied, with all peer settings after update
+    fn settings_updated(&self, _settings: &HttpSettings) {}
+
+    /// Called after connection event loop iteration with updated counters
+    fn metrics_updated(&self, _metrics: &ConnMetrics) {}
+
+    /// Called when server promises a response to `headers` request
+    /// associated with our request stream, only if `ClientConf::enable_push` is set.
+    ///
+    /// Pushed stream is reset with `CANCEL` when `response` is dropped
+    /// before completion, default implementation rejects all pushes this way.
+    fn on_push(
+        &self,
+        _associated_stream_id: StreamId,
+        _promised_stream_id: StreamId,
+        _headers: Headers,
+        _response: Response,
+    ) {
+    }
+}
+
+/// Callbacks of `Client` connections, see `ClientConf::callbacks`
+#[derive(Clone)]
+pub struct ClientCallbacks(pub Arc<ClientConnCallbacks + Send + Sync>);
+
+impl ClientCallbacks {
+    pub fn new<C: ClientConnCallbacks + Send + Sync>(callbacks: C) -> ClientCallbacks {
+        ClientCallbacks(Arc::new(callbacks))
+    }
+}
+
+impl fmt::Debug for ClientCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ClientCallbacks")
+    }
 }
 
 impl ClientConn {
@@ -190,13 +360,28 @@ impl ClientConn {
                 .map_err(|()| Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write"))),
         );
 
+        let metrics = Arc::new(ConnMetricsCounters::new());
+
         let c = ClientConn {
             write_tx: to_write_tx.clone(),
+            metrics: metrics.clone(),
         };
 
-        let settings_frame = SettingsFrame::from_settings(vec![HttpSetting::EnablePush(false)]);
+        let settings_frame = match conf.common.initial_settings_for_test {
+            Some(ref raw) => raw.clone(),
+            None => {
+                let enable_push = conf.enable_push.unwrap_or(false);
+                let mut settings_frame =
+                    RawSettingsFrame::new().setting(HttpSetting::EnablePush(enable_push));
+                if let Some(header_table_size) = conf.header_table_size {
+                    settings_frame =
+                        settings_frame.setting(HttpSetting::HeaderTableSize(header_table_size));
+                }
+                settings_frame
+            }
+        };
         let mut settings = DEFAULT_SETTINGS;
-        settings.apply_from_frame(&settings_frame);
+        settings.apply_from_frame(&settings_frame.to_settings_frame());
 
         let handshake = connect.and_then(|conn| client_handshake(conn, settings_frame));
 
@@ -210,11 +395,29 @@ impl ClientConn {
 
             let (read, write) = conn.split();
 
-            let conn_data = Conn::<ClientTypes<_>>::new(
+            let nat_keepalive = match conf.nat_keepalive_interval {
+                Some(interval) => {
+                    let grace = conf.nat_keepalive_grace.unwrap_or(interval * 3);
+                    Keepalive::new(interval, grace, true, &lh_copy).map(Some)
+                }
+                None => Ok(None),
+            };
+
+            let keepalive = match conf.keepalive_interval {
+                Some(interval) => {
+                    let timeout = conf.keepalive_timeout.unwrap_or(interval);
+                    Keepalive::new(interval, timeout, false, &lh_copy).map(Some)
+                }
+                None => Ok(None),
+            };
+
+            let mut conn_data = Conn::<ClientTypes<_>>::new(
                 lh_copy,
                 CpuPoolOption::SingleThread,
                 ClientConnData {
-                    _callbacks: Box::new(callbacks),
+                    callbacks: Box::new(callbacks),
+                    write_queue_soft_limit: conf.write_queue_soft_limit,
+                    auto_priority: conf.auto_priority.clone(),
                 },
                 conf.common,
                 settings,
@@ -224,7 +427,18 @@ impl ClientConn {
                 write,
                 conn_died_error_holder,
             );
-            conn_data.run()
+            conn_data.on_read_eof = conf.on_read_eof.unwrap_or_default();
+            // Prior knowledge: fail fast if the server speaks HTTP/1.x
+            conn_data.framed_read.set_detect_http_1_response();
+            conn_data.metrics = metrics;
+
+            let timers =
+                nat_keepalive.and_then(|nat_keepalive| keepalive.map(|k| (nat_keepalive, k)));
+            future::result(timers).and_then(move |(nat_keepalive, keepalive)| {
+                conn_data.nat_keepalive = nat_keepalive;
+                conn_data.keepalive = keepalive;
+                conn_data.run()
+            })
         });
 
         let future = conn_died_error_holder_copy.wrap_future(future);
@@ -301,6 +515,12 @@ impl ClientConn {
     {
         let domain = domain.to_owned();
 
+        let alpn = if C::supports_alpn() {
+            conf.alpn.unwrap_or(ClientAlpn::Require)
+        } else {
+            ClientAlpn::Ignore
+        };
+
         let connect = addr
             .connect(&lh)
             .map(move |c| {
@@ -313,7 +533,11 @@ impl ClientConn {
                 .map_err(|e| Error::IoError(io::Error::new(io::ErrorKind::Other, e)))
         });
 
-        let tls_conn = tls_conn.map_err(Error::from);
+        let tls_conn = tls_conn.map_err(Error::from).and_then(move |tls_conn| {
+            let negotiated = tls_conn.get_ref().get_alpn_protocol();
+            alpn.check(negotiated.as_ref().map(|p| &p[..]))?;
+            Ok(tls_conn)
+        });
 
         ClientConn::spawn_connected(lh, Box::new(tls_conn), conf, callbacks)
     }
@@ -330,15 +554,61 @@ impl ClientConn {
             })
     }
 
-    pub fn dump_state_with_resp_sender(&self, tx: oneshot::Sender<ConnStateSnapshot>) {
-        let message = ClientToWriteMessage::Common(CommonToWriteMessage::DumpState(tx));
+    /// Perform administrative operation on the connection.
+    ///
+    /// Operation is ignored if connection is already closed.
+    pub fn control(&self, control: ConnControl) {
+        let message = ClientToWriteMessage::Common(control.into());
         // ignore error
         drop(self.write_tx.unbounded_send(message));
     }
 
-    /// For tests
-    #[doc(hidden)]
-    pub fn _dump_state(&self) -> HttpFutureSend<ConnStateSnapshot> {
+    pub fn dump_state_with_resp_sender(&self, tx: oneshot::Sender<ConnStateSnapshot>) {
+        self.control(ConnControl::DumpState(tx));
+    }
+
+    /// Send SETTINGS to the server.
+    ///
+    /// Returned future resolves when the server acknowledges the settings,
+    /// i. e. when new settings are in effect.
+    /// Future fails if connection is closed before that.
+    pub fn update_settings(&self, settings: Vec<HttpSetting>) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        self.control(ConnControl::UpdateSettingsAcked(settings, tx));
+        Box::new(rx.map_err(|_| Error::Other("conn died")).and_then(|r| r))
+    }
+
+    /// Send GOAWAY with application debug data and close the connection.
+    pub fn close(&self, error_code: ErrorCode, debug_data: Bytes) {
+        self.control(ConnControl::Goaway(error_code, debug_data));
+    }
+
+    /// Send GOAWAY to the server, complete streams in flight,
+    /// and close the connection after that.
+    pub fn shutdown_gracefully(&self) {
+        self.control(ConnControl::GoawayGraceful);
+    }
+
+    /// Stop reading from the socket until `resume_reads` is called.
+    ///
+    /// Flow control windows are not replenished while reads are paused,
+    /// so the peer eventually stops sending.
+    pub fn pause_reads(&self) {
+        self.control(ConnControl::PauseReads);
+    }
+
+    /// Resume reading from the socket after `pause_reads`.
+    pub fn resume_reads(&self) {
+        self.control(ConnControl::ResumeReads);
+    }
+
+    /// Connection counters, cheap to call
+    pub fn metrics(&self) -> ConnMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Connection and stream state, e. g. for monitoring
+    pub fn dump_state(&self) -> HttpFutureSend<ConnStateSnapshot> {
         let (tx, rx) = oneshot::channel();
 
         self.dump_state_with_resp_sender(tx);
@@ -349,6 +619,11 @@ impl ClientConn {
         Box::new(rx)
     }
 
+    /// Connection is closed and cannot start new requests
+    pub fn is_closed(&self) -> bool {
+        self.write_tx.is_closed()
+    }
+
     pub fn wait_for_connect_with_resp_sender(
         &self,
         tx: oneshot::Sender<result::Result<()>>,
@@ -371,6 +646,8 @@ impl Service for ClientConn {
             headers: headers,
             body: body,
             resp_tx: resp_tx,
+            timeout: None,
+            span: None,
         };
 
         if let Err(_) = self.start_request_with_resp_sender(start) {
@@ -394,6 +671,103 @@ where
 {
     type Types = ClientTypes<I>;
 
+    fn process_push_promise(&mut self, frame: PushPromiseDecodedFrame) -> result::Result<()> {
+        let PushPromiseDecodedFrame {
+            stream_id,
+            promised_stream_id,
+            headers,
+            ..
+        } = frame;
+
+        // 6.5.2: endpoint that has set ENABLE_PUSH to 0 must treat PUSH_PROMISE
+        // as a connection error of type PROTOCOL_ERROR
+        if !self.our_settings_sent().enable_push {
+            warn!("PUSH_PROMISE on stream {}, but push is disabled", stream_id);
+            return self.send_goaway(ErrorCode::ProtocolError);
+        }
+
+        // 5.1.1: promised stream id must be server-initiated and increasing
+        if ClientTypes::<I>::init_where(promised_stream_id) == InitWhere::Locally
+            || promised_stream_id <= self.last_peer_stream_id
+        {
+            warn!("invalid promised stream id: {}", promised_stream_id);
+            return self.send_goaway(ErrorCode::ProtocolError);
+        }
+        self.last_peer_stream_id = promised_stream_id;
+
+        match self.streams.get_stream_state(stream_id) {
+            Some(StreamState::Open) | Some(StreamState::HalfClosedLocal) => {}
+            _ if self.cancelled_streams.contains(stream_id) => {
+                debug!("push on cancelled stream {}, rejecting", stream_id);
+                return self.send_rst_stream(promised_stream_id, ErrorCode::Cancel);
+            }
+            state => {
+                // 6.6: associated stream must be open or half-closed (local)
+                warn!("PUSH_PROMISE on stream {} in state {:?}", stream_id, state);
+                return self.send_goaway(ErrorCode::ProtocolError);
+            }
+        }
+
+        // 8.2: promised request must be complete, safe and cacheable,
+        // otherwise it is a stream error on the promised stream
+        if let Err(e) = headers.validate(RequestOrResponse::Request, HeadersPlace::Initial) {
+            warn!("invalid promised request headers: {:?}: {:?}", e, headers);
+            return self.send_rst_stream(promised_stream_id, ErrorCode::ProtocolError);
+        }
+        let head = match headers.method() {
+            "GET" => false,
+            "HEAD" => true,
+            method => {
+                warn!("promised request method is not safe: {}", method);
+                return self.send_rst_stream(promised_stream_id, ErrorCode::ProtocolError);
+            }
+        };
+
+        debug!(
+            "push promise on stream {}, promised stream {}",
+            stream_id, promised_stream_id
+        );
+
+        let (mut stream, mut resp_stream, _) = self.new_stream_data(
+            promised_stream_id,
+            None,
+            InMessageStage::Initial,
+            ClientStreamData {
+                request_timeout: None,
+                head,
+            },
+        );
+        // 8.2.2: reserved (remote) stream, we never send on it
+        stream.stream().close_local();
+        // promised stream is known to peer, so dropping response resets it
+        stream.stream().headers_sent = true;
+
+        resp_stream.cancel_on_drop = Some(ClientToWriteMessage::Cancel(promised_stream_id));
+
+        self.specific.callbacks.on_push(
+            stream_id,
+            promised_stream_id,
+            headers,
+            Response::from_stream(resp_stream),
+        );
+
+        Ok(())
+    }
+
+    fn peer_settings_updated(&mut self) {
+        self.specific.callbacks.settings_updated(&self.peer_settings);
+    }
+
+    fn peer_goaway_received(&mut self, last_stream_id: StreamId, raw_error_code: u32) {
+        let debug_data = match self.goaway_received {
+            Some(ref goaway) => goaway.debug_data.clone(),
+            None => Bytes::new(),
+        };
+        self.specific
+            .callbacks
+            .goaway_with_debug(last_stream_id, raw_error_code, debug_data);
+    }
+
     fn process_headers(
         &mut self,
         stream_id: StreamId,
@@ -448,13 +822,42 @@ where
                     self.send_rst_stream(stream_id, ErrorCode::ProtocolError)?;
                     return Ok(None);
                 }
+                let mut stream = self.streams.get_mut(stream_id).unwrap();
+                if let Some(in_rem_content_length) = stream.stream().in_rem_content_length {
+                    if in_rem_content_length != 0 {
+                        warn!("stream data shorter than content-length: {}", stream_id);
+                        stream.stream().content_length_mismatch();
+                        self.send_rst_stream(stream_id, ErrorCode::ProtocolError)?;
+                        return Ok(None);
+                    }
+                }
                 false
             }
         };
 
+        if headers_place == HeadersPlace::Initial && !status_1xx && end_stream == EndStream::Yes {
+            let mut stream = self.streams.get_mut(stream_id).unwrap();
+            // 304 declares content-length of the entity, but has no body
+            let no_body = stream.stream().specific.head || headers.status() == 304;
+            if !no_body && headers.content_length().unwrap_or(0) != 0 {
+                warn!("response without body declares content-length: {}", stream_id);
+                stream.stream().content_length_mismatch();
+                self.send_rst_stream(stream_id, ErrorCode::ProtocolError)?;
+                return Ok(None);
+            }
+        }
+
         let mut stream = self.streams.get_mut(stream_id).unwrap();
-        if let Some(in_rem_content_length) = headers.content_length() {
-            stream.stream().in_rem_content_length = Some(in_rem_content_length);
+        if !status_1xx {
+            // response headers arrived in time
+            stream.stream().specific.request_timeout = None;
+        }
+        if headers_place == HeadersPlace::Initial && !status_1xx {
+            // 304 declares content-length of the entity, but has no body
+            let no_body = stream.stream().specific.head || headers.status() == 304;
+            if !no_body {
+                stream.stream().in_rem_content_length = headers.content_length();
+            }
         }
 
         stream.stream().in_message_stage = match (headers_place, status_1xx) {
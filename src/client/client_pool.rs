@@ -0,0 +1,643 @@
+//! Pool of client connections to the same server
+
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use futures::future;
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::sync::mpsc::unbounded;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::sync::mpsc::UnboundedSender;
+use futures::sync::oneshot;
+
+use tokio_core::reactor;
+
+use tls_api::TlsConnector;
+
+use futures_misc::*;
+
+use error;
+use error::Error;
+use result::Result;
+
+use solicit::frame::settings::HttpSettings;
+use solicit::header::*;
+use solicit::HttpScheme;
+use solicit::StreamId;
+
+use solicit_async::*;
+
+use data_or_trailers::*;
+use service::Service;
+use socket::AnySocketAddr;
+use socket::ToClientStream;
+
+use client::client_conf::ClientConf;
+use client::client_conn::ClientConn;
+use client::client_conn::ClientConnCallbacks;
+use client::client_conn::StartRequestMessage;
+use client::client_tls::ClientTlsOption;
+use client::ClientBuilder;
+
+use resp::Response;
+use trace::RequestTracing;
+
+/// Configuration of `ClientPool`
+#[derive(Default, Debug, Clone)]
+pub struct ClientPoolConf {
+    /// Number of connections, default is 4
+    pub pool_size: Option<usize>,
+    /// Replace closed connections with this interval, default is 1 second.
+    /// Closed connection is also replaced when request is started on it.
+    pub health_check_interval: Option<Duration>,
+    /// Configuration of each connection
+    pub client: ClientConf,
+}
+
+impl ClientPoolConf {
+    pub fn new() -> ClientPoolConf {
+        Default::default()
+    }
+
+    fn pool_size(&self) -> usize {
+        self.pool_size.unwrap_or(4)
+    }
+}
+
+/// State of `ClientPool` connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientPoolStats {
+    /// Open connections, including connections being established
+    pub conns: usize,
+    /// Connection attempts failed in a row, reset when connection is established
+    pub consecutive_failures: u32,
+    /// Time until connections are reopened if reconnect is delayed by backoff
+    pub reconnect_delay: Option<Duration>,
+}
+
+/// Asynchronous HTTP/2 client with several connections to the same server.
+///
+/// Requests are distributed over connections round-robin,
+/// connections which reached peer `SETTINGS_MAX_CONCURRENT_STREAMS`
+/// are skipped unless all connections are full.
+/// Connection closed because of GOAWAY or network error is replaced.
+pub struct ClientPool {
+    controller_tx: UnboundedSender<PoolCommand>,
+    join: Option<thread::JoinHandle<()>>,
+    http_scheme: HttpScheme,
+    // used only once to send shutdown signal
+    shutdown: ShutdownSignal,
+    max_buffered_response_size: Option<usize>,
+    request_tracing: Option<RequestTracing>,
+}
+
+impl ClientPool {
+    /// Create a new pool connected to the specified host and port without using TLS.
+    pub fn new_plain(host: &str, port: u16, conf: ClientPoolConf) -> Result<ClientPool> {
+        let mut client = ClientBuilder::new_plain();
+        client.set_addr((host, port))?;
+        ClientPool::new_impl(client.addr.unwrap(), client.tls, conf)
+    }
+
+    /// Create a new pool connected to the specified host and port using TLS.
+    pub fn new_tls<C: TlsConnector>(
+        host: &str,
+        port: u16,
+        conf: ClientPoolConf,
+    ) -> Result<ClientPool> {
+        let mut client = ClientBuilder::<C>::new();
+        client.set_addr((host, port))?;
+        client.set_tls(host)?;
+        ClientPool::new_impl(client.addr.unwrap(), client.tls, conf)
+    }
+
+    /// Connect to server using plain or TLS protocol depending on `tls` parameter.
+    pub fn new_expl<C: TlsConnector>(
+        addr: &SocketAddr,
+        tls: ClientTlsOption<C>,
+        conf: ClientPoolConf,
+    ) -> Result<ClientPool> {
+        ClientPool::new_impl(AnySocketAddr::Inet(*addr), tls, conf)
+    }
+
+    fn new_impl<C: TlsConnector>(
+        addr: AnySocketAddr,
+        tls: ClientTlsOption<C>,
+        conf: ClientPoolConf,
+    ) -> Result<ClientPool> {
+        if conf.pool_size() == 0 {
+            return Err(Error::Other("pool size must be positive"));
+        }
+
+        let http_scheme = conf
+            .client
+            .http_scheme
+            .unwrap_or_else(|| tls.http_scheme());
+        let max_buffered_response_size = conf.client.max_buffered_response_size;
+        let request_tracing = conf.client.request_tracing.clone();
+
+        let (shutdown_signal, shutdown_future) = shutdown_signal();
+
+        let (controller_tx, controller_rx) = unbounded();
+        let controller_tx_copy = controller_tx.clone();
+
+        let thread_name = conf
+            .client
+            .thread_name
+            .clone()
+            .unwrap_or_else(|| "http2-client-pool-loop".to_owned());
+        let join_handle = thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || {
+                let mut lp = reactor::Core::new().expect("Core::new");
+
+                let done = run_pool_event_loop(
+                    lp.handle(),
+                    shutdown_future,
+                    addr,
+                    tls,
+                    conf,
+                    controller_tx_copy,
+                    controller_rx,
+                );
+
+                // error means shutdown
+                drop(lp.run(done));
+            })?;
+
+        Ok(ClientPool {
+            controller_tx,
+            join: Some(join_handle),
+            http_scheme,
+            shutdown: shutdown_signal,
+            max_buffered_response_size,
+            request_tracing,
+        })
+    }
+
+    /// Start HTTP/2 request.
+    pub fn start_request_simple(&self, headers: Headers, body: Bytes) -> Response {
+        self.start_request(headers, HttpStreamAfterHeaders::once_bytes(body))
+    }
+
+    /// Start HTTP/2 `GET` request.
+    pub fn start_get(&self, path: &str, authority: &str) -> Response {
+        let headers = Headers(vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", path.to_owned()),
+            Header::new(":authority", authority.to_owned()),
+            Header::new(":scheme", self.http_scheme.as_bytes()),
+        ]);
+        self.start_request_simple(headers, Bytes::new())
+    }
+
+    /// Start HTTP/2 `POST` request.
+    pub fn start_post(&self, path: &str, authority: &str, body: Bytes) -> Response {
+        let headers = Headers(vec![
+            Header::new(":method", "POST"),
+            Header::new(":path", path.to_owned()),
+            Header::new(":authority", authority.to_owned()),
+            Header::new(":scheme", self.http_scheme.as_bytes()),
+        ]);
+        self.start_request_simple(headers, body)
+    }
+
+    /// Number of open connections and reconnect backoff state
+    pub fn stats(&self) -> HttpFutureSend<ClientPoolStats> {
+        let (tx, rx) = oneshot::channel();
+        // ignore error
+        drop(self.controller_tx.unbounded_send(PoolCommand::Stats(tx)));
+        Box::new(rx.map_err(|_| error::Error::Other("client pool died")))
+    }
+}
+
+impl Service for ClientPool {
+    fn start_request(&self, mut headers: Headers, body: HttpStreamAfterHeaders) -> Response {
+        let (resp_tx, resp_rx) = oneshot::channel();
+
+        let span = self
+            .request_tracing
+            .as_ref()
+            .map(|t| t.start_client_span(&mut headers));
+
+        let start = StartRequestMessage {
+            headers,
+            body,
+            resp_tx,
+            timeout: None,
+            span: span.clone(),
+        };
+
+        if self
+            .controller_tx
+            .unbounded_send(PoolCommand::StartRequest(start))
+            .is_err()
+        {
+            return Response::err(error::Error::Other("client pool died"));
+        }
+
+        let resp_rx =
+            resp_rx.map_err(|oneshot::Canceled| error::Error::Other("client pool died"));
+
+        let resp_rx = resp_rx.map(|r| r.into_stream_flag());
+
+        let response = Response::from_stream(resp_rx.flatten_stream());
+        let response = match self.max_buffered_response_size {
+            Some(max_size) => response.max_body_size(max_size),
+            None => response,
+        };
+        match span {
+            Some(span) => span.trace_response(response),
+            None => response,
+        }
+    }
+}
+
+impl Drop for ClientPool {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+
+        // ignore errors of join, it means that event loop crashed
+        drop(self.join.take().unwrap().join());
+    }
+}
+
+enum PoolCommand {
+    StartRequest(StartRequestMessage),
+    HealthCheck,
+    Stats(oneshot::Sender<ClientPoolStats>),
+}
+
+/// Connection state shared with connection callbacks
+struct PoolConnShared {
+    /// Requests started on the connection with response not yet dropped
+    active_requests: AtomicUsize,
+    /// Peer `SETTINGS_MAX_CONCURRENT_STREAMS`
+    max_concurrent_streams: AtomicUsize,
+    /// Peer SETTINGS received, i. e. connection was established
+    connected: AtomicBool,
+    /// Connection received GOAWAY and must be replaced
+    goaway: AtomicBool,
+    /// Last request completed or connection opened
+    idle_since: Mutex<Instant>,
+}
+
+impl PoolConnShared {
+    fn has_capacity(&self) -> bool {
+        self.active_requests.load(Ordering::SeqCst)
+            < self.max_concurrent_streams.load(Ordering::SeqCst)
+    }
+}
+
+struct PoolConnCallbacks {
+    shared: Arc<PoolConnShared>,
+}
+
+impl ClientConnCallbacks for PoolConnCallbacks {
+    fn goaway(&self, _stream_id: StreamId, _error_code: u32) {
+        self.shared.goaway.store(true, Ordering::SeqCst);
+    }
+
+    fn settings_updated(&self, settings: &HttpSettings) {
+        self.shared.connected.store(true, Ordering::SeqCst);
+        self.shared
+            .max_concurrent_streams
+            .store(settings.max_concurrent_streams as usize, Ordering::SeqCst);
+    }
+}
+
+/// Decrements active requests counter when response is dropped
+struct ActiveRequestGuard(Arc<PoolConnShared>);
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        if self.0.active_requests.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *self.0.idle_since.lock().expect("lock") = Instant::now();
+        }
+    }
+}
+
+struct PoolConn {
+    conn: ClientConn,
+    shared: Arc<PoolConnShared>,
+}
+
+impl PoolConn {
+    fn is_dead(&self) -> bool {
+        self.conn.is_closed() || self.shared.goaway.load(Ordering::SeqCst)
+    }
+
+    /// No requests in flight for given time
+    fn is_idle(&self, now: Instant, idle_timeout: Duration) -> bool {
+        if self.shared.active_requests.load(Ordering::SeqCst) != 0 {
+            return false;
+        }
+        let idle_since = *self.shared.idle_since.lock().expect("lock");
+        now >= idle_since + idle_timeout
+    }
+}
+
+struct PoolState<T: ToClientStream, C: TlsConnector> {
+    handle: reactor::Handle,
+    socket_addr: T,
+    tls: ClientTlsOption<C>,
+    conf: ClientConf,
+    tx: UnboundedSender<PoolCommand>,
+    // `None` when connection is closed and reconnect is delayed
+    conns: Vec<Option<PoolConn>>,
+    // connection was closed by idle timeout and is reopened on request
+    idle_closed: Vec<bool>,
+    // next connection for round-robin
+    next: usize,
+    // connections closed before connection was established
+    consecutive_failures: u32,
+    // do not open connections until this time
+    reconnect_at: Option<Instant>,
+}
+
+impl<T: ToClientStream + 'static + Clone, C: TlsConnector> PoolState<T, C> {
+    fn spawn_conn(&self) -> PoolConn {
+        let shared = Arc::new(PoolConnShared {
+            active_requests: AtomicUsize::new(0),
+            max_concurrent_streams: AtomicUsize::new(u32::MAX as usize),
+            connected: AtomicBool::new(false),
+            goaway: AtomicBool::new(false),
+            idle_since: Mutex::new(Instant::now()),
+        });
+        let conn = ClientConn::spawn(
+            self.handle.clone(),
+            Box::new(self.socket_addr.clone()),
+            self.tls.clone(),
+            self.conf.clone(),
+            PoolConnCallbacks {
+                shared: shared.clone(),
+            },
+        );
+        PoolConn { conn, shared }
+    }
+
+    /// Drop connections which are closed or received GOAWAY,
+    /// and open new connections unless reconnect is delayed by backoff.
+    fn check_conns(&mut self) {
+        let mut failed = false;
+        for conn in &mut self.conns {
+            let (dead, connected) = match *conn {
+                Some(ref conn) => (conn.is_dead(), conn.shared.connected.load(Ordering::SeqCst)),
+                None => continue,
+            };
+            if connected {
+                self.consecutive_failures = 0;
+            }
+            if dead {
+                failed |= !connected;
+                *conn = None;
+            }
+        }
+
+        if failed {
+            self.consecutive_failures += 1;
+            if let Some(ref backoff) = self.conf.reconnect_backoff {
+                let delay = backoff.delay_with_jitter(self.consecutive_failures);
+                debug!(
+                    "{} connection attempts failed, delaying reconnect for {:?}",
+                    self.consecutive_failures, delay
+                );
+                self.reconnect_at = Some(Instant::now() + delay);
+                self.schedule_check(delay);
+            }
+        }
+
+        if let Some(reconnect_at) = self.reconnect_at {
+            if Instant::now() < reconnect_at {
+                return;
+            }
+            self.reconnect_at = None;
+        }
+
+        for i in 0..self.conns.len() {
+            if self.conns[i].is_none() && !self.idle_closed[i] {
+                debug!("opening pool connection {}", i);
+                self.conns[i] = Some(self.spawn_conn());
+            }
+        }
+    }
+
+    /// Gracefully close connections idle longer than `pool_idle_timeout`
+    fn close_idle_conns(&mut self) {
+        let idle_timeout = match self.conf.pool_idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+        let now = Instant::now();
+        for i in 0..self.conns.len() {
+            let idle = match self.conns[i] {
+                Some(ref conn) => !conn.is_dead() && conn.is_idle(now, idle_timeout),
+                None => false,
+            };
+            if idle {
+                debug!("closing idle pool connection {}", i);
+                self.conns[i].take().unwrap().conn.shutdown_gracefully();
+                self.idle_closed[i] = true;
+            }
+        }
+    }
+
+    /// Check connections after delay, not waiting for health check
+    fn schedule_check(&self, delay: Duration) {
+        let timeout = match reactor::Timeout::new(delay, &self.handle) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                warn!("failed to create timeout: {:?}", e);
+                return;
+            }
+        };
+        let tx = self.tx.clone();
+        self.handle.spawn(timeout.then(move |_| {
+            // ignore error, pool may be shut down
+            drop(tx.unbounded_send(PoolCommand::HealthCheck));
+            Ok(())
+        }));
+    }
+
+    /// Round-robin over open connections, skipping connections without free streams
+    fn select_conn(&mut self, connected_only: bool) -> Option<usize> {
+        let len = self.conns.len();
+        let open: Vec<usize> = (0..len)
+            .map(|i| (self.next + i) % len)
+            .filter(|&i| match self.conns[i] {
+                Some(ref conn) => !connected_only || conn.shared.connected.load(Ordering::SeqCst),
+                None => false,
+            }).collect();
+        let index = open
+            .iter()
+            .cloned()
+            .find(|&i| self.conns[i].as_ref().unwrap().shared.has_capacity())
+            .or_else(|| open.first().cloned())?;
+        self.next = (index + 1) % len;
+        Some(index)
+    }
+
+    fn start_request(&mut self, start: StartRequestMessage) {
+        for idle_closed in &mut self.idle_closed {
+            *idle_closed = false;
+        }
+        self.check_conns();
+
+        let StartRequestMessage {
+            headers,
+            body,
+            resp_tx,
+            timeout,
+            span,
+        } = start;
+
+        let (conn_resp_tx, conn_resp_rx) = oneshot::channel();
+        let mut start = StartRequestMessage {
+            headers,
+            body,
+            resp_tx: conn_resp_tx,
+            timeout,
+            span,
+        };
+
+        let fail_fast = self.conf.fail_fast.unwrap_or(false);
+
+        let shared = loop {
+            let index = match self.select_conn(fail_fast) {
+                Some(index) => index,
+                None if fail_fast => {
+                    let err = error::Error::NoConnectionAvailable;
+                    if resp_tx.send(Response::err(err)).is_err() {
+                        debug!("caller likely died");
+                    }
+                    return;
+                }
+                None => {
+                    let err = error::Error::Other("no pool connections, reconnect is delayed");
+                    if resp_tx.send(Response::err(err)).is_err() {
+                        debug!("caller likely died");
+                    }
+                    return;
+                }
+            };
+            let conn = self.conns[index].as_ref().unwrap();
+            match conn.conn.start_request_with_resp_sender(start) {
+                Ok(()) => break conn.shared.clone(),
+                Err(s) => {
+                    // connection died after the check
+                    start = s;
+                    self.check_conns();
+                }
+            }
+        };
+
+        shared.active_requests.fetch_add(1, Ordering::SeqCst);
+        let guard = ActiveRequestGuard(shared);
+
+        self.handle.spawn(conn_resp_rx.then(move |r| {
+            let response = match r {
+                Ok(response) => Response::from_stream(response.into_stream_flag().map(
+                    move |part| {
+                        // counter is decremented when response stream is dropped
+                        let _ = &guard;
+                        part
+                    },
+                )),
+                Err(oneshot::Canceled) => Response::err(error::Error::Other("client likely died")),
+            };
+            if resp_tx.send(response).is_err() {
+                debug!("caller likely died");
+            }
+            Ok(())
+        }));
+    }
+
+    fn stats(&self) -> ClientPoolStats {
+        let now = Instant::now();
+        ClientPoolStats {
+            conns: self.conns.iter().filter(|c| c.is_some()).count(),
+            consecutive_failures: self.consecutive_failures,
+            reconnect_delay: self
+                .reconnect_at
+                .map(|at| if at > now { at - now } else { Duration::from_secs(0) }),
+        }
+    }
+
+    fn iter(mut self, cmd: PoolCommand) -> PoolState<T, C> {
+        match cmd {
+            PoolCommand::StartRequest(start) => self.start_request(start),
+            PoolCommand::HealthCheck => {
+                self.close_idle_conns();
+                self.check_conns();
+            }
+            PoolCommand::Stats(tx) => {
+                self.check_conns();
+                // ignore error
+                drop(tx.send(self.stats()));
+            }
+        }
+        self
+    }
+}
+
+fn run_pool_event_loop<T: ToClientStream + Send + Clone + 'static, C: TlsConnector>(
+    handle: reactor::Handle,
+    shutdown_future: ShutdownFuture,
+    socket_addr: T,
+    tls: ClientTlsOption<C>,
+    conf: ClientPoolConf,
+    controller_tx: UnboundedSender<PoolCommand>,
+    controller_rx: UnboundedReceiver<PoolCommand>,
+) -> HttpFuture<()> {
+    let pool_size = conf.pool_size();
+    let health_check_interval = conf
+        .health_check_interval
+        .unwrap_or(Duration::from_secs(1));
+
+    let mut state = PoolState {
+        handle: handle.clone(),
+        socket_addr,
+        tls,
+        conf: conf.client,
+        tx: controller_tx,
+        conns: (0..pool_size).map(|_| None).collect(),
+        idle_closed: vec![false; pool_size],
+        next: 0,
+        consecutive_failures: 0,
+        reconnect_at: None,
+    };
+    state.check_conns();
+
+    let health_check = match reactor::Interval::new(health_check_interval, &handle) {
+        Ok(interval) => interval,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    let health_check = health_check
+        .map(|()| PoolCommand::HealthCheck)
+        .map_err(|_| ());
+
+    let commands = controller_rx
+        .select(health_check)
+        .map_err(|()| error::Error::Other("channel died"));
+    let controller_future = commands
+        .fold(state, |state, cmd| Ok::<_, error::Error>(state.iter(cmd)))
+        .map(|_| ());
+
+    let shutdown_future = shutdown_future.then(move |_| {
+        // Must complete with error,
+        // so `join` with this future cancels another future.
+        future::failed::<(), _>(Error::Shutdown)
+    });
+
+    Box::new(controller_future.join(shutdown_future).map(|_| ()))
+}
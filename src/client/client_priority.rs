@@ -0,0 +1,118 @@
+use std::fmt;
+use std::sync::Arc;
+
+use solicit::header::Headers;
+
+/// Request urgency, from 0 (highest) to 7 (lowest),
+/// as in `priority` header `u` parameter (RFC 9218).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Urgency(u8);
+
+impl Urgency {
+    pub const HIGHEST: Urgency = Urgency(0);
+    pub const DEFAULT: Urgency = Urgency(3);
+    pub const LOWEST: Urgency = Urgency(7);
+
+    /// Create urgency, values above 7 are clamped to 7.
+    pub fn new(urgency: u8) -> Urgency {
+        Urgency(if urgency > 7 { 7 } else { urgency })
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0
+    }
+
+    /// Parse `u` parameter of `priority` header value like `u=1, i`.
+    pub fn parse_priority_header(value: &str) -> Option<Urgency> {
+        for param in value.split(',') {
+            let param = param.trim();
+            if let Some(u) = param.strip_prefix("u=") {
+                return match u.parse::<u8>() {
+                    Ok(u) if u <= 7 => Some(Urgency(u)),
+                    _ => None,
+                };
+            }
+        }
+        None
+    }
+
+    /// HTTP/2 stream weight (in frame representation, i. e. weight minus one).
+    pub fn weight(&self) -> u8 {
+        ((8 - self.0 as u32) * 32 - 1) as u8
+    }
+}
+
+impl Default for Urgency {
+    fn default() -> Urgency {
+        Urgency::DEFAULT
+    }
+}
+
+/// How client assigns priority to requests.
+#[derive(Clone)]
+pub enum AutoPriority {
+    /// Use `priority` request header, requests without it get default urgency.
+    PriorityHeader,
+    /// Use user provided classifier.
+    Classifier(Arc<Fn(&Headers) -> Urgency + Send + Sync>),
+}
+
+impl AutoPriority {
+    pub fn urgency(&self, headers: &Headers) -> Urgency {
+        match *self {
+            AutoPriority::PriorityHeader => headers
+                .get_opt("priority")
+                .and_then(Urgency::parse_priority_header)
+                .unwrap_or_default(),
+            AutoPriority::Classifier(ref classifier) => classifier(headers),
+        }
+    }
+}
+
+impl fmt::Debug for AutoPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AutoPriority::PriorityHeader => write!(f, "PriorityHeader"),
+            AutoPriority::Classifier(..) => write!(f, "Classifier(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use solicit::header::Header;
+
+    #[test]
+    fn parse_priority_header() {
+        assert_eq!(
+            Some(Urgency::new(1)),
+            Urgency::parse_priority_header("u=1")
+        );
+        assert_eq!(
+            Some(Urgency::new(5)),
+            Urgency::parse_priority_header("i, u=5")
+        );
+        assert_eq!(None, Urgency::parse_priority_header("i"));
+        assert_eq!(None, Urgency::parse_priority_header("u=8"));
+        assert_eq!(None, Urgency::parse_priority_header("u=x"));
+    }
+
+    #[test]
+    fn weight() {
+        assert_eq!(255, Urgency::HIGHEST.weight());
+        assert_eq!(159, Urgency::DEFAULT.weight());
+        assert_eq!(31, Urgency::LOWEST.weight());
+    }
+
+    #[test]
+    fn priority_header() {
+        let headers = Headers(vec![Header::new("priority", "u=0")]);
+        assert_eq!(Urgency::HIGHEST, AutoPriority::PriorityHeader.urgency(&headers));
+        assert_eq!(
+            Urgency::DEFAULT,
+            AutoPriority::PriorityHeader.urgency(&Headers::new())
+        );
+    }
+}
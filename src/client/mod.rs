@@ -1,11 +1,19 @@
+pub mod backoff;
 pub mod client_conf;
 pub mod client_conn;
+pub mod client_pool;
+pub mod client_priority;
 pub mod client_tls;
 
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 
@@ -19,6 +27,7 @@ use futures::sync::oneshot;
 
 use tokio_core::reactor;
 
+use tls_api;
 use tls_api::TlsConnector;
 use tls_api::TlsConnectorBuilder;
 use tls_api_stub;
@@ -27,8 +36,11 @@ use futures_misc::*;
 
 use error;
 use error::Error;
+use error::ErrorCode;
 use result::Result;
 
+use solicit::frame::settings::HttpSetting;
+use solicit::frame::settings::HttpSettings;
 use solicit::header::*;
 use solicit::HttpScheme;
 use solicit::StreamId;
@@ -42,8 +54,13 @@ use data_or_trailers::*;
 use service::Service;
 use socket::AnySocketAddr;
 use socket::ToClientStream;
+use socket_tcp::HostPort;
+use trace::RequestTracing;
+
+use futures_cpupool;
 
 use client::client_conf::ClientConf;
+use client::client_conn::ClientCallbacks;
 use client::client_conn::ClientConn;
 use client::client_conn::ClientConnCallbacks;
 use client::client_conn::StartRequestMessage;
@@ -102,13 +119,45 @@ impl<C: TlsConnector> ClientBuilder<C> {
     }
 
     pub fn set_tls(&mut self, host: &str) -> Result<()> {
+        self.set_tls_configured(host, |_| Ok(()))
+    }
+
+    /// Like `set_tls`, but `configure` is called with TLS connector builder
+    /// before connector is built.
+    ///
+    /// Settings not covered by `tls_api` like minimum protocol version
+    /// or cipher list can be set on implementation builder returned by `underlying_mut`.
+    ///
+    /// ```
+    /// # extern crate httpbis;
+    /// # extern crate tls_api;
+    /// # extern crate tls_api_openssl;
+    /// use httpbis::*;
+    /// use tls_api::TlsConnectorBuilder;
+    ///
+    /// # fn main() {
+    /// let mut client = ClientBuilder::<tls_api_openssl::TlsConnector>::new();
+    /// client
+    ///     .set_tls_configured("example.com", |builder| {
+    ///         builder
+    ///             .underlying_mut()
+    ///             .set_cipher_list("ECDHE+AESGCM")
+    ///             .map_err(tls_api::Error::new)
+    ///     }).expect("tls");
+    /// # }
+    /// ```
+    pub fn set_tls_configured<F>(&mut self, host: &str, configure: F) -> Result<()>
+    where
+        F: FnOnce(&mut C::Builder) -> tls_api::Result<()>,
+    {
         let mut tls_connector = C::builder()?;
 
         if C::supports_alpn() {
-            // TODO: check negotiated protocol after connect
             tls_connector.set_alpn_protocols(&[b"h2"])?;
         }
 
+        configure(&mut tls_connector)?;
+
         let tls_connector = tls_connector.build()?;
 
         let tls_connector = Arc::new(tls_connector);
@@ -116,10 +165,26 @@ impl<C: TlsConnector> ClientBuilder<C> {
         Ok(())
     }
 
-    pub fn build(self) -> Result<Client> {
-        let addr = self.addr.expect("addr is not specified");
+    pub fn build(mut self) -> Result<Client> {
+        let addr = self.addr.take().expect("addr is not specified");
+        self.build_with_addr(addr)
+    }
 
-        let http_scheme = self.tls.http_scheme();
+    fn build_with_addr<T>(self, addr: T) -> Result<Client>
+    where
+        T: ToClientStream + Send + Clone + 'static,
+    {
+        let http_scheme = self
+            .conf
+            .http_scheme
+            .unwrap_or_else(|| self.tls.http_scheme());
+
+        let max_buffered_response_size = self.conf.max_buffered_response_size;
+        let request_tracing = self.conf.request_tracing.clone();
+        let max_stream_refused_retries = self
+            .conf
+            .max_stream_refused_retries
+            .unwrap_or(DEFAULT_STREAM_REFUSED_RETRIES);
 
         // Create a channel to receive shutdown signal.
         let (shutdown_signal, shutdown_future) = shutdown_signal();
@@ -131,6 +196,12 @@ impl<C: TlsConnector> ClientBuilder<C> {
         let client_died_error_holder = ClientDiedErrorHolder::new();
         let client_died_error_holder_copy = client_died_error_holder.clone();
 
+        let activity = Arc::new(ClientActivity::new());
+        let activity_copy = activity.clone();
+
+        let current_conn_metrics = Arc::new(CurrentConnMetrics::new());
+        let current_conn_metrics_copy = current_conn_metrics.clone();
+
         let join = if let Some(remote) = self.event_loop {
             let tls = self.tls;
             let conf = self.conf;
@@ -146,6 +217,8 @@ impl<C: TlsConnector> ClientBuilder<C> {
                     controller_tx,
                     controller_rx,
                     client_died_error_holder_copy,
+                    activity_copy,
+                    current_conn_metrics_copy,
                 );
                 future::finished(())
             });
@@ -176,6 +249,8 @@ impl<C: TlsConnector> ClientBuilder<C> {
                         controller_tx,
                         controller_rx,
                         client_died_error_holder_copy,
+                        activity_copy,
+                        current_conn_metrics_copy,
                     );
 
                     lp.run(done_rx).expect("run");
@@ -185,10 +260,19 @@ impl<C: TlsConnector> ClientBuilder<C> {
 
         Ok(Client {
             join: Some(join),
-            controller_tx,
+            controller_tx: controller_tx.clone(),
             http_scheme,
+            starter: RequestStarter {
+                controller_tx,
+                authority: None,
+                max_buffered_response_size,
+                max_stream_refused_retries,
+                request_tracing,
+                client_died_error_holder,
+                activity,
+            },
             shutdown: shutdown_signal,
-            client_died_error_holder,
+            current_conn_metrics,
         })
     }
 }
@@ -207,9 +291,11 @@ pub struct Client {
     controller_tx: UnboundedSender<ControllerCommand>,
     join: Option<Completion>,
     http_scheme: HttpScheme,
+    starter: RequestStarter,
     // used only once to send shutdown signal
     shutdown: ShutdownSignal,
-    client_died_error_holder: ClientDiedErrorHolder<ClientDiedType>,
+    // counters of connection used for new requests
+    current_conn_metrics: Arc<CurrentConnMetrics>,
 }
 
 impl Client {
@@ -248,6 +334,37 @@ impl Client {
         client.build()
     }
 
+    /// Create a new client from URL like `https://host:port`.
+    ///
+    /// Scheme (`http` or `https`) selects plain or TLS connection,
+    /// port defaults to 80 or 443, IPv6 literals must be enclosed in brackets.
+    /// URL authority is used as `:authority` for requests which do not specify it.
+    ///
+    /// This function does not block: host name is resolved in a background thread
+    /// on each (re)connect, resolution errors are reported as connection errors.
+    pub fn new_from_url<C: TlsConnector>(url: &str, conf: ClientConf) -> Result<Client> {
+        let url = ParsedUrl::parse(url)?;
+
+        let resolver = futures_cpupool::Builder::new()
+            .pool_size(1)
+            .name_prefix("http2-client-resolver")
+            .create();
+        let addr = HostPort {
+            host: url.host.clone(),
+            port: url.port,
+            resolver,
+        };
+
+        let mut client = ClientBuilder::<C>::new();
+        client.conf = conf;
+        if url.tls {
+            client.set_tls(&url.host)?;
+        }
+        let mut client = client.build_with_addr(addr)?;
+        client.starter.authority = Some(url.authority);
+        Ok(client)
+    }
+
     /// Connect to server using plain or TLS protocol depending on `tls` parameter.
     pub fn new_expl<C: TlsConnector>(
         addr: &SocketAddr,
@@ -261,9 +378,45 @@ impl Client {
         client.build()
     }
 
+    /// Start HTTP/2 request which is reset with `RST_STREAM(CANCEL)`
+    /// if response headers are not received within `timeout`.
+    ///
+    /// Response fails with `Error::RequestTimeout` in that case.
+    pub fn start_request_with_timeout(
+        &self,
+        headers: Headers,
+        body: HttpStreamAfterHeaders,
+        timeout: Duration,
+    ) -> Response {
+        self.start_request_impl(headers, body, Some(timeout))
+    }
+
+    fn start_request_impl(
+        &self,
+        headers: Headers,
+        body: HttpStreamAfterHeaders,
+        timeout: Option<Duration>,
+    ) -> Response {
+        match body.retryable_bytes() {
+            Some(body) => {
+                let retries = self.starter.max_stream_refused_retries;
+                self.starter
+                    .clone()
+                    .start_retry_refused(headers, body, timeout, retries)
+            }
+            None => self.starter.start(headers, body, timeout),
+        }
+    }
+
     /// Start HTTP/2 request.
+    ///
+    /// Request is resent if the server refuses it without processing:
+    /// sends GOAWAY with last stream id below request stream id
+    /// (e. g. when the server is restarted or the connection is rotated)
+    /// or resets request stream with `REFUSED_STREAM`.
+    /// See `ClientConf::max_stream_refused_retries`.
     pub fn start_request_simple(&self, headers: Headers, body: Bytes) -> Response {
-        self.start_request(headers, HttpStreamAfterHeaders::once_bytes(body))
+        self.start_request_impl(headers, HttpStreamAfterHeaders::retryable(body), None)
     }
 
     /// Start HTTP/2 `GET` request.
@@ -288,15 +441,58 @@ impl Client {
         self.start_request_simple(headers, body)
     }
 
-    /// For tests
-    #[doc(hidden)]
-    pub fn dump_state(&self) -> HttpFutureSend<ConnStateSnapshot> {
-        let (tx, rx) = oneshot::channel();
+    /// Perform administrative operation on current connection.
+    pub fn control(&self, control: ConnControl) {
         // ignore error
         drop(
             self.controller_tx
-                .unbounded_send(ControllerCommand::_DumpState(tx)),
+                .unbounded_send(ControllerCommand::Control(control)),
         );
+    }
+
+    /// Send SETTINGS to the server on current connection.
+    ///
+    /// Returned future resolves when the server acknowledges the settings.
+    /// Settings are not resent after reconnect.
+    pub fn update_settings(&self, settings: Vec<HttpSetting>) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        self.control(ConnControl::UpdateSettingsAcked(settings, tx));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")).and_then(|r| r))
+    }
+
+    /// Send GOAWAY to the server and close current connection.
+    ///
+    /// `debug_data` (e. g. shutdown reason) is sent in GOAWAY frame
+    /// to aid the server diagnostics.
+    pub fn close(&self, error_code: ErrorCode, debug_data: Bytes) {
+        self.control(ConnControl::Goaway(error_code, debug_data));
+    }
+
+    /// Stop reading from the socket of current connection
+    /// until `resume_reads` is called.
+    ///
+    /// Flow control windows are not replenished while reads are paused,
+    /// so the server eventually stops sending.
+    pub fn pause_reads(&self) {
+        self.control(ConnControl::PauseReads);
+    }
+
+    /// Resume reading from the socket after `pause_reads`.
+    pub fn resume_reads(&self) {
+        self.control(ConnControl::ResumeReads);
+    }
+
+    /// Counters of current connection, cheap to call.
+    ///
+    /// Counters are reset when client reconnects.
+    pub fn metrics(&self) -> ConnMetrics {
+        self.current_conn_metrics.snapshot()
+    }
+
+    /// State of current connection and its streams, e. g. for monitoring
+    pub fn dump_state(&self) -> HttpFutureSend<ConnStateSnapshot> {
+        let (tx, rx) = oneshot::channel();
+        self.control(ConnControl::DumpState(tx));
         Box::new(rx.map_err(|_| error::Error::Other("conn died")))
     }
 
@@ -319,12 +515,98 @@ impl Client {
 impl Service for Client {
     // TODO: copy-paste with ClientConnection::start_request
     fn start_request(&self, headers: Headers, body: HttpStreamAfterHeaders) -> Response {
+        self.start_request_impl(headers, body, None)
+    }
+}
+
+/// Parts of `Client` needed to start a request,
+/// cloned into the response future to retry the request.
+#[derive(Clone)]
+struct RequestStarter {
+    controller_tx: UnboundedSender<ControllerCommand>,
+    // used when request headers do not contain `:authority`
+    authority: Option<String>,
+    max_buffered_response_size: Option<usize>,
+    max_stream_refused_retries: u32,
+    request_tracing: Option<RequestTracing>,
+    client_died_error_holder: ClientDiedErrorHolder<ClientDiedType>,
+    activity: Arc<ClientActivity>,
+}
+
+/// Requests in flight, used to close idle connection after `pool_idle_timeout`
+struct ClientActivity {
+    active_requests: AtomicUsize,
+    /// Last request completed or client created
+    idle_since: Mutex<Instant>,
+}
+
+impl ClientActivity {
+    fn new() -> ClientActivity {
+        ClientActivity {
+            active_requests: AtomicUsize::new(0),
+            idle_since: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// No requests in flight for given time
+    fn is_idle(&self, now: Instant, idle_timeout: Duration) -> bool {
+        if self.active_requests.load(Ordering::SeqCst) != 0 {
+            return false;
+        }
+        let idle_since = *self.idle_since.lock().expect("lock");
+        now >= idle_since + idle_timeout
+    }
+}
+
+/// Request is in flight until response stream is dropped
+struct ActiveRequestGuard(Arc<ClientActivity>);
+
+impl ActiveRequestGuard {
+    fn new(activity: Arc<ClientActivity>) -> ActiveRequestGuard {
+        activity.active_requests.fetch_add(1, Ordering::SeqCst);
+        ActiveRequestGuard(activity)
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        if self.0.active_requests.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *self.0.idle_since.lock().expect("lock") = Instant::now();
+        }
+    }
+}
+
+/// How many times refused request is resent by default
+const DEFAULT_STREAM_REFUSED_RETRIES: u32 = 3;
+
+impl RequestStarter {
+    fn start(
+        &self,
+        mut headers: Headers,
+        body: HttpStreamAfterHeaders,
+        timeout: Option<Duration>,
+    ) -> Response {
+        if let Some(ref authority) = self.authority {
+            if headers.get_opt(":authority").is_none() {
+                headers.add(":authority", authority);
+            }
+        }
+
         let (resp_tx, resp_rx) = oneshot::channel();
 
+        let span = self
+            .request_tracing
+            .as_ref()
+            .map(|t| t.start_client_span(&mut headers));
+
+        let guard = ActiveRequestGuard::new(self.activity.clone());
+
         let start = StartRequestMessage {
             headers,
             body,
             resp_tx,
+            timeout,
+            span: span.clone(),
         };
 
         if let Err(_) = self
@@ -341,7 +623,129 @@ impl Service for Client {
 
         let resp_rx = resp_rx.flatten_stream();
 
-        Response::from_stream(resp_rx)
+        // guard is dropped with response stream
+        let resp_rx = resp_rx.map(move |part| {
+            let _ = &guard;
+            part
+        });
+
+        let response = Response::from_stream(resp_rx);
+        let response = match self.max_buffered_response_size {
+            Some(max_size) => response.max_body_size(max_size),
+            None => response,
+        };
+        match span {
+            Some(span) => span.trace_response(response),
+            None => response,
+        }
+    }
+
+    fn start_retry_refused(
+        self,
+        headers: Headers,
+        body: Bytes,
+        timeout: Option<Duration>,
+        retries: u32,
+    ) -> Response {
+        let response = self.start(
+            headers.clone(),
+            HttpStreamAfterHeaders::once_bytes(body.clone()),
+            timeout,
+        );
+        if retries == 0 {
+            return response;
+        }
+        Response::new(response.0.or_else(move |e| match e {
+            Error::RefusedByGoaway(..) => {
+                debug!("request is not processed by server before GOAWAY, retrying");
+                self.start_retry_refused(headers, body, timeout, retries - 1).0
+            }
+            Error::CodeError(ErrorCode::RefusedStream) => {
+                debug!("request is refused by server, retrying");
+                self.start_retry_refused(headers, body, timeout, retries - 1).0
+            }
+            e => Box::new(future::err(e)),
+        }))
+    }
+}
+
+/// Parts of URL passed to `Client::new_from_url`
+#[derive(Debug, PartialEq)]
+struct ParsedUrl {
+    tls: bool,
+    /// Host without brackets
+    host: String,
+    port: u16,
+    /// Host and port as specified in URL
+    authority: String,
+}
+
+impl ParsedUrl {
+    fn parse(url: &str) -> Result<ParsedUrl> {
+        let scheme_end = match url.find("://") {
+            Some(pos) => pos,
+            None => return Err(Error::InvalidUrl(format!("no scheme in URL: {}", url))),
+        };
+
+        let (tls, default_port) = match &url[..scheme_end].to_ascii_lowercase()[..] {
+            "http" => (false, 80),
+            "https" => (true, 443),
+            scheme => {
+                return Err(Error::InvalidUrl(format!(
+                    "unsupported URL scheme: {}, expecting http or https",
+                    scheme
+                )))
+            }
+        };
+
+        let rem = &url[scheme_end + 3..];
+        let authority = match rem.find(['/', '?', '#']) {
+            Some(pos) => &rem[..pos],
+            None => rem,
+        };
+
+        if !authority.is_empty() && !is_valid_authority(authority.as_bytes()) {
+            return Err(Error::InvalidUrl(format!("invalid authority in URL: {}", url)));
+        }
+
+        let (host, port) = if authority.starts_with('[') {
+            match authority.find(']') {
+                Some(pos) => (&authority[1..pos], &authority[pos + 1..]),
+                None => {
+                    return Err(Error::InvalidUrl(format!(
+                        "unterminated IPv6 address in URL: {}",
+                        url
+                    )))
+                }
+            }
+        } else {
+            match authority.rfind(':') {
+                Some(pos) => (&authority[..pos], &authority[pos..]),
+                None => (authority, ""),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(Error::InvalidUrl(format!("no host in URL: {}", url)));
+        }
+
+        let port = if port.is_empty() || port == ":" {
+            default_port
+        } else if let Some(port) = port.strip_prefix(':') {
+            match port.parse() {
+                Ok(port) => port,
+                Err(_) => return Err(Error::InvalidUrl(format!("invalid port in URL: {}", url))),
+            }
+        } else {
+            return Err(Error::InvalidUrl(format!("invalid authority in URL: {}", url)));
+        };
+
+        Ok(ParsedUrl {
+            tls,
+            host: host.to_owned(),
+            port,
+            authority: authority.to_owned(),
+        })
     }
 }
 
@@ -349,7 +753,9 @@ enum ControllerCommand {
     GoAway,
     StartRequest(StartRequestMessage),
     WaitForConnect(oneshot::Sender<Result<()>>),
-    _DumpState(oneshot::Sender<ConnStateSnapshot>),
+    Control(ConnControl),
+    /// Check if connection is idle longer than `pool_idle_timeout`
+    CheckIdle,
 }
 
 struct ControllerState<T: ToClientStream, C: TlsConnector> {
@@ -359,11 +765,25 @@ struct ControllerState<T: ToClientStream, C: TlsConnector> {
     conf: ClientConf,
     // current connection
     conn: Arc<ClientConn>,
+    // when current connection was created
+    conn_created: Instant,
+    // requests started on current connection
+    conn_requests: u64,
+    // connection was closed by idle timeout and is reopened on request
+    idle_closed: bool,
+    activity: Arc<ClientActivity>,
     tx: UnboundedSender<ControllerCommand>,
+    // sequence number of current connection
+    conn_seq: u64,
+    // shared with `Client`
+    current_conn_metrics: Arc<CurrentConnMetrics>,
 }
 
 impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C> {
     fn init_conn(&mut self) {
+        self.conn_seq += 1;
+        self.current_conn_metrics.set_current(self.conn_seq);
+
         let conn = ClientConn::spawn(
             self.handle.clone(),
             Box::new(self.socket_addr.clone()),
@@ -371,10 +791,58 @@ impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C>
             self.conf.clone(),
             CallbacksImpl {
                 tx: self.tx.clone(),
+                conn_seq: self.conn_seq,
+                current_conn_metrics: self.current_conn_metrics.clone(),
+                callbacks: self.conf.callbacks.clone(),
             },
         );
 
         self.conn = Arc::new(conn);
+        self.conn_created = Instant::now();
+        self.conn_requests = 0;
+        self.idle_closed = false;
+    }
+
+    /// Gracefully close connection without requests in flight for `pool_idle_timeout`
+    fn close_if_idle(&mut self) {
+        let idle_timeout = match self.conf.pool_idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+        if !self.idle_closed && self.activity.is_idle(Instant::now(), idle_timeout) {
+            debug!("closing idle connection");
+            self.conn.shutdown_gracefully();
+            self.idle_closed = true;
+        }
+    }
+
+    fn reopen_if_idle_closed(&mut self) {
+        if self.idle_closed {
+            debug!("reopening connection closed by idle timeout");
+            self.init_conn();
+        }
+    }
+
+    /// Replace current connection if it is too old or served too many requests,
+    /// old connection is closed after requests in flight complete.
+    fn rotate_conn_if_needed(&mut self) {
+        let too_old = match self.conf.max_connection_age {
+            Some(max_age) => self.conn_created.elapsed() >= max_age,
+            None => false,
+        };
+        let too_many_requests = match self.conf.max_connection_requests {
+            Some(max_requests) => self.conn_requests >= max_requests,
+            None => false,
+        };
+        if too_old || too_many_requests {
+            debug!(
+                "rotating connection after {} requests, age {:?}",
+                self.conn_requests,
+                self.conn_created.elapsed()
+            );
+            self.conn.shutdown_gracefully();
+            self.init_conn();
+        }
     }
 
     fn iter(mut self, cmd: ControllerCommand) -> ControllerState<T, C> {
@@ -383,6 +851,9 @@ impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C>
                 self.init_conn();
             }
             ControllerCommand::StartRequest(start) => {
+                self.reopen_if_idle_closed();
+                self.rotate_conn_if_needed();
+                self.conn_requests += 1;
                 if let Err(start) = self.conn.start_request_with_resp_sender(start) {
                     self.init_conn();
                     if let Err(start) = self.conn.start_request_with_resp_sender(start) {
@@ -395,6 +866,7 @@ impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C>
                 }
             }
             ControllerCommand::WaitForConnect(tx) => {
+                self.reopen_if_idle_closed();
                 if let Err(tx) = self.conn.wait_for_connect_with_resp_sender(tx) {
                     self.init_conn();
                     if let Err(tx) = self.conn.wait_for_connect_with_resp_sender(tx) {
@@ -404,8 +876,11 @@ impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C>
                     }
                 }
             }
-            ControllerCommand::_DumpState(tx) => {
-                self.conn.dump_state_with_resp_sender(tx);
+            ControllerCommand::Control(control) => {
+                self.conn.control(control);
+            }
+            ControllerCommand::CheckIdle => {
+                self.close_if_idle();
             }
         }
         self
@@ -421,12 +896,75 @@ impl<T: ToClientStream + 'static + Clone, C: TlsConnector> ControllerState<T, C>
 
 struct CallbacksImpl {
     tx: UnboundedSender<ControllerCommand>,
+    conn_seq: u64,
+    current_conn_metrics: Arc<CurrentConnMetrics>,
+    // `ClientConf::callbacks`
+    callbacks: Option<ClientCallbacks>,
 }
 
 impl ClientConnCallbacks for CallbacksImpl {
     fn goaway(&self, _stream_id: StreamId, _error_code: u32) {
         drop(self.tx.unbounded_send(ControllerCommand::GoAway));
     }
+
+    fn goaway_with_debug(&self, stream_id: StreamId, raw_error_code: u32, debug_data: Bytes) {
+        self.goaway(stream_id, raw_error_code);
+        if let Some(ref callbacks) = self.callbacks {
+            callbacks
+                .0
+                .goaway_with_debug(stream_id, raw_error_code, debug_data);
+        }
+    }
+
+    fn settings_updated(&self, settings: &HttpSettings) {
+        if let Some(ref callbacks) = self.callbacks {
+            callbacks.0.settings_updated(settings);
+        }
+    }
+
+    fn metrics_updated(&self, metrics: &ConnMetrics) {
+        self.current_conn_metrics.store(self.conn_seq, metrics);
+        if let Some(ref callbacks) = self.callbacks {
+            callbacks.0.metrics_updated(metrics);
+        }
+    }
+
+    fn on_push(
+        &self,
+        associated_stream_id: StreamId,
+        promised_stream_id: StreamId,
+        headers: Headers,
+        response: Response,
+    ) {
+        if let Some(ref callbacks) = self.callbacks {
+            callbacks
+                .0
+                .on_push(associated_stream_id, promised_stream_id, headers, response);
+        }
+    }
+}
+
+/// Periodically ask controller to close idle connection,
+/// timer stops when controller is gone
+fn spawn_idle_check(
+    handle: &reactor::Handle,
+    idle_timeout: Duration,
+    tx: UnboundedSender<ControllerCommand>,
+) {
+    let interval = match reactor::Interval::new(idle_timeout / 2, handle) {
+        Ok(interval) => interval,
+        Err(e) => {
+            warn!("failed to create idle check timer: {:?}", e);
+            return;
+        }
+    };
+    let check = interval
+        .map_err(|e| warn!("idle check timer failed: {:?}", e))
+        .for_each(move |()| {
+            tx.unbounded_send(ControllerCommand::CheckIdle)
+                .map_err(|_| ())
+        });
+    handle.spawn(check);
 }
 
 // Event loop entry point
@@ -440,7 +978,12 @@ fn spawn_client_event_loop<T: ToClientStream + Send + Clone + 'static, C: TlsCon
     controller_tx: UnboundedSender<ControllerCommand>,
     controller_rx: UnboundedReceiver<ControllerCommand>,
     client_died_error_holder: ClientDiedErrorHolder<ClientDiedType>,
+    activity: Arc<ClientActivity>,
+    current_conn_metrics: Arc<CurrentConnMetrics>,
 ) {
+    let conn_seq = 1;
+    current_conn_metrics.set_current(conn_seq);
+
     let http_conn = ClientConn::spawn(
         handle.clone(),
         Box::new(socket_addr.clone()),
@@ -448,18 +991,33 @@ fn spawn_client_event_loop<T: ToClientStream + Send + Clone + 'static, C: TlsCon
         conf.clone(),
         CallbacksImpl {
             tx: controller_tx.clone(),
+            conn_seq,
+            current_conn_metrics: current_conn_metrics.clone(),
+            callbacks: conf.callbacks.clone(),
         },
     );
 
+    let http_conn = Arc::new(http_conn);
+
     let init = ControllerState {
         handle: handle.clone(),
         socket_addr: socket_addr.clone(),
         tls: tls,
         conf: conf,
-        conn: Arc::new(http_conn),
-        tx: controller_tx,
+        conn: http_conn,
+        conn_created: Instant::now(),
+        conn_requests: 0,
+        idle_closed: false,
+        activity,
+        tx: controller_tx.clone(),
+        conn_seq,
+        current_conn_metrics,
     };
 
+    if let Some(idle_timeout) = init.conf.pool_idle_timeout {
+        spawn_idle_check(&handle, idle_timeout, controller_tx);
+    }
+
     let controller_future = init.run(controller_rx);
 
     let shutdown_future = shutdown_future.then(move |_| {
@@ -498,3 +1056,70 @@ impl Drop for Client {
         };
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            ParsedUrl {
+                tls: true,
+                host: "example.com".to_owned(),
+                port: 443,
+                authority: "example.com".to_owned(),
+            },
+            ParsedUrl::parse("https://example.com/foo").unwrap()
+        );
+        assert_eq!(
+            ParsedUrl {
+                tls: false,
+                host: "example.com".to_owned(),
+                port: 8080,
+                authority: "example.com:8080".to_owned(),
+            },
+            ParsedUrl::parse("http://example.com:8080").unwrap()
+        );
+        assert_eq!(
+            ParsedUrl {
+                tls: false,
+                host: "::1".to_owned(),
+                port: 80,
+                authority: "[::1]".to_owned(),
+            },
+            ParsedUrl::parse("http://[::1]").unwrap()
+        );
+        assert_eq!(
+            ParsedUrl {
+                tls: true,
+                host: "::1".to_owned(),
+                port: 8443,
+                authority: "[::1]:8443".to_owned(),
+            },
+            ParsedUrl::parse("https://[::1]:8443/").unwrap()
+        );
+        assert_eq!(
+            ParsedUrl {
+                tls: false,
+                host: "example.com".to_owned(),
+                port: 80,
+                authority: "example.com:".to_owned(),
+            },
+            ParsedUrl::parse("http://example.com:/").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_url_error() {
+        assert!(ParsedUrl::parse("ftp://example.com").is_err());
+        assert!(ParsedUrl::parse("example.com").is_err());
+        assert!(ParsedUrl::parse("http://").is_err());
+        assert!(ParsedUrl::parse("http://example.com:abc").is_err());
+        assert!(ParsedUrl::parse("http://[::1").is_err());
+        assert!(ParsedUrl::parse("http://user@example.com/").is_err());
+        assert!(ParsedUrl::parse("http://exa mple.com/").is_err());
+        assert!(ParsedUrl::parse("http://example.com:65536").is_err());
+        assert!(ParsedUrl::parse("http://[example.com]").is_err());
+    }
+}
@@ -2,7 +2,9 @@ use std::marker;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use futures::future;
 use futures::future::Future;
+use futures::Async;
 
 use error;
 use misc::any_to_string;
@@ -57,9 +59,13 @@ impl<D: DiedType> ClientDiedErrorHolder<D> {
         future: impl Future<Item = (), Error = error::Error>,
     ) -> impl Future<Item = (), Error = ()> {
         let holder = self.clone();
-        let future = future.then(move |r| {
-            match r {
-                Ok(()) => {
+        let mut future = future;
+        // Set error before the future is dropped: dropping it closes streams,
+        // and stream readers fetch the error from the holder
+        let future = future::poll_fn(move || {
+            match future.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {
                     info!("{} completed without errors", D::what());
                     holder.set_once(error::Error::ClientCompletedWithoutError);
                 }
@@ -68,7 +74,7 @@ impl<D: DiedType> ClientDiedErrorHolder<D> {
                     holder.set_once(e);
                 }
             }
-            Ok::<(), ()>(())
+            Ok::<_, ()>(Async::Ready(()))
         });
 
         let holder = self.clone();
@@ -1,9 +1,12 @@
+use codec::http_framed_read::HttpFrameJoinedOrGoaway;
 use codec::http_framed_read::HttpFramedJoinContinuationRead;
 use error;
 use futures::Async;
 use futures::Poll;
 use hpack;
 use solicit::frame::headers::HeadersDecodedFrame;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
+use solicit::frame::FrameCounts;
 use solicit::frame::HttpFrame;
 use solicit::frame::HttpFrameDecoded;
 use tokio_io::AsyncRead;
@@ -15,6 +18,8 @@ pub struct HttpDecodeRead<R: AsyncRead> {
     framed_read: HttpFramedJoinContinuationRead<R>,
     /// HPACK decoder used to decode incoming headers before passing them on to the session.
     decoder: hpack::Decoder,
+    /// Our SETTINGS_MAX_HEADER_LIST_SIZE
+    max_header_list_size: u32,
 }
 
 pub enum HttpFrameDecodedOrGoaway {
@@ -23,36 +28,111 @@ pub enum HttpFrameDecodedOrGoaway {
 }
 
 impl<R: AsyncRead> HttpDecodeRead<R> {
-    pub fn new(read: R) -> Self {
+    pub fn new(read: R, header_table_size: u32, max_header_list_size: u32) -> Self {
+        let mut decoder = hpack::Decoder::new();
+        decoder.set_max_allowed_table_size(header_table_size);
         HttpDecodeRead {
             framed_read: HttpFramedJoinContinuationRead::new(read),
-            decoder: hpack::Decoder::new(),
+            decoder,
+            max_header_list_size,
         }
     }
 
+    /// Apply our new SETTINGS_HEADER_TABLE_SIZE to HPACK decoder
+    pub fn set_header_table_size(&mut self, header_table_size: u32) {
+        self.decoder.set_max_allowed_table_size(header_table_size);
+    }
+
+    /// Limit number of CONTINUATION frames and total fragment size of a header block
+    pub fn set_continuation_limits(
+        &mut self,
+        max_continuation_frames: u32,
+        max_header_block_bytes: u32,
+    ) {
+        self.framed_read
+            .set_continuation_limits(max_continuation_frames, max_header_block_bytes);
+    }
+
+    /// Check that the first bytes from the peer are not an HTTP/1.x response
+    pub fn set_detect_http_1_response(&mut self) {
+        self.framed_read.set_detect_http_1_response();
+    }
+
+    /// Apply our new SETTINGS_MAX_HEADER_LIST_SIZE
+    pub fn set_max_header_list_size(&mut self, max_header_list_size: u32) {
+        self.max_header_list_size = max_header_list_size;
+    }
+
+    /// HPACK decoder dynamic table counters
+    pub fn hpack_stats(&self) -> hpack::DynamicTableStats {
+        self.decoder.dynamic_table_stats()
+    }
+
+    /// Bytes held by the reader: HPACK decoder dynamic table,
+    /// read buffer and header block waiting for CONTINUATION
+    pub fn buffered_bytes(&self) -> usize {
+        self.decoder.dynamic_table_size() + self.framed_read.buffered_bytes()
+    }
+
+    /// Total number of bytes read from the socket
+    pub fn bytes_read(&self) -> u64 {
+        self.framed_read.bytes_read()
+    }
+
+    /// Frames read from the socket by type
+    pub fn frames_read(&self) -> &FrameCounts {
+        self.framed_read.frames_read()
+    }
+
+    /// Decode header block of HEADERS or PUSH_PROMISE,
+    /// error code of connection error on failure
+    fn decode_headers(&mut self, header_fragment: &[u8]) -> Result<Headers, ErrorCode> {
+        let max_header_list_size = self.max_header_list_size as usize;
+        let headers = match self
+            .decoder
+            .decode_with_limit(header_fragment, max_header_list_size)
+        {
+            Err(hpack::decoder::DecoderError::HeaderListSizeExceeded) => {
+                // peer ignored our SETTINGS_MAX_HEADER_LIST_SIZE,
+                // HPACK state is lost, so this is a connection error
+                warn!("header list size exceeds {}", max_header_list_size);
+                return Err(ErrorCode::ProtocolError);
+            }
+            Err(e) => {
+                warn!("failed to decode headers: {:?}", e);
+                return Err(ErrorCode::CompressionError);
+            }
+            Ok(headers) => headers,
+        };
+
+        Ok(Headers(headers.into_iter().map(|h| Header::new(h.0, h.1)).collect()))
+    }
+
     pub fn poll_http_frame(
         &mut self,
         max_frame_size: u32,
     ) -> Poll<HttpFrameDecodedOrGoaway, error::Error> {
         let frame = match self.framed_read.poll_http_frame(max_frame_size)? {
-            Async::Ready(frame) => frame,
+            Async::Ready(HttpFrameJoinedOrGoaway::Frame(frame)) => frame,
+            Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(error_code)) => {
+                return Ok(Async::Ready(HttpFrameDecodedOrGoaway::SendGoaway(
+                    error_code,
+                )));
+            }
             Async::NotReady => return Ok(Async::NotReady),
         };
         Ok(Async::Ready(HttpFrameDecodedOrGoaway::Frame(match frame {
             HttpFrame::Data(frame) => HttpFrameDecoded::Data(frame),
             HttpFrame::Headers(frame) => {
-                let headers = match self.decoder.decode(&frame.header_fragment()) {
-                    Err(e) => {
-                        warn!("failed to decode headers: {:?}", e);
+                let headers = match self.decode_headers(&frame.header_fragment()) {
+                    Ok(headers) => headers,
+                    Err(error_code) => {
                         return Ok(Async::Ready(HttpFrameDecodedOrGoaway::SendGoaway(
-                            ErrorCode::CompressionError,
+                            error_code,
                         )));
                     }
-                    Ok(headers) => headers,
                 };
 
-                let headers = Headers(headers.into_iter().map(|h| Header::new(h.0, h.1)).collect());
-
                 HttpFrameDecoded::Headers(HeadersDecodedFrame {
                     flags: frame.flags,
                     stream_id: frame.stream_id,
@@ -64,7 +144,24 @@ impl<R: AsyncRead> HttpDecodeRead<R> {
             HttpFrame::Priority(frame) => HttpFrameDecoded::Priority(frame),
             HttpFrame::RstStream(frame) => HttpFrameDecoded::RstStream(frame),
             HttpFrame::Settings(frame) => HttpFrameDecoded::Settings(frame),
-            HttpFrame::PushPromise(frame) => HttpFrameDecoded::PushPromise(frame),
+            HttpFrame::PushPromise(frame) => {
+                // decoded even if push is rejected to keep HPACK state in sync
+                let headers = match self.decode_headers(&frame.header_fragment) {
+                    Ok(headers) => headers,
+                    Err(error_code) => {
+                        return Ok(Async::Ready(HttpFrameDecodedOrGoaway::SendGoaway(
+                            error_code,
+                        )));
+                    }
+                };
+
+                HttpFrameDecoded::PushPromise(PushPromiseDecodedFrame {
+                    flags: frame.flags,
+                    stream_id: frame.stream_id,
+                    promised_stream_id: frame.promised_stream_id,
+                    headers,
+                })
+            }
             HttpFrame::Ping(frame) => HttpFrameDecoded::Ping(frame),
             HttpFrame::Goaway(frame) => HttpFrameDecoded::Goaway(frame),
             HttpFrame::WindowUpdate(frame) => HttpFrameDecoded::WindowUpdate(frame),
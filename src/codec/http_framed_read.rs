@@ -7,8 +7,10 @@ use futures::Poll;
 use solicit::frame::headers::HeadersFlag;
 use solicit::frame::push_promise::PushPromiseFlag;
 use solicit::frame::unpack_header_from_slice;
+use solicit::frame::FrameCounts;
 use solicit::frame::HeadersFrame;
 use solicit::frame::HttpFrame;
+use solicit::frame::ParseFrameError;
 use solicit::frame::PushPromiseFrame;
 use solicit::frame::RawFrame;
 use solicit::frame::FRAME_HEADER_LEN;
@@ -20,6 +22,12 @@ use ErrorCode;
 pub struct HttpFramedRead<R: AsyncRead> {
     read: R,
     buf: BytesMut,
+    /// Total number of bytes read from the socket
+    bytes_read: u64,
+    /// Frames read from the socket by type
+    frames_read: FrameCounts,
+    /// Fail with `Error::NotHttp2` if the first frame is an HTTP/1.x status line
+    detect_http_1_response: bool,
 }
 
 impl<R: AsyncRead> HttpFramedRead<R> {
@@ -27,9 +35,30 @@ impl<R: AsyncRead> HttpFramedRead<R> {
         HttpFramedRead {
             read,
             buf: BytesMut::new(),
+            bytes_read: 0,
+            frames_read: FrameCounts::default(),
+            detect_http_1_response: false,
         }
     }
 
+    /// Check that the first bytes from the peer are not an HTTP/1.x response
+    pub fn set_detect_http_1_response(&mut self) {
+        self.detect_http_1_response = true;
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Bytes read from the socket but not yet parsed into frames
+    pub fn buffer_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn frames_read(&self) -> &FrameCounts {
+        &self.frames_read
+    }
+
     fn fill_buf(&mut self) -> Result<Async<()>, error::Error> {
         self.buf.reserve(8192);
         let n = match self.read.read_buf(&mut self.buf)? {
@@ -37,8 +66,12 @@ impl<R: AsyncRead> HttpFramedRead<R> {
             Async::NotReady => return Ok(Async::NotReady),
         };
         if n == 0 {
+            if self.buf.is_empty() {
+                return Err(error::Error::EofFromPeer);
+            }
             return Err(error::Error::Other("EOF from stream"));
         }
+        self.bytes_read += n as u64;
         Ok(Async::Ready(()))
     }
 
@@ -56,6 +89,14 @@ impl<R: AsyncRead> HttpFramedRead<R> {
             return Ok(Async::NotReady);
         }
 
+        if self.detect_http_1_response {
+            self.detect_http_1_response = false;
+            if self.buf.starts_with(b"HTTP/1.") {
+                warn!("peer responded with HTTP/1.x");
+                return Err(error::Error::NotHttp2(self.buf.take().freeze()));
+            }
+        }
+
         let header = {
             let header = &self.buf[..FRAME_HEADER_LEN];
             unpack_header_from_slice(header)
@@ -75,6 +116,8 @@ impl<R: AsyncRead> HttpFramedRead<R> {
             return Ok(Async::NotReady);
         }
 
+        self.frames_read.add(header.frame_type);
+
         Ok(Async::Ready(RawFrame {
             raw_content: self.buf.split_to(total_len).freeze(),
         }))
@@ -122,6 +165,13 @@ impl Continuable {
         }
     }
 
+    fn header_fragment_len(&self) -> usize {
+        match *self {
+            Continuable::Headers(ref headers) => headers.header_fragment.len(),
+            Continuable::PushPromise(ref push_promise) => push_promise.header_fragment.len(),
+        }
+    }
+
     fn get_stream_id(&self) -> StreamId {
         match self {
             &Continuable::Headers(ref headers) => headers.stream_id,
@@ -130,10 +180,18 @@ impl Continuable {
     }
 }
 
+pub enum HttpFrameJoinedOrGoaway {
+    Frame(HttpFrame),
+    SendGoaway(ErrorCode),
+}
+
 pub struct HttpFramedJoinContinuationRead<R: AsyncRead> {
     framed_read: HttpFramedRead<R>,
-    // TODO: check total size is not exceeded some limit
     header_opt: Option<Continuable>,
+    /// CONTINUATION frames joined into `header_opt`
+    continuation_frames: u32,
+    max_continuation_frames: u32,
+    max_header_block_bytes: usize,
 }
 
 impl<R: AsyncRead> HttpFramedJoinContinuationRead<R> {
@@ -141,41 +199,114 @@ impl<R: AsyncRead> HttpFramedJoinContinuationRead<R> {
         HttpFramedJoinContinuationRead {
             framed_read: HttpFramedRead::new(read),
             header_opt: None,
+            continuation_frames: 0,
+            max_continuation_frames: u32::MAX,
+            max_header_block_bytes: usize::MAX,
         }
     }
 
-    pub fn poll_http_frame(&mut self, max_frame_size: u32) -> Poll<HttpFrame, error::Error> {
+    /// Limit number of CONTINUATION frames and total fragment size of a header block
+    pub fn set_continuation_limits(
+        &mut self,
+        max_continuation_frames: u32,
+        max_header_block_bytes: u32,
+    ) {
+        self.max_continuation_frames = max_continuation_frames;
+        self.max_header_block_bytes = max_header_block_bytes as usize;
+    }
+
+    /// Check that the first bytes from the peer are not an HTTP/1.x response
+    pub fn set_detect_http_1_response(&mut self) {
+        self.framed_read.set_detect_http_1_response();
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.framed_read.bytes_read()
+    }
+
+    /// Read buffer and header fragments waiting for CONTINUATION
+    pub fn buffered_bytes(&self) -> usize {
+        self.framed_read.buffer_len()
+            + self
+                .header_opt
+                .as_ref()
+                .map(|h| h.header_fragment_len())
+                .unwrap_or(0)
+    }
+
+    pub fn frames_read(&self) -> &FrameCounts {
+        self.framed_read.frames_read()
+    }
+
+    pub fn poll_http_frame(
+        &mut self,
+        max_frame_size: u32,
+    ) -> Poll<HttpFrameJoinedOrGoaway, error::Error> {
         loop {
-            let frame = match self.framed_read.poll_http_frame(max_frame_size)? {
-                Async::NotReady => return Ok(Async::NotReady),
-                Async::Ready(frame) => frame,
+            let frame = match self.framed_read.poll_http_frame(max_frame_size) {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(frame)) => frame,
+                // 6.5.2
+                // Values outside this range MUST be treated as a connection error
+                // (Section 5.4.1) of type PROTOCOL_ERROR.
+                Err(error::Error::ParseFrameError(ParseFrameError::IncorrectSettingsMaxFrameSize(
+                    max_frame_size,
+                ))) => {
+                    warn!("incorrect SETTINGS_MAX_FRAME_SIZE: {}", max_frame_size);
+                    return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                        ErrorCode::ProtocolError,
+                    )));
+                }
+                Err(error::Error::ParseFrameError(ParseFrameError::IncorrectSettingsPushValue(
+                    value,
+                ))) => {
+                    warn!("incorrect SETTINGS_ENABLE_PUSH: {}", value);
+                    return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                        ErrorCode::ProtocolError,
+                    )));
+                }
+                Err(e) => return Err(e),
             };
 
+            // 6.10
+            // A HEADERS frame without the END_HEADERS flag set MUST be followed
+            // by a CONTINUATION frame for the same stream.  A receiver MUST
+            // treat the receipt of any other type of frame or a frame on a
+            // different stream as a connection error (Section 5.4.1) of type
+            // PROTOCOL_ERROR.
             match frame {
                 HttpFrame::Headers(h) => {
                     if let Some(_) = self.header_opt {
-                        return Err(error::Error::Other(
-                            "expecting CONTINUATION frame, got HEADERS",
-                        ));
+                        warn!("expecting CONTINUATION frame, got HEADERS");
+                        return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                            ErrorCode::ProtocolError,
+                        )));
                     } else {
                         if h.flags.is_set(HeadersFlag::EndHeaders) {
-                            return Ok(Async::Ready(HttpFrame::Headers(h)));
+                            return Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(
+                                HttpFrame::Headers(h),
+                            )));
                         } else {
                             self.header_opt = Some(Continuable::Headers(h));
+                            self.continuation_frames = 0;
                             continue;
                         }
                     }
                 }
                 HttpFrame::PushPromise(p) => {
                     if let Some(_) = self.header_opt {
-                        return Err(error::Error::Other(
-                            "expecting CONTINUATION frame, got PUSH_PROMISE",
-                        ));
+                        warn!("expecting CONTINUATION frame, got PUSH_PROMISE");
+                        return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                            ErrorCode::ProtocolError,
+                        )));
                     } else {
                         if p.flags.is_set(PushPromiseFlag::EndHeaders) {
-                            return Ok(Async::Ready(HttpFrame::PushPromise(p)));
+                            return Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(
+                                HttpFrame::PushPromise(p),
+                            )));
                         } else {
                             self.header_opt = Some(Continuable::PushPromise(p));
+                            self.continuation_frames = 0;
                             continue;
                         }
                     }
@@ -183,32 +314,271 @@ impl<R: AsyncRead> HttpFramedJoinContinuationRead<R> {
                 HttpFrame::Continuation(c) => {
                     if let Some(mut h) = self.header_opt.take() {
                         if h.get_stream_id() != c.stream_id {
-                            return Err(error::Error::Other(
-                                "CONTINUATION frame with different stream id",
-                            ));
+                            warn!(
+                                "CONTINUATION frame with different stream id: {} != {}",
+                                c.stream_id,
+                                h.get_stream_id()
+                            );
+                            return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                                ErrorCode::ProtocolError,
+                            )));
                         } else {
+                            self.continuation_frames += 1;
+                            if self.continuation_frames > self.max_continuation_frames {
+                                warn!(
+                                    "too many CONTINUATION frames for stream {}",
+                                    c.stream_id
+                                );
+                                return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                                    ErrorCode::EnhanceYourCalm,
+                                )));
+                            }
+                            if h.header_fragment_len() + c.header_fragment.len()
+                                > self.max_header_block_bytes
+                            {
+                                warn!("header block is too large for stream {}", c.stream_id);
+                                return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                                    ErrorCode::EnhanceYourCalm,
+                                )));
+                            }
                             let header_end = c.is_headers_end();
                             h.extend_header_fragment(c.header_fragment);
                             if header_end {
                                 h.set_end_headers();
-                                return Ok(Async::Ready(h.into_frame()));
+                                return Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(
+                                    h.into_frame(),
+                                )));
                             } else {
                                 self.header_opt = Some(h);
                                 continue;
                             }
                         }
                     } else {
-                        return Err(error::Error::Other("CONTINUATION frame without headers"));
+                        warn!("CONTINUATION frame without headers");
+                        return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                            ErrorCode::ProtocolError,
+                        )));
                     }
                 }
                 f => {
                     if let Some(_) = self.header_opt {
-                        return Err(error::Error::Other("expecting CONTINUATION frame"));
+                        warn!("expecting CONTINUATION frame, got {:?}", f.frame_type());
+                        return Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(
+                            ErrorCode::ProtocolError,
+                        )));
                     } else {
-                        return Ok(Async::Ready(f));
+                        return Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(f)));
                     }
                 }
             };
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io;
+
+    use solicit::frame::continuation::ContinuationFlag;
+    use solicit::frame::continuation::ContinuationFrame;
+    use solicit::frame::flags::Flags;
+    use solicit::frame::settings::HttpSetting;
+    use solicit::frame::DataFrame;
+    use solicit::frame::FrameIR;
+    use solicit::frame::GoawayFrame;
+    use solicit::frame::PingFrame;
+    use solicit::frame::RstStreamFrame;
+    use solicit::frame::SettingsFrame;
+    use solicit::frame::WindowUpdateFrame;
+    use solicit::DEFAULT_SETTINGS;
+
+    /// Returns at most one byte per read, alternating with `WouldBlock`
+    struct OneByteRead {
+        data: Vec<u8>,
+        pos: usize,
+        would_block: bool,
+    }
+
+    impl io::Read for OneByteRead {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.would_block = !self.would_block;
+            if self.would_block {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            if self.pos == self.data.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.data[self.pos];
+            self.pos += 1;
+            Ok(1)
+        }
+    }
+
+    impl AsyncRead for OneByteRead {}
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let mut headers = HeadersFrame::new(Bytes::from("abc"), 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+
+        let push_promise = PushPromiseFrame {
+            flags: Flags::new(PushPromiseFlag::EndHeaders as u8),
+            stream_id: 1,
+            promised_stream_id: 2,
+            header_fragment: Bytes::from("de"),
+            padding_len: 0,
+        };
+
+        let frames: Vec<HttpFrame> = vec![
+            HttpFrame::Settings(SettingsFrame::from_settings(vec![
+                HttpSetting::MaxConcurrentStreams(10),
+            ])),
+            HttpFrame::Settings(SettingsFrame::new_ack()),
+            HttpFrame::Headers(headers),
+            HttpFrame::Data(DataFrame::with_data(1, Bytes::from(vec![17; 1000]))),
+            HttpFrame::Data(DataFrame::new(1)),
+            HttpFrame::PushPromise(push_promise),
+            HttpFrame::RstStream(RstStreamFrame::new(3, ErrorCode::Cancel)),
+            HttpFrame::Ping(PingFrame::with_data(0x0102030405060708)),
+            HttpFrame::WindowUpdate(WindowUpdateFrame::for_connection(100)),
+            HttpFrame::Goaway(GoawayFrame::with_debug_data(
+                5,
+                ErrorCode::NoError,
+                Bytes::from("bye"),
+            )),
+        ];
+
+        let mut data = Vec::new();
+        for frame in frames.clone() {
+            data.extend(frame.serialize_into_vec());
+        }
+        let len = data.len();
+
+        let mut read = HttpFramedJoinContinuationRead::new(OneByteRead {
+            data,
+            pos: 0,
+            would_block: false,
+        });
+
+        for expected in frames {
+            let frame = loop {
+                match read.poll_http_frame(DEFAULT_SETTINGS.max_frame_size) {
+                    Ok(Async::NotReady) => continue,
+                    Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(frame))) => break frame,
+                    Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(e))) => {
+                        panic!("unexpected GOAWAY: {:?}", e)
+                    }
+                    Err(e) => panic!("{:?}", e),
+                }
+            };
+            assert_eq!(expected, frame);
+        }
+
+        assert_eq!(len as u64, read.bytes_read());
+    }
+
+    #[test]
+    fn one_byte_at_a_time_continuation() {
+        let headers = HeadersFrame::new(Bytes::from("ab"), 1);
+        let continuation1 = ContinuationFrame::new(Bytes::from("cd"), 1);
+        let mut continuation2 = ContinuationFrame::new(Bytes::from("ef"), 1);
+        continuation2.set_flag(ContinuationFlag::EndHeaders);
+
+        let mut data = Vec::new();
+        data.extend(headers.serialize_into_vec());
+        data.extend(continuation1.serialize_into_vec());
+        data.extend(continuation2.serialize_into_vec());
+
+        let mut read = HttpFramedJoinContinuationRead::new(OneByteRead {
+            data,
+            pos: 0,
+            would_block: false,
+        });
+
+        let frame = loop {
+            match read.poll_http_frame(DEFAULT_SETTINGS.max_frame_size) {
+                Ok(Async::NotReady) => continue,
+                Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(frame))) => break frame,
+                Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(e))) => {
+                    panic!("unexpected GOAWAY: {:?}", e)
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        };
+
+        match frame {
+            HttpFrame::Headers(h) => {
+                assert_eq!(1, h.stream_id);
+                assert!(h.flags.is_set(HeadersFlag::EndHeaders));
+                assert_eq!(&b"abcdef"[..], &h.header_fragment[..]);
+            }
+            f => panic!("expecting HEADERS, got {:?}", f),
+        }
+    }
+
+    #[test]
+    fn detect_http_1_response() {
+        let response = b"HTTP/1.1 400 Bad Request\r\n\r\n";
+        let mut read = HttpFramedRead::new(OneByteRead {
+            data: response.to_vec(),
+            pos: 0,
+            would_block: false,
+        });
+        read.set_detect_http_1_response();
+
+        loop {
+            match read.poll_http_frame(DEFAULT_SETTINGS.max_frame_size) {
+                Ok(Async::NotReady) => continue,
+                Ok(Async::Ready(f)) => panic!("unexpected frame: {:?}", f),
+                Err(error::Error::NotHttp2(bytes)) => {
+                    assert_eq!(&response[..FRAME_HEADER_LEN], &bytes[..]);
+                    break;
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    fn poll_goaway(continuations: &[&'static str], max_frames: u32, max_bytes: u32) -> ErrorCode {
+        let mut data = HeadersFrame::new(Bytes::from("ab"), 1).serialize_into_vec();
+        for fragment in continuations {
+            data.extend(ContinuationFrame::new(Bytes::from(*fragment), 1).serialize_into_vec());
+        }
+
+        let mut read = HttpFramedJoinContinuationRead::new(OneByteRead {
+            data,
+            pos: 0,
+            would_block: false,
+        });
+        read.set_continuation_limits(max_frames, max_bytes);
+
+        loop {
+            match read.poll_http_frame(DEFAULT_SETTINGS.max_frame_size) {
+                Ok(Async::NotReady) => continue,
+                Ok(Async::Ready(HttpFrameJoinedOrGoaway::Frame(f))) => {
+                    panic!("unexpected frame: {:?}", f)
+                }
+                Ok(Async::Ready(HttpFrameJoinedOrGoaway::SendGoaway(e))) => return e,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn max_continuation_frames() {
+        assert_eq!(
+            ErrorCode::EnhanceYourCalm,
+            poll_goaway(&["", "", "", ""], 3, 1000)
+        );
+    }
+
+    #[test]
+    fn max_header_block_bytes() {
+        assert_eq!(
+            ErrorCode::EnhanceYourCalm,
+            poll_goaway(&["cd", "ef", "gh"], 10, 5)
+        );
+    }
+}
@@ -5,11 +5,18 @@ use bytes::Buf;
 use codec::write_buffer::WriteBuffer;
 use futures::Async;
 use futures::Poll;
+use solicit::frame::unpack_header_from_slice;
+use solicit::frame::FrameCounts;
 use solicit::frame::FrameIR;
+use solicit::frame::FRAME_HEADER_LEN;
 
 pub struct HttpFramedWrite<W: AsyncWrite> {
     write: W,
     buf: WriteBuffer,
+    /// Total number of bytes written to the socket
+    bytes_written: u64,
+    /// Frames buffered for writing by type
+    frames_written: FrameCounts,
 }
 
 impl<W: AsyncWrite> HttpFramedWrite<W> {
@@ -17,9 +24,19 @@ impl<W: AsyncWrite> HttpFramedWrite<W> {
         HttpFramedWrite {
             write,
             buf: WriteBuffer::new(),
+            bytes_written: 0,
+            frames_written: FrameCounts::default(),
         }
     }
 
+    pub fn frames_written(&self) -> &FrameCounts {
+        &self.frames_written
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
     pub fn data_len(&self) -> usize {
         self.buf.remaining()
     }
@@ -27,7 +44,16 @@ impl<W: AsyncWrite> HttpFramedWrite<W> {
     pub fn buffer_frame<F: FrameIR>(&mut self, frame: F) {
         debug!("send {:?}", frame);
 
+        let start = self.buf.remaining();
         frame.serialize_into(&mut self.buf);
+
+        // frame may be serialized as HEADERS followed by CONTINUATION frames
+        let mut pos = start;
+        while pos < self.buf.remaining() {
+            let header = unpack_header_from_slice(&self.buf.bytes()[pos..pos + FRAME_HEADER_LEN]);
+            self.frames_written.add(header.frame_type);
+            pos += FRAME_HEADER_LEN + header.payload_len as usize;
+        }
     }
 
     pub fn poll_flush(&mut self) -> Poll<(), error::Error> {
@@ -36,8 +62,9 @@ impl<W: AsyncWrite> HttpFramedWrite<W> {
                 return Ok(Async::Ready(()));
             }
 
-            if let Async::NotReady = self.write.write_buf(&mut self.buf)? {
-                return Ok(Async::NotReady);
+            match self.write.write_buf(&mut self.buf)? {
+                Async::Ready(n) => self.bytes_written += n as u64,
+                Async::NotReady => return Ok(Async::NotReady),
             }
         }
     }
@@ -1,6 +1,7 @@
 use codec::http_framed_write::HttpFramedWrite;
 use error;
 use futures::Poll;
+use solicit::frame::FrameCounts;
 use solicit::frame::FrameIR;
 use solicit::frame::GoawayFrame;
 use tokio_io::AsyncWrite;
@@ -23,6 +24,16 @@ impl<W: AsyncWrite> QueuedWrite<W> {
         self.framed_write.data_len()
     }
 
+    /// Total number of bytes written to the socket
+    pub fn bytes_written(&self) -> u64 {
+        self.framed_write.bytes_written()
+    }
+
+    /// Frames queued for writing by type
+    pub fn frames_written(&self) -> &FrameCounts {
+        self.framed_write.frames_written()
+    }
+
     pub fn queued_empty(&self) -> bool {
         self.queued_bytes_len() == 0
     }
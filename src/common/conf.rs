@@ -1,8 +1,131 @@
+use std::cmp;
+
+use futures::sync::mpsc::UnboundedSender;
+
+use common::conn_error_event::ConnErrorEvent;
+use solicit::frame::settings::RawSettingsFrame;
+use solicit::DEFAULT_SETTINGS;
+use solicit::MAX_WINDOW_SIZE;
+
+/// What to do when peer closes its side of the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnReadEof {
+    /// Fail streams still waiting for peer data, finish sending
+    /// to streams completed by peer, then close the connection
+    FinishWrites,
+    /// Fail all streams and close the connection immediately
+    #[default]
+    CloseImmediately,
+}
+
+/// When to send WINDOW_UPDATE for received data consumed by the application.
+///
+/// Applies to both connection and stream windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowUpdateStrategy {
+    /// Increment by the initial window size when less than half of the window is left
+    #[default]
+    HalfWindow,
+    /// Restore the initial window size when at least this number of bytes is consumed,
+    /// threshold larger than the window is treated as the window size
+    Threshold(u32),
+}
+
+impl WindowUpdateStrategy {
+    /// WINDOW_UPDATE increment for window of `window_size` with `current` bytes left
+    pub(crate) fn increment(&self, window_size: u32, current: i64) -> Option<u32> {
+        let consumed = window_size as i64 - current;
+        if consumed <= 0 {
+            return None;
+        }
+        match *self {
+            WindowUpdateStrategy::HalfWindow if current < (window_size / 2) as i64 => {
+                Some(cmp::min(window_size as i64, MAX_WINDOW_SIZE as i64 - current) as u32)
+            }
+            WindowUpdateStrategy::Threshold(threshold)
+                if consumed >= cmp::min(threshold, window_size) as i64 =>
+            {
+                Some(consumed as u32)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
-pub struct CommonConf {}
+pub struct CommonConf {
+    /// Send this initial SETTINGS frame as is instead of the one built from config.
+    ///
+    /// For interop testing only: parameters are not validated.
+    #[doc(hidden)]
+    pub initial_settings_for_test: Option<RawSettingsFrame>,
+
+    /// Send stream and connection errors of each connection to this channel,
+    /// events are dropped if receiver is closed.
+    pub error_events: Option<UnboundedSender<ConnErrorEvent>>,
+
+    /// Stop sending stream WINDOW_UPDATE while more than this number of received
+    /// bytes is waiting to be consumed by the application.
+    /// Disabled by default.
+    pub stream_queue_high_water: Option<u32>,
+    /// Resume sending stream WINDOW_UPDATE when number of bytes waiting
+    /// to be consumed drops below this, default is half of high-water mark.
+    pub stream_queue_low_water: Option<u32>,
+
+    /// When to send connection and stream WINDOW_UPDATE, default is `HalfWindow`.
+    pub window_update_strategy: Option<WindowUpdateStrategy>,
+    /// Connection receive window size. Window larger than protocol default
+    /// of 65535 is announced with WINDOW_UPDATE after initial SETTINGS,
+    /// smaller values are ignored because connection window cannot shrink.
+    pub conn_initial_window_size: Option<u32>,
+
+    /// Close connection with GOAWAY(ENHANCE_YOUR_CALM) when peer sends more
+    /// CONTINUATION frames for a single header block, default is 128.
+    pub max_continuation_frames: Option<u32>,
+    /// Close connection with GOAWAY(ENHANCE_YOUR_CALM) when header block
+    /// fragments of HEADERS or PUSH_PROMISE and its CONTINUATION frames
+    /// exceed this number of bytes before HPACK decoding, default is 1 MiB.
+    pub max_header_block_bytes: Option<u32>,
+}
 
 impl CommonConf {
     pub fn new() -> CommonConf {
         Default::default()
     }
+
+    pub(crate) fn max_continuation_frames(&self) -> u32 {
+        self.max_continuation_frames.unwrap_or(128)
+    }
+
+    pub(crate) fn max_header_block_bytes(&self) -> u32 {
+        self.max_header_block_bytes.unwrap_or(1 << 20)
+    }
+
+    pub(crate) fn conn_initial_window_size(&self) -> u32 {
+        let size = self
+            .conn_initial_window_size
+            .unwrap_or(DEFAULT_SETTINGS.initial_window_size);
+        size.clamp(DEFAULT_SETTINGS.initial_window_size, MAX_WINDOW_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_update_strategy() {
+        let half = WindowUpdateStrategy::HalfWindow;
+        assert_eq!(None, half.increment(100, 100));
+        assert_eq!(None, half.increment(100, 50));
+        assert_eq!(Some(100), half.increment(100, 49));
+
+        let threshold = WindowUpdateStrategy::Threshold(30);
+        assert_eq!(None, threshold.increment(100, 71));
+        assert_eq!(Some(30), threshold.increment(100, 70));
+        assert_eq!(Some(90), threshold.increment(100, 10));
+        // threshold larger than window
+        assert_eq!(None, WindowUpdateStrategy::Threshold(1000).increment(100, 1));
+        assert_eq!(Some(100), WindowUpdateStrategy::Threshold(1000).increment(100, 0));
+    }
 }
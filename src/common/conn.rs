@@ -1,4 +1,11 @@
+use std::cmp;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use futures::sync::mpsc::UnboundedSender;
 
@@ -20,6 +27,10 @@ use solicit::DEFAULT_SETTINGS;
 
 use super::closed_streams::*;
 use super::conf::*;
+use super::conn_error_event::ConnErrorEvent;
+use super::window_accounting::WindowAccounting;
+use super::conn_metrics::*;
+use super::priority_tree::PriorityTree;
 use super::pump_stream_to_write_loop::PumpStreamToWrite;
 use super::stream::*;
 use super::stream_from_network::StreamFromNetwork;
@@ -39,6 +50,8 @@ use common::conn_read::ConnReadSideCustom;
 use common::conn_write::ConnWriteSideCustom;
 use common::init_where::InitWhere;
 use common::iteration_exit::IterationExit;
+use common::keepalive::Keepalive;
+use common::keepalive::KeepaliveAction;
 use data_or_headers_with_flag::DataOrHeadersWithFlagStream;
 use futures::future;
 use futures::sync::oneshot;
@@ -47,6 +60,7 @@ use futures::Async;
 use futures::Future;
 use futures::Poll;
 use hpack;
+use hpack::DynamicTableStats;
 use solicit::WindowSize;
 use solicit_async::HttpFutureStreamSend;
 use std::collections::HashSet;
@@ -72,20 +86,38 @@ pub struct Conn<T: Types> {
     pub streams: StreamMap<T>,
     /// Last streams known to be closed by peer
     pub peer_closed_streams: ClosedStreams,
+    /// Streams reset by us with CANCEL, frames peer sent
+    /// before it received RST_STREAM are ignored
+    pub cancelled_streams: ClosedStreams,
 
     /// Window size from pumper point of view
     pub pump_out_window_size: window_size::ConnOutWindowSender,
 
     pub last_local_stream_id: StreamId,
     pub last_peer_stream_id: StreamId,
+    /// Last peer stream refused after GOAWAY, not counted in `last_peer_stream_id`
+    pub last_refused_stream_id: StreamId,
     pub goaway_sent: Option<GoawayFrame>,
     pub goaway_received: Option<GoawayFrame>,
+    /// Opaque data of outstanding PING requested with `ConnControl::Ping`
     pub ping_sent: Option<u64>,
+    /// When outstanding PING was sent
+    pub ping_sent_at: Option<Instant>,
+    /// Round-trip time of the last acknowledged PING
+    pub ping_rtt: Option<Duration>,
+    /// Opaque data of the last PING sent, unique within connection
+    pub last_ping_data: u64,
 
     /// Tracks the size of the outbound flow control window
     pub out_window_size: WindowSize,
     /// Tracks the size of the inbound flow control window
     pub in_window_size: WindowSize,
+    /// Inbound connection window restored by WINDOW_UPDATE
+    pub in_window_size_target: u32,
+    /// When to send WINDOW_UPDATE
+    pub window_update_strategy: WindowUpdateStrategy,
+    /// Flow control totals to check connection windows in debug builds
+    pub window_accounting: WindowAccounting,
 
     pub framed_read: HttpDecodeRead<ReadHalf<T::Io>>,
 
@@ -103,23 +135,117 @@ pub struct Conn<T: Types> {
     pub peer_settings: HttpSettings,
     /// Last our settings acknowledged
     pub our_settings_ack: HttpSettings,
-    /// Last our settings sent
-    pub our_settings_sent: Option<HttpSettings>,
+    /// Our settings sent but not yet acknowledged, oldest first,
+    /// with senders notified when peer acknowledges them
+    pub our_settings_sent: VecDeque<(HttpSettings, Option<oneshot::Sender<result::Result<()>>>)>,
+
+    /// Send GOAWAY when connection transferred more than this number of bytes
+    pub max_connection_bytes: Option<u64>,
+    /// Send GOAWAY when `memory_usage` exceeds this number of bytes
+    pub max_connection_memory: Option<usize>,
+
+    /// Split outgoing DATA into frames not larger than this,
+    /// peer SETTINGS_MAX_FRAME_SIZE is used if it is smaller
+    pub out_data_frame_max_size: Option<u32>,
+
+    /// Do not read from the socket until resumed
+    pub reads_paused: bool,
+
+    /// Behavior on EOF from peer
+    pub on_read_eof: OnReadEof,
+    /// EOF received from peer, connection is closed when writes are finished
+    pub read_eof: bool,
+
+    /// High and low water marks of stream receive queue:
+    /// stream WINDOW_UPDATE is deferred while application does not consume data
+    pub stream_queue_water: Option<(u32, u32)>,
+    /// WINDOW_UPDATE increments deferred by streams with full receive queue
+    pub deferred_window_update_bytes: Arc<AtomicUsize>,
+
+    /// Periodic PING to keep idle connection alive in intermediaries
+    pub nat_keepalive: Option<Keepalive>,
+    /// Periodic PING regardless of connection activity
+    pub keepalive: Option<Keepalive>,
+
+    /// Close connection if peer initial SETTINGS is not received before timeout fires
+    pub preface_timeout: Option<reactor::Timeout>,
+
+    /// Order writes by peer stream priorities, writable streams
+    /// are ordered by own weight when not set
+    pub priority_tree: Option<PriorityTree>,
+
+    /// Channel for stream and connection error events
+    pub error_events: Option<UnboundedSender<ConnErrorEvent>>,
+
+    /// Counters published to `metrics` after each event loop iteration
+    pub streams_opened: u64,
+    pub rst_sent: u64,
+    pub rst_received: u64,
+    pub max_concurrent_streams_violations: u64,
+    pub metrics: Arc<ConnMetricsCounters>,
 }
 
+/// Connection state returned by `dump_state` of client or server
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ConnStateSnapshot {
     pub in_window_size: i32,
     pub out_window_size: i32,
-    pub streams: HashMap<StreamId, HttpStreamStateSnapshot>,
+    pub streams: HashMap<StreamId, StreamStateSnapshot>,
+    /// Connection receive window restored by WINDOW_UPDATE
+    pub in_window_size_target: u32,
+    /// Initial receive window of new streams, our acknowledged SETTINGS_INITIAL_WINDOW_SIZE
+    pub stream_in_window_size: u32,
+    /// Initial send window of new streams, peer SETTINGS_INITIAL_WINDOW_SIZE
+    pub stream_out_window_size: u32,
+    /// When WINDOW_UPDATE is sent
+    pub window_update_strategy: WindowUpdateStrategy,
+    /// Bytes of frames waiting to be written to the socket
+    pub queued_write_bytes: usize,
+    /// HPACK encoder dynamic table counters
+    pub hpack_encoder_stats: DynamicTableStats,
+    /// HPACK decoder dynamic table counters
+    pub hpack_decoder_stats: DynamicTableStats,
+    /// Round-trip time of the last acknowledged PING
+    pub ping_rtt: Option<Duration>,
+    /// Stream WINDOW_UPDATE increments not sent because receive queues are full
+    pub deferred_window_update_bytes: usize,
+    /// Bytes read from the socket
+    pub bytes_read: u64,
+    /// Bytes written to the socket
+    pub bytes_written: u64,
+    pub goaway_sent: bool,
+    pub goaway_received: bool,
+    /// Frames written to the socket by type
+    pub frames_sent: FrameCounts,
+    /// Frames read from the socket by type
+    pub frames_received: FrameCounts,
+    /// Our last SETTINGS is not yet acknowledged by the peer
+    pub settings_ack_pending: bool,
 }
 
 impl ConnStateSnapshot {
-    pub fn single_stream(&self) -> (u32, &HttpStreamStateSnapshot) {
-        let mut iter = self.streams.iter();
-        let (&id, stream) = iter.next().expect("no streams");
-        assert!(iter.next().is_none(), "more than one stream");
-        (id, stream)
+    /// Total bytes waiting to be sent: serialized frames
+    /// and stream data waiting for flow control window.
+    pub fn write_queue_bytes(&self) -> usize {
+        self.queued_write_bytes + self
+            .streams
+            .values()
+            .map(|s| s.out_data_size)
+            .sum::<usize>()
+    }
+
+    /// Streams ordered by id
+    pub fn streams_by_id(&self) -> Vec<(StreamId, &StreamStateSnapshot)> {
+        let mut streams: Vec<_> = self.streams.iter().map(|(&id, s)| (id, s)).collect();
+        streams.sort_by_key(|&(id, _)| id);
+        streams
+    }
+
+    /// The only stream, panics if there are no streams or more than one stream
+    pub fn single_stream(&self) -> (StreamId, &StreamStateSnapshot) {
+        let streams = self.streams_by_id();
+        assert!(streams.len() <= 1, "more than one stream");
+        streams.into_iter().next().expect("no streams")
     }
 }
 
@@ -134,7 +260,7 @@ where
         loop_handle: reactor::Handle,
         exec: CpuPoolOption,
         specific: T::ConnSpecific,
-        _conf: CommonConf,
+        conf: CommonConf,
         sent_settings: HttpSettings,
         to_write_tx: UnboundedSender<T::ToWriteMessage>,
         write_rx: HttpFutureStreamSend<T::ToWriteMessage>,
@@ -147,35 +273,88 @@ where
 
         let pump_window_size = window_size::ConnOutWindowSender::new(out_window_size.0 as u32);
 
-        let framed_read = HttpDecodeRead::new(read);
+        // Peer may use default table size until it acknowledges our SETTINGS
+        let mut framed_read = HttpDecodeRead::new(
+            read,
+            cmp::max(sent_settings.header_table_size, DEFAULT_SETTINGS.header_table_size),
+            sent_settings.max_header_list_size,
+        );
+        framed_read.set_continuation_limits(
+            conf.max_continuation_frames(),
+            conf.max_header_block_bytes(),
+        );
         let queued_write = QueuedWrite::new(write);
 
-        Conn {
+        let stream_queue_water = conf.stream_queue_high_water.map(|high| {
+            let low = conf.stream_queue_low_water.unwrap_or(high / 2);
+            (high, cmp::min(low, high))
+        });
+
+        let error_events = conf.error_events.clone();
+
+        let mut conn = Conn {
             conn_died_error_holder,
             specific,
             to_write_tx,
             streams: StreamMap::new(),
             last_local_stream_id: 0,
             last_peer_stream_id: 0,
+            last_refused_stream_id: 0,
             exec: exec.make_executor(&loop_handle),
             loop_handle,
             goaway_sent: None,
             goaway_received: None,
             ping_sent: None,
+            ping_sent_at: None,
+            ping_rtt: None,
+            last_ping_data: 0,
             pump_out_window_size: pump_window_size,
             peer_closed_streams: ClosedStreams::new(),
+            cancelled_streams: ClosedStreams::new(),
             framed_read,
             queued_write,
             write_rx,
             flush_conn: false,
             encoder: hpack::Encoder::new(),
             in_window_size,
+            in_window_size_target: conf.conn_initial_window_size(),
+            window_update_strategy: conf.window_update_strategy.unwrap_or_default(),
             out_window_size,
+            window_accounting: WindowAccounting::new(DEFAULT_SETTINGS.initial_window_size),
             peer_settings: DEFAULT_SETTINGS,
             our_settings_ack: DEFAULT_SETTINGS,
-            our_settings_sent: Some(sent_settings),
+            our_settings_sent: vec![(sent_settings, None)].into_iter().collect(),
             flush_streams: HashSet::new(),
+            max_connection_bytes: None,
+            max_connection_memory: None,
+            out_data_frame_max_size: None,
+            reads_paused: false,
+            on_read_eof: OnReadEof::default(),
+            read_eof: false,
+            stream_queue_water,
+            deferred_window_update_bytes: Arc::new(AtomicUsize::new(0)),
+            nat_keepalive: None,
+            keepalive: None,
+            preface_timeout: None,
+            error_events,
+            streams_opened: 0,
+            rst_sent: 0,
+            rst_received: 0,
+            max_concurrent_streams_violations: 0,
+            priority_tree: None,
+            metrics: Arc::new(ConnMetricsCounters::new()),
+        };
+
+        // Connection window can only be enlarged with WINDOW_UPDATE
+        let increment = conn.in_window_size_target - DEFAULT_SETTINGS.initial_window_size;
+        if increment > 0 {
+            conn.in_window_size.0 += increment as i32;
+            conn.window_accounting.increment_sent(increment);
+            conn.queued_write
+                .queue_not_goaway(WindowUpdateFrame::for_connection(increment));
         }
+
+        conn
     }
 
     /// Allocate stream id for locally initiated stream
@@ -220,6 +399,7 @@ where
         );
 
         let stream = self.streams.insert(stream_id, stream);
+        self.streams_opened += 1;
 
         (stream, stream_from_network, out_window_receiver)
     }
@@ -235,6 +415,14 @@ where
             stream_id,
             to_write_tx: self.to_write_tx.clone(),
             in_window_size,
+            in_window_size_target: in_window_size,
+            window_update_strategy: self.window_update_strategy,
+            queue_water: self.stream_queue_water,
+            queue_full: false,
+            deferred_window_update: 0,
+            deferred_window_update_total: self.deferred_window_update_bytes.clone(),
+            cancel_on_drop: None,
+            complete: false,
         }
     }
 
@@ -242,18 +430,57 @@ where
         ConnStateSnapshot {
             in_window_size: self.in_window_size.0,
             out_window_size: self.out_window_size.0,
+            in_window_size_target: self.in_window_size_target,
+            stream_in_window_size: self.our_settings_ack.initial_window_size,
+            stream_out_window_size: self.peer_settings.initial_window_size,
+            window_update_strategy: self.window_update_strategy,
             streams: self.streams.snapshot(),
+            queued_write_bytes: self.queued_write.queued_bytes_len(),
+            hpack_encoder_stats: self.encoder.dynamic_table_stats(),
+            hpack_decoder_stats: self.framed_read.hpack_stats(),
+            ping_rtt: self.ping_rtt,
+            deferred_window_update_bytes: self
+                .deferred_window_update_bytes
+                .load(Ordering::SeqCst),
+            bytes_read: self.framed_read.bytes_read(),
+            bytes_written: self.queued_write.bytes_written(),
+            goaway_sent: self.goaway_sent.is_some(),
+            goaway_received: self.goaway_received.is_some(),
+            frames_sent: *self.queued_write.frames_written(),
+            frames_received: *self.framed_read.frames_read(),
+            settings_ack_pending: !self.our_settings_sent.is_empty(),
         }
     }
 
+    /// Same as `dump_state().write_queue_bytes()`
+    pub fn write_queue_bytes(&self) -> usize {
+        self.queued_write.queued_bytes_len() + self
+            .streams
+            .map
+            .values()
+            .map(|s| s.outgoing.data_size())
+            .sum::<usize>()
+    }
+
+    /// Last our settings sent, acknowledged or not
     pub fn our_settings_sent(&self) -> &HttpSettings {
-        if let Some(ref sent) = self.our_settings_sent {
-            &sent
-        } else {
-            &self.our_settings_ack
+        match self.our_settings_sent.back() {
+            Some((sent, _)) => sent,
+            None => &self.our_settings_ack,
         }
     }
 
+    /// Limit HPACK decoder by the largest SETTINGS_HEADER_TABLE_SIZE peer may use:
+    /// lowered size applies only when peer acknowledges SETTINGS.
+    pub fn update_decoder_table_size(&mut self) {
+        let header_table_size = self
+            .our_settings_sent
+            .iter()
+            .map(|(s, _)| s.header_table_size)
+            .fold(self.our_settings_ack.header_table_size, cmp::max);
+        self.framed_read.set_header_table_size(header_table_size);
+    }
+
     /// Internal helper method that decreases the outbound flow control window size.
     fn _decrease_out_window(&mut self, size: u32) -> result::Result<()> {
         // The size by which we decrease the window must be at most 2^31 - 1. We should be able to
@@ -274,7 +501,9 @@ where
         debug_assert!(size < 0x80000000);
         self.in_window_size
             .try_decrease_to_positive(size as i32)
-            .map_err(|_| error::Error::WindowSizeOverflow)
+            .map_err(|_| error::Error::WindowSizeOverflow)?;
+        self.window_accounting.data_received(size);
+        Ok(())
     }
 
     pub fn process_dump_state(
@@ -295,10 +524,33 @@ where
         self.streams.remove_stream(stream_id);
 
         let rst_stream = RstStreamFrame::new(stream_id, error_code);
+        self.rst_sent += 1;
+        self.report_rst_stream_sent(stream_id, error_code);
         self.send_frame_and_notify(rst_stream);
         Ok(())
     }
 
+    /// Send event to `CommonConf::error_events` if configured
+    pub fn report_error_event(&self, event: ConnErrorEvent) {
+        if let Some(ref error_events) = self.error_events {
+            // receiver might be dropped
+            drop(error_events.unbounded_send(event));
+        }
+    }
+
+    pub fn report_rst_stream_sent(&self, stream_id: StreamId, error_code: ErrorCode) {
+        if error_code != ErrorCode::NoError {
+            self.report_error_event(ConnErrorEvent::RstStreamSent(stream_id, error_code));
+        }
+    }
+
+    /// Adjust in windows of all streams after SETTINGS_INITIAL_WINDOW_SIZE change
+    pub fn change_streams_in_window(&mut self, delta: i32) {
+        for s in self.streams.map.values_mut() {
+            s.in_window_size.0 += delta;
+        }
+    }
+
     pub fn send_flow_control_error(&mut self) -> result::Result<()> {
         self.send_goaway(ErrorCode::FlowControlError)
     }
@@ -332,6 +584,9 @@ where
 
         match stream_state {
             StreamState::Idle => {
+                // Receiving any frame other than HEADERS or PRIORITY on a stream
+                // in this state MUST be treated as a connection error
+                // (Section 5.4.1) of type PROTOCOL_ERROR.
                 let send_connection_error = match frame_type {
                     HttpFrameType::Headers
                     | HttpFrameType::Priority
@@ -341,7 +596,7 @@ where
 
                 if send_connection_error {
                     debug!("stream is idle: {}, sending GOAWAY", stream_id);
-                    self.send_goaway(ErrorCode::StreamClosed)?;
+                    self.send_goaway(ErrorCode::ProtocolError)?;
                 }
             }
             StreamState::Open | StreamState::HalfClosedLocal => {}
@@ -397,7 +652,9 @@ where
                 // TODO: http2 spec requires sending stream or connection error
                 // depending on how stream was closed
                 if send_stream_closed {
-                    if self.peer_closed_streams.contains(stream_id) {
+                    if self.cancelled_streams.contains(stream_id) {
+                        debug!("stream is cancelled by us: {}, ignoring frame", stream_id);
+                    } else if self.peer_closed_streams.contains(stream_id) {
                         debug!("stream is closed by peer: {}, sending GOAWAY", stream_id);
                         self.send_goaway(ErrorCode::StreamClosed)?;
                     } else {
@@ -430,13 +687,149 @@ where
         Ok(())
     }
 
-    /// Should we close the connection because of GOAWAY state
+    /// Total number of bytes read from and written to the socket
+    pub fn transferred_bytes(&self) -> u64 {
+        self.framed_read.bytes_read() + self.queued_write.bytes_written()
+    }
+
+    fn check_max_connection_bytes(&mut self) -> result::Result<()> {
+        if let Some(max_connection_bytes) = self.max_connection_bytes {
+            let transferred_bytes = self.transferred_bytes();
+            if transferred_bytes > max_connection_bytes && !self.queued_write.goaway_queued() {
+                warn!(
+                    "connection transferred {} bytes, limit is {}, sending GOAWAY",
+                    transferred_bytes, max_connection_bytes
+                );
+                self.send_goaway(ErrorCode::EnhanceYourCalm)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes held by connection buffers: HPACK tables, read buffer,
+    /// received data not yet consumed by application, and data waiting to be sent
+    pub fn memory_usage(&self) -> usize {
+        let in_data_size = self
+            .streams
+            .map
+            .values()
+            .filter_map(|s| s.peer_tx.as_ref())
+            .map(|tx| tx.data_size())
+            .sum::<usize>();
+        self.framed_read.buffered_bytes()
+            + self.encoder.dynamic_table_size()
+            + in_data_size
+            + self.write_queue_bytes()
+    }
+
+    fn check_max_connection_memory(&mut self) -> result::Result<()> {
+        if let Some(max_connection_memory) = self.max_connection_memory {
+            let memory_usage = self.memory_usage();
+            if memory_usage > max_connection_memory && !self.queued_write.goaway_queued() {
+                warn!(
+                    "connection buffers hold {} bytes, limit is {}, sending GOAWAY",
+                    memory_usage, max_connection_memory
+                );
+                self.send_goaway(ErrorCode::EnhanceYourCalm)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_keepalive(&mut self) -> result::Result<()> {
+        let transferred_bytes = self.transferred_bytes();
+        let now = Instant::now();
+        let mut pings = Vec::new();
+        for keepalive in self.nat_keepalive.iter_mut().chain(self.keepalive.iter_mut()) {
+            match keepalive.poll(transferred_bytes)? {
+                KeepaliveAction::Nothing => {}
+                KeepaliveAction::SendPing => {
+                    self.last_ping_data += 1;
+                    keepalive.ping_sent(self.last_ping_data, now);
+                    pings.push(self.last_ping_data);
+                }
+                KeepaliveAction::Timeout => {
+                    warn!("keepalive PING is not acknowledged in time, closing connection");
+                    return Err(error::Error::KeepaliveTimeout);
+                }
+            }
+        }
+
+        for opaque_data in pings {
+            debug!("sending keepalive PING");
+            self.send_frame_and_notify(PingFrame::with_data(opaque_data));
+        }
+        Ok(())
+    }
+
+    /// Send PING requested with `ConnControl::Ping` to measure round-trip time
+    pub fn send_ping(&mut self) {
+        if self.ping_sent.is_some() {
+            debug!("previous PING is not acknowledged, skipping PING");
+            return;
+        }
+
+        self.last_ping_data += 1;
+        self.ping_sent = Some(self.last_ping_data);
+        self.ping_sent_at = Some(Instant::now());
+        self.send_frame_and_notify(PingFrame::with_data(self.last_ping_data));
+    }
+
+    /// Match PING ACK to outstanding PING and record round-trip time
+    pub fn process_ping_ack(&mut self, opaque_data: u64) -> result::Result<()> {
+        let now = Instant::now();
+        if self.ping_sent == Some(opaque_data) {
+            self.ping_sent = None;
+            self.ping_rtt = self.ping_sent_at.take().map(|sent_at| now - sent_at);
+            return Ok(());
+        }
+
+        let mut outstanding = self.ping_sent.is_some();
+        for keepalive in self.nat_keepalive.iter_mut().chain(self.keepalive.iter_mut()) {
+            if let Some(rtt) = keepalive.ping_ack(opaque_data, now) {
+                self.ping_rtt = Some(rtt);
+                return Ok(());
+            }
+            outstanding |= keepalive.ping_outstanding();
+        }
+
+        if outstanding {
+            Err(error::Error::Other("PING ACK opaque data mismatch"))
+        } else {
+            // RFC 7540 section 6.7: endpoint must not respond to PING ACK,
+            // unsolicited ACK is a protocol violation
+            warn!("PING ACK without PING, sending GOAWAY");
+            self.send_goaway(ErrorCode::ProtocolError)
+        }
+    }
+
+    fn poll_preface_timeout(&mut self) -> result::Result<()> {
+        let fired = match self.preface_timeout {
+            Some(ref mut timeout) => timeout.poll()? != Async::NotReady,
+            None => return Ok(()),
+        };
+
+        if fired {
+            warn!("initial SETTINGS is not received in time, closing connection");
+            return Err(error::Error::Other("preface timeout"));
+        }
+        Ok(())
+    }
+
+    /// Should we close the connection because of GOAWAY state or EOF from peer
     pub fn end_loop(&self) -> bool {
-        let goaway = self.goaway_sent.is_some() || self.goaway_received.is_some();
+        let goaway =
+            self.goaway_sent.is_some() || self.goaway_received.is_some() || self.read_eof;
         let no_streams = self.streams.is_empty();
         goaway && no_streams
     }
 
+    /// Graceful shutdown is complete: all streams are complete
+    /// and their last frames are written to the socket
+    fn drained(&self) -> bool {
+        self.goaway_sent.is_some() && self.end_loop() && self.queued_write.queued_empty()
+    }
+
     pub fn new_pump_stream_to_write_loop(
         &self,
         stream_id: StreamId,
@@ -460,7 +853,8 @@ where
     ) {
         let stream = stream.catch_unwind();
         self.exec.execute(Box::new(
-            self.new_pump_stream_to_write_loop(stream_id, stream, out_window),
+            self.new_pump_stream_to_write_loop(stream_id, stream, out_window)
+                .map(|_| ()),
         ));
     }
 
@@ -487,8 +881,32 @@ where
         }
 
         let write_ready = self.poll_write()? != Async::NotReady;
+
+        if self.drained() {
+            info!("connection drained");
+            return Ok(Async::Ready(()));
+        }
         let read_ready = self.read_process_frame()? != Async::NotReady;
 
+        self.window_accounting
+            .check(self.in_window_size.0, self.out_window_size.0);
+
+        if self.drained() {
+            info!("connection drained");
+            return Ok(Async::Ready(()));
+        }
+
+        if self.read_eof && self.end_loop() {
+            info!("connection drained after EOF from peer");
+            return Ok(Async::Ready(()));
+        }
+
+        self.check_max_connection_bytes()?;
+        self.check_max_connection_memory()?;
+        self.poll_keepalive()?;
+        self.poll_preface_timeout()?;
+        self.poll_timers()?;
+
         Ok(if write_ready || read_ready {
             info!("connection loop complete");
             Async::Ready(())
@@ -497,7 +915,30 @@ where
         })
     }
 
+    fn update_metrics(&mut self) {
+        let open_streams = self.streams.map.len() as u64;
+        let metrics = ConnMetrics {
+            bytes_sent: self.queued_write.bytes_written(),
+            bytes_received: self.framed_read.bytes_read(),
+            streams_opened: self.streams_opened,
+            streams_closed: self.streams_opened - open_streams,
+            open_streams,
+            rst_sent: self.rst_sent,
+            rst_received: self.rst_received,
+            max_concurrent_streams_violations: self.max_concurrent_streams_violations,
+        };
+        self.metrics.store(&metrics);
+        self.metrics_updated(&metrics);
+    }
+
     pub fn run(mut self) -> impl Future<Item = (), Error = error::Error> {
-        future::poll_fn(move || self.poll())
+        future::poll_fn(move || {
+            let r = self.poll();
+            self.update_metrics();
+            if let Err(ref e) = r {
+                self.report_error_event(ConnErrorEvent::Closed(e.to_string()));
+            }
+            r
+        })
     }
 }
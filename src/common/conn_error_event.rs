@@ -0,0 +1,26 @@
+//! Structured connection error events
+
+use bytes::Bytes;
+
+use solicit::StreamId;
+use ErrorCode;
+
+/// Stream or connection error and the action taken,
+/// sent to `CommonConf::error_events`.
+///
+/// RST_STREAM and GOAWAY with `NO_ERROR` are not errors and are not reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnErrorEvent {
+    /// Stream is reset by us, e. g. because of stream flow control
+    /// or protocol violation by peer, or request cancellation
+    RstStreamSent(StreamId, ErrorCode),
+    /// Stream is reset by peer
+    RstStreamReceived(StreamId, ErrorCode),
+    /// Connection error detected by us, e. g. HPACK decoding failure,
+    /// connection flow control or protocol violation, with GOAWAY debug data
+    GoawaySent(ErrorCode, Bytes),
+    /// Connection error reported by peer, with GOAWAY debug data
+    GoawayReceived(ErrorCode, Bytes),
+    /// Connection is closed with error, e. g. I/O error or frame which cannot be parsed
+    Closed(String),
+}
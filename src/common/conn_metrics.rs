@@ -0,0 +1,137 @@
+//! Connection counters readable without a round-trip to the connection event loop
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Connection counters returned by `metrics()` of client or server connection.
+///
+/// Counters are updated by the connection event loop after each iteration
+/// and loaded one by one, so fields of a snapshot are not mutually consistent:
+/// e. g. `open_streams` may be loaded before and `streams_closed` after an iteration.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ConnMetrics {
+    /// Bytes written to the socket
+    pub bytes_sent: u64,
+    /// Bytes read from the socket
+    pub bytes_received: u64,
+    /// Streams opened by either side
+    pub streams_opened: u64,
+    /// Streams closed or reset
+    pub streams_closed: u64,
+    /// Streams currently open
+    pub open_streams: u64,
+    /// RST_STREAM frames sent
+    pub rst_sent: u64,
+    /// RST_STREAM frames received
+    pub rst_received: u64,
+    /// Streams opened by peer over our SETTINGS_MAX_CONCURRENT_STREAMS
+    /// and refused with `REFUSED_STREAM`
+    pub max_concurrent_streams_violations: u64,
+}
+
+/// Shared between connection and its handle
+#[derive(Debug, Default)]
+pub struct ConnMetricsCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    streams_opened: AtomicU64,
+    streams_closed: AtomicU64,
+    open_streams: AtomicU64,
+    rst_sent: AtomicU64,
+    rst_received: AtomicU64,
+    max_concurrent_streams_violations: AtomicU64,
+}
+
+impl ConnMetricsCounters {
+    pub fn new() -> ConnMetricsCounters {
+        Default::default()
+    }
+
+    pub fn store(&self, metrics: &ConnMetrics) {
+        let store = |counter: &AtomicU64, value: u64| counter.store(value, Ordering::SeqCst);
+        store(&self.bytes_sent, metrics.bytes_sent);
+        store(&self.bytes_received, metrics.bytes_received);
+        store(&self.streams_opened, metrics.streams_opened);
+        store(&self.streams_closed, metrics.streams_closed);
+        store(&self.open_streams, metrics.open_streams);
+        store(&self.rst_sent, metrics.rst_sent);
+        store(&self.rst_received, metrics.rst_received);
+        store(
+            &self.max_concurrent_streams_violations,
+            metrics.max_concurrent_streams_violations,
+        );
+    }
+
+    pub fn snapshot(&self) -> ConnMetrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::SeqCst);
+        ConnMetrics {
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            streams_opened: load(&self.streams_opened),
+            streams_closed: load(&self.streams_closed),
+            open_streams: load(&self.open_streams),
+            rst_sent: load(&self.rst_sent),
+            rst_received: load(&self.rst_received),
+            max_concurrent_streams_violations: load(&self.max_concurrent_streams_violations),
+        }
+    }
+}
+
+/// Counters of the current connection of `Client`, readable without lock.
+///
+/// Each connection publishes counters with its sequence number,
+/// counters of connections replaced by a newer connection are ignored.
+/// Connections of a client share the event loop, so stores are not concurrent.
+#[derive(Debug, Default)]
+pub struct CurrentConnMetrics {
+    conn_seq: AtomicU64,
+    counters: ConnMetricsCounters,
+}
+
+impl CurrentConnMetrics {
+    pub fn new() -> CurrentConnMetrics {
+        Default::default()
+    }
+
+    /// Connection with given sequence number becomes current, counters are reset
+    pub fn set_current(&self, conn_seq: u64) {
+        self.conn_seq.store(conn_seq, Ordering::SeqCst);
+        self.counters.store(&ConnMetrics::default());
+    }
+
+    pub fn store(&self, conn_seq: u64, metrics: &ConnMetrics) {
+        if self.conn_seq.load(Ordering::SeqCst) == conn_seq {
+            self.counters.store(metrics);
+        }
+    }
+
+    pub fn snapshot(&self) -> ConnMetrics {
+        self.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn current_conn_metrics() {
+        let current = CurrentConnMetrics::new();
+        let metrics = |rst_sent| ConnMetrics {
+            rst_sent,
+            ..ConnMetrics::default()
+        };
+
+        current.set_current(1);
+        current.store(1, &metrics(3));
+        assert_eq!(3, current.snapshot().rst_sent);
+
+        // new connection resets counters, old connection is ignored
+        current.set_current(2);
+        assert_eq!(0, current.snapshot().rst_sent);
+        current.store(1, &metrics(4));
+        assert_eq!(0, current.snapshot().rst_sent);
+        current.store(2, &metrics(1));
+        assert_eq!(1, current.snapshot().rst_sent);
+    }
+}
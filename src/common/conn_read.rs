@@ -1,5 +1,7 @@
 use codec::http_decode_read::HttpFrameDecodedOrGoaway;
+use common::conf::OnReadEof;
 use common::conn::Conn;
+use common::conn_error_event::ConnErrorEvent;
 use common::conn_write::ConnWriteSideCustom;
 use common::init_where::InitWhere;
 use common::stream::HttpStreamCommon;
@@ -13,6 +15,7 @@ use futures::Poll;
 use result;
 use solicit::end_stream::EndStream;
 use solicit::frame::headers::HeadersDecodedFrame;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
 use solicit::frame::DataFrame;
 use solicit::frame::Frame;
 use solicit::frame::GoawayFrame;
@@ -25,7 +28,6 @@ use solicit::frame::RstStreamFrame;
 use solicit::frame::SettingsFrame;
 use solicit::frame::WindowUpdateFrame;
 use solicit::StreamId;
-use solicit::DEFAULT_SETTINGS;
 use solicit::MAX_WINDOW_SIZE;
 use solicit_misc::HttpFrameClassified;
 use solicit_misc::HttpFrameConn;
@@ -42,6 +44,20 @@ pub trait ConnReadSideCustom {
         end_stream: EndStream,
         headers: Headers,
     ) -> result::Result<Option<HttpStreamRef<Self::Types>>>;
+
+    /// Process PUSH_PROMISE received on a stream
+    fn process_push_promise(&mut self, frame: PushPromiseDecodedFrame) -> result::Result<()>;
+
+    /// Called after peer SETTINGS frame is applied to `peer_settings`
+    fn peer_settings_updated(&mut self) {}
+
+    /// Called after GOAWAY is received from peer
+    fn peer_goaway_received(&mut self, _last_stream_id: StreamId, _raw_error_code: u32) {}
+
+    /// Called after RST_STREAM is received from peer
+    fn peer_rst_stream_received(&mut self, _stream_id: StreamId) -> result::Result<()> {
+        Ok(())
+    }
 }
 
 impl<T> Conn<T>
@@ -63,17 +79,15 @@ where
 
         self.decrease_in_window(frame.payload_len())?;
 
-        let increment_conn =
-        // TODO: need something better
-            if self.in_window_size.size() < (DEFAULT_SETTINGS.initial_window_size / 2) as i32 {
-                let increment = DEFAULT_SETTINGS.initial_window_size;
-                self.in_window_size.try_increase(increment)
-                    .map_err(|()| error::Error::Other("failed to increase window size"))?;
-
-                Some(increment)
-            } else {
-                None
-            };
+        let increment_conn = self
+            .window_update_strategy
+            .increment(self.in_window_size_target, self.in_window_size.size() as i64);
+        if let Some(increment) = increment_conn {
+            self.in_window_size
+                .try_increase(increment)
+                .map_err(|()| error::Error::Other("failed to increase window size"))?;
+            self.window_accounting.increment_sent(increment);
+        }
 
         let mut error = None;
 
@@ -85,22 +99,39 @@ where
                 .get_stream_maybe_send_error(frame.get_stream_id(), HttpFrameType::Data)?
             {
                 Some(stream) => stream,
-                None => {
-                    return Ok(None);
-                }
+                // connection window is still updated below
+                None => break,
             };
 
             if let Some(in_rem_content_length) = stream.stream().in_rem_content_length {
                 if in_rem_content_length < frame.data.len() as u64 {
                     warn!("stream data underflow content-length");
+                    stream.stream().content_length_mismatch();
                     error = Some(ErrorCode::ProtocolError);
                     break;
                 }
 
                 let in_rem_content_length = in_rem_content_length - frame.data.len() as u64;
+                if frame.is_end_of_stream() && in_rem_content_length != 0 {
+                    warn!("stream data shorter than content-length");
+                    stream.stream().content_length_mismatch();
+                    error = Some(ErrorCode::ProtocolError);
+                    break;
+                }
                 stream.stream().in_rem_content_length = Some(in_rem_content_length);
             }
 
+            if let Some(in_rem_body_limit) = stream.stream().in_rem_body_limit {
+                if in_rem_body_limit < frame.data.len() as u64 {
+                    warn!("stream data exceeds body size limit");
+                    stream.stream().body_too_large();
+                    error = Some(ErrorCode::Cancel);
+                    break;
+                }
+                stream.stream().in_rem_body_limit =
+                    Some(in_rem_body_limit - frame.data.len() as u64);
+            }
+
             assert_eq!(
                 InMessageStage::AfterInitialHeaders,
                 stream.stream().in_message_stage
@@ -127,25 +158,12 @@ where
             return Ok(None);
         }
 
-        Ok(Some(
-            self.streams
-                .get_mut(stream_id)
-                .expect("stream must be found"),
-        ))
+        Ok(self.streams.get_mut(stream_id))
     }
 
     fn process_ping(&mut self, frame: PingFrame) -> result::Result<()> {
         if frame.is_ack() {
-            if let Some(opaque_data) = self.ping_sent.take() {
-                if opaque_data == frame.opaque_data {
-                    Ok(())
-                } else {
-                    Err(error::Error::Other("PING ACK opaque data mismatch"))
-                }
-            } else {
-                warn!("PING ACK without PING");
-                Ok(())
-            }
+            self.process_ping_ack(frame.opaque_data)
         } else {
             let ping = PingFrame::new_ack(frame.opaque_data());
             self.send_frame_and_notify(ping);
@@ -160,13 +178,26 @@ where
 
         let last_stream_id = frame.last_stream_id;
         let raw_error_code = frame.raw_error_code;
+        let error_code = frame.error_code();
+        let debug_data = frame.debug_data.clone();
+
+        if error_code != ErrorCode::NoError {
+            self.report_error_event(ConnErrorEvent::GoawayReceived(
+                error_code,
+                debug_data.clone(),
+            ));
+        }
 
         self.goaway_received = Some(frame);
 
+        // notify client before streams, so client reconnects
+        // before requests refused by GOAWAY are retried
+        self.peer_goaway_received(last_stream_id, raw_error_code);
+
         for (stream_id, mut stream) in self.streams.remove_local_streams_with_id_gt(last_stream_id)
         {
             debug!("removed stream {} because of GOAWAY", stream_id);
-            stream.goaway_recvd(raw_error_code);
+            stream.goaway_recvd(error_code, debug_data.clone());
         }
 
         Ok(())
@@ -182,6 +213,18 @@ where
             EndStream::No
         };
 
+        if let Some(ref mut priority_tree) = self.priority_tree {
+            // Streams without priority are added to the tree when they have data to write
+            if let Some(ref dep) = frame.stream_dep {
+                priority_tree.reprioritize(
+                    frame.stream_id,
+                    dep.stream_id,
+                    dep.weight,
+                    dep.is_exclusive,
+                );
+            }
+        }
+
         self.process_headers(frame.stream_id, end_stream, frame.headers)
     }
 
@@ -189,14 +232,28 @@ where
         &mut self,
         frame: PriorityFrame,
     ) -> result::Result<Option<HttpStreamRef<T>>> {
+        if let Some(ref mut priority_tree) = self.priority_tree {
+            priority_tree.reprioritize(
+                frame.stream_id,
+                frame.stream_dep,
+                frame.weight,
+                frame.exclusive,
+            );
+        }
         Ok(self.streams.get_mut(frame.get_stream_id()))
     }
 
     fn process_settings_ack(&mut self, frame: SettingsFrame) -> result::Result<()> {
         assert!(frame.is_ack());
 
-        if let Some(settings) = self.our_settings_sent.take() {
+        // SETTINGS are acknowledged in order they were sent
+        if let Some((settings, tx)) = self.our_settings_sent.pop_front() {
             self.our_settings_ack = settings;
+            self.update_decoder_table_size();
+            if let Some(tx) = tx {
+                // ignore error, caller is not interested in ACK anymore
+                drop(tx.send(Ok(())));
+            }
             Ok(())
         } else {
             Err(error::Error::Other("SETTINGS ack without settings sent"))
@@ -206,6 +263,14 @@ where
     fn process_settings_req(&mut self, frame: SettingsFrame) -> result::Result<()> {
         assert!(!frame.is_ack());
 
+        self.preface_timeout = None;
+
+        self.apply_peer_settings(frame, true)
+    }
+
+    /// Apply peer SETTINGS and acknowledge them if `ack`
+    /// (settings of h2c upgrade request are acknowledged by `101` response)
+    pub fn apply_peer_settings(&mut self, frame: SettingsFrame, ack: bool) -> result::Result<()> {
         let mut out_window_increased = false;
 
         for setting in frame.settings {
@@ -243,14 +308,20 @@ where
                         }
                     }
                 }
-                HttpSetting::HeaderTableSize(_new_size) => {}
+                HttpSetting::HeaderTableSize(new_size) => {
+                    self.encoder.set_max_table_size(new_size as usize);
+                }
                 _ => {}
             }
 
             self.peer_settings.apply(setting);
         }
 
-        self.send_ack_settings()?;
+        self.peer_settings_updated();
+
+        if ack {
+            self.send_ack_settings()?;
+        }
 
         if out_window_increased {
             self.out_window_increased(None)?;
@@ -341,6 +412,7 @@ where
             old_window_size, self.out_window_size
         );
 
+        self.window_accounting.increment_received(frame.increment);
         self.pump_out_window_size.increase(frame.increment);
 
         self.out_window_increased(None)
@@ -351,6 +423,13 @@ where
         frame: RstStreamFrame,
     ) -> result::Result<Option<HttpStreamRef<T>>> {
         let stream_id = frame.get_stream_id();
+        self.rst_received += 1;
+        if frame.error_code() != ErrorCode::NoError {
+            self.report_error_event(ConnErrorEvent::RstStreamReceived(
+                stream_id,
+                frame.error_code(),
+            ));
+        }
         if let Some(stream) =
             self.get_stream_maybe_send_error(stream_id, HttpFrameType::RstStream)?
         {
@@ -359,6 +438,8 @@ where
 
         self.peer_closed_streams.add(stream_id);
 
+        self.peer_rst_stream_received(stream_id)?;
+
         Ok(None)
     }
 
@@ -378,12 +459,23 @@ where
         // 6.8
         // Once sent, the sender will ignore frames sent on streams initiated by the receiver
         // if the stream has an identifier higher than the included last stream identifier.
-        if let Some(ref f) = self.goaway_sent.as_ref() {
-            if T::init_where(stream_id) != InitWhere::Locally {
-                if stream_id > f.last_stream_id {
-                    return Ok(());
+        let ignored_after_goaway = match self.goaway_sent {
+            Some(ref f) => {
+                T::init_where(stream_id) != InitWhere::Locally && stream_id > f.last_stream_id
+            }
+            None => false,
+        };
+        if ignored_after_goaway {
+            // Tell peer the stream is not processed, so the request
+            // can be retried on another connection
+            if let HttpFrameStream::Headers(..) = frame {
+                if stream_id > self.last_refused_stream_id {
+                    debug!("refusing stream {} opened after GOAWAY", stream_id);
+                    self.last_refused_stream_id = stream_id;
+                    self.send_rst_stream(stream_id, ErrorCode::RefusedStream)?;
                 }
             }
+            return Ok(());
         }
 
         {
@@ -392,8 +484,9 @@ where
                 HttpFrameStream::Headers(headers) => self.process_headers_frame(headers)?,
                 HttpFrameStream::Priority(priority) => self.process_priority_frame(priority)?,
                 HttpFrameStream::RstStream(rst) => self.process_rst_stream_frame(rst)?,
-                HttpFrameStream::PushPromise(_f) => {
-                    return Err(error::Error::NotImplemented("PUSH_PROMISE"))
+                HttpFrameStream::PushPromise(push_promise) => {
+                    self.process_push_promise(push_promise)?;
+                    None
                 }
                 HttpFrameStream::WindowUpdate(window_update) => {
                     self.process_stream_window_update_frame(window_update)?
@@ -427,20 +520,64 @@ where
         }
     }
 
+    /// Peer closed its side of the connection: fail streams still waiting
+    /// for peer data and keep sending to streams already completed by peer.
+    fn process_read_eof(&mut self) -> result::Result<()> {
+        info!("EOF from peer, finishing writes");
+
+        self.read_eof = true;
+
+        let stream_ids: Vec<StreamId> = self
+            .streams
+            .map
+            .iter()
+            .filter(|&(_, s)| !s.state.is_closed_remote())
+            .map(|(&id, _)| id)
+            .collect();
+
+        for stream_id in stream_ids {
+            if let Some(mut stream) = self.streams.get_mut(stream_id) {
+                stream.stream().eof_recvd();
+            }
+            self.send_rst_stream(stream_id, ErrorCode::Cancel)?;
+        }
+
+        Ok(())
+    }
+
     /// Loop forever, never return `Ready`
     pub fn read_process_frame(&mut self) -> Poll<(), error::Error> {
         loop {
+            if self.read_eof {
+                return Ok(Async::NotReady);
+            }
+
             if self.end_loop() {
+                // after graceful GOAWAY connection is closed by `poll`
+                // when write queue is flushed
+                if self.goaway_sent.is_some() {
+                    return Ok(Async::NotReady);
+                }
                 return Err(error::Error::Other("GOAWAY"));
             }
 
-            let frame = match self.recv_http_frame()? {
-                Async::Ready(HttpFrameDecodedOrGoaway::Frame(frame)) => frame,
-                Async::Ready(HttpFrameDecodedOrGoaway::SendGoaway(error_code)) => {
+            // Task is notified on resume by write queue message
+            if self.reads_paused {
+                return Ok(Async::NotReady);
+            }
+
+            let frame = match self.recv_http_frame() {
+                Ok(Async::Ready(HttpFrameDecodedOrGoaway::Frame(frame))) => frame,
+                Ok(Async::Ready(HttpFrameDecodedOrGoaway::SendGoaway(error_code))) => {
                     self.send_goaway(error_code)?;
                     return Ok(Async::NotReady);
                 }
-                Async::NotReady => return Ok(Async::NotReady),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error::Error::EofFromPeer) if self.on_read_eof == OnReadEof::FinishWrites => {
+                    self.process_read_eof()?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
             };
 
             self.process_http_frame(frame)?;
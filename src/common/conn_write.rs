@@ -8,6 +8,8 @@ use data_or_headers_with_flag::DataOrHeadersWithFlag;
 
 use bytes::Bytes;
 use common::conn::ConnStateSnapshot;
+use common::conn_error_event::ConnErrorEvent;
+use common::conn_metrics::ConnMetrics;
 use common::conn_read::ConnReadSideCustom;
 use common::iteration_exit::IterationExit;
 use common::stream::HttpStreamCommand;
@@ -20,17 +22,23 @@ use result;
 use solicit::end_stream::EndStream;
 use solicit::frame::flags::Flags;
 use solicit::frame::headers::HeadersMultiFrame;
+use solicit::frame::headers::StreamDependency;
 use solicit::frame::DataFlag;
 use solicit::frame::DataFrame;
 use solicit::frame::GoawayFrame;
 use solicit::frame::HeadersFlag;
 use solicit::frame::HttpFrame;
 use solicit::frame::RstStreamFrame;
+use solicit::frame::settings::HttpSetting;
 use solicit::frame::SettingsFrame;
 use std::cmp;
+use std::collections::HashSet;
 use ErrorCode;
 use Headers;
 
+// 5.3.5 Default Priorities: weight 16 (15 in frame representation)
+const DEFAULT_WEIGHT: u8 = 15;
+
 pub trait ConnWriteSideCustom {
     type Types: Types;
 
@@ -38,6 +46,14 @@ pub trait ConnWriteSideCustom {
         &mut self,
         message: <Self::Types as Types>::ToWriteMessage,
     ) -> result::Result<()>;
+
+    /// Poll client or server specific timers
+    fn poll_timers(&mut self) -> result::Result<()> {
+        Ok(())
+    }
+
+    /// Called after counters are published to `metrics`
+    fn metrics_updated(&mut self, _metrics: &ConnMetrics) {}
 }
 
 impl<T> Conn<T>
@@ -48,7 +64,12 @@ where
     HttpStreamCommon<T>: HttpStreamData<Types = T>,
 {
     fn write_part_data(&mut self, stream_id: StreamId, data: Bytes, end_stream: EndStream) {
-        let max_frame_size = self.peer_settings.max_frame_size as usize;
+        self.window_accounting.data_sent(data.len());
+
+        let max_frame_size = match self.out_data_frame_max_size {
+            Some(size) => cmp::min(size, self.peer_settings.max_frame_size),
+            None => self.peer_settings.max_frame_size,
+        } as usize;
 
         // if client requested end of stream,
         // we must send at least one frame with end stream flag
@@ -84,16 +105,23 @@ where
         }
     }
 
-    fn write_part_headers(&mut self, stream_id: StreamId, headers: Headers, end_stream: EndStream) {
+    fn write_part_headers(
+        &mut self,
+        stream_id: StreamId,
+        headers: Headers,
+        end_stream: EndStream,
+        weight: Option<u8>,
+    ) {
         let mut flags = Flags::new(0);
         if end_stream == EndStream::Yes {
             flags.set(HeadersFlag::EndStream);
         }
+        let stream_dep = weight.map(|weight| StreamDependency::new(0, weight, false));
         self.queued_write.queue_not_goaway(HeadersMultiFrame {
             flags,
             stream_id,
             headers,
-            stream_dep: None,
+            stream_dep,
             padding_len: 0,
             encoder: &mut self.encoder,
             max_frame_size: self.peer_settings.max_frame_size,
@@ -103,18 +131,25 @@ where
     fn write_part_rst(&mut self, stream_id: StreamId, error_code: ErrorCode) {
         let frame = RstStreamFrame::new(stream_id, error_code);
 
+        self.rst_sent += 1;
+        self.report_rst_stream_sent(stream_id, error_code);
         self.queued_write.queue_not_goaway(frame);
     }
 
-    fn write_part(&mut self, stream_id: StreamId, part: HttpStreamCommand) {
+    fn write_part(&mut self, stream_id: StreamId, part: HttpStreamCommand, weight: Option<u8>) {
         match part {
             HttpStreamCommand::Data(data, end_stream) => {
                 self.write_part_data(stream_id, data, end_stream);
             }
             HttpStreamCommand::Headers(headers, end_stream) => {
-                self.write_part_headers(stream_id, headers, end_stream);
+                self.write_part_headers(stream_id, headers, end_stream, weight);
             }
             HttpStreamCommand::Rst(error_code) => {
+                if error_code == ErrorCode::NoError {
+                    // Sent after complete response (`StreamEndRstNoError`),
+                    // DATA in flight from peer is ignored like after CANCEL
+                    self.cancelled_streams.add(stream_id);
+                }
                 self.write_part_rst(stream_id, error_code);
             }
         }
@@ -127,15 +162,58 @@ where
     fn pop_outg_for_stream(
         &mut self,
         stream_id: StreamId,
-    ) -> Option<(StreamId, HttpStreamCommand, bool)> {
-        let stream = self.streams.get_mut(stream_id).unwrap();
+    ) -> Option<(StreamId, HttpStreamCommand, Option<u8>, bool)> {
+        let mut stream = self.streams.get_mut(stream_id).unwrap();
+        let weight = stream.stream().weight;
         if let (Some(command), stream) = stream.pop_outg_maybe_remove(&mut self.out_window_size) {
-            return Some((stream_id, command, stream.is_some()));
+            return Some((stream_id, command, weight, stream.is_some()));
         }
 
         None
     }
 
+    /// Write one frame at a time choosing the stream by priority tree
+    fn buffer_outg_conn_by_priority(&mut self) -> result::Result<bool> {
+        let mut updated = false;
+
+        let mut priority_tree = self.priority_tree.take().unwrap();
+
+        // Closed streams and idle streams which only were given priority are forgotten
+        {
+            let streams = &self.streams.map;
+            priority_tree.retain(|stream_id| streams.contains_key(&stream_id));
+        }
+
+        while self.has_write_buffer_capacity() {
+            let writable: HashSet<StreamId> =
+                self.streams.writable_stream_ids().to_vec().into_iter().collect();
+            for &stream_id in &writable {
+                priority_tree.insert_default(stream_id);
+            }
+
+            let stream_id = match priority_tree.next(|id| writable.contains(&id)) {
+                Some(stream_id) => stream_id,
+                None => break,
+            };
+
+            match self.pop_outg_for_stream(stream_id) {
+                Some((stream_id, part, weight, _)) => {
+                    let len = match part {
+                        HttpStreamCommand::Data(ref data, ..) => data.len(),
+                        _ => 0,
+                    };
+                    self.write_part(stream_id, part, weight);
+                    priority_tree.charge(stream_id, len);
+                    updated = true;
+                }
+                None => break,
+            }
+        }
+
+        self.priority_tree = Some(priority_tree);
+        Ok(updated)
+    }
+
     pub fn buffer_outg_conn(&mut self) -> result::Result<bool> {
         let mut updated = false;
 
@@ -144,7 +222,23 @@ where
             return Ok(updated);
         }
 
-        let writable_stream_ids = self.streams.writable_stream_ids();
+        if self.priority_tree.is_some() {
+            return self.buffer_outg_conn_by_priority();
+        }
+
+        let mut writable_stream_ids = self.streams.writable_stream_ids().to_vec();
+
+        // Heavier streams first, streams without priority have default weight
+        if writable_stream_ids.len() > 1 {
+            let streams = &mut self.streams;
+            writable_stream_ids.sort_by_key(|&stream_id| {
+                let weight = streams
+                    .get_mut(stream_id)
+                    .and_then(|mut s| s.stream().weight)
+                    .unwrap_or(DEFAULT_WEIGHT);
+                cmp::Reverse(weight)
+            });
+        }
 
         for &stream_id in &writable_stream_ids {
             loop {
@@ -152,8 +246,8 @@ where
                     return Ok(updated);
                 }
 
-                if let Some((stream_id, part, cont)) = self.pop_outg_for_stream(stream_id) {
-                    self.write_part(stream_id, part);
+                if let Some((stream_id, part, weight, cont)) = self.pop_outg_for_stream(stream_id) {
+                    self.write_part(stream_id, part, weight);
                     updated = true;
 
                     // Stream is removed from map, need to continue to the next stream
@@ -196,6 +290,14 @@ where
         Ok(())
     }
 
+    fn process_stream_end_rst_no_error(&mut self, stream_id: StreamId) -> result::Result<()> {
+        let stream = self.streams.get_mut(stream_id);
+        if let Some(mut stream) = stream {
+            stream.close_outgoing_rst_no_error();
+        }
+        Ok(())
+    }
+
     fn process_stream_enqueue(
         &mut self,
         stream_id: StreamId,
@@ -213,6 +315,9 @@ where
             CommonToWriteMessage::StreamEnd(stream_id, error_code) => {
                 self.process_stream_end(stream_id, error_code)?;
             }
+            CommonToWriteMessage::StreamEndRstNoError(stream_id) => {
+                self.process_stream_end_rst_no_error(stream_id)?;
+            }
             CommonToWriteMessage::StreamEnqueue(stream_id, part) => {
                 self.process_stream_enqueue(stream_id, part)?;
             }
@@ -222,18 +327,115 @@ where
             CommonToWriteMessage::DumpState(sender) => {
                 self.process_dump_state(sender)?;
             }
+            CommonToWriteMessage::Goaway(error_code, debug_data) => {
+                self.send_goaway_with_debug_data(error_code, debug_data)?;
+            }
+            CommonToWriteMessage::GoawayGraceful => {
+                self.send_goaway_graceful()?;
+            }
+            CommonToWriteMessage::ForceClose => {
+                self.force_close()?;
+            }
+            CommonToWriteMessage::Ping => {
+                self.send_ping();
+            }
+            CommonToWriteMessage::UpdateSettings(settings, ack_tx) => {
+                self.send_settings_update(settings, ack_tx)?;
+            }
+            CommonToWriteMessage::PauseReads => {
+                debug!("pause reads");
+                self.reads_paused = true;
+            }
+            CommonToWriteMessage::ResumeReads => {
+                debug!("resume reads");
+                self.reads_paused = false;
+            }
         }
         Ok(())
     }
 
+    /// Send SETTINGS, `ack_tx` is notified when peer acknowledges it.
+    ///
+    /// Several SETTINGS may be outstanding, peer acknowledges them in order.
+    pub fn send_settings_update(
+        &mut self,
+        settings: Vec<HttpSetting>,
+        ack_tx: Option<oneshot::Sender<result::Result<()>>>,
+    ) -> result::Result<()> {
+        let frame = SettingsFrame::from_settings(settings);
+        let prev_settings = *self.our_settings_sent();
+        let mut new_settings = prev_settings;
+        new_settings.apply_from_frame(&frame);
+
+        // New streams are created with window from sent settings,
+        // so existing streams are adjusted when SETTINGS is sent
+        let delta =
+            new_settings.initial_window_size as i32 - prev_settings.initial_window_size as i32;
+        if delta != 0 {
+            self.change_streams_in_window(delta);
+        }
+
+        self.framed_read
+            .set_max_header_list_size(new_settings.max_header_list_size);
+
+        self.our_settings_sent.push_back((new_settings, ack_tx));
+        // Increased table size may be used by peer as soon as it receives SETTINGS
+        self.update_decoder_table_size();
+        self.send_frame_and_notify(frame);
+        Ok(())
+    }
+
     pub fn send_goaway(&mut self, error_code: ErrorCode) -> result::Result<()> {
-        debug!("requesting to send GOAWAY with code {:?}", error_code);
-        let frame = GoawayFrame::new(self.last_peer_stream_id, error_code);
+        self.send_goaway_with_debug_data(error_code, Bytes::new())
+    }
+
+    pub fn send_goaway_with_debug_data(
+        &mut self,
+        error_code: ErrorCode,
+        debug_data: Bytes,
+    ) -> result::Result<()> {
+        debug!(
+            "requesting to send GOAWAY with code {:?}, debug data {:?}",
+            error_code, debug_data
+        );
+        if error_code != ErrorCode::NoError {
+            self.report_error_event(ConnErrorEvent::GoawaySent(error_code, debug_data.clone()));
+        }
+        let frame = GoawayFrame::with_debug_data(self.last_peer_stream_id, error_code, debug_data);
         self.queued_write.queue_goaway(frame);
         task::current().notify();
         Ok(())
     }
 
+    /// Send GOAWAY(NO_ERROR), but keep processing existing streams.
+    /// Connection is closed when all streams are complete.
+    pub fn send_goaway_graceful(&mut self) -> result::Result<()> {
+        if self.goaway_sent.is_some() {
+            return Ok(());
+        }
+        debug!(
+            "sending graceful GOAWAY, last stream id {}",
+            self.last_peer_stream_id
+        );
+        let frame = GoawayFrame::new(self.last_peer_stream_id, ErrorCode::NoError);
+        self.goaway_sent = Some(frame.clone());
+        self.send_frame_and_notify(frame);
+        Ok(())
+    }
+
+    /// Reset all streams and close the connection
+    pub fn force_close(&mut self) -> result::Result<()> {
+        let stream_ids: Vec<StreamId> = self.streams.map.keys().cloned().collect();
+        for stream_id in stream_ids {
+            debug!("resetting stream {} on connection close", stream_id);
+            if let Some(mut stream) = self.streams.get_mut(stream_id) {
+                stream.stream().reset_locally(ErrorCode::Cancel);
+            }
+            self.send_rst_stream(stream_id, ErrorCode::Cancel)?;
+        }
+        self.send_goaway(ErrorCode::NoError)
+    }
+
     pub fn process_goaway_state(&mut self) -> result::Result<IterationExit> {
         Ok(if self.queued_write.goaway_queued() {
             self.queued_write.poll()?;
@@ -287,5 +489,60 @@ pub enum CommonToWriteMessage {
     IncreaseInWindow(StreamId, u32),
     StreamEnqueue(StreamId, DataOrHeadersWithFlag),
     StreamEnd(StreamId, ErrorCode), // send when user provided handler completed the stream
+    StreamEndRstNoError(StreamId), // complete the stream, then RST_STREAM(NO_ERROR) if peer is still sending
+    DumpState(oneshot::Sender<ConnStateSnapshot>),
+    Goaway(ErrorCode, Bytes), // send GOAWAY and close the connection
+    GoawayGraceful, // send GOAWAY and close the connection when streams complete
+    ForceClose, // reset all streams and close the connection
+    PauseReads,
+    ResumeReads,
+    Ping, // send keepalive PING unless previous PING is not acknowledged
+    // send SETTINGS and notify when it is acknowledged
+    UpdateSettings(Vec<HttpSetting>, Option<oneshot::Sender<result::Result<()>>>),
+}
+
+/// Administrative operation on established connection.
+pub enum ConnControl {
+    /// Send SETTINGS frame with given settings.
+    UpdateSettings(Vec<HttpSetting>),
+    /// Send SETTINGS frame and complete the sender when the peer acknowledges it,
+    /// i. e. when new settings are in effect.
+    UpdateSettingsAcked(Vec<HttpSetting>, oneshot::Sender<result::Result<()>>),
+    /// Send PING unless previous PING is not acknowledged,
+    /// round-trip time is available in `ConnStateSnapshot`.
+    Ping,
+    /// Send GOAWAY with application debug data and close the connection.
+    Goaway(ErrorCode, Bytes),
+    /// Send GOAWAY, complete streams in flight and close the connection after that.
+    GoawayGraceful,
+    /// Send connection state to given sender.
     DumpState(oneshot::Sender<ConnStateSnapshot>),
+    /// Stop reading from the socket until `ResumeReads`.
+    ///
+    /// Flow control windows are not replenished while reads are paused,
+    /// so the peer eventually stops sending.
+    PauseReads,
+    /// Resume reading from the socket after `PauseReads`.
+    ResumeReads,
+}
+
+impl From<ConnControl> for CommonToWriteMessage {
+    fn from(control: ConnControl) -> CommonToWriteMessage {
+        match control {
+            ConnControl::UpdateSettings(settings) => {
+                CommonToWriteMessage::UpdateSettings(settings, None)
+            }
+            ConnControl::UpdateSettingsAcked(settings, tx) => {
+                CommonToWriteMessage::UpdateSettings(settings, Some(tx))
+            }
+            ConnControl::Ping => CommonToWriteMessage::Ping,
+            ConnControl::Goaway(error_code, debug_data) => {
+                CommonToWriteMessage::Goaway(error_code, debug_data)
+            }
+            ConnControl::GoawayGraceful => CommonToWriteMessage::GoawayGraceful,
+            ConnControl::DumpState(tx) => CommonToWriteMessage::DumpState(tx),
+            ConnControl::PauseReads => CommonToWriteMessage::PauseReads,
+            ConnControl::ResumeReads => CommonToWriteMessage::ResumeReads,
+        }
+    }
 }
@@ -0,0 +1,188 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::stream::Stream;
+use futures::Async;
+
+use tokio_core::reactor;
+use tokio_core::reactor::Interval;
+
+use error;
+use result;
+
+/// What connection should do after keepalive timer tick
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeepaliveAction {
+    Nothing,
+    /// Send PING, opaque data is assigned by connection and passed to `ping_sent`
+    SendPing,
+    /// PING is not acknowledged within timeout
+    Timeout,
+}
+
+/// Periodic keepalive PING driven by connection timer.
+///
+/// In idle-only mode (NAT keepalive) PING is sent when no bytes were transferred
+/// during the last interval, otherwise PING is sent on every tick
+/// unless the previous one is still outstanding.
+/// Connection is closed only if PING is not acknowledged within timeout
+/// (checked on interval ticks).
+pub struct Keepalive {
+    idle_only: bool,
+    timeout: Duration,
+    interval: Interval,
+    last_transferred: u64,
+    /// Opaque data of outstanding PING and when it was sent
+    ping_sent: Option<(u64, Instant)>,
+    /// PING was acknowledged since the last tick
+    ping_acked: bool,
+}
+
+impl Keepalive {
+    pub fn new(
+        interval: Duration,
+        timeout: Duration,
+        idle_only: bool,
+        handle: &reactor::Handle,
+    ) -> result::Result<Keepalive> {
+        Ok(Keepalive {
+            idle_only,
+            timeout,
+            interval: Interval::new(interval, handle)?,
+            last_transferred: 0,
+            ping_sent: None,
+            ping_acked: false,
+        })
+    }
+
+    /// Poll the timer.
+    ///
+    /// `transferred` is total number of bytes read and written.
+    pub fn poll(&mut self, transferred: u64) -> result::Result<KeepaliveAction> {
+        let mut action = KeepaliveAction::Nothing;
+        loop {
+            match self.interval.poll() {
+                Ok(Async::NotReady) => return Ok(action),
+                Ok(Async::Ready(Some(()))) => {}
+                Ok(Async::Ready(None)) => {
+                    return Err(error::Error::Other("keepalive timer failed"))
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            match self.tick(Instant::now(), transferred) {
+                KeepaliveAction::Nothing => {}
+                a => action = a,
+            }
+            if let KeepaliveAction::Timeout = action {
+                return Ok(action);
+            }
+        }
+    }
+
+    /// Record PING sent after `KeepaliveAction::SendPing`
+    pub fn ping_sent(&mut self, opaque_data: u64, now: Instant) {
+        self.ping_sent = Some((opaque_data, now));
+    }
+
+    /// Process PING ACK, return round-trip time if ACK is for our PING
+    pub fn ping_ack(&mut self, opaque_data: u64, now: Instant) -> Option<Duration> {
+        match self.ping_sent {
+            Some((sent_data, sent_at)) if sent_data == opaque_data => {
+                self.ping_sent = None;
+                self.ping_acked = true;
+                Some(now - sent_at)
+            }
+            _ => None,
+        }
+    }
+
+    /// PING is sent and not yet acknowledged
+    pub fn ping_outstanding(&self) -> bool {
+        self.ping_sent.is_some()
+    }
+
+    fn tick(&mut self, now: Instant, transferred: u64) -> KeepaliveAction {
+        let mut idle = transferred == self.last_transferred;
+        self.last_transferred = transferred;
+
+        if let Some((_, sent_at)) = self.ping_sent {
+            return if now - sent_at >= self.timeout {
+                KeepaliveAction::Timeout
+            } else {
+                KeepaliveAction::Nothing
+            };
+        }
+
+        if self.ping_acked {
+            // PING and its ACK are not connection activity
+            self.ping_acked = false;
+            idle = true;
+        }
+
+        if self.idle_only && !idle {
+            return KeepaliveAction::Nothing;
+        }
+
+        KeepaliveAction::SendPing
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn tick_idle_only() {
+        let core = Core::new().unwrap();
+        let start = Instant::now();
+        let sec = |s| start + Duration::from_secs(s);
+        let mut keepalive = Keepalive::new(
+            Duration::from_secs(1),
+            Duration::from_secs(3),
+            true,
+            &core.handle(),
+        ).unwrap();
+
+        // active connection
+        assert_eq!(KeepaliveAction::Nothing, keepalive.tick(sec(1), 100));
+        // idle connection
+        assert_eq!(KeepaliveAction::SendPing, keepalive.tick(sec(2), 100));
+        keepalive.ping_sent(1, sec(2));
+        assert_eq!(None, keepalive.ping_ack(2, sec(2)));
+        assert_eq!(Some(Duration::from_secs(0)), keepalive.ping_ack(1, sec(2)));
+        // acknowledged, PING bytes are not activity
+        assert_eq!(KeepaliveAction::SendPing, keepalive.tick(sec(3), 134));
+        keepalive.ping_sent(2, sec(3));
+        // ACK is late, but within timeout
+        assert_eq!(KeepaliveAction::Nothing, keepalive.tick(sec(4), 151));
+        assert_eq!(KeepaliveAction::Nothing, keepalive.tick(sec(5), 151));
+        assert_eq!(KeepaliveAction::Timeout, keepalive.tick(sec(6), 151));
+    }
+
+    #[test]
+    fn tick_periodic() {
+        let core = Core::new().unwrap();
+        let start = Instant::now();
+        let sec = |s| start + Duration::from_secs(s);
+        let mut keepalive = Keepalive::new(
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+            false,
+            &core.handle(),
+        ).unwrap();
+
+        // PING is sent on active connection
+        assert_eq!(KeepaliveAction::SendPing, keepalive.tick(sec(1), 100));
+        keepalive.ping_sent(1, sec(1));
+        // previous PING is outstanding
+        assert_eq!(KeepaliveAction::Nothing, keepalive.tick(sec(2), 200));
+        assert_eq!(Some(Duration::from_secs(1)), keepalive.ping_ack(1, sec(2)));
+        assert_eq!(KeepaliveAction::SendPing, keepalive.tick(sec(3), 300));
+        keepalive.ping_sent(2, sec(3));
+        assert_eq!(KeepaliveAction::Nothing, keepalive.tick(sec(4), 300));
+        assert_eq!(KeepaliveAction::Timeout, keepalive.tick(sec(5), 300));
+    }
+}
@@ -5,11 +5,15 @@ pub mod client_or_server;
 mod closed_streams;
 mod conf;
 mod conn;
+mod conn_error_event;
+mod conn_metrics;
 mod conn_read;
 mod conn_write;
 mod hash_set_shallow_clone;
 pub mod init_where;
 mod iteration_exit;
+mod keepalive;
+mod priority_tree;
 mod pump_stream_to_write_loop;
 mod stream;
 mod stream_from_network;
@@ -18,13 +22,18 @@ mod stream_queue;
 pub mod stream_queue_sync;
 mod types;
 pub mod waiters;
+mod window_accounting;
 mod window_size;
 
 pub use self::closed_streams::*;
 pub use self::conf::*;
 pub use self::conn::*;
+pub use self::conn_error_event::*;
+pub use self::conn_metrics::*;
 pub use self::conn_read::*;
 pub use self::conn_write::*;
+pub use self::keepalive::*;
+pub use self::priority_tree::*;
 pub use self::pump_stream_to_write_loop::*;
 pub use self::stream::*;
 pub use self::stream_from_network::*;
@@ -0,0 +1,348 @@
+//! RFC 7540 section 5.3 stream dependency tree used to choose the next stream to write
+
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+
+use solicit::StreamId;
+
+// 5.3.5 Default Priorities: weight 16
+const DEFAULT_WEIGHT: u16 = 16;
+
+/// Concurrent streams accounted for when SETTINGS_MAX_CONCURRENT_STREAMS is unlimited
+const MAX_TRACKED_STREAMS: usize = 1000;
+/// Idle and closed streams kept in the tree in addition to concurrent streams
+/// (5.3.4 allows limiting priority state)
+const IDLE_STREAMS_ALLOWANCE: usize = 100;
+
+struct Node {
+    parent: StreamId,
+    children: Vec<StreamId>,
+    /// Weight in range 1..=256
+    weight: u16,
+    /// Bytes sent by stream and its dependents divided by weight,
+    /// sibling with smallest value is served first
+    vtime: u64,
+}
+
+impl Node {
+    fn new(parent: StreamId, weight: u16) -> Node {
+        Node {
+            parent,
+            children: Vec::new(),
+            weight,
+            vtime: 0,
+        }
+    }
+}
+
+/// Streams depend on parent streams, stream 0 is the root.
+///
+/// Dependent stream is served only when its parent cannot proceed,
+/// siblings share bandwidth proportionally to their weights.
+///
+/// Priority frames may reference any number of idle streams, so the number
+/// of streams given priority by the peer is limited, frames adding streams
+/// over the limit are ignored.
+pub struct PriorityTree {
+    nodes: HashMap<StreamId, Node>,
+    /// Maximum number of streams (root excluded) added by `reprioritize`
+    max_nodes: usize,
+}
+
+impl PriorityTree {
+    /// `max_concurrent_streams` is our SETTINGS_MAX_CONCURRENT_STREAMS
+    pub fn new(max_concurrent_streams: u32) -> PriorityTree {
+        let mut nodes = HashMap::new();
+        nodes.insert(0, Node::new(0, DEFAULT_WEIGHT));
+        let max_nodes = cmp::min(max_concurrent_streams as usize, MAX_TRACKED_STREAMS)
+            + IDLE_STREAMS_ALLOWANCE;
+        PriorityTree { nodes, max_nodes }
+    }
+
+    pub fn contains(&self, stream_id: StreamId) -> bool {
+        self.nodes.contains_key(&stream_id)
+    }
+
+    fn attach(&mut self, stream_id: StreamId, parent: StreamId) {
+        // New sibling starts with the smallest share of siblings,
+        // so it does not starve siblings which are already sending
+        let vtime = {
+            let siblings = &self.nodes[&parent].children;
+            siblings.iter().map(|id| self.nodes[id].vtime).min().unwrap_or(0)
+        };
+        self.nodes.get_mut(&parent).unwrap().children.push(stream_id);
+        let node = self.nodes.get_mut(&stream_id).unwrap();
+        node.parent = parent;
+        node.vtime = vtime;
+    }
+
+    fn detach(&mut self, stream_id: StreamId) {
+        let parent = self.nodes[&stream_id].parent;
+        self.nodes
+            .get_mut(&parent)
+            .unwrap()
+            .children
+            .retain(|&id| id != stream_id);
+    }
+
+    /// Add stream depending on root with default weight if it is not in the tree
+    pub fn insert_default(&mut self, stream_id: StreamId) {
+        if stream_id == 0 || self.contains(stream_id) {
+            return;
+        }
+        self.nodes.insert(stream_id, Node::new(0, DEFAULT_WEIGHT));
+        self.attach(stream_id, 0);
+    }
+
+    fn is_descendant(&self, stream_id: StreamId, ancestor: StreamId) -> bool {
+        let mut id = stream_id;
+        while id != 0 {
+            id = self.nodes[&id].parent;
+            if id == ancestor {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Apply priority from HEADERS or PRIORITY frame, `weight` is frame representation
+    pub fn reprioritize(
+        &mut self,
+        stream_id: StreamId,
+        depends_on: StreamId,
+        weight: u8,
+        exclusive: bool,
+    ) {
+        if stream_id == 0 || stream_id == depends_on {
+            return;
+        }
+
+        let new_nodes = [stream_id, depends_on]
+            .iter()
+            .filter(|&&id| id != 0 && !self.contains(id))
+            .count();
+        if self.nodes.len() - 1 + new_nodes > self.max_nodes {
+            debug!(
+                "priority tree is full, ignoring priority of stream {}",
+                stream_id
+            );
+            return;
+        }
+
+        self.insert_default(stream_id);
+        self.insert_default(depends_on);
+
+        // 5.3.3: when stream is made dependent on its own dependency,
+        // the dependency is first moved to the former parent of the stream
+        if self.is_descendant(depends_on, stream_id) {
+            let old_parent = self.nodes[&stream_id].parent;
+            self.detach(depends_on);
+            self.attach(depends_on, old_parent);
+        }
+
+        self.detach(stream_id);
+
+        if exclusive {
+            let children = mem::take(&mut self.nodes.get_mut(&depends_on).unwrap().children);
+            for child in children {
+                self.nodes.get_mut(&child).unwrap().parent = stream_id;
+                self.nodes.get_mut(&stream_id).unwrap().children.push(child);
+            }
+        }
+
+        self.attach(stream_id, depends_on);
+        self.nodes.get_mut(&stream_id).unwrap().weight = weight as u16 + 1;
+    }
+
+    /// Remove stream, its dependents take its place sharing its weight (5.3.4)
+    pub fn remove(&mut self, stream_id: StreamId) {
+        if stream_id == 0 || !self.contains(stream_id) {
+            return;
+        }
+        self.detach(stream_id);
+        let node = self.nodes.remove(&stream_id).unwrap();
+        let total: u32 = node
+            .children
+            .iter()
+            .map(|id| self.nodes[id].weight as u32)
+            .sum();
+        for child in node.children {
+            {
+                let child = self.nodes.get_mut(&child).unwrap();
+                let weight = child.weight as u32 * node.weight as u32 / total;
+                child.weight = weight.max(1) as u16;
+            }
+            self.attach(child, node.parent);
+        }
+    }
+
+    /// Remove streams for which `keep` returns `false`
+    pub fn retain<F: Fn(StreamId) -> bool>(&mut self, keep: F) {
+        let remove: Vec<StreamId> = self
+            .nodes
+            .keys()
+            .cloned()
+            .filter(|&id| id != 0 && !keep(id))
+            .collect();
+        for stream_id in remove {
+            self.remove(stream_id);
+        }
+    }
+
+    /// Push children of `parent` to `stack` so that the child to visit first is on top
+    fn push_children(&self, parent: StreamId, stack: &mut Vec<StreamId>) {
+        let mut children = self.nodes[&parent].children.clone();
+        children.sort_by_key(|id| cmp::Reverse((self.nodes[id].vtime, *id)));
+        stack.extend(children);
+    }
+
+    fn next_under<F>(&self, parent: StreamId, writable: &F) -> Option<StreamId>
+    where
+        F: Fn(StreamId) -> bool,
+    {
+        // Depth-first with explicit stack: dependency chain may be as long as the tree
+        let mut stack = Vec::new();
+        self.push_children(parent, &mut stack);
+        while let Some(stream_id) = stack.pop() {
+            if writable(stream_id) {
+                return Some(stream_id);
+            }
+            self.push_children(stream_id, &mut stack);
+        }
+        None
+    }
+
+    /// Stream to write next among streams for which `writable` returns `true`
+    pub fn next<F: Fn(StreamId) -> bool>(&self, writable: F) -> Option<StreamId> {
+        self.next_under(0, &writable)
+    }
+
+    /// Account bytes written to stream to the stream and all its ancestors
+    pub fn charge(&mut self, stream_id: StreamId, bytes: usize) {
+        let mut id = stream_id;
+        while id != 0 {
+            let node = match self.nodes.get_mut(&id) {
+                Some(node) => node,
+                None => return,
+            };
+            // frames without payload still take a turn
+            node.vtime += (bytes as u64 + 1) * 256 / node.weight as u64;
+            id = node.parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    fn parent(tree: &PriorityTree, stream_id: StreamId) -> StreamId {
+        tree.nodes[&stream_id].parent
+    }
+
+    #[test]
+    fn exclusive() {
+        let mut tree = PriorityTree::new(100);
+        tree.insert_default(1);
+        tree.insert_default(3);
+        tree.reprioritize(5, 0, 15, true);
+
+        assert_eq!(5, parent(&tree, 1));
+        assert_eq!(5, parent(&tree, 3));
+        assert_eq!(0, parent(&tree, 5));
+
+        let all = |_| true;
+        assert_eq!(Some(5), tree.next(all));
+        assert_eq!(Some(1), tree.next(|id| id != 5));
+    }
+
+    #[test]
+    fn depend_on_dependent() {
+        // 5.3.3 example: A depends on its dependent D
+        let mut tree = PriorityTree::new(100);
+        tree.reprioritize(1, 0, 15, false);
+        tree.reprioritize(3, 1, 15, false);
+        tree.reprioritize(5, 3, 15, false);
+
+        tree.reprioritize(1, 5, 15, false);
+
+        assert_eq!(0, parent(&tree, 5));
+        assert_eq!(5, parent(&tree, 1));
+        assert_eq!(1, parent(&tree, 3));
+    }
+
+    #[test]
+    fn weights() {
+        let mut tree = PriorityTree::new(100);
+        // weight 64 and 16
+        tree.reprioritize(1, 0, 63, false);
+        tree.reprioritize(3, 0, 15, false);
+
+        let mut counts = [0, 0];
+        for _ in 0..100 {
+            let stream_id = tree.next(|_| true).unwrap();
+            counts[(stream_id / 2) as usize] += 1;
+            tree.charge(stream_id, 1000);
+        }
+        assert!(counts[0] >= 75 && counts[0] <= 85, "{:?}", counts);
+    }
+
+    #[test]
+    fn remove_moves_dependents() {
+        let mut tree = PriorityTree::new(100);
+        tree.reprioritize(1, 0, 15, false);
+        tree.reprioritize(3, 1, 15, false);
+        tree.reprioritize(5, 1, 47, false);
+
+        tree.remove(1);
+        assert_eq!(0, parent(&tree, 3));
+        assert_eq!(0, parent(&tree, 5));
+        // weight 16 is split 1:3
+        assert_eq!(4, tree.nodes[&3].weight);
+        assert_eq!(12, tree.nodes[&5].weight);
+
+        let keep: HashSet<StreamId> = vec![5].into_iter().collect();
+        tree.retain(|id| keep.contains(&id));
+        assert!(!tree.contains(3));
+        assert!(tree.contains(5));
+    }
+
+    #[test]
+    fn deep_chain() {
+        let mut tree = PriorityTree::new(100);
+        // chain 1 <- 3 <- 5 <- ... deeper than recursion would handle
+        let depth = 100_000;
+        for i in 0..depth {
+            let stream_id = i * 2 + 1;
+            tree.nodes.insert(stream_id, Node::new(0, DEFAULT_WEIGHT));
+            tree.attach(stream_id, stream_id.saturating_sub(2));
+        }
+        let last = depth * 2 - 1;
+        assert_eq!(Some(last), tree.next(|id| id == last));
+        assert_eq!(None, tree.next(|_| false));
+        tree.charge(last, 10);
+    }
+
+    #[test]
+    fn idle_streams_limit() {
+        let mut tree = PriorityTree::new(10);
+        tree.reprioritize(1, 0, 15, false);
+        // PRIORITY frames for idle streams
+        for i in 1..1000 {
+            tree.reprioritize(i * 2 + 1, (i - 1) * 2 + 1, 15, false);
+        }
+        assert_eq!(10 + IDLE_STREAMS_ALLOWANCE + 1, tree.nodes.len());
+
+        // existing streams can still be reprioritized
+        tree.reprioritize(5, 0, 31, true);
+        assert_eq!(0, parent(&tree, 5));
+        assert_eq!(32, tree.nodes[&5].weight);
+
+        // streams with data are always added
+        tree.insert_default(5001);
+        assert_eq!(Some(5001), tree.next(|id| id == 5001));
+    }
+}
@@ -11,10 +11,20 @@ use solicit::StreamId;
 use data_or_headers::DataOrHeaders;
 use data_or_headers_with_flag::DataOrHeadersWithFlagStream;
 
+use error;
 use error::ErrorCode;
 
 use super::*;
 
+/// How `PumpStreamToWrite` finished
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PumpStreamEnd {
+    /// Stream was fully consumed or failed
+    Completed,
+    /// Connection or stream was closed before stream was fully consumed
+    Aborted,
+}
+
 /// Poll the stream and enqueues frames
 pub struct PumpStreamToWrite<T: Types> {
     // TODO: this is not thread-safe
@@ -25,29 +35,54 @@ pub struct PumpStreamToWrite<T: Types> {
 }
 
 impl<T: Types> Future for PumpStreamToWrite<T> {
-    type Item = ();
+    type Item = PumpStreamEnd;
     type Error = Void;
 
-    fn poll(&mut self) -> Poll<(), Void> {
+    fn poll(&mut self) -> Poll<PumpStreamEnd, Void> {
         loop {
             match self.out_window.poll() {
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
                 Ok(Async::Ready(())) => {}
                 Err(window_size::StreamDead::Conn) => {
                     warn!("conn dead");
-                    return Ok(Async::Ready(()));
+                    return Ok(Async::Ready(PumpStreamEnd::Aborted));
                 }
                 Err(window_size::StreamDead::Stream) => {
                     warn!("stream {} dead", self.stream_id);
-                    return Ok(Async::Ready(()));
+                    return Ok(Async::Ready(PumpStreamEnd::Aborted));
                 }
             }
 
             let part_opt = match self.stream.poll() {
-                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::NotReady) => {
+                    // Wake up when stream is closed to drop the handler
+                    if self.out_window.register_task().is_err() {
+                        continue;
+                    }
+                    return Ok(Async::NotReady);
+                }
                 Ok(Async::Ready(r)) => r,
+                Err(error::Error::DiscardRequestBody) => {
+                    // Stream is complete, but the rest of the peer stream is not needed
+                    let msg = CommonToWriteMessage::StreamEndRstNoError(self.stream_id);
+                    if let Err(e) = self.to_write_tx.unbounded_send(msg.into()) {
+                        warn!(
+                            "failed to write to channel, probably connection is closed: {:?}",
+                            e
+                        );
+                    }
+                    break;
+                }
                 Err(e) => {
-                    warn!("stream error: {:?}", e);
+                    // Headers may be already sent, so the only option is to reset the stream
+                    match e {
+                        error::Error::HandlerPanicked(ref message) => {
+                            warn!("stream {} handler panicked: {}", self.stream_id, message);
+                        }
+                        ref e => {
+                            warn!("stream {} error: {:?}", self.stream_id, e);
+                        }
+                    }
                     let stream_end =
                         CommonToWriteMessage::StreamEnd(self.stream_id, ErrorCode::InternalError);
                     if let Err(e) = self.to_write_tx.unbounded_send(stream_end.into()) {
@@ -95,6 +130,6 @@ impl<T: Types> Future for PumpStreamToWrite<T> {
             }
         }
 
-        Ok(Async::Ready(()))
+        Ok(Async::Ready(PumpStreamEnd::Completed))
     }
 }
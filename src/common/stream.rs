@@ -40,14 +40,19 @@ impl HttpStreamCommand {
     }
 }
 
+/// Stream state returned in `ConnStateSnapshot`
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct HttpStreamStateSnapshot {
+pub struct StreamStateSnapshot {
     pub state: StreamState,
     pub out_window_size: i32,
     pub in_window_size: i32,
+    /// Bytes of data waiting to be sent, e. g. for flow control window
     pub out_data_size: usize,
+    /// Which part of incoming message is received
+    pub in_message_stage: InMessageStage,
 }
 
+/// Which part of incoming message was received
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum InMessageStage {
     Initial,
@@ -69,7 +74,15 @@ pub struct HttpStreamCommon<T: Types> {
     pub pump_out_window: window_size::StreamOutWindowSender,
     // Incoming remaining content-length
     pub in_rem_content_length: Option<u64>,
+    // Incoming remaining body bytes allowed when content-length is unknown
+    pub in_rem_body_limit: Option<u64>,
     pub in_message_stage: InMessageStage,
+    // Send RST_STREAM(NO_ERROR) after END_STREAM if peer is still sending
+    pub rst_no_error_after_end: bool,
+    // HTTP/2 weight sent with HEADERS and used to order writes
+    pub weight: Option<u8>,
+    // HEADERS frame is written, so peer knows about the stream
+    pub headers_sent: bool,
 }
 
 impl<T: Types> HttpStreamCommon<T> {
@@ -91,16 +104,21 @@ impl<T: Types> HttpStreamCommon<T> {
             peer_tx: Some(incoming),
             pump_out_window,
             in_rem_content_length,
+            in_rem_body_limit: None,
             in_message_stage,
+            rst_no_error_after_end: false,
+            weight: None,
+            headers_sent: false,
         }
     }
 
-    pub fn snapshot(&self) -> HttpStreamStateSnapshot {
-        HttpStreamStateSnapshot {
+    pub fn snapshot(&self) -> StreamStateSnapshot {
+        StreamStateSnapshot {
             state: self.state,
             out_window_size: self.out_window_size.0,
             in_window_size: self.in_window_size.0,
             out_data_size: self.outgoing.data_size(),
+            in_message_stage: self.in_message_stage,
         }
     }
 
@@ -125,6 +143,19 @@ impl<T: Types> HttpStreamCommon<T> {
         }
     }
 
+    // Response is complete, but peer is still sending; drop the rest
+    fn reset_remote_no_error(&mut self) {
+        trace!("reset remote with NO_ERROR");
+        self.state = StreamState::Closed;
+
+        if let Some(handler) = self.peer_tx.take() {
+            handler
+                .send(ResultOrEof::Error(error::Error::CodeError(
+                    ErrorCode::NoError,
+                ))).ok();
+        }
+    }
+
     /// Must be kept in sync with `pop_outg`.
     pub fn is_writable(&self) -> bool {
         match self.outgoing.front() {
@@ -137,6 +168,9 @@ impl<T: Types> HttpStreamCommon<T> {
                     if !self.state.is_closed_local() {
                         return true;
                     }
+                    if self.rst_no_error_after_end && !self.state.is_closed_remote() {
+                        return true;
+                    }
                 };
 
                 false
@@ -170,7 +204,13 @@ impl<T: Types> HttpStreamCommon<T> {
         if self.outgoing.is_empty() {
             return if let Some(error_code) = self.outgoing.end() {
                 if self.state.is_closed_local() {
-                    None
+                    if self.rst_no_error_after_end && !self.state.is_closed_remote() {
+                        self.rst_no_error_after_end = false;
+                        self.reset_remote_no_error();
+                        Some(HttpStreamCommand::Rst(ErrorCode::NoError))
+                    } else {
+                        None
+                    }
                 } else {
                     self.close_local();
                     Some(match error_code {
@@ -189,6 +229,7 @@ impl<T: Types> HttpStreamCommon<T> {
             false
         };
         if pop_headers {
+            self.headers_sent = true;
             let r = self.outgoing.pop_front().unwrap();
             let last = self.outgoing.end() == Some(ErrorCode::NoError);
             if last {
@@ -256,11 +297,43 @@ impl<T: Types> HttpStreamCommon<T> {
         }
     }
 
-    pub fn goaway_recvd(&mut self, _raw_error_code: u32) {
+    /// Received body length does not match declared content-length
+    pub fn content_length_mismatch(&mut self) {
+        if let Some(response_handler) = self.peer_tx.take() {
+            let error = error::Error::CodeError(ErrorCode::ProtocolError);
+            response_handler.send_error(error).ok();
+        }
+    }
+
+    /// Received body is larger than allowed
+    pub fn body_too_large(&mut self) {
+        if let Some(response_handler) = self.peer_tx.take() {
+            response_handler.send_error(error::Error::BodyTooLarge).ok();
+        }
+    }
+
+    /// Stream is reset by us, e. g. because connection is force closed
+    pub fn reset_locally(&mut self, error_code: ErrorCode) {
+        if let Some(response_handler) = self.peer_tx.take() {
+            let error = error::Error::CodeError(error_code);
+            response_handler.send_error(error).ok();
+        }
+    }
+
+    /// Peer closed the connection before completing the stream
+    pub fn eof_recvd(&mut self) {
+        if let Some(response_handler) = self.peer_tx.take() {
+            response_handler.send_error(error::Error::EofFromPeer).ok();
+        }
+    }
+
+    pub fn goaway_recvd(&mut self, error_code: ErrorCode, debug_data: Bytes) {
         if let Some(response_handler) = self.peer_tx.take() {
             // it is OK to ignore error: handler may be already dead
             drop(
-                response_handler.send(ResultOrEof::Error(error::Error::Other("peer sent GOAWAY"))),
+                response_handler.send(ResultOrEof::Error(error::Error::RefusedByGoaway(
+                    error_code, debug_data,
+                ))),
             );
         }
     }
@@ -1,17 +1,21 @@
 #![allow(dead_code)]
 
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
 use futures::stream::Stream;
 use futures::sync::mpsc::UnboundedSender;
 use futures::Async;
 use futures::Poll;
 
 use solicit::StreamId;
-use solicit::DEFAULT_SETTINGS;
 
 use error;
 
 use super::stream_queue_sync::StreamQueueSyncReceiver;
 use super::types::Types;
+use common::conf::WindowUpdateStrategy;
 use common::conn_write::CommonToWriteMessage;
 use data_or_headers::DataOrHeaders;
 use data_or_headers_with_flag::DataOrHeadersWithFlag;
@@ -23,6 +27,31 @@ pub struct StreamFromNetwork<T: Types> {
     pub stream_id: StreamId,
     pub to_write_tx: UnboundedSender<T::ToWriteMessage>,
     pub in_window_size: u32,
+    /// Message sent to conn if stream is dropped before it is complete
+    pub cancel_on_drop: Option<T::ToWriteMessage>,
+    /// Stream returned end or error
+    pub complete: bool,
+    /// Stream initial window restored by WINDOW_UPDATE
+    pub in_window_size_target: u32,
+    /// When to send WINDOW_UPDATE
+    pub window_update_strategy: WindowUpdateStrategy,
+    /// High and low water marks of buffered data
+    pub queue_water: Option<(u32, u32)>,
+    /// Buffered data exceeded high-water mark and not yet drained below low-water mark
+    pub queue_full: bool,
+    /// WINDOW_UPDATE increment not sent because queue is full
+    pub deferred_window_update: u32,
+    /// Sum of `deferred_window_update` of all connection streams
+    pub deferred_window_update_total: Arc<AtomicUsize>,
+}
+
+impl<T: Types> StreamFromNetwork<T> {
+    fn set_deferred_window_update(&mut self, increment: u32) {
+        let total = &self.deferred_window_update_total;
+        total.fetch_add(increment as usize, Ordering::SeqCst);
+        total.fetch_sub(self.deferred_window_update as usize, Ordering::SeqCst);
+        self.deferred_window_update = increment;
+    }
 }
 
 impl<T: Types> Stream for StreamFromNetwork<T> {
@@ -32,8 +61,14 @@ impl<T: Types> Stream for StreamFromNetwork<T> {
     fn poll(&mut self) -> Poll<Option<DataOrHeadersWithFlag>, error::Error> {
         let part = match self.rx.poll() {
             Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Err(e) => return Err(e),
-            Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+            Err(e) => {
+                self.complete = true;
+                return Err(e);
+            }
+            Ok(Async::Ready(None)) => {
+                self.complete = true;
+                return Ok(Async::Ready(None));
+            }
             Ok(Async::Ready(Some(part))) => part,
         };
 
@@ -44,16 +79,33 @@ impl<T: Types> Stream for StreamFromNetwork<T> {
         {
             self.in_window_size -= b.len() as u32;
 
-            // TODO: use different
+            let data_size = self.rx.data_size();
+            if let Some((high, low)) = self.queue_water {
+                if !self.queue_full && data_size > high {
+                    debug!("stream {} receive queue is full", self.stream_id);
+                    self.queue_full = true;
+                } else if self.queue_full && data_size < low {
+                    debug!("stream {} receive queue is drained", self.stream_id);
+                    self.queue_full = false;
+                }
+            }
+
             // TODO: increment after process of the frame (i. e. on next poll)
-            let edge = DEFAULT_SETTINGS.initial_window_size / 2;
-            if self.in_window_size + self.rx.data_size() < edge {
-                let inc = DEFAULT_SETTINGS.initial_window_size;
-                let m = CommonToWriteMessage::IncreaseInWindow(self.stream_id, inc);
-                if let Err(_) = self.to_write_tx.unbounded_send(m.into()) {
-                    return Err(error::Error::Other("failed to send to conn; likely died"));
+            let current = self.in_window_size as i64 + data_size as i64;
+            let increment = self
+                .window_update_strategy
+                .increment(self.in_window_size_target, current);
+            if let Some(inc) = increment {
+                if self.queue_full {
+                    self.set_deferred_window_update(inc);
+                } else {
+                    self.set_deferred_window_update(0);
+                    let m = CommonToWriteMessage::IncreaseInWindow(self.stream_id, inc);
+                    if let Err(_) = self.to_write_tx.unbounded_send(m.into()) {
+                        return Err(error::Error::Other("failed to send to conn; likely died"));
+                    }
+                    self.in_window_size += inc;
                 }
-                self.in_window_size += inc;
             }
         }
 
@@ -63,6 +115,12 @@ impl<T: Types> Stream for StreamFromNetwork<T> {
 
 impl<T: Types> Drop for StreamFromNetwork<T> {
     fn drop(&mut self) {
-        // TODO: reset stream
+        self.set_deferred_window_update(0);
+        if !self.complete {
+            if let Some(cancel) = self.cancel_on_drop.take() {
+                // conn might be already dead
+                drop(self.to_write_tx.unbounded_send(cancel));
+            }
+        }
     }
 }
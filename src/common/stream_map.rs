@@ -7,7 +7,7 @@ use error::ErrorCode;
 
 use super::stream::HttpStreamCommand;
 use super::stream::HttpStreamCommon;
-use super::stream::HttpStreamStateSnapshot;
+use super::stream::StreamStateSnapshot;
 use super::types::Types;
 use common::hash_set_shallow_clone::HashSetShallowClone;
 use common::hash_set_shallow_clone::HashSetShallowCloneItems;
@@ -102,7 +102,7 @@ impl<T: Types> StreamMap<T> {
         self.writable_streams.items()
     }
 
-    pub fn snapshot(&self) -> HashMap<StreamId, HttpStreamStateSnapshot> {
+    pub fn snapshot(&self) -> HashMap<StreamId, StreamStateSnapshot> {
         self.map.iter().map(|(&k, s)| (k, s.snapshot())).collect()
     }
 }
@@ -221,6 +221,12 @@ impl<'m, T: Types + 'm> HttpStreamRef<'m, T> {
         self.sync_writable();
     }
 
+    pub fn close_outgoing_rst_no_error(&mut self) {
+        self.stream().outgoing.close(ErrorCode::NoError);
+        self.stream().rst_no_error_after_end = true;
+        self.sync_writable();
+    }
+
     pub fn close_remote(mut self) {
         self.stream().close_remote();
         self.remove_if_closed();
@@ -49,6 +49,11 @@ impl StreamQueueSyncSender {
         self.sender.unbounded_send(item).map_err(|_| ())
     }
 
+    /// Bytes of data sent but not yet received
+    pub fn data_size(&self) -> usize {
+        self.shared.data_size.load(Ordering::SeqCst)
+    }
+
     pub fn send_part(&self, part: DataOrHeadersWithFlag) -> Result<(), ()> {
         self.send(ResultOrEof::Item(part))
     }
@@ -0,0 +1,112 @@
+//! Flow control invariants checked in debug builds
+
+/// Totals of flow-controlled bytes and connection window increments.
+///
+/// Connection window must be equal to initial window plus increments minus DATA
+/// in each direction. Totals are tracked and checked only with `debug_assertions`,
+/// in release builds the struct is empty and all methods are no-op.
+#[derive(Default, Debug)]
+pub struct WindowAccounting {
+    /// Initial connection window size
+    #[cfg(debug_assertions)]
+    initial: i64,
+    /// DATA payload received
+    #[cfg(debug_assertions)]
+    in_data: i64,
+    /// Connection WINDOW_UPDATE increments sent
+    #[cfg(debug_assertions)]
+    in_increments: i64,
+    /// DATA payload sent
+    #[cfg(debug_assertions)]
+    out_data: i64,
+    /// Connection WINDOW_UPDATE increments received
+    #[cfg(debug_assertions)]
+    out_increments: i64,
+}
+
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+impl WindowAccounting {
+    pub fn new(initial: u32) -> WindowAccounting {
+        #[allow(unused_mut)]
+        let mut accounting = WindowAccounting::default();
+        #[cfg(debug_assertions)]
+        {
+            accounting.initial = initial as i64;
+        }
+        accounting
+    }
+
+    pub fn data_received(&mut self, len: u32) {
+        #[cfg(debug_assertions)]
+        {
+            self.in_data += len as i64;
+        }
+    }
+
+    pub fn increment_sent(&mut self, increment: u32) {
+        #[cfg(debug_assertions)]
+        {
+            self.in_increments += increment as i64;
+        }
+    }
+
+    pub fn data_sent(&mut self, len: usize) {
+        #[cfg(debug_assertions)]
+        {
+            self.out_data += len as i64;
+        }
+    }
+
+    pub fn increment_received(&mut self, increment: u32) {
+        #[cfg(debug_assertions)]
+        {
+            self.out_increments += increment as i64;
+        }
+    }
+
+    /// Panic if connection windows do not match totals or are negative
+    pub fn check(&self, in_window_size: i32, out_window_size: i32) {
+        #[cfg(debug_assertions)]
+        {
+            let in_expected = self.initial + self.in_increments - self.in_data;
+            assert_eq!(
+                in_expected, in_window_size as i64,
+                "conn in window does not match: initial {} + increments {} - data {}",
+                self.initial, self.in_increments, self.in_data
+            );
+            let out_expected = self.initial + self.out_increments - self.out_data;
+            assert_eq!(
+                out_expected, out_window_size as i64,
+                "conn out window does not match: initial {} + increments {} - data {}",
+                self.initial, self.out_increments, self.out_data
+            );
+
+            // we never send DATA over window and peer DATA over window is an error
+            assert!(in_window_size >= 0, "conn in window is negative");
+            assert!(out_window_size >= 0, "conn out window is negative");
+        }
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let mut accounting = WindowAccounting::new(100);
+        accounting.data_received(30);
+        accounting.increment_sent(50);
+        accounting.data_sent(100);
+        accounting.increment_received(10);
+        accounting.check(120, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "conn out window does not match")]
+    fn check_mismatch() {
+        let mut accounting = WindowAccounting::new(100);
+        accounting.data_sent(10);
+        accounting.check(100, 100);
+    }
+}
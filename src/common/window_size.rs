@@ -180,6 +180,15 @@ impl StreamOutWindowReceiver {
         )
     }
 
+    /// Notify current task when stream or connection is closed.
+    pub fn register_task(&self) -> Result<(), StreamDead> {
+        self.shared
+            .task
+            .store_box(Box::new(task::current()), Ordering::SeqCst);
+
+        self.check_stream_closed()
+    }
+
     pub fn poll(&self) -> Poll<(), StreamDead> {
         self.check_stream_closed()?;
 
@@ -1,10 +1,18 @@
+use std::collections::VecDeque;
+use std::io;
 use std::panic;
 
+use futures::future;
+use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
+use futures::Async;
 use futures::Poll;
 
 use bytes::Bytes;
+use bytes::BytesMut;
+
+use tokio_io::AsyncRead;
 
 use error;
 
@@ -52,7 +60,11 @@ impl DataOrTrailers {
 ///
 /// Most users won't need anything except data, so this type provides
 /// convenient constructors and accessors.
-pub struct HttpStreamAfterHeaders(pub HttpFutureStreamSend<DataOrTrailers>);
+pub struct HttpStreamAfterHeaders(
+    pub HttpFutureStreamSend<DataOrTrailers>,
+    /// In-memory content if the stream is created with `retryable`
+    Option<Bytes>,
+);
 
 impl HttpStreamAfterHeaders {
     // constructors
@@ -62,7 +74,7 @@ impl HttpStreamAfterHeaders {
     where
         S: Stream<Item = DataOrTrailers, Error = error::Error> + Send + 'static,
     {
-        HttpStreamAfterHeaders(Box::new(s))
+        HttpStreamAfterHeaders(Box::new(s), None)
     }
 
     pub(crate) fn from_parts<S>(s: S) -> HttpStreamAfterHeaders
@@ -85,6 +97,38 @@ impl HttpStreamAfterHeaders {
         HttpStreamAfterHeaders::new(bytes.map(DataOrTrailers::intermediate_data))
     }
 
+    /// Create a stream from a stream of bytes followed by trailers known upfront.
+    ///
+    /// Same as `bytes(bytes).with_trailers(future::ok(trailers))`.
+    pub fn bytes_with_trailers<S>(bytes: S, trailers: Headers) -> HttpStreamAfterHeaders
+    where
+        S: Stream<Item = Bytes, Error = error::Error> + Send + 'static,
+    {
+        HttpStreamAfterHeaders::bytes(bytes).with_trailers(future::ok(trailers))
+    }
+
+    /// Create a stream reading `DATA` lazily from `read` in chunks
+    /// of up to `chunk_size` bytes, e. g. to proxy large request body.
+    ///
+    /// Reader is polled only when the stream is polled, i. e. when
+    /// flow control window allows sending more data. Stream ends
+    /// (and `END_STREAM` is sent) on EOF, read error fails the stream,
+    /// so the peer stream is reset.
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is zero.
+    pub fn from_read<R>(read: R, chunk_size: usize) -> HttpStreamAfterHeaders
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        HttpStreamAfterHeaders::bytes(ReadStream {
+            read,
+            buf: vec![0; chunk_size],
+        })
+    }
+
     pub fn once(part: DataOrHeaders) -> HttpStreamAfterHeaders {
         let part = match part {
             DataOrHeaders::Data(data) => DataOrTrailers::Data(data, EndStream::Yes),
@@ -105,8 +149,126 @@ impl HttpStreamAfterHeaders {
         HttpStreamAfterHeaders::once(DataOrHeaders::Data(bytes.into()))
     }
 
+    /// Create stream from in-memory bytes and mark the request as retryable.
+    ///
+    /// Client resends such request when the server refuses it without processing
+    /// (see `ClientConf::max_stream_refused_retries`), other request bodies
+    /// cannot be replayed, so their requests fail immediately.
+    pub fn retryable<B>(bytes: B) -> HttpStreamAfterHeaders
+    where
+        B: Into<Bytes>,
+    {
+        let bytes = bytes.into();
+        let mut stream = HttpStreamAfterHeaders::once_bytes(bytes.clone());
+        stream.1 = Some(bytes);
+        stream
+    }
+
+    /// Stream is created with `retryable`
+    pub fn is_retryable(&self) -> bool {
+        self.1.is_some()
+    }
+
+    /// Content of stream created with `retryable`
+    pub(crate) fn retryable_bytes(&self) -> Option<Bytes> {
+        self.1.clone()
+    }
+
+    /// Fail with `Error::BodyTooLarge` when total size of `DATA` exceeds `max_size`
+    pub fn max_size(self, max_size: usize) -> HttpStreamAfterHeaders {
+        let mut size = 0;
+        HttpStreamAfterHeaders::new(self.0.and_then(move |part| {
+            if let DataOrTrailers::Data(ref data, _) = part {
+                size += data.len();
+                if size > max_size {
+                    return Err(error::Error::BodyTooLarge);
+                }
+            }
+            Ok(part)
+        }))
+    }
+
+    /// Re-chunk `DATA` into chunks of exactly `size` bytes: small frames
+    /// are coalesced and large frames are split. The last chunk before
+    /// trailers or the end of the stream may be shorter.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero.
+    pub fn chunked(self, size: usize) -> HttpStreamAfterHeaders {
+        assert!(size > 0, "chunk size must be positive");
+        HttpStreamAfterHeaders::new(Rechunk {
+            stream: self,
+            size,
+            buf: BytesMut::new(),
+            ready: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Send trailing HEADERS frame with END_STREAM after this stream is complete.
+    ///
+    /// `trailers` future is polled only after the last frame of this stream,
+    /// so it may depend on the body, e. g. gRPC `grpc-status`.
+    /// END_STREAM flag is cleared on DATA frames of this stream,
+    /// and the stream fails if it already contains trailers.
+    /// Stream also fails if trailers contain pseudo-headers (RFC 7540 section 8.1.2.1),
+    /// so the peer stream is reset instead of receiving malformed trailers.
+    pub fn with_trailers<F>(self, trailers: F) -> HttpStreamAfterHeaders
+    where
+        F: Future<Item = Headers, Error = error::Error> + Send + 'static,
+    {
+        let parts = self.0.and_then(|part| match part {
+            DataOrTrailers::Data(data, _) => Ok(DataOrTrailers::intermediate_data(data)),
+            DataOrTrailers::Trailers(..) => Err(error::Error::Other(
+                "stream already has trailers",
+            )),
+        });
+        let trailers = trailers
+            .and_then(|trailers| {
+                if trailers.contains_preudo_headers() {
+                    return Err(error::Error::Other("pseudo-headers in trailers"));
+                }
+                Ok(DataOrTrailers::Trailers(trailers))
+            }).into_stream();
+        HttpStreamAfterHeaders::new(parts.chain(trailers))
+    }
+
     // getters
 
+    /// Read the rest of the stream discarding the content,
+    /// resolves to the number of `DATA` bytes read.
+    ///
+    /// Server handler may return a response immediately and spawn this future
+    /// to keep reading the request body: request stream stays open for reading
+    /// after the response is complete, and the flow control window is updated
+    /// as data is consumed. Dropping the request stream instead leaves the rest
+    /// of the body unread, which stalls the client when the window is exhausted.
+    pub fn drain(self) -> impl Future<Item = u64, Error = error::Error> + Send {
+        self.fold(0, |size, part| {
+            let size = match part {
+                DataOrTrailers::Data(data, ..) => size + data.len() as u64,
+                DataOrTrailers::Trailers(..) => size,
+            };
+            Ok::<_, error::Error>(size)
+        })
+    }
+
+    /// Read the whole stream, resolves to concatenated `DATA`
+    /// and trailers if peer sent them, e. g. request body checksum
+    /// sent by client after the body.
+    pub fn collect_with_trailers(
+        self,
+    ) -> impl Future<Item = (Bytes, Option<Headers>), Error = error::Error> + Send {
+        self.fold((BytesMut::new(), None), |(mut body, trailers), part| {
+            match part {
+                DataOrTrailers::Data(data, ..) => body.extend_from_slice(&data),
+                DataOrTrailers::Trailers(t) => return Ok((body, Some(t))),
+            }
+            Ok::<_, error::Error>((body, trailers))
+        }).map(|(body, trailers)| (body.freeze(), trailers))
+    }
+
     /// Take only `DATA` frames from the stream
     pub fn filter_data(self) -> impl Stream<Item = Bytes, Error = error::Error> + Send {
         self.filter_map(|p| match p {
@@ -150,3 +312,196 @@ impl Stream for HttpStreamAfterHeaders {
         self.0.poll()
     }
 }
+
+/// Implementation of `HttpStreamAfterHeaders::from_read`
+struct ReadStream<R> {
+    read: R,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead> Stream for ReadStream<R> {
+    type Item = Bytes;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, error::Error> {
+        loop {
+            match self.read.poll_read(&mut self.buf) {
+                Ok(Async::Ready(0)) => return Ok(Async::Ready(None)),
+                Ok(Async::Ready(n)) => {
+                    return Ok(Async::Ready(Some(Bytes::from(&self.buf[..n]))))
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Implementation of `HttpStreamAfterHeaders::chunked`
+struct Rechunk {
+    stream: HttpStreamAfterHeaders,
+    size: usize,
+    /// Incomplete chunk
+    buf: BytesMut,
+    /// Parts to return before polling the stream again
+    ready: VecDeque<DataOrTrailers>,
+    done: bool,
+}
+
+impl Rechunk {
+    fn push_data(&mut self, mut data: Bytes) {
+        if !self.buf.is_empty() {
+            let take = (self.size - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data.split_to(take));
+            if self.buf.len() < self.size {
+                return;
+            }
+            let chunk = self.buf.take().freeze();
+            self.ready.push_back(DataOrTrailers::intermediate_data(chunk));
+        }
+        // full chunks of large frame are returned without copying
+        while data.len() >= self.size {
+            let chunk = data.split_to(self.size);
+            self.ready.push_back(DataOrTrailers::intermediate_data(chunk));
+        }
+        self.buf.extend_from_slice(&data);
+    }
+
+    fn flush(&mut self, end_stream: EndStream) {
+        if !self.buf.is_empty() {
+            let chunk = self.buf.take().freeze();
+            self.ready.push_back(DataOrTrailers::Data(chunk, end_stream));
+            return;
+        }
+        if end_stream == EndStream::Yes {
+            // keep END_STREAM on the last chunk
+            match self.ready.back_mut() {
+                Some(DataOrTrailers::Data(_, ref mut last)) => *last = EndStream::Yes,
+                _ => self
+                    .ready
+                    .push_back(DataOrTrailers::Data(Bytes::new(), EndStream::Yes)),
+            }
+        }
+    }
+}
+
+impl Stream for Rechunk {
+    type Item = DataOrTrailers;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<DataOrTrailers>, error::Error> {
+        loop {
+            if let Some(part) = self.ready.pop_front() {
+                return Ok(Async::Ready(Some(part)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            let part = match self.stream.poll()? {
+                Async::Ready(part) => part,
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            match part {
+                Some(DataOrTrailers::Data(data, end_stream)) => {
+                    self.push_data(data);
+                    if end_stream == EndStream::Yes {
+                        self.flush(EndStream::Yes);
+                        self.done = true;
+                    }
+                }
+                Some(DataOrTrailers::Trailers(trailers)) => {
+                    self.flush(EndStream::No);
+                    self.ready.push_back(DataOrTrailers::Trailers(trailers));
+                    self.done = true;
+                }
+                None => {
+                    self.flush(EndStream::No);
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn collect_parts(stream: HttpStreamAfterHeaders) -> Vec<(Vec<u8>, bool)> {
+        stream
+            .map(|part| match part {
+                DataOrTrailers::Data(data, end_stream) => {
+                    (data.to_vec(), end_stream == EndStream::Yes)
+                }
+                DataOrTrailers::Trailers(..) => (b"trailers".to_vec(), true),
+            }).collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn from_read() {
+        let content: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        let stream = HttpStreamAfterHeaders::from_read(io::Cursor::new(content.clone()), 100);
+        let parts = collect_parts(stream);
+        assert_eq!(vec![100, 100, 50], parts.iter().map(|p| p.0.len()).collect::<Vec<_>>());
+        let data: Vec<u8> = parts.into_iter().flat_map(|p| p.0).collect();
+        assert_eq!(content, data);
+
+        struct FailingRead;
+
+        impl io::Read for FailingRead {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "test"))
+            }
+        }
+
+        impl AsyncRead for FailingRead {}
+
+        match HttpStreamAfterHeaders::from_read(FailingRead, 100).collect().wait() {
+            Err(error::Error::IoError(..)) => {}
+            r => panic!("{:?}", r.map(|v| v.len())),
+        }
+    }
+
+    #[test]
+    fn chunked() {
+        let frames = vec![&b"ab"[..], b"cde", b"", b"fghijkl", b"m"];
+        let stream = HttpStreamAfterHeaders::bytes(stream::iter_ok(
+            frames.into_iter().map(Bytes::from_static),
+        ));
+        assert_eq!(
+            vec![
+                (b"abc".to_vec(), false),
+                (b"def".to_vec(), false),
+                (b"ghi".to_vec(), false),
+                (b"jkl".to_vec(), false),
+                (b"m".to_vec(), false),
+            ],
+            collect_parts(stream.chunked(3))
+        );
+
+        // END_STREAM is kept on the last full chunk
+        let stream = HttpStreamAfterHeaders::once_bytes(&b"abcdef"[..]);
+        assert_eq!(
+            vec![(b"abc".to_vec(), false), (b"def".to_vec(), true)],
+            collect_parts(stream.chunked(3))
+        );
+
+        // partial chunk is flushed before trailers
+        let stream = HttpStreamAfterHeaders::bytes_with_trailers(
+            stream::once(Ok(Bytes::from_static(b"abcd"))),
+            Headers::new(),
+        );
+        assert_eq!(
+            vec![
+                (b"abc".to_vec(), false),
+                (b"d".to_vec(), false),
+                (b"trailers".to_vec(), true),
+            ],
+            collect_parts(stream.chunked(3))
+        );
+    }
+}
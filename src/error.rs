@@ -5,6 +5,8 @@ use std::sync::Arc;
 
 use assert_types::*;
 
+use bytes::Bytes;
+
 use hpack::decoder::DecoderError;
 
 use tls_api;
@@ -115,6 +117,12 @@ pub enum Error {
     /// The underlying IO layer raised an error
     IoError(io::Error),
     TlsError(tls_api::Error),
+    /// TLS handshake negotiated ALPN protocol other than `h2`,
+    /// contains negotiated protocol or `None` if no protocol was negotiated
+    AlpnMismatch(Option<String>),
+    /// Peer responded with HTTP/1.x instead of HTTP/2,
+    /// contains the bytes received from the peer
+    NotHttp2(Bytes),
     CodeError(ErrorCode),
     /// The HTTP/2 connection received an invalid HTTP/2 frame
     InvalidFrame(String),
@@ -129,12 +137,35 @@ pub enum Error {
     UnableToConnect,
     MalformedResponse,
     ConnectionTimeout,
+    /// Response headers are not received within request timeout
+    RequestTimeout,
+    /// Keepalive PING is not acknowledged in time
+    KeepaliveTimeout,
     /// Shutdown of local client or server
     Shutdown,
     HandlerPanicked(String),
     ParseFrameError(ParseFrameError),
     InternalError(String),
     NotImplemented(&'static str),
+    InvalidUrl(String),
+    /// Too many bytes are waiting to be sent, try again later
+    WriteQueueFull,
+    /// Body is larger than allowed
+    BodyTooLarge,
+    /// Peer closed the connection between frames
+    EofFromPeer,
+    /// Peer sent GOAWAY with last stream id lower than the stream id,
+    /// so the stream was not processed and can be safely retried.
+    /// Contains error code and debug data of the GOAWAY frame.
+    RefusedByGoaway(ErrorCode, Bytes),
+    /// Client disabled server push, or connection is going away
+    PushNotAllowed,
+    /// `ClientConf::fail_fast` is set and no pool connection is established
+    NoConnectionAvailable,
+    /// Not an error: marks the end of response stream created by
+    /// `Response::discard_request_body`, never returned to the user
+    #[doc(hidden)]
+    DiscardRequestBody,
     // TODO: replace with variants
     Other(&'static str),
     ClientDied(Option<Arc<Error>>),
@@ -184,6 +215,8 @@ impl StdError for Error {
         match *self {
             Error::IoError(_) => "Encountered an IO error",
             Error::TlsError(_) => "Encountered TLS error",
+            Error::AlpnMismatch(_) => "Negotiated ALPN protocol is not h2",
+            Error::NotHttp2(_) => "Peer responded with HTTP/1.x, not HTTP/2",
             Error::CodeError(_) => "Encountered HTTP named error",
             Error::InvalidFrame(..) => "Encountered an invalid or unexpected HTTP/2 frame",
             Error::CompressionError(_) => "Encountered an error with HPACK compression",
@@ -192,6 +225,8 @@ impl StdError for Error {
             Error::UnableToConnect => "An error attempting to establish an HTTP/2 connection",
             Error::MalformedResponse => "The received response was malformed",
             Error::ConnectionTimeout => "Connection time out",
+            Error::RequestTimeout => "Request time out",
+            Error::KeepaliveTimeout => "Keepalive PING time out",
             Error::Shutdown => "Local shutdown",
             Error::HandlerPanicked(_) => "Handler panicked",
             Error::ParseFrameError(_) => "Failed to parse frame",
@@ -200,6 +235,14 @@ impl StdError for Error {
             Error::ClientDied(_) => "Client died",
             Error::ClientPanicked(_) => "Client panicked",
             Error::ClientCompletedWithoutError => "Client completed without error",
+            Error::InvalidUrl(_) => "Invalid URL",
+            Error::WriteQueueFull => "Write queue is full",
+            Error::BodyTooLarge => "Body is too large",
+            Error::RefusedByGoaway(..) => "Stream is not processed by peer before GOAWAY",
+            Error::PushNotAllowed => "Server push is not allowed",
+            Error::NoConnectionAvailable => "No established connection is available",
+            Error::DiscardRequestBody => "Discard request body",
+            Error::EofFromPeer => "EOF from peer",
             Error::Other(_) => "An unknown error",
         }
     }
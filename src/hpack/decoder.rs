@@ -11,6 +11,7 @@ use bytes::Bytes;
 use super::huffman::HuffmanDecoder;
 use super::huffman::HuffmanDecoderError;
 
+use super::dynamic_table::DynamicTableStats;
 use super::static_table::StaticTable;
 use super::HeaderTable;
 
@@ -194,6 +195,9 @@ pub enum DecoderError {
     /// made by SizeUpdate blocks).
     InvalidMaxDynamicSize(u32, u32),
     SizeUpdateMustBeFirstField,
+    /// Decoded header list size (as defined for SETTINGS_MAX_HEADER_LIST_SIZE)
+    /// exceeds the limit passed to the decoder.
+    HeaderListSizeExceeded,
 }
 
 /// The result returned by the `decode` method of the `Decoder`.
@@ -235,6 +239,16 @@ impl Decoder {
         }
     }
 
+    /// Dynamic table insertion and eviction counters.
+    pub fn dynamic_table_stats(&self) -> DynamicTableStats {
+        self.header_table.dynamic_table.stats()
+    }
+
+    /// Current dynamic table size in octets as defined by HPACK.
+    pub fn dynamic_table_size(&self) -> usize {
+        self.header_table.dynamic_table.get_size()
+    }
+
     /// Sets a new maximum dynamic table size for the decoder.
     pub fn set_max_table_size(&mut self, new_max_size: usize) {
         self.max_size = new_max_size as u32;
@@ -243,6 +257,13 @@ impl Decoder {
             .set_max_table_size(new_max_size);
     }
 
+    /// Sets the maximum dynamic table size the encoder is allowed to switch to
+    /// (our SETTINGS_HEADER_TABLE_SIZE). Table itself is resized only when
+    /// the encoder sends dynamic table size update.
+    pub fn set_max_allowed_table_size(&mut self, max_size: u32) {
+        self.max_size = max_size;
+    }
+
     /// Decodes the headers found in the given buffer `buf`. Invokes the callback `cb` for each
     /// decoded header in turn, by providing it the header name and value as `Cow` byte array
     /// slices.
@@ -258,11 +279,30 @@ impl Decoder {
     ///
     /// If an error is encountered during the decoding of any header, decoding halts and the
     /// appropriate error is returned as the `Err` variant of the `Result`.
-    pub fn decode_with_cb<F>(&mut self, buf: &[u8], mut cb: F) -> Result<(), DecoderError>
+    pub fn decode_with_cb<F>(&mut self, buf: &[u8], cb: F) -> Result<(), DecoderError>
+    where
+        F: FnMut(Bytes, Bytes),
+    {
+        self.decode_with_limit_cb(buf, usize::MAX, cb)
+    }
+
+    /// Same as `decode_with_cb`, but stops with `HeaderListSizeExceeded` error
+    /// as soon as the size of the headers decoded so far exceeds `max_header_list_size`,
+    /// header size is name length plus value length plus 32 (RFC 7540 section 6.5.2).
+    ///
+    /// Since indexed representation is one octet for a header of any size,
+    /// this must be checked during decoding to avoid materializing huge header lists.
+    pub fn decode_with_limit_cb<F>(
+        &mut self,
+        buf: &[u8],
+        max_header_list_size: usize,
+        mut cb: F,
+    ) -> Result<(), DecoderError>
     where
         F: FnMut(Bytes, Bytes),
     {
         let mut current_octet_index = 0;
+        let mut header_list_size: usize = 0;
 
         let mut current_size_update = true;
 
@@ -273,45 +313,28 @@ impl Decoder {
             // byte.
             let initial_octet = buf[current_octet_index];
             let buffer_leftover = &buf[current_octet_index..];
-            let consumed = match FieldRepresentation::new(initial_octet) {
+            let (header, consumed) = match FieldRepresentation::new(initial_octet) {
                 FieldRepresentation::Indexed => {
-                    let ((name, value), consumed) = self.decode_indexed(buffer_leftover)?;
-                    cb(name, value);
-
-                    consumed
+                    let (header, consumed) = self.decode_indexed(buffer_leftover)?;
+                    (Some(header), consumed)
                 }
                 FieldRepresentation::LiteralWithIncrementalIndexing => {
-                    let ((name, value), consumed) = {
-                        let ((name, value), consumed) =
-                            self.decode_literal(buffer_leftover, true)?;
-                        cb(name.clone(), value.clone());
-
-                        ((name, value), consumed)
-                    };
-                    // This cannot be done in the same scope as the `decode_literal` call, since
-                    // Rust cannot figure out that the `into_owned` calls effectively drop the
-                    // borrow on `self` that the `decode_literal` return value had. Since adding
-                    // a header to the table requires a `&mut self`, it fails to compile.
-                    // Manually separating it out here works around it...
-                    self.header_table.add_header(name, value);
-
-                    consumed
+                    let ((name, value), consumed) = self.decode_literal(buffer_leftover, true)?;
+                    self.header_table.add_header(name.clone(), value.clone());
+
+                    (Some((name, value)), consumed)
                 }
                 FieldRepresentation::LiteralWithoutIndexing => {
-                    let ((name, value), consumed) = self.decode_literal(buffer_leftover, false)?;
-                    cb(name, value);
-
-                    consumed
+                    let (header, consumed) = self.decode_literal(buffer_leftover, false)?;
+                    (Some(header), consumed)
                 }
                 FieldRepresentation::LiteralNeverIndexed => {
                     // Same as the previous one, except if we were also a proxy
                     // we would need to make sure not to change the
                     // representation received here. We don't care about this
                     // for now.
-                    let ((name, value), consumed) = self.decode_literal(buffer_leftover, false)?;
-                    cb(name, value);
-
-                    consumed
+                    let (header, consumed) = self.decode_literal(buffer_leftover, false)?;
+                    (Some(header), consumed)
                 }
                 FieldRepresentation::SizeUpdate => {
                     if !current_size_update {
@@ -319,10 +342,21 @@ impl Decoder {
                     }
 
                     // Handle the dynamic table size update...
-                    self.update_max_dynamic_size(buffer_leftover)?
+                    (None, self.update_max_dynamic_size(buffer_leftover)?)
                 }
             };
 
+            if let Some((name, value)) = header {
+                header_list_size = header_list_size
+                    .saturating_add(name.len())
+                    .saturating_add(value.len())
+                    .saturating_add(32);
+                if header_list_size > max_header_list_size {
+                    return Err(DecoderError::HeaderListSizeExceeded);
+                }
+                cb(name, value);
+            }
+
             current_octet_index += consumed;
 
             match FieldRepresentation::new(initial_octet) {
@@ -343,9 +377,15 @@ impl Decoder {
     /// For example, in HTTP/2, all continuation frames need to be concatenated
     /// to a single buffer before passing them to the decoder.
     pub fn decode(&mut self, buf: &[u8]) -> DecoderResult {
+        self.decode_with_limit(buf, usize::MAX)
+    }
+
+    /// Decode the header block, failing with `HeaderListSizeExceeded`
+    /// before the decoded list grows over `max_header_list_size`.
+    pub fn decode_with_limit(&mut self, buf: &[u8], max_header_list_size: usize) -> DecoderResult {
         let mut header_list = Vec::new();
 
-        self.decode_with_cb(buf, |n, v| header_list.push((n, v)))?;
+        self.decode_with_limit_cb(buf, max_header_list_size, |n, v| header_list.push((n, v)))?;
 
         Ok(header_list)
     }
@@ -1498,6 +1538,41 @@ mod tests {
             _ => false,
         });
     }
+
+    /// Tests that a block of repeated references to a large dynamic table entry
+    /// is rejected once decoded size exceeds the limit.
+    #[test]
+    fn test_header_list_size_exceeded() {
+        let mut decoder = Decoder::new();
+        // Literal with incremental indexing, new name "x", 1000 octets value
+        let mut block = vec![0x40, 0x01, b'x'];
+        block.extend(encode_integer(1000, 7));
+        block.extend(vec![b'a'; 1000]);
+        // 1000 indexed references to that entry (index 62), ~1 MB decoded
+        block.extend(vec![0xbe; 1000]);
+
+        let result = decoder.decode_with_limit(&block, 16 * 1024);
+        assert!(is_decoder_error(&DecoderError::HeaderListSizeExceeded, &result));
+
+        let mut decoder = Decoder::new();
+        let header_list = decoder.decode_with_limit(&block, 2 * 1024 * 1024).unwrap();
+        assert_eq!(1001, header_list.len());
+    }
+
+    /// Tests that header list of exactly the limit is accepted
+    #[test]
+    fn test_header_list_size_limit_boundary() {
+        // Literal without indexing, new name "x", 10 octets value: 1 + 10 + 32 octets
+        let mut block = vec![0x00, 0x01, b'x', 10];
+        block.extend(vec![b'a'; 10]);
+
+        let mut decoder = Decoder::new();
+        assert_eq!(1, decoder.decode_with_limit(&block, 43).unwrap().len());
+
+        let mut decoder = Decoder::new();
+        let result = decoder.decode_with_limit(&block, 42);
+        assert!(is_decoder_error(&DecoderError::HeaderListSizeExceeded, &result));
+    }
 }
 
 /// The module defines interop tests between this HPACK decoder
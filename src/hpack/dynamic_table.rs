@@ -31,6 +31,19 @@ pub(crate) struct DynamicTable {
     table: VecDeque<(Bytes, Bytes)>,
     size: usize,
     max_size: usize,
+    stats: DynamicTableStats,
+}
+
+/// Dynamic table counters over the connection lifetime.
+///
+/// High eviction rate means that `SETTINGS_HEADER_TABLE_SIZE`
+/// is too small for header values sent over the connection.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DynamicTableStats {
+    /// Headers inserted into the table
+    pub insertions: u64,
+    /// Headers evicted from the table (including headers larger than the table)
+    pub evictions: u64,
 }
 
 impl DynamicTable {
@@ -47,6 +60,7 @@ impl DynamicTable {
             table: VecDeque::new(),
             size: 0,
             max_size: max_size,
+            stats: DynamicTableStats::default(),
         }
     }
 
@@ -56,6 +70,11 @@ impl DynamicTable {
         self.size
     }
 
+    /// Returns insertion and eviction counters.
+    pub fn stats(&self) -> DynamicTableStats {
+        self.stats
+    }
+
     /// Returns an `Iterator` through the headers stored in the `DynamicTable`.
     ///
     /// The iterator will yield elements of type `(&[u8], &[u8])`,
@@ -79,7 +98,6 @@ impl DynamicTable {
     }
 
     /// Returns the maximum size of the table in octets.
-    #[cfg(test)]
     pub fn get_max_table_size(&self) -> usize {
         self.max_size
     }
@@ -97,6 +115,7 @@ impl DynamicTable {
         // a magic number determined by them (under reasonable assumptions of
         // how the table is stored).
         self.size += name.len() + value.len() + 32;
+        self.stats.insertions += 1;
         debug!("New dynamic table size {}", self.size);
         // Now add it to the internal buffer
         self.table.push_front((name, value));
@@ -127,6 +146,7 @@ impl DynamicTable {
                 self.size -= last_header.0.len() + last_header.1.len() + 32;
             }
             self.table.pop_back();
+            self.stats.evictions += 1;
         }
     }
 
@@ -227,6 +247,23 @@ mod test {
         assert_eq!(0, table.to_vec_of_bytes().len());
     }
 
+    #[test]
+    fn test_dynamic_table_stats() {
+        let mut table = DynamicTable::with_size(38);
+        table.add_header_for_test(b"a".to_vec(), b"b".to_vec());
+        table.add_header_for_test(b"123".to_vec(), b"456".to_vec());
+        // larger than the table
+        table.add_header_for_test(b"123".to_vec(), b"4567".to_vec());
+
+        assert_eq!(
+            DynamicTableStats {
+                insertions: 3,
+                evictions: 3,
+            },
+            table.stats()
+        );
+    }
+
     /// Tests that when changing the maximum size of the `DynamicTable`, the
     /// headers are correctly evicted in order to keep its size below the new
     /// max.
@@ -3,12 +3,14 @@
 //!
 //! Clients should use the `Encoder` struct as the API for performing HPACK
 //! encoding.
+use std::cmp;
 use std::num::Wrapping;
 
 use bytes::Bytes;
 
 use super::HeaderTable;
 use bytes::BytesMut;
+use hpack::dynamic_table::DynamicTableStats;
 use hpack::static_table::StaticTable;
 use hpack::HeaderValueFound;
 
@@ -103,6 +105,9 @@ pub fn encode_integer(value: usize, prefix_size: u8) -> Vec<u8> {
 pub struct Encoder {
     /// The header table represents the encoder's context
     header_table: HeaderTable,
+    /// Smallest and last table size set since previous header block,
+    /// signalled to the decoder at the beginning of the next block.
+    pending_size_update: Option<(usize, usize)>,
 }
 
 impl Encoder {
@@ -111,9 +116,40 @@ impl Encoder {
     pub fn new() -> Encoder {
         Encoder {
             header_table: HeaderTable::with_static_table(StaticTable::new()),
+            pending_size_update: None,
         }
     }
 
+    /// Change the maximum dynamic table size, e. g. when peer changes
+    /// SETTINGS_HEADER_TABLE_SIZE.
+    ///
+    /// Dynamic table size update is emitted at the beginning of the next header block.
+    pub fn set_max_table_size(&mut self, new_max_size: usize) {
+        if self.pending_size_update.is_none()
+            && new_max_size == self.header_table.dynamic_table.get_max_table_size()
+        {
+            return;
+        }
+        let min_size = match self.pending_size_update {
+            Some((min_size, _)) => cmp::min(min_size, new_max_size),
+            None => new_max_size,
+        };
+        self.pending_size_update = Some((min_size, new_max_size));
+        self.header_table
+            .dynamic_table
+            .set_max_table_size(new_max_size);
+    }
+
+    /// Dynamic table insertion and eviction counters.
+    pub fn dynamic_table_stats(&self) -> DynamicTableStats {
+        self.header_table.dynamic_table.stats()
+    }
+
+    /// Current dynamic table size in octets as defined by HPACK.
+    pub fn dynamic_table_size(&self) -> usize {
+        self.header_table.dynamic_table.get_size()
+    }
+
     /// Encodes the given headers using the HPACK rules and returns a newly
     /// allocated `Vec` containing the bytes representing the encoded header
     /// set.
@@ -152,6 +188,14 @@ impl Encoder {
         I: IntoIterator<Item = (&'b [u8], &'b [u8])>,
         W: EncodeBuf,
     {
+        // HPACK 4.2: when table size was reduced and then increased
+        // between header blocks, the smallest size must be signalled too
+        if let Some((min_size, size)) = self.pending_size_update.take() {
+            if min_size < size {
+                encode_integer_into(min_size, 5, 0x20, writer);
+            }
+            encode_integer_into(size, 5, 0x20, writer);
+        }
         for header in headers {
             self.encode_header_into(header, writer);
         }
@@ -395,4 +439,30 @@ mod tests {
 
         assert!(is_decodable(&result, &headers));
     }
+
+    /// Tests that table size changes are signalled at the beginning
+    /// of the next header block, including the smallest intermediate size.
+    #[test]
+    fn test_size_update_emitted_in_next_block() {
+        let mut encoder = Encoder::new();
+        let headers = vec![(b"custom-key".to_vec(), b"custom-value".to_vec())];
+
+        encoder.set_max_table_size(100);
+        encoder.set_max_table_size(200);
+
+        let result = encoder.encode_for_test(headers.iter().map(|h| (&h.0[..], &h.1[..])));
+
+        let mut expected = Vec::new();
+        for &size in &[100, 200] {
+            let mut update = encode_integer(size, 5);
+            update[0] |= 0x20;
+            expected.extend(update);
+        }
+        assert_eq!(&expected[..], &result[..expected.len()]);
+        assert!(is_decodable(&result, &headers));
+
+        // Size update is sent only once
+        let result = encoder.encode_for_test(headers.iter().map(|h| (&h.0[..], &h.1[..])));
+        assert_eq!(vec![0x80 | 62], result);
+    }
 }
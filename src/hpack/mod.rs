@@ -7,6 +7,7 @@
 
 // Re-export the main HPACK API entry points.
 pub use self::decoder::Decoder;
+pub use self::dynamic_table::DynamicTableStats;
 pub use self::encoder::Encoder;
 use bytes::Bytes;
 use hpack::dynamic_table::DynamicTable;
@@ -16,6 +16,10 @@ extern crate tls_api_stub;
 extern crate tokio_tls_api;
 
 extern crate bytes;
+#[cfg(feature = "compression")]
+extern crate flate2;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 extern crate net2;
 extern crate void;
 
@@ -62,13 +66,31 @@ mod solicit_misc;
 mod misc;
 
 mod resp;
+mod response_sink;
+mod byte_range;
+mod sse;
+mod prometheus;
+mod static_files;
+mod trace;
+#[cfg(feature = "tracing")]
+mod trace_tracing;
 
 mod exec;
 
 pub use socket::AnySocketAddr;
 
+pub use hpack::DynamicTableStats;
+
+pub use solicit::header::CacheDirectives;
+pub use solicit::header::EntityTag;
 pub use solicit::header::Header;
 pub use solicit::header::Headers;
+pub use solicit::header::PathQuery;
+pub use solicit::header::StatusCode;
+pub use solicit::frame::settings::HttpSetting;
+pub use solicit::frame::settings::HttpSettings;
+pub use solicit::frame::FrameCounts;
+pub use solicit::session::StreamState;
 pub use solicit::HttpScheme;
 
 pub use service::Service;
@@ -76,20 +98,59 @@ pub use service_paths::ServicePaths;
 
 pub use exec::CpuPoolOption;
 
+pub use client::backoff::BackoffConfig;
+pub use client::client_conf::ClientAlpn;
 pub use client::client_conf::ClientConf;
+pub use client::client_conn::ClientCallbacks;
+pub use client::client_conn::ClientConnCallbacks;
+pub use client::client_pool::ClientPool;
+pub use client::client_pool::ClientPoolConf;
+pub use client::client_pool::ClientPoolStats;
+pub use client::client_priority::AutoPriority;
+pub use client::client_priority::Urgency;
 pub use client::client_tls::ClientTlsOption;
 pub use client::Client;
 pub use client::ClientBuilder;
-
+pub use common::ConnControl;
+pub use common::ConnErrorEvent;
+pub use common::ConnMetrics;
+pub use common::ConnStateSnapshot;
+pub use common::InMessageStage;
+pub use common::StreamStateSnapshot;
+pub use common::OnReadEof;
+pub use common::WindowUpdateStrategy;
+
+#[cfg(feature = "compression")]
+pub use server::server_compression::CompressionPolicy;
+#[cfg(feature = "compression")]
+pub use server::server_compression::ContentEncoding;
+pub use server::server_conf::HandlerAbortedCallback;
+pub use server::server_conf::PanicResponseCallback;
 pub use server::server_conf::ServerAlpn;
 pub use server::server_conf::ServerConf;
+pub use server::server_push::PushSink;
+pub use server::server_push::ServerPush;
 pub use server::server_tls::ServerTlsOption;
 pub use server::Server;
 pub use server::ServerBuilder;
+pub use server::ServerStateSnapshot;
 
 pub use data_or_trailers::DataOrTrailers;
 pub use data_or_trailers::HttpStreamAfterHeaders;
 pub use resp::Response;
+pub use resp::ResponseConsumer;
+pub use response_sink::ResponseSink;
+pub use response_sink::DEFAULT_SINK_BUFFER_SIZE;
+pub use byte_range::ByteRange;
+pub use byte_range::RangeRequest;
+pub use sse::SseEvent;
+pub use static_files::StaticFiles;
+pub use trace::RequestSpan;
+pub use trace::RequestTracer;
+pub use trace::RequestTracing;
+pub use trace::TraceParent;
+#[cfg(feature = "tracing")]
+pub use trace_tracing::TracingRequestTracer;
 
 pub use message::SimpleHttpMessage;
 
@@ -100,11 +161,14 @@ pub use result::Result;
 /// Functions used in tests
 #[doc(hidden)]
 pub mod for_test {
+    pub use client::client_conn::ClientConn;
+    pub use client::client_conn::ClientConnCallbacks;
     pub use common::ConnStateSnapshot;
     pub use server::server_conn::ServerConn;
     pub use solicit_async::recv_raw_frame_sync;
 
     pub use solicit::frame::settings::HttpSettings;
+    pub use solicit::frame::settings::RawSettingsFrame;
     pub use solicit::WindowSize;
     pub use solicit::DEFAULT_SETTINGS;
 
@@ -0,0 +1,293 @@
+//! Render connection state snapshots in Prometheus text exposition format
+
+use std::fmt::Write;
+
+use common::ConnStateSnapshot;
+use server::ServerStateSnapshot;
+use solicit::frame::FrameCounts;
+
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn name(&self) -> &'static str {
+        match *self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+enum Value {
+    /// `None` if value is unknown, sample is not written then
+    Single(fn(&ConnStateSnapshot) -> Option<f64>),
+    /// Sample for each frame type labeled with `type`
+    ByFrameType(fn(&ConnStateSnapshot) -> &FrameCounts),
+}
+
+struct Metric {
+    name: &'static str,
+    metric_type: MetricType,
+    help: &'static str,
+    value: Value,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "httpbis_open_streams",
+        metric_type: MetricType::Gauge,
+        help: "Streams known to the connection",
+        value: Value::Single(|s| Some(s.streams.len() as f64)),
+    },
+    Metric {
+        name: "httpbis_bytes_read_total",
+        metric_type: MetricType::Counter,
+        help: "Bytes read from the socket",
+        value: Value::Single(|s| Some(s.bytes_read as f64)),
+    },
+    Metric {
+        name: "httpbis_bytes_written_total",
+        metric_type: MetricType::Counter,
+        help: "Bytes written to the socket",
+        value: Value::Single(|s| Some(s.bytes_written as f64)),
+    },
+    Metric {
+        name: "httpbis_write_queue_bytes",
+        metric_type: MetricType::Gauge,
+        help: "Bytes of frames and stream data waiting to be sent",
+        value: Value::Single(|s| Some(s.write_queue_bytes() as f64)),
+    },
+    Metric {
+        name: "httpbis_in_window_size",
+        metric_type: MetricType::Gauge,
+        help: "Connection inbound flow control window",
+        value: Value::Single(|s| Some(s.in_window_size as f64)),
+    },
+    Metric {
+        name: "httpbis_out_window_size",
+        metric_type: MetricType::Gauge,
+        help: "Connection outbound flow control window",
+        value: Value::Single(|s| Some(s.out_window_size as f64)),
+    },
+    Metric {
+        name: "httpbis_deferred_window_update_bytes",
+        metric_type: MetricType::Gauge,
+        help: "Stream WINDOW_UPDATE increments deferred because receive queues are full",
+        value: Value::Single(|s| Some(s.deferred_window_update_bytes as f64)),
+    },
+    Metric {
+        name: "httpbis_goaway_sent",
+        metric_type: MetricType::Gauge,
+        help: "1 if GOAWAY was sent on the connection",
+        value: Value::Single(|s| Some(if s.goaway_sent { 1.0 } else { 0.0 })),
+    },
+    Metric {
+        name: "httpbis_goaway_received",
+        metric_type: MetricType::Gauge,
+        help: "1 if GOAWAY was received on the connection",
+        value: Value::Single(|s| Some(if s.goaway_received { 1.0 } else { 0.0 })),
+    },
+    Metric {
+        name: "httpbis_ping_rtt_seconds",
+        metric_type: MetricType::Gauge,
+        help: "Round-trip time of the last acknowledged PING",
+        value: Value::Single(|s| {
+            s.ping_rtt
+                .map(|d| d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9)
+        }),
+    },
+    Metric {
+        name: "httpbis_hpack_encoder_insertions_total",
+        metric_type: MetricType::Counter,
+        help: "Headers inserted into HPACK encoder dynamic table",
+        value: Value::Single(|s| Some(s.hpack_encoder_stats.insertions as f64)),
+    },
+    Metric {
+        name: "httpbis_hpack_encoder_evictions_total",
+        metric_type: MetricType::Counter,
+        help: "Headers evicted from HPACK encoder dynamic table",
+        value: Value::Single(|s| Some(s.hpack_encoder_stats.evictions as f64)),
+    },
+    Metric {
+        name: "httpbis_hpack_decoder_insertions_total",
+        metric_type: MetricType::Counter,
+        help: "Headers inserted into HPACK decoder dynamic table",
+        value: Value::Single(|s| Some(s.hpack_decoder_stats.insertions as f64)),
+    },
+    Metric {
+        name: "httpbis_hpack_decoder_evictions_total",
+        metric_type: MetricType::Counter,
+        help: "Headers evicted from HPACK decoder dynamic table",
+        value: Value::Single(|s| Some(s.hpack_decoder_stats.evictions as f64)),
+    },
+    Metric {
+        name: "httpbis_rst_sent_total",
+        metric_type: MetricType::Counter,
+        help: "RST_STREAM frames sent",
+        value: Value::Single(|s| Some(s.frames_sent.rst_stream as f64)),
+    },
+    Metric {
+        name: "httpbis_rst_received_total",
+        metric_type: MetricType::Counter,
+        help: "RST_STREAM frames received",
+        value: Value::Single(|s| Some(s.frames_received.rst_stream as f64)),
+    },
+    Metric {
+        name: "httpbis_frames_sent_total",
+        metric_type: MetricType::Counter,
+        help: "Frames sent by frame type",
+        value: Value::ByFrameType(|s| &s.frames_sent),
+    },
+    Metric {
+        name: "httpbis_frames_received_total",
+        metric_type: MetricType::Counter,
+        help: "Frames received by frame type",
+        value: Value::ByFrameType(|s| &s.frames_received),
+    },
+];
+
+/// Write a sample, labels are written in given order
+fn write_sample(r: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    r.push_str(name);
+    if !labels.is_empty() {
+        r.push('{');
+        for (i, &(label, label_value)) in labels.iter().enumerate() {
+            if i != 0 {
+                r.push(',');
+            }
+            write!(r, "{}=\"{}\"", label, label_value).unwrap();
+        }
+        r.push('}');
+    }
+    writeln!(r, " {}", value).unwrap();
+}
+
+/// Write all metrics, each sample is labeled with connection id if it is known
+fn render(conns: &[(Option<u64>, &ConnStateSnapshot)]) -> String {
+    let mut r = String::new();
+    for metric in METRICS {
+        writeln!(r, "# HELP {} {}", metric.name, metric.help).unwrap();
+        writeln!(r, "# TYPE {} {}", metric.name, metric.metric_type.name()).unwrap();
+        for &(conn_id, conn) in conns {
+            let conn_id = conn_id.map(|id| id.to_string());
+            let mut labels = Vec::new();
+            if let Some(ref conn_id) = conn_id {
+                labels.push(("conn", &conn_id[..]));
+            }
+            match metric.value {
+                Value::Single(value) => {
+                    if let Some(value) = value(conn) {
+                        write_sample(&mut r, metric.name, &labels, value);
+                    }
+                }
+                Value::ByFrameType(counts) => {
+                    for &(frame_type, count) in counts(conn).by_name().iter() {
+                        labels.push(("type", frame_type));
+                        write_sample(&mut r, metric.name, &labels, count as f64);
+                        labels.pop();
+                    }
+                }
+            }
+        }
+    }
+    r
+}
+
+impl ConnStateSnapshot {
+    /// Render connection state in Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        render(&[(None, self)])
+    }
+}
+
+impl ServerStateSnapshot {
+    /// Render state of all connections in Prometheus text exposition format,
+    /// samples are labeled with connection id
+    pub fn to_prometheus(&self) -> String {
+        let mut conns: Vec<_> = self.conns.iter().map(|(&id, c)| (Some(id), c)).collect();
+        conns.sort_by_key(|&(id, _)| id);
+        render(&conns)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use common::WindowUpdateStrategy;
+    use hpack::DynamicTableStats;
+
+    fn snapshot() -> ConnStateSnapshot {
+        ConnStateSnapshot {
+            in_window_size: 65535,
+            out_window_size: 100,
+            in_window_size_target: 65535,
+            stream_in_window_size: 65535,
+            stream_out_window_size: 65535,
+            window_update_strategy: WindowUpdateStrategy::HalfWindow,
+            streams: HashMap::new(),
+            queued_write_bytes: 10,
+            hpack_encoder_stats: DynamicTableStats {
+                insertions: 3,
+                evictions: 1,
+            },
+            hpack_decoder_stats: DynamicTableStats::default(),
+            ping_rtt: None,
+            deferred_window_update_bytes: 0,
+            bytes_read: 1000,
+            bytes_written: 2000,
+            goaway_sent: true,
+            goaway_received: false,
+            frames_sent: FrameCounts {
+                rst_stream: 4,
+                ..FrameCounts::default()
+            },
+            frames_received: FrameCounts {
+                data: 7,
+                ..FrameCounts::default()
+            },
+            settings_ack_pending: false,
+        }
+    }
+
+    #[test]
+    fn conn() {
+        let text = snapshot().to_prometheus();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE httpbis_bytes_read_total counter"));
+        assert!(lines.contains(&"httpbis_bytes_read_total 1000"));
+        assert!(lines.contains(&"# TYPE httpbis_out_window_size gauge"));
+        assert!(lines.contains(&"httpbis_out_window_size 100"));
+        assert!(lines.contains(&"httpbis_write_queue_bytes 10"));
+        assert!(lines.contains(&"httpbis_goaway_sent 1"));
+        assert!(lines.contains(&"httpbis_hpack_encoder_insertions_total 3"));
+        assert!(lines.contains(&"httpbis_rst_sent_total 4"));
+        assert!(lines.contains(&"httpbis_rst_received_total 0"));
+        assert!(lines.contains(&"httpbis_frames_received_total{type=\"DATA\"} 7"));
+        assert!(lines.contains(&"httpbis_frames_sent_total{type=\"GOAWAY\"} 0"));
+        // unknown RTT is not reported
+        assert!(!lines.iter().any(|l| l.starts_with("httpbis_ping_rtt_seconds")));
+    }
+
+    #[test]
+    fn server() {
+        let mut conn2 = snapshot();
+        conn2.ping_rtt = Some(Duration::from_millis(1500));
+        let mut conns = HashMap::new();
+        conns.insert(2, conn2);
+        conns.insert(1, snapshot());
+        let text = ServerStateSnapshot { conns }.to_prometheus();
+        assert_eq!(1, text.matches("# HELP httpbis_open_streams ").count());
+        assert!(text.contains(
+            "httpbis_open_streams{conn=\"1\"} 0\nhttpbis_open_streams{conn=\"2\"} 0\n"
+        ));
+        assert!(text.contains("httpbis_ping_rtt_seconds{conn=\"2\"} 1.5\n"));
+        assert!(text.contains("httpbis_frames_received_total{conn=\"2\",type=\"DATA\"} 7\n"));
+        assert!(!text.contains("httpbis_ping_rtt_seconds{conn=\"1\"}"));
+    }
+}
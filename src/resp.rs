@@ -1,12 +1,29 @@
+use std::io::Read;
+use std::io::Seek;
+use std::panic;
+
 use futures::future;
 use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
+use futures::sync::mpsc::unbounded;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::Async;
+use futures::Poll;
+
+use tokio_core::reactor;
 
 use bytes::Bytes;
 
+use byte_range;
+use byte_range::RangeRequest;
 use message::SimpleHttpMessage;
+use misc::any_to_string;
+use response_sink::ResponseSink;
+use sse::SseEvent;
+use solicit::header::EntityTag;
 use solicit::header::Headers;
+use solicit::header::StatusCode;
 use solicit_async::*;
 
 use error::Error;
@@ -40,16 +57,147 @@ impl Response {
         Response::headers_and_stream(headers, HttpStreamAfterHeaders::bytes(content))
     }
 
+    /// Create a response with body written to the sink,
+    /// see `ResponseSink` for buffering and flush.
+    pub fn sink(headers: Headers) -> (Response, ResponseSink) {
+        let (tx, rx) = unbounded();
+        let response =
+            Response::headers_and_bytes_stream(headers, rx.map_err(|()| unreachable!()));
+        (response, ResponseSink::new(tx))
+    }
+
+    /// Create a response with body chunks sent to the channel,
+    /// response is completed when the sender is dropped.
+    pub fn from_receiver(headers: Headers, receiver: UnboundedReceiver<Bytes>) -> Response {
+        Response::headers_and_bytes_stream(headers, receiver.map_err(|()| unreachable!()))
+    }
+
+    /// Like `from_receiver`, but the producer may fail the response:
+    /// the stream is reset after `Err` is received.
+    pub fn from_result_receiver(
+        headers: Headers,
+        receiver: UnboundedReceiver<Result<Bytes, Error>>,
+    ) -> Response {
+        Response::headers_and_bytes_stream(
+            headers,
+            receiver.map_err(|()| unreachable!()).and_then(|r| r),
+        )
+    }
+
+    /// Create a response which headers and body are computed asynchronously,
+    /// e. g. after a database query.
+    ///
+    /// Headers are sent when `headers` future resolves, body is streamed
+    /// after `body` future resolves. `500` response is sent if `headers` future fails,
+    /// stream is reset if `body` future or stream fails.
+    pub fn from_future<H, B, S>(headers: H, body: B) -> Response
+    where
+        H: Future<Item = Headers, Error = Error> + Send + 'static,
+        B: Future<Item = S, Error = Error> + Send + 'static,
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+    {
+        Response::from_future_or_else(headers, body, |e| {
+            warn!("response headers future failed: {:?}", e);
+            Response::headers(Headers::internal_error_500())
+        })
+    }
+
+    /// Like `from_future`, but response sent when `headers` future fails
+    /// is created by `on_error`.
+    pub fn from_future_or_else<H, B, S, F>(headers: H, body: B, on_error: F) -> Response
+    where
+        H: Future<Item = Headers, Error = Error> + Send + 'static,
+        B: Future<Item = S, Error = Error> + Send + 'static,
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+        F: FnOnce(Error) -> Response + Send + 'static,
+    {
+        let body = HttpStreamAfterHeaders::bytes(body.flatten_stream());
+        Response::new(headers.then(move |r| -> HttpFutureSend<_> {
+            match r {
+                Ok(headers) => Box::new(future::ok((headers, body))),
+                Err(e) => on_error(e).0,
+            }
+        }))
+    }
+
     /// Create a response with only headers
     pub fn headers(headers: Headers) -> Response {
         Response::headers_and_bytes_stream(headers, stream::empty())
     }
 
+    /// gRPC "Trailers-Only" response: single `HEADERS` frame with `END_STREAM`
+    /// containing both `:status` and trailers like `grpc-status`.
+    pub fn trailers_only(headers: Headers) -> Response {
+        Response::headers(headers)
+    }
+
     /// Create a response with headers and response body
     pub fn headers_and_bytes<B: Into<Bytes>>(header: Headers, content: B) -> Response {
         Response::headers_and_bytes_stream(header, stream::once(Ok(content.into())))
     }
 
+    /// Create a response with body and strong `etag` computed from the body,
+    /// or `304 Not Modified` without body if `GET` or `HEAD` request
+    /// `if-none-match` matches the tag.
+    ///
+    /// `304` response keeps `cache-control`, `content-location`, `date`,
+    /// `expires` and `vary` of `headers` (RFC 7232 section 4.1).
+    pub fn headers_and_bytes_with_etag<B: Into<Bytes>>(
+        req: &Headers,
+        mut headers: Headers,
+        content: B,
+    ) -> Response {
+        let content = content.into();
+        let etag = EntityTag::from_content(&content);
+
+        let conditional = matches!(req.get_opt(":method"), Some("GET") | Some("HEAD"));
+        if conditional && req.if_none_match(&etag) == Some(true) {
+            let mut not_modified = Headers::from_status_code(StatusCode::NOT_MODIFIED);
+            for h in &headers.0 {
+                match h.name() {
+                    b"cache-control" | b"content-location" | b"date" | b"expires" | b"vary" => {
+                        not_modified.0.push(h.clone())
+                    }
+                    _ => {}
+                }
+            }
+            not_modified.add("etag", &etag.to_string());
+            return Response::headers(not_modified);
+        }
+
+        headers.0.retain(|h| h.name() != b"etag");
+        headers.add("etag", &etag.to_string());
+        Response::headers_and_bytes(headers, content)
+    }
+
+    /// Create a `text/event-stream` response from a stream of server-sent events.
+    ///
+    /// Each event is sent in separate `DATA` frame.
+    pub fn event_stream<S>(events: S) -> Response
+    where
+        S: Stream<Item = SseEvent, Error = Error> + Send + 'static,
+    {
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/event-stream");
+        Response::headers_and_bytes_stream(headers, events.map(|e| e.to_bytes()))
+    }
+
+    /// Respond with content of seekable `source` of length `len` according to `range`.
+    ///
+    /// Whole content is sent with `200` for `RangeRequest::Full`,
+    /// single range is sent with `206 Partial Content` and `content-range`,
+    /// multiple ranges are sent as `multipart/byteranges`
+    /// with `content-type` of `headers` moved into the parts,
+    /// `416` with `content-range: bytes */len` is sent for unsatisfiable range.
+    ///
+    /// `:status`, `content-length` and `content-range` of `headers` are replaced.
+    pub fn byte_ranges<R>(headers: Headers, source: R, len: u64, range: RangeRequest) -> Response
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        byte_range::range_response(headers, source, len, range)
+    }
+
     pub fn message(message: SimpleHttpMessage) -> Response {
         Response::headers_and_bytes(message.headers, message.body)
     }
@@ -94,6 +242,85 @@ impl Response {
         Response::new(future::err(err))
     }
 
+    /// Send `RST_STREAM(NO_ERROR)` after the response is completely sent
+    /// if the client is still sending request body.
+    ///
+    /// This tells the client to stop uploading the request
+    /// without failing already complete response (RFC 7540 section 8.1).
+    pub fn discard_request_body(self) -> Response {
+        Response::new(self.0.map(|(headers, rem)| {
+            let rem = rem
+                .0
+                .chain(stream::once(Err(Error::DiscardRequestBody)));
+            (headers, HttpStreamAfterHeaders::new(rem))
+        }))
+    }
+
+    /// Send trailing HEADERS frame with END_STREAM after the response body,
+    /// e. g. gRPC `grpc-status` known only after the body is streamed.
+    ///
+    /// See `HttpStreamAfterHeaders::with_trailers`.
+    pub fn with_trailers<F>(self, trailers: F) -> Response
+    where
+        F: Future<Item = Headers, Error = Error> + Send + 'static,
+    {
+        Response::new(
+            self.0
+                .map(|(headers, rem)| (headers, rem.with_trailers(trailers))),
+        )
+    }
+
+    /// Fail response body stream (and `collect`) with `Error::BodyTooLarge`
+    /// if response body is larger than given size.
+    ///
+    /// See `HttpStreamAfterHeaders::max_size`.
+    pub fn max_body_size(self, max_size: usize) -> Response {
+        Response::new(self.0.map(move |(headers, rem)| (headers, rem.max_size(max_size))))
+    }
+
+    /// Re-chunk response body into chunks of `size` bytes,
+    /// only the last chunk may be shorter.
+    ///
+    /// See `HttpStreamAfterHeaders::chunked`.
+    pub fn chunked(self, size: usize) -> Response {
+        Response::new(self.0.map(move |(headers, rem)| (headers, rem.chunked(size))))
+    }
+
+    /// Call a function on each body chunk passed to the consumer
+    /// (e. g. for logging), stream content is not changed.
+    pub fn inspect_body<F>(self, f: F) -> Response
+    where
+        F: Fn(&Bytes) + Send + 'static,
+    {
+        Response::new(self.0.map(move |(headers, rem)| {
+            let rem = rem.0.map(move |part| {
+                if let DataOrTrailers::Data(ref data, _) = part {
+                    f(data);
+                }
+                part
+            });
+            (headers, HttpStreamAfterHeaders::new(rem))
+        }))
+    }
+
+    /// Consume response body with callbacks instead of stream combinators.
+    ///
+    /// `f` is called with each body chunk, trailers are ignored.
+    /// More callbacks can be registered on returned `ResponseConsumer`,
+    /// consumption starts on `ResponseConsumer::spawn`.
+    pub fn for_each_chunk<F>(self, f: F) -> ResponseConsumer
+    where
+        F: FnMut(Bytes) + Send + 'static,
+    {
+        ResponseConsumer {
+            response: self,
+            on_headers: None,
+            on_chunk: Box::new(f),
+            on_complete: None,
+            on_error: None,
+        }
+    }
+
     // getters
 
     pub fn into_stream_flag(self) -> HttpFutureStreamSend<DataOrHeadersWithFlag> {
@@ -113,8 +340,22 @@ impl Response {
         Box::new(self.into_stream_flag().map(|c| c.content))
     }
 
+    /// Stream of parts to be sent to the network.
+    ///
+    /// Unlike `into_stream_flag`, headers are flagged last
+    /// if the body is known to be empty when headers are ready,
+    /// so they are sent in single `HEADERS` frame with `END_STREAM`.
     pub fn into_part_stream(self) -> DataOrHeadersWithFlagStream {
-        DataOrHeadersWithFlagStream::new(self.into_stream_flag())
+        DataOrHeadersWithFlagStream::new(
+            self.0
+                .map(|(headers, rem)| HeadersThenBody {
+                    headers: Some(headers),
+                    first: None,
+                    // body is polled together with headers,
+                    // so body panic must not prevent sending headers
+                    rem: rem.catch_unwind().into_flag_stream(),
+                }).flatten_stream(),
+        )
     }
 
     pub fn collect(self) -> HttpFutureSend<SimpleHttpMessage> {
@@ -126,4 +367,176 @@ impl Response {
                 }),
         )
     }
+
+    /// Abandon the response.
+    ///
+    /// Same as drop: client stream is reset with `CANCEL` unless the response
+    /// is already received completely.
+    pub fn cancel(self) {}
+}
+
+/// Callbacks consuming the response, created by `Response::for_each_chunk`.
+///
+/// Callbacks are invoked sequentially in the event loop,
+/// either `on_complete` or `on_error` is called exactly once
+/// after all chunks are delivered.
+#[must_use = "response is not consumed until `spawn` is called"]
+pub struct ResponseConsumer {
+    response: Response,
+    on_headers: Option<Box<FnOnce(Headers) + Send>>,
+    on_chunk: Box<FnMut(Bytes) + Send>,
+    on_complete: Option<Box<FnOnce() + Send>>,
+    on_error: Option<Box<FnOnce(Error) + Send>>,
+}
+
+impl ResponseConsumer {
+    /// Called with response headers before the first chunk
+    pub fn on_headers<F>(mut self, f: F) -> ResponseConsumer
+    where
+        F: FnOnce(Headers) + Send + 'static,
+    {
+        self.on_headers = Some(Box::new(f));
+        self
+    }
+
+    /// Called when response body is completely received
+    pub fn on_complete<F>(mut self, f: F) -> ResponseConsumer
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_complete = Some(Box::new(f));
+        self
+    }
+
+    /// Called when response fails, no more chunks are delivered after that
+    pub fn on_error<F>(mut self, f: F) -> ResponseConsumer
+    where
+        F: FnOnce(Error) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(f));
+        self
+    }
+
+    /// Start consuming the response in given event loop.
+    ///
+    /// Panic in `on_headers` or `on_chunk` is reported to `on_error`
+    /// as `Error::HandlerPanicked`.
+    /// If the event loop is shut down before the response is complete,
+    /// `on_error` is called with `Error::Shutdown`.
+    pub fn spawn(self, handle: &reactor::Handle) {
+        let ResponseConsumer {
+            response,
+            on_headers,
+            mut on_chunk,
+            on_complete,
+            on_error,
+        } = self;
+
+        let mut completion = ConsumerCompletion {
+            on_complete,
+            on_error,
+        };
+
+        let consume = response.0.and_then(move |(headers, rem)| {
+            if let Some(on_headers) = on_headers {
+                on_headers(headers);
+            }
+            rem.filter_data().for_each(move |chunk| {
+                on_chunk(chunk);
+                Ok(())
+            })
+        });
+
+        let consume = panic::AssertUnwindSafe(consume)
+            .catch_unwind()
+            .then(move |r| {
+                let r = match r {
+                    Ok(r) => r,
+                    Err(e) => Err(Error::HandlerPanicked(any_to_string(e))),
+                };
+                completion.complete(r);
+                Ok(())
+            });
+
+        handle.spawn(consume);
+    }
+}
+
+/// Calls either `on_complete` or `on_error` exactly once,
+/// `on_error` is called on drop if the response is not complete.
+struct ConsumerCompletion {
+    on_complete: Option<Box<FnOnce() + Send>>,
+    on_error: Option<Box<FnOnce(Error) + Send>>,
+}
+
+impl ConsumerCompletion {
+    fn complete(&mut self, r: Result<(), Error>) {
+        let on_complete = self.on_complete.take();
+        let on_error = self.on_error.take();
+        // callbacks must not unwind into the event loop
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(move || match r {
+            Ok(()) => {
+                if let Some(on_complete) = on_complete {
+                    on_complete();
+                }
+            }
+            Err(e) => {
+                if let Some(on_error) = on_error {
+                    on_error(e);
+                }
+            }
+        }));
+        if let Err(e) = r {
+            warn!("response consumer callback panicked: {}", any_to_string(e));
+        }
+    }
+}
+
+impl Drop for ConsumerCompletion {
+    fn drop(&mut self) {
+        if self.on_error.is_some() || self.on_complete.is_some() {
+            self.complete(Err(Error::Shutdown));
+        }
+    }
+}
+
+/// Headers followed by body, headers are flagged last if body is empty
+struct HeadersThenBody<S> {
+    headers: Option<Headers>,
+    first: Option<Result<Option<DataOrHeadersWithFlag>, Error>>,
+    rem: S,
+}
+
+impl<S> Stream for HeadersThenBody<S>
+where
+    S: Stream<Item = DataOrHeadersWithFlag, Error = Error>,
+{
+    type Item = DataOrHeadersWithFlag;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<DataOrHeadersWithFlag>, Error> {
+        if let Some(headers) = self.headers.take() {
+            // Do not wait for body, headers should be sent as soon as possible
+            return Ok(Async::Ready(Some(match self.rem.poll() {
+                Ok(Async::Ready(None)) => {
+                    self.first = Some(Ok(None));
+                    DataOrHeadersWithFlag::last_headers(headers)
+                }
+                Ok(Async::Ready(Some(part))) => {
+                    self.first = Some(Ok(Some(part)));
+                    DataOrHeadersWithFlag::intermediate_headers(headers)
+                }
+                Ok(Async::NotReady) => DataOrHeadersWithFlag::intermediate_headers(headers),
+                Err(e) => {
+                    self.first = Some(Err(e));
+                    DataOrHeadersWithFlag::intermediate_headers(headers)
+                }
+            })));
+        }
+
+        match self.first.take() {
+            Some(first) => first.map(Async::Ready),
+            None => self.rem.poll(),
+        }
+    }
 }
@@ -0,0 +1,97 @@
+//! Response body written by handler piece by piece
+
+use bytes::Bytes;
+use bytes::BytesMut;
+
+use futures::sync::mpsc::UnboundedSender;
+
+use error;
+use result;
+
+/// Default size of data buffered without explicit flush,
+/// same as default `SETTINGS_MAX_FRAME_SIZE`.
+pub const DEFAULT_SINK_BUFFER_SIZE: usize = 16384;
+
+/// Writer of streaming response body, created by `Response::sink`.
+///
+/// Small writes are buffered and sent when buffer size is reached,
+/// `flush` sends buffered data immediately, e. g. a progress update.
+/// Buffered data is sent and the response is completed when the sink is dropped.
+pub struct ResponseSink {
+    tx: UnboundedSender<Bytes>,
+    buf: BytesMut,
+    buffer_size: usize,
+}
+
+impl ResponseSink {
+    pub(crate) fn new(tx: UnboundedSender<Bytes>) -> ResponseSink {
+        ResponseSink {
+            tx,
+            buf: BytesMut::new(),
+            buffer_size: DEFAULT_SINK_BUFFER_SIZE,
+        }
+    }
+
+    /// Send data when this number of bytes is buffered, zero disables buffering
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size;
+    }
+
+    /// Append data to response body.
+    ///
+    /// Error is returned if response stream is closed, e. g. reset by peer.
+    pub fn write(&mut self, data: &[u8]) -> result::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= self.buffer_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send buffered data to connection immediately
+    pub fn flush(&mut self) -> result::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let data = self.buf.take().freeze();
+        self.tx
+            .unbounded_send(data)
+            .map_err(|_| error::Error::Other("response stream is closed"))
+    }
+}
+
+impl Drop for ResponseSink {
+    fn drop(&mut self) {
+        // stream might be already closed
+        drop(self.flush());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::future::Future;
+    use futures::stream::Stream;
+    use futures::sync::mpsc::unbounded;
+
+    #[test]
+    fn buffer_and_flush() {
+        let (tx, rx) = unbounded();
+        let mut sink = ResponseSink::new(tx);
+        sink.set_buffer_size(4);
+        sink.write(b"ab").unwrap();
+        sink.write(b"cd").unwrap();
+        sink.write(b"e").unwrap();
+        sink.flush().unwrap();
+        sink.flush().unwrap();
+        sink.write(b"f").unwrap();
+        drop(sink);
+
+        let chunks: Vec<Bytes> = rx.collect().wait().unwrap();
+        assert_eq!(
+            vec![Bytes::from("abcd"), Bytes::from("e"), Bytes::from("f")],
+            chunks
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! HTTP/1.1 `Upgrade: h2c` (RFC 7540 section 3.2)
+
+use std::str;
+
+use solicit::frame::settings::SettingsFrame;
+use solicit::header::Headers;
+
+/// Response to accepted upgrade request, followed by server connection preface
+pub const H2C_101_RESPONSE: &[u8] = b"\
+HTTP/1.1 101 Switching Protocols\r\n\
+Connection: Upgrade\r\n\
+Upgrade: h2c\r\n\
+\r\n\
+";
+
+/// Upgrade request is not accepted if its head is larger than this
+pub const MAX_REQUEST_HEAD_SIZE: usize = 16 * 1024;
+
+/// Accepted upgrade request
+pub struct H2cUpgrade {
+    /// HTTP/1.1 request converted to HTTP/2 request headers, stream 1
+    pub headers: Headers,
+    /// Decoded `HTTP2-Settings`
+    pub settings: SettingsFrame,
+}
+
+/// Request head is complete
+pub fn is_request_head_complete(buf: &[u8]) -> bool {
+    buf.ends_with(b"\r\n\r\n") || buf.ends_with(b"\n\n")
+}
+
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+// 8.1.2.2: connection-specific header fields must not be forwarded to HTTP/2
+fn is_connection_specific(name: &str) -> bool {
+    matches!(
+        name,
+        "connection"
+            | "upgrade"
+            | "http2-settings"
+            | "keep-alive"
+            | "proxy-connection"
+            | "transfer-encoding"
+            | "host"
+    )
+}
+
+/// Parse HTTP/1.1 request head, `None` if it is not a valid upgrade request.
+///
+/// Requests with body are not upgraded.
+pub fn parse_upgrade_request(head: &[u8]) -> Option<H2cUpgrade> {
+    let head = str::from_utf8(head).ok()?;
+    let mut lines = head.lines();
+
+    let request_line = lines.next()?;
+    let mut parts = request_line.split(' ');
+    let method = parts.next()?;
+    let target = parts.next()?;
+    if parts.next()? != "HTTP/1.1" || parts.next().is_some() {
+        return None;
+    }
+
+    let mut fields = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let colon = line.find(':')?;
+        let name = line[..colon].trim().to_ascii_lowercase();
+        let value = line[colon + 1..].trim();
+        if name.is_empty() {
+            return None;
+        }
+        fields.push((name, value));
+    }
+
+    let get = |name: &str| -> Vec<&str> {
+        fields
+            .iter()
+            .filter(|f| f.0 == name)
+            .map(|f| f.1)
+            .collect()
+    };
+
+    if !get("upgrade").iter().any(|v| has_token(v, "h2c")) {
+        return None;
+    }
+    let connection = get("connection");
+    if !connection.iter().any(|v| has_token(v, "upgrade"))
+        || !connection.iter().any(|v| has_token(v, "http2-settings"))
+    {
+        return None;
+    }
+
+    // 3.2.1: exactly one HTTP2-Settings header field
+    let http2_settings = get("http2-settings");
+    if http2_settings.len() != 1 {
+        return None;
+    }
+    let payload = base64url_decode(http2_settings[0])?;
+    let settings = SettingsFrame::parse_payload(&payload).ok()?;
+
+    if !get("transfer-encoding").is_empty() {
+        return None;
+    }
+    match get("content-length").first() {
+        None | Some(&"0") => {}
+        Some(..) => return None,
+    }
+
+    let mut headers = Headers::new();
+    headers.add(":method", method);
+    headers.add(":path", target);
+    headers.add(":scheme", "http");
+    if let Some(host) = get("host").first() {
+        headers.add(":authority", host);
+    }
+    for &(ref name, value) in &fields {
+        if !is_connection_specific(name) {
+            headers.add(name, value);
+        }
+    }
+
+    Some(H2cUpgrade {
+        headers,
+        settings: SettingsFrame::from_settings(settings),
+    })
+}
+
+/// Decode base64url without padding (RFC 4648 section 5)
+fn base64url_decode(s: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        Some(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    let s = s.trim_end_matches('=').as_bytes();
+    if s.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut r = Vec::with_capacity(s.len() * 3 / 4);
+    for chunk in s.chunks(4) {
+        let mut acc = 0;
+        for &c in chunk {
+            acc = (acc << 6) | value(c)?;
+        }
+        acc <<= 6 * (4 - chunk.len()) as u32;
+        r.push((acc >> 16) as u8);
+        if chunk.len() > 2 {
+            r.push((acc >> 8) as u8);
+        }
+        if chunk.len() > 3 {
+            r.push(acc as u8);
+        }
+    }
+    Some(r)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solicit::frame::settings::HttpSetting;
+
+    #[test]
+    fn test_base64url_decode() {
+        assert_eq!(Some(Vec::new()), base64url_decode(""));
+        assert_eq!(Some(b"f".to_vec()), base64url_decode("Zg"));
+        assert_eq!(Some(b"fo".to_vec()), base64url_decode("Zm8"));
+        assert_eq!(Some(b"foo".to_vec()), base64url_decode("Zm9v"));
+        assert_eq!(Some(b"foo".to_vec()), base64url_decode("Zm9v=="));
+        assert_eq!(Some(vec![0xfb, 0xff]), base64url_decode("-_8"));
+        assert_eq!(None, base64url_decode("Z"));
+        assert_eq!(None, base64url_decode("Zm+v"));
+    }
+
+    #[test]
+    fn test_parse_upgrade_request() {
+        // SETTINGS_INITIAL_WINDOW_SIZE = 5
+        let head = b"GET /a?b HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAQAAAAF\r\n\
+            X-Test: 1\r\n\
+            \r\n";
+        let upgrade = parse_upgrade_request(head).expect("upgrade");
+        assert_eq!(
+            vec![HttpSetting::InitialWindowSize(5)],
+            upgrade.settings.settings
+        );
+        assert_eq!("GET", upgrade.headers.method());
+        assert_eq!("/a?b", upgrade.headers.path());
+        assert_eq!(Some("example.com"), upgrade.headers.get_opt(":authority"));
+        assert_eq!(Some("1"), upgrade.headers.get_opt("x-test"));
+        assert_eq!(None, upgrade.headers.get_opt("upgrade"));
+        assert_eq!(None, upgrade.headers.get_opt("http2-settings"));
+
+        // no HTTP2-Settings
+        assert!(parse_upgrade_request(
+            b"GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n"
+        ).is_none());
+        // body
+        assert!(parse_upgrade_request(
+            b"POST / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: h2c\r\nHTTP2-Settings: \r\nContent-Length: 3\r\n\r\n"
+        ).is_none());
+        // not h2c
+        assert!(parse_upgrade_request(
+            b"GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+            Upgrade: websocket\r\nHTTP2-Settings: \r\n\r\n"
+        ).is_none());
+    }
+}
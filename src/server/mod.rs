@@ -1,6 +1,12 @@
+#[cfg(feature = "compression")]
+pub mod server_compression;
 pub mod server_conf;
 pub mod server_conn;
+pub mod server_push;
 pub mod server_tls;
+pub mod request_id;
+mod stream_rate_limit;
+pub(crate) mod h2c;
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -9,6 +15,7 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
 
 use tls_api;
 
@@ -23,9 +30,12 @@ use futures::sync::oneshot;
 
 use futures_cpupool;
 
+use bytes::Bytes;
+
 use exec::CpuPoolOption;
 
 use error::Error;
+use error::ErrorCode;
 use result::Result;
 
 use solicit_async::*;
@@ -33,6 +43,7 @@ use solicit_async::*;
 use futures_misc::*;
 
 use tls_api::TlsAcceptor;
+use tls_api::TlsAcceptorBuilder;
 use tls_api_stub;
 
 use super::common::*;
@@ -139,13 +150,33 @@ impl<A: tls_api::TlsAcceptor> ServerBuilder<A> {
         self.tls = ServerTlsOption::Tls(Arc::new(acceptor));
     }
 
+    /// Build TLS acceptor with `h2` ALPN protocol (if supported) and use it.
+    ///
+    /// Settings not covered by `tls_api` like minimum protocol version
+    /// or cipher list can be set on implementation builder
+    /// returned by `underlying_mut` before calling this function.
+    pub fn set_tls_builder(&mut self, mut builder: A::Builder) -> Result<()> {
+        if A::supports_alpn() {
+            builder.set_alpn_protocols(&[b"h2"])?;
+        }
+        self.set_tls(builder.build()?);
+        Ok(())
+    }
+
     pub fn build(self) -> Result<Server> {
+        self.conf.validate()?;
+
         let (alive_tx, alive_rx) = mpsc::channel();
 
         let state: Arc<Mutex<ServerState>> = Default::default();
 
+        let (stop_accept_tx, stop_accept_rx) = oneshot::channel();
+        state.lock().expect("lock").stop_accept = Some(stop_accept_tx);
+
         let state_copy = state.clone();
 
+        let shutdown_timeout = self.conf.shutdown_timeout();
+
         let (shutdown_signal, shutdown_future) = shutdown_signal();
 
         // TODO: why done_tx is unused?
@@ -169,6 +200,7 @@ impl<A: tls_api::TlsAcceptor> ServerBuilder<A> {
                     listen,
                     cpu_pool,
                     shutdown_future,
+                    stop_accept_rx,
                     conf,
                     service,
                     alive_tx,
@@ -196,6 +228,7 @@ impl<A: tls_api::TlsAcceptor> ServerBuilder<A> {
                         listen,
                         cpu_pool,
                         shutdown_future,
+                        stop_accept_rx,
                         conf,
                         service,
                         alive_tx,
@@ -211,6 +244,7 @@ impl<A: tls_api::TlsAcceptor> ServerBuilder<A> {
             local_addr: local_addr,
             join: Some(join),
             alive_rx: alive_rx,
+            shutdown_timeout,
         })
     }
 }
@@ -226,15 +260,42 @@ pub struct Server {
     shutdown: ShutdownSignal,
     alive_rx: mpsc::Receiver<()>,
     join: Option<Completion>,
+    shutdown_timeout: Duration,
 }
 
 #[derive(Default)]
 struct ServerState {
     last_conn_id: u64,
     conns: HashMap<u64, ServerConn>,
+    // stop accepting new connections and drain existing with given timeout
+    stop_accept: Option<oneshot::Sender<Duration>>,
+    // notified when all connections are closed
+    conns_closed: Vec<oneshot::Sender<()>>,
 }
 
 impl ServerState {
+    fn remove_conn(&mut self, conn_id: u64) {
+        let removed = self.conns.remove(&conn_id);
+        assert!(removed.is_some());
+        self.notify_if_conns_closed();
+    }
+
+    fn notify_if_conns_closed(&mut self) {
+        if self.conns.is_empty() {
+            for tx in self.conns_closed.drain(..) {
+                // ignore error
+                tx.send(()).ok();
+            }
+        }
+    }
+
+    /// Resolves when connections are closed after accept is stopped
+    fn wait_conns_closed(&mut self) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        self.conns_closed.push(tx);
+        Box::new(rx.map_err(|_| Error::Other("server is shut down")))
+    }
+
     fn snapshot(&self) -> HttpFutureSend<ServerStateSnapshot> {
         let futures: Vec<_> = self
             .conns
@@ -248,19 +309,86 @@ impl ServerState {
     }
 }
 
+/// State of all server connections returned by `Server::dump_state`
 pub struct ServerStateSnapshot {
+    /// Connections by connection id
     pub conns: HashMap<u64, ConnStateSnapshot>,
 }
 
 impl ServerStateSnapshot {
+    /// Connections ordered by id, i. e. by accept time
+    pub fn conns_by_id(&self) -> Vec<(u64, &ConnStateSnapshot)> {
+        let mut conns: Vec<_> = self.conns.iter().map(|(&id, c)| (id, c)).collect();
+        conns.sort_by_key(|&(id, _)| id);
+        conns
+    }
+
+    /// The only connection, panics if there are no connections or more than one
     pub fn single_conn(&self) -> (u64, &ConnStateSnapshot) {
-        let mut iter = self.conns.iter();
-        let (&id, conn) = iter.next().expect("no conns");
-        assert!(iter.next().is_none(), "more than one conn");
-        (id, conn)
+        let conns = self.conns_by_id();
+        assert!(conns.len() <= 1, "more than one conn");
+        conns.into_iter().next().expect("no conns")
     }
 }
 
+/// Timeout reduced by `max_streams / open_streams` when too many streams are open
+fn drain_timeout(timeout: Duration, open_streams: u64, max_streams: Option<u64>) -> Duration {
+    match max_streams {
+        Some(max_streams) if open_streams > max_streams => {
+            let millis = timeout.as_secs() as f64 * 1000.0
+                + timeout.subsec_nanos() as f64 / 1_000_000.0;
+            let millis = millis * max_streams as f64 / open_streams as f64;
+            Duration::from_millis(millis as u64)
+        }
+        _ => timeout,
+    }
+}
+
+/// Send GOAWAY to all connections, and close connections
+/// which are not complete after `timeout`.
+fn drain_conns(
+    handle: &reactor::Handle,
+    state: Arc<Mutex<ServerState>>,
+    timeout: Duration,
+    max_streams: Option<u64>,
+) {
+    let timeout = {
+        let mut g = state.lock().expect("lock");
+        let open_streams: u64 = g.conns.values().map(|c| c.metrics().open_streams).sum();
+        for conn in g.conns.values() {
+            conn.shutdown_gracefully();
+        }
+        g.notify_if_conns_closed();
+        let drain_timeout = drain_timeout(timeout, open_streams, max_streams);
+        if drain_timeout != timeout {
+            info!(
+                "{} streams open at shutdown, reducing timeout to {:?}",
+                open_streams, drain_timeout
+            );
+        }
+        drain_timeout
+    };
+
+    let timeout = match reactor::Timeout::new(timeout, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            warn!("failed to create drain timeout: {}", e);
+            return;
+        }
+    };
+
+    handle.spawn(timeout.then(move |_| {
+        let g = state.lock().expect("lock");
+        if !g.conns.is_empty() {
+            warn!("graceful shutdown timed out, closing connections");
+        }
+        for conn in g.conns.values() {
+            conn.force_close();
+        }
+        Ok(())
+    }));
+}
+
 fn spawn_server_event_loop<S, A>(
     handle: reactor::Handle,
     state: Arc<Mutex<ServerState>>,
@@ -268,6 +396,7 @@ fn spawn_server_event_loop<S, A>(
     listen: Box<ToTokioListener + Send>,
     exec: CpuPoolOption,
     shutdown_future: ShutdownFuture,
+    stop_accept: oneshot::Receiver<Duration>,
     conf: ServerConf,
     service: S,
     _alive_tx: mpsc::Sender<()>,
@@ -277,10 +406,11 @@ where
     A: TlsAcceptor,
 {
     let service = Arc::new(service);
+    let drain_max_streams = conf.drain_max_streams;
 
     let tokio_listener = listen.to_tokio_listener(&handle);
 
-    let stuff = stream::repeat((handle.clone(), service, state, tls, conf));
+    let stuff = stream::repeat((handle.clone(), service, state.clone(), tls, conf));
 
     let loop_run = tokio_listener
         .incoming()
@@ -316,8 +446,7 @@ where
                     future
                         .then(move |r| {
                             let mut g = state.lock().expect("lock");
-                            let removed = g.conns.remove(&conn_id);
-                            assert!(removed.is_some());
+                            g.remove_conn(conn_id);
                             r
                         }).map_err(|e| {
                             warn!("connection end: {:?}", e);
@@ -328,6 +457,19 @@ where
             },
         );
 
+    // Stop accepting on graceful shutdown, but keep serving accepted connections
+    let drain_handle = handle.clone();
+    let loop_run = loop_run.select2(stop_accept).then(move |r| match r {
+        Ok(future::Either::A(((), _))) => future::Either::A(future::finished(())),
+        Err(future::Either::A((e, _))) => future::Either::A(future::failed(e)),
+        Ok(future::Either::B((timeout, _))) => {
+            info!("stopped accepting connections");
+            drain_conns(&drain_handle, state, timeout, drain_max_streams);
+            future::Either::B(future::empty())
+        }
+        Err(future::Either::B(..)) => future::Either::B(future::empty()),
+    });
+
     let (done_tx, done_rx) = oneshot::channel();
 
     let shutdown_future = shutdown_future.then(move |_| {
@@ -359,7 +501,52 @@ impl Server {
         self.alive_rx.try_recv() != Err(mpsc::TryRecvError::Disconnected)
     }
 
-    // for tests
+    /// Stop accepting connections, send GOAWAY(NO_ERROR) to all connections
+    /// and wait for already started streams to complete.
+    ///
+    /// Streams not completed within `timeout` are reset and connections are closed.
+    /// Returned future resolves when all connections are closed.
+    pub fn shutdown_gracefully_with_timeout(&self, timeout: Duration) -> HttpFutureSend<()> {
+        let mut g = self.state.lock().expect("lock");
+        let closed = g.wait_conns_closed();
+        match g.stop_accept.take() {
+            // ignore error
+            Some(stop_accept) => drop(stop_accept.send(timeout)),
+            // already shutting down
+            None => g.notify_if_conns_closed(),
+        }
+        closed
+    }
+
+    /// `shutdown_gracefully_with_timeout` with `ServerConf::shutdown_timeout`.
+    ///
+    /// New streams opened by clients after GOAWAY are refused with `REFUSED_STREAM`.
+    /// Subsequent calls do not send GOAWAY again and return futures
+    /// resolved at the same time.
+    pub fn shutdown_gracefully(&self) -> HttpFutureSend<()> {
+        self.shutdown_gracefully_with_timeout(self.shutdown_timeout)
+    }
+
+    /// Counters of open connections by connection id, cheap to call
+    pub fn conn_metrics(&self) -> HashMap<u64, ConnMetrics> {
+        let g = self.state.lock().expect("lock");
+        g.conns.iter().map(|(&id, conn)| (id, conn.metrics())).collect()
+    }
+
+    /// Send GOAWAY with given error code and debug data to connection
+    /// with given id and close it, `false` if there's no such connection.
+    pub fn close_conn(&self, conn_id: u64, error_code: ErrorCode, debug_data: Bytes) -> bool {
+        let g = self.state.lock().expect("lock");
+        match g.conns.get(&conn_id) {
+            Some(conn) => {
+                conn.close(error_code, debug_data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// State of all connections, e. g. for monitoring
     pub fn dump_state(&self) -> HttpFutureSend<ServerStateSnapshot> {
         let g = self.state.lock().expect("lock");
         g.snapshot()
@@ -0,0 +1,93 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use futures::future::Future;
+
+use resp::Response;
+use solicit::header::Header;
+use solicit::header::Headers;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn random_u64(salt: u64) -> u64 {
+    // `RandomState` is seeded with random keys, which is good enough for ids
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u64(now.as_secs());
+        hasher.write_u32(now.subsec_nanos());
+    }
+    hasher.write_u64(salt);
+    hasher.finish()
+}
+
+/// Generate random id formatted like version 4 UUID
+pub fn generate_request_id() -> String {
+    let hi = random_u64(0);
+    let lo = random_u64(hi);
+    // version 4, variant 1
+    let hi = (hi & !0xf000) | 0x4000;
+    let lo = (lo & !(0xc << 60)) | (0x8 << 60);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        hi >> 32,
+        (hi >> 16) & 0xffff,
+        hi & 0xffff,
+        lo >> 48,
+        lo & 0xffff_ffff_ffff,
+    )
+}
+
+/// Take request id from request headers, or generate it and add to request headers
+pub fn request_id(headers: &mut Headers, name: &str) -> String {
+    if let Some(id) = headers.get_opt(name) {
+        return id.to_owned();
+    }
+    let id = generate_request_id();
+    headers.add(name, &id);
+    id
+}
+
+/// Echo request id in response headers unless handler already set it
+pub fn add_to_response(response: Response, name: String, id: String) -> Response {
+    Response::new(response.0.map(move |(mut headers, rem)| {
+        if headers.get_opt(&name).is_none() {
+            headers.0.push(Header::new(name, id));
+        }
+        (headers, rem)
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generate() {
+        let a = generate_request_id();
+        let b = generate_request_id();
+        assert_ne!(a, b);
+        assert_eq!(36, a.len());
+        let parts: Vec<&str> = a.split('-').collect();
+        assert_eq!(vec![8, 4, 4, 4, 12], parts.iter().map(|p| p.len()).collect::<Vec<_>>());
+        assert!(parts[2].starts_with('4'));
+        assert!("89ab".contains(&parts[3][..1]));
+    }
+
+    #[test]
+    fn propagate() {
+        let mut headers = Headers::new();
+        headers.add("x-request-id", "abc");
+        assert_eq!("abc", request_id(&mut headers, "x-request-id"));
+        assert_eq!(1, headers.0.len());
+
+        let mut headers = Headers::new();
+        let id = request_id(&mut headers, "x-request-id");
+        assert_eq!(Some(&id[..]), headers.get_opt("x-request-id"));
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+
+use bytes::Bytes;
+
+use flate2;
+use flate2::write::GzEncoder;
+use flate2::write::ZlibEncoder;
+
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::Async;
+use futures::Poll;
+
+use data_or_trailers::DataOrTrailers;
+use data_or_trailers::HttpStreamAfterHeaders;
+use error;
+use resp::Response;
+use solicit::end_stream::EndStream;
+use solicit::header::Header;
+use solicit::header::Headers;
+
+/// Content coding supported by response compression
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    /// zlib format, as required by HTTP `deflate` coding
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Supported encodings in order of preference
+    fn all() -> &'static [ContentEncoding] {
+        &[ContentEncoding::Gzip, ContentEncoding::Deflate]
+    }
+
+    /// Select encoding from `accept-encoding` header value.
+    ///
+    /// Encoding with highest quality value is selected,
+    /// gzip is preferred when qualities are equal.
+    pub fn from_accept_encoding(value: &str) -> Option<ContentEncoding> {
+        let mut best: Option<(ContentEncoding, u32)> = None;
+        let mut wildcard: Option<u32> = None;
+        let mut explicit: Vec<ContentEncoding> = Vec::new();
+
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let mut q = 1000;
+            for param in parts {
+                let param = param.trim();
+                if param.starts_with("q=") || param.starts_with("Q=") {
+                    q = match param[2..].trim().parse::<f32>() {
+                        Ok(q) if (0.0..=1.0).contains(&q) => (q * 1000.0) as u32,
+                        _ => 0,
+                    };
+                }
+            }
+
+            if coding == "*" {
+                wildcard = Some(q);
+                continue;
+            }
+
+            for &encoding in ContentEncoding::all() {
+                let matches = coding == encoding.name()
+                    || (coding == "x-gzip" && encoding == ContentEncoding::Gzip);
+                if matches {
+                    explicit.push(encoding);
+                    if q > 0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                        best = Some((encoding, q));
+                    }
+                }
+            }
+        }
+
+        // `*` matches encodings not listed explicitly
+        if let Some(q) = wildcard {
+            for &encoding in ContentEncoding::all() {
+                if !explicit.contains(&encoding)
+                    && q > 0
+                    && best.is_none_or(|(_, best_q)| q > best_q)
+                {
+                    best = Some((encoding, q));
+                }
+            }
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+/// Which responses are compressed by server.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    /// Responses with `content-length` less than this are not compressed.
+    /// Responses without `content-length` are compressed regardless of size.
+    pub min_size: u64,
+    /// Compress only responses with these content types
+    /// (prefix match, e. g. `text/` matches `text/html; charset=utf-8`).
+    pub content_types: Vec<String>,
+    /// Compress only responses to requests with these path prefixes,
+    /// all paths if empty.
+    pub paths: Vec<String>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> CompressionPolicy {
+        CompressionPolicy {
+            min_size: 1024,
+            content_types: vec![
+                "text/".to_owned(),
+                "application/json".to_owned(),
+                "application/javascript".to_owned(),
+                "application/xml".to_owned(),
+                "image/svg+xml".to_owned(),
+            ],
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Select encoding for request, `None` if client does not accept any,
+    /// request path is not configured for compression or request is `HEAD`
+    /// (response to `HEAD` has no body, so its headers must not describe
+    /// compressed body the client would not get with `GET`).
+    pub fn request_encoding(&self, req_headers: &Headers) -> Option<ContentEncoding> {
+        if req_headers.get_opt(":method") == Some("HEAD") {
+            return None;
+        }
+        if !self.paths.is_empty() {
+            let path = req_headers.get_opt(":path").unwrap_or("");
+            if !self.paths.iter().any(|p| path.starts_with(&p[..])) {
+                return None;
+            }
+        }
+        req_headers
+            .get_opt("accept-encoding")
+            .and_then(ContentEncoding::from_accept_encoding)
+    }
+
+    /// Whether response with given headers should be compressed.
+    ///
+    /// Handler can opt out by setting `content-encoding` (e. g. `identity`).
+    fn should_compress(&self, headers: &Headers) -> bool {
+        let status = headers.status();
+        if status < 200 || status == 204 || status == 206 || status == 304 {
+            return false;
+        }
+        if headers.get_opt("content-encoding").is_some() {
+            return false;
+        }
+        if let Some(content_length) = headers.content_length() {
+            if content_length < self.min_size {
+                return false;
+            }
+        }
+        let content_type = match headers.get_opt("content-type") {
+            Some(content_type) => content_type.to_ascii_lowercase(),
+            None => return false,
+        };
+        self.content_types
+            .iter()
+            .any(|t| content_type.starts_with(&t.to_ascii_lowercase()[..]))
+    }
+
+    /// Compress response body if response matches the policy
+    pub(crate) fn compress(&self, response: Response, encoding: ContentEncoding) -> Response {
+        let policy = self.clone();
+        Response::new(response.0.map(move |(mut headers, rem)| {
+            if !policy.should_compress(&headers) {
+                return (headers, rem);
+            }
+
+            headers.0.retain(|h| h.name() != b"content-length");
+            headers.0.push(Header::new("content-encoding", encoding.name()));
+            add_vary_accept_encoding(&mut headers);
+            weaken_etag(&mut headers);
+
+            let rem = HttpStreamAfterHeaders::new(CompressStream {
+                stream: rem,
+                encoder: Some(Encoder::new(encoding)),
+                queue: VecDeque::new(),
+                done: false,
+            });
+            (headers, rem)
+        }))
+    }
+}
+
+/// Append `accept-encoding` to `vary`, keeping fields already listed by handler
+fn add_vary_accept_encoding(headers: &mut Headers) {
+    let listed = headers
+        .0
+        .iter()
+        .filter(|h| h.name() == b"vary")
+        .flat_map(|h| h.value().split(|&b| b == b','))
+        .map(|f| String::from_utf8_lossy(f).trim().to_ascii_lowercase())
+        .any(|f| f == "*" || f == "accept-encoding");
+    if listed {
+        return;
+    }
+
+    match headers.0.iter().position(|h| h.name() == b"vary") {
+        Some(pos) => {
+            let mut value = headers.0[pos].value().to_vec();
+            value.extend_from_slice(b", accept-encoding");
+            headers.0[pos] = Header::new("vary", value);
+        }
+        None => headers.0.push(Header::new("vary", "accept-encoding")),
+    }
+}
+
+/// Compressed representation is not byte-identical to the original,
+/// so strong `etag` computed by handler is replaced with weak one
+/// (RFC 7232 section 2.1).
+fn weaken_etag(headers: &mut Headers) {
+    for h in &mut headers.0 {
+        if h.name() == b"etag" && !h.value().starts_with(b"W/") {
+            let mut value = b"W/".to_vec();
+            value.extend_from_slice(h.value());
+            *h = Header::new("etag", value);
+        }
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: ContentEncoding) -> Encoder {
+        let level = flate2::Compression::default();
+        match encoding {
+            ContentEncoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            ContentEncoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match *self {
+            Encoder::Gzip(ref mut e) => e.write_all(data),
+            Encoder::Deflate(ref mut e) => e.write_all(data),
+        }
+    }
+
+    /// Compress data and flush, so the peer could decode everything sent so far
+    fn write_flush(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let buf = match *self {
+            Encoder::Gzip(ref mut e) => {
+                e.write_all(data)?;
+                e.flush()?;
+                e.get_mut()
+            }
+            Encoder::Deflate(ref mut e) => {
+                e.write_all(data)?;
+                e.flush()?;
+                e.get_mut()
+            }
+        };
+        Ok(Bytes::from(buf.split_off(0)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        Ok(Bytes::from(match self {
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Deflate(e) => e.finish()?,
+        }))
+    }
+}
+
+/// Response body compressing stream
+struct CompressStream {
+    stream: HttpStreamAfterHeaders,
+    encoder: Option<Encoder>,
+    queue: VecDeque<DataOrTrailers>,
+    done: bool,
+}
+
+impl CompressStream {
+    fn finish(&mut self, end_stream: EndStream) -> Result<(), error::Error> {
+        if let Some(encoder) = self.encoder.take() {
+            let data = encoder.finish()?;
+            self.queue.push_back(DataOrTrailers::Data(data, end_stream));
+        }
+        Ok(())
+    }
+}
+
+impl Stream for CompressStream {
+    type Item = DataOrTrailers;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<DataOrTrailers>, error::Error> {
+        loop {
+            if let Some(part) = self.queue.pop_front() {
+                return Ok(Async::Ready(Some(part)));
+            }
+
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match self.stream.poll()? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(Some(DataOrTrailers::Data(data, EndStream::Yes))) => {
+                    if let Some(ref mut encoder) = self.encoder {
+                        encoder.write_all(&data)?;
+                    }
+                    self.finish(EndStream::Yes)?;
+                    self.done = true;
+                }
+                Async::Ready(Some(DataOrTrailers::Data(data, EndStream::No))) => {
+                    if let Some(ref mut encoder) = self.encoder {
+                        let data = encoder.write_flush(&data)?;
+                        if !data.is_empty() {
+                            self.queue.push_back(DataOrTrailers::Data(data, EndStream::No));
+                        }
+                    }
+                }
+                Async::Ready(Some(DataOrTrailers::Trailers(trailers))) => {
+                    self.finish(EndStream::No)?;
+                    self.queue.push_back(DataOrTrailers::Trailers(trailers));
+                    self.done = true;
+                }
+                Async::Ready(None) => {
+                    self.finish(EndStream::Yes)?;
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_accept_encoding() {
+        assert_eq!(
+            Some(ContentEncoding::Gzip),
+            ContentEncoding::from_accept_encoding("gzip, deflate, br")
+        );
+        assert_eq!(
+            Some(ContentEncoding::Deflate),
+            ContentEncoding::from_accept_encoding("br, deflate")
+        );
+        assert_eq!(
+            Some(ContentEncoding::Deflate),
+            ContentEncoding::from_accept_encoding("gzip;q=0.5, deflate")
+        );
+        assert_eq!(
+            Some(ContentEncoding::Deflate),
+            ContentEncoding::from_accept_encoding("gzip;q=0, *")
+        );
+        assert_eq!(
+            Some(ContentEncoding::Gzip),
+            ContentEncoding::from_accept_encoding("*")
+        );
+        assert_eq!(None, ContentEncoding::from_accept_encoding("br"));
+        assert_eq!(None, ContentEncoding::from_accept_encoding("identity"));
+        assert_eq!(None, ContentEncoding::from_accept_encoding("gzip;q=0"));
+    }
+
+    #[test]
+    fn request_encoding_paths() {
+        let mut policy = CompressionPolicy::default();
+        policy.paths = vec!["/static/".to_owned()];
+
+        let mut headers = Headers::new_get("/static/app.js");
+        headers.add("accept-encoding", "gzip");
+        assert_eq!(Some(ContentEncoding::Gzip), policy.request_encoding(&headers));
+
+        let mut headers = Headers::new_get("/api");
+        headers.add("accept-encoding", "gzip");
+        assert_eq!(None, policy.request_encoding(&headers));
+    }
+
+    #[test]
+    fn should_compress() {
+        let policy = CompressionPolicy::default();
+
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/html; charset=utf-8");
+        assert!(policy.should_compress(&headers));
+
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "image/png");
+        assert!(!policy.should_compress(&headers));
+
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/plain");
+        headers.add("content-length", "10");
+        assert!(!policy.should_compress(&headers));
+
+        let mut headers = Headers::ok_200();
+        headers.add("content-type", "text/plain");
+        headers.add("content-encoding", "gzip");
+        assert!(!policy.should_compress(&headers));
+
+        let mut headers = Headers::from_status(304);
+        headers.add("content-type", "text/plain");
+        assert!(!policy.should_compress(&headers));
+    }
+
+    #[test]
+    fn request_encoding_head() {
+        let policy = CompressionPolicy::default();
+
+        let mut headers = Headers::new_get("/");
+        headers.add("accept-encoding", "gzip");
+        assert_eq!(Some(ContentEncoding::Gzip), policy.request_encoding(&headers));
+
+        let mut headers = Headers(vec![
+            Header::new(":method", "HEAD"),
+            Header::new(":path", "/"),
+        ]);
+        headers.add("accept-encoding", "gzip");
+        assert_eq!(None, policy.request_encoding(&headers));
+    }
+
+    #[test]
+    fn add_vary_accept_encoding() {
+        let mut headers = Headers::ok_200();
+        super::add_vary_accept_encoding(&mut headers);
+        assert_eq!("accept-encoding", headers.get("vary"));
+
+        let mut headers = Headers::ok_200();
+        headers.add("vary", "cookie");
+        super::add_vary_accept_encoding(&mut headers);
+        assert_eq!("cookie, accept-encoding", headers.get("vary"));
+
+        let mut headers = Headers::ok_200();
+        headers.add("vary", "Accept-Encoding, cookie");
+        super::add_vary_accept_encoding(&mut headers);
+        assert_eq!("Accept-Encoding, cookie", headers.get("vary"));
+
+        let mut headers = Headers::ok_200();
+        headers.add("vary", "*");
+        super::add_vary_accept_encoding(&mut headers);
+        assert_eq!("*", headers.get("vary"));
+    }
+
+    #[test]
+    fn weaken_etag() {
+        let mut headers = Headers::ok_200();
+        headers.add("etag", "\"abc\"");
+        super::weaken_etag(&mut headers);
+        assert_eq!("W/\"abc\"", headers.get("etag"));
+
+        let mut headers = Headers::ok_200();
+        headers.add("etag", "W/\"abc\"");
+        super::weaken_etag(&mut headers);
+        assert_eq!("W/\"abc\"", headers.get("etag"));
+    }
+}
@@ -1,4 +1,19 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
 use common::CommonConf;
+use resp::Response;
+use solicit::header::Headers;
+use trace::RequestTracing;
+#[cfg(feature = "compression")]
+use server::server_compression::CompressionPolicy;
+
+use error::Error;
+use result::Result;
+
+use solicit::frame::settings::HttpSetting;
+use solicit::MAX_WINDOW_SIZE;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ServerAlpn {
@@ -8,6 +23,48 @@ pub enum ServerAlpn {
     Require,
 }
 
+/// Callback invoked with request path when handler response is abandoned
+/// because connection or stream was closed by peer before response completed.
+#[derive(Clone)]
+pub struct HandlerAbortedCallback(pub Arc<Fn(&str) + Send + Sync>);
+
+impl HandlerAbortedCallback {
+    pub fn new<F>(f: F) -> HandlerAbortedCallback
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        HandlerAbortedCallback(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for HandlerAbortedCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HandlerAbortedCallback")
+    }
+}
+
+type PanicResponseFn = Fn(&Headers, &str) -> Response + Send + Sync;
+
+/// Callback invoked with request headers and panic message
+/// to build a response when handler panics.
+#[derive(Clone)]
+pub struct PanicResponseCallback(pub Arc<PanicResponseFn>);
+
+impl PanicResponseCallback {
+    pub fn new<F>(f: F) -> PanicResponseCallback
+    where
+        F: Fn(&Headers, &str) -> Response + Send + Sync + 'static,
+    {
+        PanicResponseCallback(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for PanicResponseCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PanicResponseCallback")
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct ServerConf {
     /// TCP_NODELAY
@@ -21,8 +78,112 @@ pub struct ServerConf {
 
     /// Ignored on Windows
     pub reuse_port: Option<bool>,
+    /// Accept queue backlog passed to `listen()` of TCP listener,
+    /// default is 1024.
     pub backlog: Option<i32>,
 
+    /// Called when handler is dropped before response is fully sent,
+    /// e. g. because client disconnected.
+    pub on_handler_aborted: Option<HandlerAbortedCallback>,
+
+    /// Build response when handler panics,
+    /// plain text 500 response is sent by default.
+    pub panic_response: Option<PanicResponseCallback>,
+
+    /// Close connection with GOAWAY after it transferred
+    /// (read and written) more than given number of bytes.
+    pub max_connection_bytes: Option<u64>,
+
+    /// Close connection with GOAWAY(ENHANCE_YOUR_CALM) when its buffers
+    /// hold more than given number of bytes: HPACK dynamic tables,
+    /// unparsed input, request data not yet read by handlers
+    /// and response data waiting to be sent.
+    pub max_connection_memory: Option<usize>,
+
+    /// Send PING when connection was idle for this interval,
+    /// round-trip time is available in `Server::dump_state`.
+    /// Disabled by default.
+    pub ping_interval: Option<Duration>,
+    /// Close connection with `Error::KeepaliveTimeout`
+    /// if PING is not acknowledged within this time
+    /// (checked on PING interval ticks), default is PING interval.
+    pub ping_timeout: Option<Duration>,
+
+    /// Limit rate of new streams per connection to (streams per second, burst).
+    /// Streams over the limit are reset with `REFUSED_STREAM`,
+    /// connection is closed with GOAWAY if client keeps opening streams.
+    pub new_stream_rate_limit: Option<(u32, u32)>,
+
+    /// Close connection with GOAWAY(ENHANCE_YOUR_CALM) when client sends
+    /// more than given number of RST_STREAM frames within given time window,
+    /// e. g. when it opens and immediately resets streams ("rapid reset").
+    pub max_rst_stream_rate: Option<(u32, Duration)>,
+
+    /// Order response DATA frames by stream dependencies and weights
+    /// sent by client in HEADERS and PRIORITY frames (RFC 7540 section 5.3).
+    /// Disabled by default, streams are then written in order of own weight.
+    pub enable_priority: Option<bool>,
+
+    /// Compress response bodies with gzip or deflate
+    /// according to request `accept-encoding`.
+    /// Disabled by default. Brotli is not supported.
+    /// Requires `compression` feature.
+    #[cfg(feature = "compression")]
+    pub response_compression: Option<CompressionPolicy>,
+
+    /// Close accepted connection if client does not send preface
+    /// and initial SETTINGS within given time, default is 10 seconds.
+    pub preface_timeout: Option<Duration>,
+
+    /// Request id header name, e. g. `x-request-id`.
+    /// Id is generated if request does not have it,
+    /// id is added to response and to log lines of the stream.
+    pub request_id_header: Option<String>,
+
+    /// Deadline of `Server::shutdown_gracefully`, default is 30 seconds.
+    pub shutdown_timeout: Option<Duration>,
+
+    /// Respond `413 Payload Too Large` without calling the handler
+    /// when request `content-length` exceeds this limit.
+    /// Request body without `content-length` fails with `Error::BodyTooLarge`
+    /// and the stream is reset with `CANCEL` when more bytes are received.
+    pub max_content_length: Option<u64>,
+
+    /// Accept HTTP/1.1 request with `Upgrade: h2c` on plaintext connections:
+    /// respond with `101 Switching Protocols` and serve the request as stream 1
+    /// of HTTP/2 connection. Disabled by default, HTTP/1.1 requests are answered with 500.
+    pub allow_h2c_upgrade: Option<bool>,
+
+    /// Start a span for each request, parent context is taken
+    /// from request `traceparent` header. Disabled by default.
+    pub request_tracing: Option<RequestTracing>,
+
+    /// Send response body in DATA frames not larger than this,
+    /// e. g. to interleave streams more finely. Frames are never larger
+    /// than SETTINGS_MAX_FRAME_SIZE of the client.
+    pub out_data_frame_max_size: Option<u32>,
+
+    /// When more than this number of streams are open on all connections
+    /// at the start of graceful shutdown, shutdown timeout is reduced
+    /// proportionally, e. g. with limit 10 and 40 open streams
+    /// connections are force-closed after a quarter of the timeout.
+    pub drain_max_streams: Option<u64>,
+
+    // Settings advertised in initial SETTINGS frame,
+    // protocol defaults are used when not specified.
+    /// SETTINGS_HEADER_TABLE_SIZE
+    pub header_table_size: Option<u32>,
+    /// SETTINGS_ENABLE_PUSH, server must not set it to `true`
+    pub enable_push: Option<bool>,
+    /// SETTINGS_MAX_CONCURRENT_STREAMS
+    pub max_concurrent_streams: Option<u32>,
+    /// SETTINGS_INITIAL_WINDOW_SIZE
+    pub initial_window_size: Option<u32>,
+    /// SETTINGS_MAX_FRAME_SIZE
+    pub max_frame_size: Option<u32>,
+    /// SETTINGS_MAX_HEADER_LIST_SIZE
+    pub max_header_list_size: Option<u32>,
+
     pub common: CommonConf,
 }
 
@@ -30,4 +191,72 @@ impl ServerConf {
     pub fn new() -> ServerConf {
         Default::default()
     }
+
+    /// Check settings are within ranges allowed by RFC 7540 section 6.5.2
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.enable_push == Some(true) {
+            return Err(Error::Other("server must not enable push"));
+        }
+        if let Some(initial_window_size) = self.initial_window_size {
+            if initial_window_size > MAX_WINDOW_SIZE {
+                return Err(Error::Other("initial_window_size is too large"));
+            }
+        }
+        if let Some(conn_initial_window_size) = self.common.conn_initial_window_size {
+            if conn_initial_window_size > MAX_WINDOW_SIZE {
+                return Err(Error::Other("conn_initial_window_size is too large"));
+            }
+        }
+        if let Some(max_frame_size) = self.max_frame_size {
+            if !(0x4000..=0xff_ffff).contains(&max_frame_size) {
+                return Err(Error::Other("max_frame_size is out of range"));
+            }
+        }
+        if self.out_data_frame_max_size == Some(0) {
+            return Err(Error::Other("out_data_frame_max_size must be positive"));
+        }
+        if let Some(ref name) = self.request_id_header {
+            if name.is_empty() || name.starts_with(':') {
+                return Err(Error::Other("request_id_header is not a valid header name"));
+            }
+        }
+        if let Some((_, 0)) = self.new_stream_rate_limit {
+            return Err(Error::Other("new_stream_rate_limit burst must be positive"));
+        }
+        if let Some((_, window)) = self.max_rst_stream_rate {
+            if window == Duration::from_secs(0) {
+                return Err(Error::Other("max_rst_stream_rate window must be positive"));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn preface_timeout(&self) -> Duration {
+        self.preface_timeout.unwrap_or(Duration::from_secs(10))
+    }
+
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout.unwrap_or(Duration::from_secs(30))
+    }
+
+    /// Settings sent to client in initial SETTINGS frame
+    pub(crate) fn initial_settings(&self) -> Vec<HttpSetting> {
+        let mut settings = vec![HttpSetting::EnablePush(self.enable_push.unwrap_or(false))];
+        if let Some(header_table_size) = self.header_table_size {
+            settings.push(HttpSetting::HeaderTableSize(header_table_size));
+        }
+        if let Some(max_concurrent_streams) = self.max_concurrent_streams {
+            settings.push(HttpSetting::MaxConcurrentStreams(max_concurrent_streams));
+        }
+        if let Some(initial_window_size) = self.initial_window_size {
+            settings.push(HttpSetting::InitialWindowSize(initial_window_size));
+        }
+        if let Some(max_frame_size) = self.max_frame_size {
+            settings.push(HttpSetting::MaxFrameSize(max_frame_size));
+        }
+        if let Some(max_header_list_size) = self.max_header_list_size {
+            settings.push(HttpSetting::MaxHeaderListSize(max_header_list_size));
+        }
+        settings
+    }
 }
@@ -1,6 +1,7 @@
 use std::io;
 use std::panic;
 use std::sync::Arc;
+use std::time::Instant;
 
 use error;
 use result;
@@ -8,7 +9,14 @@ use result;
 use exec::CpuPoolOption;
 
 use solicit::end_stream::EndStream;
+use solicit::frame::continuation::ContinuationFlag;
+use solicit::frame::continuation::ContinuationFrame;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
+use solicit::frame::push_promise::PushPromiseFlag;
 use solicit::frame::settings::*;
+use solicit::session::StreamState;
+use solicit::frame::flags::Flags;
+use solicit::frame::PushPromiseFrame;
 use solicit::header::*;
 use solicit::StreamId;
 use solicit::DEFAULT_SETTINGS;
@@ -49,6 +57,18 @@ use headers_place::HeadersPlace;
 use misc::any_to_string;
 use req_resp::RequestOrResponse;
 use result_or_eof::ResultOrEof;
+#[cfg(feature = "compression")]
+use server::server_compression::CompressionPolicy;
+use server::server_conf::HandlerAbortedCallback;
+use server::h2c::H2cUpgrade;
+use server::request_id;
+use trace::RequestTracing;
+use server::server_push::ServerPush;
+use server::server_push::ServerPushShared;
+use server::server_conf::PanicResponseCallback;
+use server::stream_rate_limit::RstStreamRateLimit;
+use server::stream_rate_limit::StreamRateDecision;
+use server::stream_rate_limit::StreamRateLimit;
 use std::marker;
 use ErrorCode;
 use ServerConf;
@@ -103,6 +123,16 @@ where
 
 struct ServerConnData {
     factory: Arc<Service>,
+    on_handler_aborted: Option<HandlerAbortedCallback>,
+    panic_response: Option<PanicResponseCallback>,
+    #[cfg(feature = "compression")]
+    response_compression: Option<CompressionPolicy>,
+    new_stream_rate_limit: Option<StreamRateLimit>,
+    rst_stream_rate_limit: Option<RstStreamRateLimit>,
+    request_id_header: Option<String>,
+    request_tracing: Option<RequestTracing>,
+    push: Arc<ServerPushShared>,
+    max_content_length: Option<u64>,
 }
 
 impl ConnSpecific for ServerConnData {}
@@ -114,10 +144,62 @@ impl<I> ServerInner<I>
 where
     I: AsyncWrite + AsyncRead + Send + 'static,
 {
+    /// Refuse stream opened over our acknowledged SETTINGS_MAX_CONCURRENT_STREAMS,
+    /// `false` if stream is refused
+    fn check_max_concurrent_streams(&mut self, stream_id: StreamId) -> result::Result<bool> {
+        let max_concurrent_streams = self.our_settings_ack.max_concurrent_streams;
+        // pushed streams are limited by client SETTINGS_MAX_CONCURRENT_STREAMS
+        let peer_streams = self
+            .streams
+            .map
+            .keys()
+            .filter(|&&id| ServerTypes::<I>::init_where(id) == InitWhere::Peer)
+            .count();
+        if (peer_streams as u64) < max_concurrent_streams as u64 {
+            return Ok(true);
+        }
+
+        self.max_concurrent_streams_violations += 1;
+        warn!(
+            "client opened stream {} over max concurrent streams {}, violations: {}",
+            stream_id, max_concurrent_streams, self.max_concurrent_streams_violations
+        );
+        if stream_id > self.last_peer_stream_id {
+            self.last_peer_stream_id = stream_id;
+        }
+        self.send_rst_stream(stream_id, ErrorCode::RefusedStream)?;
+        Ok(false)
+    }
+
+    /// Apply new stream rate limit, `false` if stream is refused
+    fn check_new_stream_rate(&mut self, stream_id: StreamId) -> result::Result<bool> {
+        let decision = match self.specific.new_stream_rate_limit {
+            Some(ref mut limit) => limit.new_stream(Instant::now()),
+            None => return Ok(true),
+        };
+
+        match decision {
+            StreamRateDecision::Accept => Ok(true),
+            StreamRateDecision::Refuse => {
+                warn!("new stream rate limit exceeded, refusing stream {}", stream_id);
+                if stream_id > self.last_peer_stream_id {
+                    self.last_peer_stream_id = stream_id;
+                }
+                self.send_rst_stream(stream_id, ErrorCode::RefusedStream)?;
+                Ok(false)
+            }
+            StreamRateDecision::Goaway => {
+                warn!("client keeps opening streams over rate limit, sending GOAWAY");
+                self.send_goaway(ErrorCode::EnhanceYourCalm)?;
+                Ok(false)
+            }
+        }
+    }
+
     fn new_stream_from_client(
         &mut self,
         stream_id: StreamId,
-        headers: Headers,
+        mut headers: Headers,
     ) -> result::Result<HttpStreamRef<ServerTypes<I>>> {
         if ServerTypes::<I>::init_where(stream_id) == InitWhere::Locally {
             return Err(error::Error::Other(
@@ -133,58 +215,293 @@ where
 
         self.last_peer_stream_id = stream_id;
 
-        debug!("new stream: {}", stream_id);
+        let request_id = self
+            .specific
+            .request_id_header
+            .as_ref()
+            .map(|name| (name.clone(), request_id::request_id(&mut headers, name)));
+        // appended to log lines of this stream
+        let log_id = match request_id {
+            Some((_, ref id)) => format!(" request-id={}", id),
+            None => String::new(),
+        };
+
+        debug!("new stream: {}{}", stream_id, log_id);
+
+        let span = self
+            .specific
+            .request_tracing
+            .as_ref()
+            .map(|t| t.start_server_span(&headers, stream_id));
+
+        let content_length = headers.content_length();
+
+        // Reject declared oversized body before it is sent,
+        // body of unknown length is limited while it is received
+        let payload_too_large = match (self.specific.max_content_length, content_length) {
+            (Some(max), Some(len)) => len > max,
+            _ => false,
+        };
+        if payload_too_large {
+            warn!("request content-length exceeds limit{}", log_id);
+        }
 
         let (_, req_stream, out_window) = self.new_stream_data(
             stream_id,
-            headers.content_length(),
+            content_length,
             InMessageStage::AfterInitialHeaders,
             ServerStreamData {},
         );
 
+        if content_length.is_none() {
+            if let Some(max_content_length) = self.specific.max_content_length {
+                let mut stream = self.streams.get_mut(stream_id).expect("get stream");
+                stream.stream().in_rem_body_limit = Some(max_content_length);
+            }
+        }
+
         let req_stream = HttpStreamAfterHeaders::from_parts(req_stream);
 
         let factory = self.specific.factory.clone();
 
         let to_write_tx = self.to_write_tx.clone();
 
+        let push = ServerPush {
+            associated_stream_id: stream_id,
+            shared: self.specific.push.clone(),
+            to_write_tx: to_write_tx.clone(),
+        };
+
+        let on_handler_aborted = self.specific.on_handler_aborted.clone();
+        let path = match on_handler_aborted {
+            Some(..) => headers.get_opt(":path").unwrap_or("").to_owned(),
+            None => String::new(),
+        };
+
+        // request headers are needed for panic response after they are moved to handler
+        let panic_response = self.specific.panic_response.clone();
+        let panic_req_headers = match panic_response {
+            Some(..) => headers.clone(),
+            None => Headers::new(),
+        };
+
+        #[cfg(feature = "compression")]
+        let compression = self.specific.response_compression.as_ref().and_then(|p| {
+            p.request_encoding(&headers)
+                .map(|encoding| (p.clone(), encoding))
+        });
+
         self.exec.execute(Box::new(future::lazy(move || {
             let response = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                if payload_too_large {
+                    return Response::headers(Headers::from_status(413)).discard_request_body();
+                }
                 // TODO: do start request in executor
-                factory.start_request(headers, req_stream)
+                factory.start_request_with_push(headers, req_stream, push)
             }));
 
             let response = response.unwrap_or_else(|e| {
                 let e = any_to_string(e);
-                warn!("handler panicked: {}", e);
-
-                let headers = Headers::internal_error_500();
-                Response::from_stream(stream::iter_ok(vec![
-                    DataOrHeadersWithFlag::intermediate_headers(headers),
-                    DataOrHeadersWithFlag::last_data(Bytes::from(format!(
-                        "handler panicked: {}",
-                        e
-                    ))),
-                ]))
+                warn!("handler panicked: {}{}", e, log_id);
+
+                let custom = panic_response.and_then(|panic_response| {
+                    let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                        (panic_response.0)(&panic_req_headers, &e)
+                    }));
+                    if r.is_err() {
+                        warn!("panic response callback panicked{}", log_id);
+                    }
+                    r.ok()
+                });
+
+                custom.unwrap_or_else(|| {
+                    let headers = Headers::internal_error_500();
+                    Response::from_stream(stream::iter_ok(vec![
+                        DataOrHeadersWithFlag::intermediate_headers(headers),
+                        DataOrHeadersWithFlag::last_data(Bytes::from(format!(
+                            "handler panicked: {}",
+                            e
+                        ))),
+                    ]))
+                })
             });
 
+            #[cfg(feature = "compression")]
+            let response = match compression {
+                Some((policy, encoding)) => policy.compress(response, encoding),
+                None => response,
+            };
+
+            let response = match request_id {
+                Some((name, id)) => request_id::add_to_response(response, name, id),
+                None => response,
+            };
+
+            let response = match span {
+                Some(span) => span.trace_response(response),
+                None => response,
+            };
+
             let response = response.into_part_stream();
             let response = response.catch_unwind();
 
-            PumpStreamToWrite::<ServerTypes<I>> {
+            let pump = PumpStreamToWrite::<ServerTypes<I>> {
                 to_write_tx,
                 stream_id,
                 out_window,
                 stream: response,
-            }
+            };
+
+            pump.map(move |end| {
+                if end == PumpStreamEnd::Aborted {
+                    if let Some(on_handler_aborted) = on_handler_aborted {
+                        debug!("handler aborted for path {}{}", path, log_id);
+                        (on_handler_aborted.0)(&path);
+                    }
+                }
+            })
         })));
 
         Ok(self.streams.get_mut(stream_id).expect("get stream"))
     }
+
+    /// Apply settings of h2c upgrade request and start stream 1
+    /// with the request received over HTTP/1.1
+    fn process_h2c_upgrade(&mut self, upgrade: H2cUpgrade) -> result::Result<()> {
+        // 3.2: settings are applied before the request is processed
+        self.apply_peer_settings(upgrade.settings, false)?;
+
+        // 3.2: request is assigned stream 1, which is half-closed (remote)
+        if let Some(stream) = self.process_headers(1, EndStream::Yes, upgrade.headers)? {
+            stream.close_remote();
+        }
+        self.peer_closed_streams.add(1);
+        Ok(())
+    }
+
+    /// Push is allowed by peer and connection is not going away
+    fn push_allowed(&self) -> bool {
+        self.peer_settings.enable_push
+            && self.goaway_sent.is_none()
+            && self.goaway_received.is_none()
+    }
+
+    fn update_push_allowed(&self) {
+        self.specific.push.set_allowed(self.push_allowed());
+    }
+
+    /// Write PUSH_PROMISE on the request stream and start pushed response
+    /// on a new stream, or silently reject the push
+    /// (dropping response receiver fails `PushSink::send`).
+    fn process_push(
+        &mut self,
+        associated_stream_id: StreamId,
+        headers: Headers,
+        response_rx: oneshot::Receiver<Response>,
+    ) -> result::Result<()> {
+        if !self.push_allowed() {
+            debug!("push on stream {} rejected: push is not allowed", associated_stream_id);
+            return Ok(());
+        }
+
+        // 8.2.1: PUSH_PROMISE frames MUST only be sent on a peer-initiated stream
+        // that is in either the "open" or "half-closed (remote)" state
+        match self.streams.get_stream_state(associated_stream_id) {
+            Some(StreamState::Open) | Some(StreamState::HalfClosedRemote) => {}
+            state => {
+                debug!(
+                    "push on stream {} rejected: stream state {:?}",
+                    associated_stream_id, state
+                );
+                return Ok(());
+            }
+        }
+
+        let pushed_streams = self
+            .streams
+            .map
+            .keys()
+            .filter(|&&id| ServerTypes::<I>::init_where(id) == InitWhere::Locally)
+            .count();
+        if pushed_streams as u64 >= self.peer_settings.max_concurrent_streams as u64 {
+            debug!(
+                "push on stream {} rejected: {} pushed streams are open",
+                associated_stream_id, pushed_streams
+            );
+            return Ok(());
+        }
+
+        let promised_stream_id = self.next_local_stream_id();
+
+        debug!(
+            "push promise on stream {}, promised stream {}",
+            associated_stream_id, promised_stream_id
+        );
+
+        let header_fragment = self
+            .encoder
+            .encode(headers.0.iter().map(|h| (h.name(), h.value())));
+        self.write_push_promise(associated_stream_id, promised_stream_id, header_fragment);
+
+        let (stream, _, out_window) = self.new_stream_data(
+            promised_stream_id,
+            None,
+            InMessageStage::AfterTrailingHeaders,
+            ServerStreamData {},
+        );
+        // 8.2.2: promised stream is "reserved (local)" and the client never sends on it
+        stream.close_remote();
+
+        let response = Response::new(
+            response_rx
+                .map_err(|_| error::Error::Other("pushed response sink dropped"))
+                .and_then(|response| response.0),
+        );
+        self.pump_stream_to_write_loop(promised_stream_id, response.into_part_stream(), out_window);
+
+        Ok(())
+    }
+
+    /// Queue PUSH_PROMISE, followed by CONTINUATION frames
+    /// if header block does not fit in a frame
+    fn write_push_promise(
+        &mut self,
+        stream_id: StreamId,
+        promised_stream_id: StreamId,
+        mut header_fragment: Bytes,
+    ) {
+        // promised stream id field is 4 bytes
+        let max_fragment = self.peer_settings.max_frame_size as usize - 4;
+        let first = header_fragment.split_to(max_fragment.min(header_fragment.len()));
+
+        let mut flags = Flags::new(0);
+        if header_fragment.is_empty() {
+            flags.set(PushPromiseFlag::EndHeaders);
+        }
+        self.queued_write.queue_not_goaway(PushPromiseFrame {
+            flags,
+            stream_id,
+            promised_stream_id,
+            header_fragment: first,
+            padding_len: 0,
+        });
+
+        let max_fragment = self.peer_settings.max_frame_size as usize;
+        while !header_fragment.is_empty() {
+            let fragment = header_fragment.split_to(max_fragment.min(header_fragment.len()));
+            let mut frame = ContinuationFrame::new(fragment, stream_id);
+            if header_fragment.is_empty() {
+                frame.set_flag(ContinuationFlag::EndHeaders);
+            }
+            self.queued_write.queue_not_goaway(frame);
+        }
+    }
 }
 
-enum ServerToWriteMessage {
+pub(crate) enum ServerToWriteMessage {
     Common(CommonToWriteMessage),
+    /// Promise a response to request with given headers on request stream
+    Push(StreamId, Headers, oneshot::Receiver<Response>),
 }
 
 impl From<CommonToWriteMessage> for ServerToWriteMessage {
@@ -200,9 +517,15 @@ where
     type Types = ServerTypes<I>;
 
     fn process_message(&mut self, message: ServerToWriteMessage) -> result::Result<()> {
-        match message {
+        let r = match message {
             ServerToWriteMessage::Common(common) => self.process_common_message(common),
-        }
+            ServerToWriteMessage::Push(associated_stream_id, headers, response_rx) => {
+                self.process_push(associated_stream_id, headers, response_rx)
+            }
+        };
+        // GOAWAY may have been sent
+        self.update_push_allowed();
+        r
     }
 }
 
@@ -212,6 +535,35 @@ where
 {
     type Types = ServerTypes<I>;
 
+    fn process_push_promise(&mut self, frame: PushPromiseDecodedFrame) -> result::Result<()> {
+        // 8.2: a client cannot push
+        warn!("PUSH_PROMISE from client on stream {}", frame.stream_id);
+        self.send_goaway(ErrorCode::ProtocolError)
+    }
+
+    fn peer_settings_updated(&mut self) {
+        self.update_push_allowed();
+    }
+
+    fn peer_goaway_received(&mut self, _last_stream_id: StreamId, _raw_error_code: u32) {
+        self.update_push_allowed();
+    }
+
+    fn peer_rst_stream_received(&mut self, stream_id: StreamId) -> result::Result<()> {
+        let ok = match self.specific.rst_stream_rate_limit {
+            Some(ref mut limit) => limit.rst_received(Instant::now()),
+            None => true,
+        };
+        if !ok && !self.queued_write.goaway_queued() {
+            warn!(
+                "RST_STREAM rate limit exceeded at stream {}, sending GOAWAY",
+                stream_id
+            );
+            self.send_goaway(ErrorCode::EnhanceYourCalm)?;
+        }
+        Ok(())
+    }
+
     fn process_headers(
         &mut self,
         stream_id: StreamId,
@@ -234,6 +586,17 @@ where
         }
 
         if !existing_stream {
+            if !self.check_max_concurrent_streams(stream_id)? {
+                return Ok(None);
+            }
+            if !self.check_new_stream_rate(stream_id)? {
+                return Ok(None);
+            }
+            if end_stream == EndStream::Yes && headers.content_length().unwrap_or(0) != 0 {
+                warn!("request without body declares content-length: {}", stream_id);
+                self.send_rst_stream(stream_id, ErrorCode::ProtocolError)?;
+                return Ok(None);
+            }
             return self.new_stream_from_client(stream_id, headers).map(Some);
         }
 
@@ -243,6 +606,18 @@ where
             return Ok(None);
         }
 
+        {
+            let mut stream = self.streams.get_mut(stream_id).unwrap();
+            if let Some(in_rem_content_length) = stream.stream().in_rem_content_length {
+                if in_rem_content_length != 0 {
+                    warn!("stream data shorter than content-length: {}", stream_id);
+                    stream.stream().content_length_mismatch();
+                    self.send_rst_stream(stream_id, ErrorCode::ProtocolError)?;
+                    return Ok(None);
+                }
+            }
+        }
+
         let mut stream = self.streams.get_mut(stream_id).unwrap();
         stream.stream().trailers_recvd(headers);
         Ok(Some(stream))
@@ -251,6 +626,7 @@ where
 
 pub struct ServerConn {
     write_tx: UnboundedSender<ServerToWriteMessage>,
+    metrics: Arc<ConnMetricsCounters>,
 }
 
 impl ServerConn {
@@ -269,28 +645,87 @@ impl ServerConn {
 
         let (write_tx, write_rx) = unbounded::<ServerToWriteMessage>();
 
+        let metrics = Arc::new(ConnMetricsCounters::new());
+        let metrics_copy = metrics.clone();
+
         let write_rx =
             Box::new(write_rx.map_err(|()| {
                 error::Error::IoError(io::Error::new(io::ErrorKind::Other, "to_write"))
             }));
 
-        let settings_frame = SettingsFrame::from_settings(vec![HttpSetting::EnablePush(false)]);
+        let settings_frame = match conf.common.initial_settings_for_test {
+            Some(ref raw) => raw.clone(),
+            None => {
+                RawSettingsFrame::from_frame(&SettingsFrame::from_settings(conf.initial_settings()))
+            }
+        };
         let mut settings = DEFAULT_SETTINGS;
-        settings.apply_from_frame(&settings_frame);
+        settings.apply_from_frame(&settings_frame.to_settings_frame());
+
+        // armed at accept, cancelled by connection when initial SETTINGS is received
+        let preface_timeout = match reactor::Timeout::new(conf.preface_timeout(), &lh) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                let conn = ServerConn { write_tx, metrics };
+                return (conn, Box::new(future::failed(e.into())));
+            }
+        };
 
-        let handshake = socket.and_then(|conn| server_handshake(conn, settings_frame));
+        let allow_h2c_upgrade = conf.allow_h2c_upgrade.unwrap_or(false);
+
+        let handshake = socket
+            .and_then(move |conn| server_handshake(conn, settings_frame, allow_h2c_upgrade))
+            .select2(preface_timeout)
+            .then(|r| match r {
+                Ok(future::Either::A(r)) => Ok(r),
+                Ok(future::Either::B(..)) => Err(error::Error::Other("preface timeout")),
+                Err(future::Either::A((e, _))) => Err(e),
+                Err(future::Either::B((e, _))) => Err(e.into()),
+            });
 
         let write_tx_copy = write_tx.clone();
 
-        let run = handshake.and_then(move |conn| {
+        let max_connection_bytes = conf.max_connection_bytes;
+        let max_connection_memory = conf.max_connection_memory;
+        let ping_interval = conf.ping_interval;
+        let ping_timeout = conf.ping_timeout;
+        let out_data_frame_max_size = conf.out_data_frame_max_size;
+        let enable_priority = conf.enable_priority.unwrap_or(false);
+        let max_concurrent_streams = settings.max_concurrent_streams;
+
+        let run = handshake.and_then(move |((conn, upgrade), preface_timeout)| {
             let conn_died_error_holder = ClientDiedErrorHolder::new();
 
+            let keepalive = match ping_interval {
+                Some(interval) => {
+                    let timeout = ping_timeout.unwrap_or(interval);
+                    Keepalive::new(interval, timeout, true, &lh).map(Some)
+                }
+                None => Ok(None),
+            };
+
             let (read, write) = conn.split();
 
-            let conn_data = Conn::<ServerTypes<I>>::new(
+            let mut conn_data = Conn::<ServerTypes<I>>::new(
                 lh,
                 cpu_pool,
-                ServerConnData { factory: service },
+                ServerConnData {
+                    factory: service,
+                    on_handler_aborted: conf.on_handler_aborted,
+                    panic_response: conf.panic_response,
+                    new_stream_rate_limit: conf
+                        .new_stream_rate_limit
+                        .map(|(rate, burst)| StreamRateLimit::new(rate, burst, Instant::now())),
+                    rst_stream_rate_limit: conf
+                        .max_rst_stream_rate
+                        .map(|(max, window)| RstStreamRateLimit::new(max, window, Instant::now())),
+                    #[cfg(feature = "compression")]
+                    response_compression: conf.response_compression,
+                    request_id_header: conf.request_id_header.map(|h| h.to_ascii_lowercase()),
+                    request_tracing: conf.request_tracing,
+                    push: Arc::new(ServerPushShared::new()),
+                    max_content_length: conf.max_content_length,
+                },
                 conf.common,
                 settings,
                 write_tx_copy,
@@ -299,8 +734,24 @@ impl ServerConn {
                 write,
                 conn_died_error_holder,
             );
+            conn_data.max_connection_bytes = max_connection_bytes;
+            conn_data.max_connection_memory = max_connection_memory;
+            conn_data.out_data_frame_max_size = out_data_frame_max_size;
+            conn_data.preface_timeout = Some(preface_timeout);
+            conn_data.metrics = metrics_copy;
+            if enable_priority {
+                conn_data.priority_tree = Some(PriorityTree::new(max_concurrent_streams));
+            }
 
-            conn_data.run()
+            let started = keepalive.and_then(|keepalive| {
+                conn_data.nat_keepalive = keepalive;
+                match upgrade {
+                    Some(upgrade) => conn_data.process_h2c_upgrade(upgrade),
+                    None => Ok(()),
+                }
+            });
+
+            future::result(started).and_then(move |()| conn_data.run())
         });
 
         let future = Box::new(run.then(|x| {
@@ -308,7 +759,7 @@ impl ServerConn {
             x
         }));
 
-        (ServerConn { write_tx }, future)
+        (ServerConn { write_tx, metrics }, future)
     }
 
     pub fn new<S, A>(
@@ -316,7 +767,7 @@ impl ServerConn {
         socket: Box<StreamItem>,
         tls: ServerTlsOption<A>,
         exec: CpuPoolOption,
-        conf: ServerConf,
+        mut conf: ServerConf,
         service: Arc<S>,
     ) -> (ServerConn, HttpFuture<()>)
     where
@@ -329,6 +780,8 @@ impl ServerConn {
                 ServerConn::connected(lh, socket, exec, conf, service)
             }
             ServerTlsOption::Tls(acceptor) => {
+                // h2c is HTTP/2 over cleartext only
+                conf.allow_h2c_upgrade = None;
                 let socket = Box::new(
                     tokio_tls_api::accept_async(&*acceptor, socket).map_err(error::Error::from),
                 );
@@ -380,7 +833,76 @@ impl ServerConn {
         ServerConn::new_plain_single_thread(lh, socket, conf, Arc::new(HttpServiceFn(f)))
     }
 
-    /// For tests
+    /// Stop reading from the socket until `resume_reads` is called.
+    ///
+    /// Flow control windows are not replenished while reads are paused,
+    /// so the peer eventually stops sending.
+    pub fn pause_reads(&self) {
+        let message = ServerToWriteMessage::Common(CommonToWriteMessage::PauseReads);
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Resume reading from the socket after `pause_reads`.
+    pub fn resume_reads(&self) {
+        let message = ServerToWriteMessage::Common(CommonToWriteMessage::ResumeReads);
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Send GOAWAY(NO_ERROR) and close the connection
+    /// once already started streams are complete.
+    pub fn shutdown_gracefully(&self) {
+        let message = ServerToWriteMessage::Common(CommonToWriteMessage::GoawayGraceful);
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Send GOAWAY with application error code and debug data
+    /// and close the connection after already queued frames are written.
+    ///
+    /// Subsequent calls are ignored.
+    pub fn close(&self, error_code: ErrorCode, debug_data: Bytes) {
+        let message = ServerToWriteMessage::Common(CommonToWriteMessage::Goaway(
+            error_code, debug_data,
+        ));
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Perform administrative operation on the connection.
+    ///
+    /// Operation is ignored if connection is already closed.
+    pub fn control(&self, control: ConnControl) {
+        let message = ServerToWriteMessage::Common(control.into());
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Send SETTINGS to the client.
+    ///
+    /// Returned future resolves when the client acknowledges the settings,
+    /// i. e. when new settings are in effect.
+    /// Future fails if connection is closed before that.
+    pub fn update_settings(&self, settings: Vec<HttpSetting>) -> HttpFutureSend<()> {
+        let (tx, rx) = oneshot::channel();
+        self.control(ConnControl::UpdateSettingsAcked(settings, tx));
+        Box::new(rx.map_err(|_| error::Error::Other("conn died")).and_then(|r| r))
+    }
+
+    /// Reset all streams and close the connection.
+    pub fn force_close(&self) {
+        let message = ServerToWriteMessage::Common(CommonToWriteMessage::ForceClose);
+        // ignore error
+        drop(self.write_tx.unbounded_send(message));
+    }
+
+    /// Connection counters, cheap to call
+    pub fn metrics(&self) -> ConnMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Connection and stream state, e. g. for monitoring
     pub fn dump_state(&self) -> HttpFutureSend<ConnStateSnapshot> {
         let (tx, rx) = oneshot::channel();
 
@@ -0,0 +1,107 @@
+//! Server push (PUSH_PROMISE)
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use futures::sync::mpsc::UnboundedSender;
+use futures::sync::oneshot;
+
+use error;
+use result;
+
+use headers_place::HeadersPlace;
+use req_resp::RequestOrResponse;
+use resp::Response;
+use server::server_conn::ServerToWriteMessage;
+use solicit::header::Headers;
+use solicit::StreamId;
+
+/// Connection state shared with `ServerPush` handles
+pub(crate) struct ServerPushShared {
+    /// Peer did not disable push and no GOAWAY was sent or received
+    allowed: AtomicBool,
+}
+
+impl ServerPushShared {
+    pub fn new() -> ServerPushShared {
+        ServerPushShared {
+            allowed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_allowed(&self, allowed: bool) {
+        self.allowed.store(allowed, Ordering::SeqCst);
+    }
+
+    fn allowed(&self) -> bool {
+        self.allowed.load(Ordering::SeqCst)
+    }
+}
+
+/// Push responses associated with a request, passed to `Service::start_request_with_push`.
+///
+/// Each push sends PUSH_PROMISE with the promised request on the request stream
+/// and the pushed response on a new server-initiated stream.
+#[derive(Clone)]
+pub struct ServerPush {
+    pub(crate) associated_stream_id: StreamId,
+    pub(crate) shared: Arc<ServerPushShared>,
+    pub(crate) to_write_tx: UnboundedSender<ServerToWriteMessage>,
+}
+
+impl ServerPush {
+    /// Whether push is currently possible: client did not disable it
+    /// with `SETTINGS_ENABLE_PUSH` and connection is not going away
+    pub fn is_allowed(&self) -> bool {
+        self.shared.allowed()
+    }
+
+    /// Promise a response to the request with given headers.
+    ///
+    /// Headers must be a complete `GET` or `HEAD` request.
+    /// Fails with `Error::PushNotAllowed` if push is disabled by client
+    /// or connection is going away.
+    /// Push may still be rejected later if request stream is already complete
+    /// when the promise is written.
+    pub fn push_request(&self, headers: Headers) -> result::Result<PushSink> {
+        if !self.is_allowed() {
+            return Err(error::Error::PushNotAllowed);
+        }
+
+        if let Err(e) = headers.validate(RequestOrResponse::Request, HeadersPlace::Initial) {
+            warn!("invalid pushed request headers: {:?}", e);
+            return Err(error::Error::Other("invalid pushed request headers"));
+        }
+
+        // 8.2: promised requests MUST be cacheable, MUST be safe and MUST NOT include a request body
+        match headers.get_opt(":method") {
+            Some("GET") | Some("HEAD") => {}
+            _ => return Err(error::Error::Other("pushed request must be GET or HEAD")),
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let message = ServerToWriteMessage::Push(self.associated_stream_id, headers, rx);
+        if self.to_write_tx.unbounded_send(message).is_err() {
+            return Err(error::Error::PushNotAllowed);
+        }
+
+        Ok(PushSink { tx })
+    }
+}
+
+/// Receiver of the pushed response, created by `ServerPush::push_request`.
+///
+/// Promised stream is reset if sink is dropped without a response.
+pub struct PushSink {
+    tx: oneshot::Sender<Response>,
+}
+
+impl PushSink {
+    /// Send pushed response, fails with `Error::PushNotAllowed` if the push is already rejected
+    pub fn send(self, response: Response) -> result::Result<()> {
+        self.tx
+            .send(response)
+            .map_err(|_| error::Error::PushNotAllowed)
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+use std::time::Instant;
+
+/// Decision for new stream opened by client
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamRateDecision {
+    Accept,
+    /// Reset stream with `REFUSED_STREAM`
+    Refuse,
+    /// Client keeps opening streams after being refused, close connection
+    Goaway,
+}
+
+/// Per-connection token bucket limiting rate of new streams.
+///
+/// Bucket holds up to `burst` tokens and is refilled with `rate` tokens per second,
+/// each new stream takes one token.
+pub struct StreamRateLimit {
+    rate: u32,
+    burst: u32,
+    tokens: f64,
+    last_refill: Instant,
+    /// Streams refused since last accepted stream
+    refused: u32,
+}
+
+impl StreamRateLimit {
+    pub fn new(rate: u32, burst: u32, now: Instant) -> StreamRateLimit {
+        StreamRateLimit {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last_refill: now,
+            refused: 0,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.last_refill {
+            return;
+        }
+        let elapsed = now - self.last_refill;
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Take a token for new stream.
+    ///
+    /// Connection is closed when client opens another `burst` streams
+    /// after it was refused.
+    pub fn new_stream(&mut self, now: Instant) -> StreamRateDecision {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.refused = 0;
+            StreamRateDecision::Accept
+        } else {
+            self.refused += 1;
+            if self.refused > self.burst {
+                StreamRateDecision::Goaway
+            } else {
+                StreamRateDecision::Refuse
+            }
+        }
+    }
+}
+
+/// Per-connection counter of RST_STREAM frames received from client
+/// in fixed time windows, detects streams opened and immediately reset.
+pub struct RstStreamRateLimit {
+    max: u32,
+    window: Duration,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RstStreamRateLimit {
+    pub fn new(max: u32, window: Duration, now: Instant) -> RstStreamRateLimit {
+        RstStreamRateLimit {
+            max,
+            window,
+            window_start: now,
+            count: 0,
+        }
+    }
+
+    /// Count RST_STREAM, `false` if more than `max` were received in current window
+    pub fn rst_received(&mut self, now: Instant) -> bool {
+        if now >= self.window_start + self.window {
+            self.window_start = now;
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        self.count <= self.max
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn burst_then_refill() {
+        let start = Instant::now();
+        let mut limit = StreamRateLimit::new(10, 3, start);
+
+        for _ in 0..3 {
+            assert_eq!(StreamRateDecision::Accept, limit.new_stream(start));
+        }
+        assert_eq!(StreamRateDecision::Refuse, limit.new_stream(start));
+
+        // one token per 100ms
+        let later = start + Duration::from_millis(150);
+        assert_eq!(StreamRateDecision::Accept, limit.new_stream(later));
+        assert_eq!(StreamRateDecision::Refuse, limit.new_stream(later));
+
+        // bucket is not filled above burst
+        let much_later = start + Duration::from_secs(100);
+        for _ in 0..3 {
+            assert_eq!(StreamRateDecision::Accept, limit.new_stream(much_later));
+        }
+        assert_eq!(StreamRateDecision::Refuse, limit.new_stream(much_later));
+    }
+
+    #[test]
+    fn goaway_on_abuse() {
+        let now = Instant::now();
+        let mut limit = StreamRateLimit::new(1, 2, now);
+
+        assert_eq!(StreamRateDecision::Accept, limit.new_stream(now));
+        assert_eq!(StreamRateDecision::Accept, limit.new_stream(now));
+        assert_eq!(StreamRateDecision::Refuse, limit.new_stream(now));
+        assert_eq!(StreamRateDecision::Refuse, limit.new_stream(now));
+        assert_eq!(StreamRateDecision::Goaway, limit.new_stream(now));
+    }
+
+    #[test]
+    fn rst_stream_window() {
+        let start = Instant::now();
+        let mut limit = RstStreamRateLimit::new(2, Duration::from_secs(1), start);
+
+        assert!(limit.rst_received(start));
+        assert!(limit.rst_received(start + Duration::from_millis(500)));
+        assert!(!limit.rst_received(start + Duration::from_millis(900)));
+
+        // counter is reset in the next window
+        let later = start + Duration::from_millis(1500);
+        assert!(limit.rst_received(later));
+        assert!(limit.rst_received(later));
+        assert!(!limit.rst_received(later));
+    }
+}
@@ -1,5 +1,6 @@
 use data_or_trailers::HttpStreamAfterHeaders;
 use resp::Response;
+use server::server_push::ServerPush;
 use solicit::header::Headers;
 
 /// Central HTTP/2 service interface.
@@ -16,5 +17,23 @@ pub trait Service: Send + Sync + 'static {
     /// `req` param contains asynchronous stream of request content,
     /// stream of zero or more `DATA` frames followed by optional
     /// trailer `HEADERS` frame.
+    ///
+    /// Server may respond before the request body is read:
+    /// `req` stays readable after the response is complete,
+    /// e. g. respond with `202 Accepted` and spawn `req.drain()`.
     fn start_request(&self, headers: Headers, req: HttpStreamAfterHeaders) -> Response;
+
+    /// Start HTTP/2 request on server, with ability to push responses
+    /// associated with this request.
+    ///
+    /// Default implementation does not push and calls `start_request`.
+    fn start_request_with_push(
+        &self,
+        headers: Headers,
+        req: HttpStreamAfterHeaders,
+        push: ServerPush,
+    ) -> Response {
+        drop(push);
+        self.start_request(headers, req)
+    }
 }
@@ -1,37 +1,48 @@
 use std::collections::hash_map;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use futures::future::Future;
+
 use data_or_trailers::HttpStreamAfterHeaders;
 use resp::Response;
+use server::server_push::ServerPush;
 use service::Service;
 use solicit::header::Headers;
 
 #[derive(Default)]
 struct Node {
     service: Option<Arc<Service>>,
+    /// Services for specific methods, take precedence over `service`
+    methods: BTreeMap<String, Arc<Service>>,
     children: HashMap<String, Node>,
 }
 
 impl Node {
-    fn add_service(&mut self, path: &str, service: Arc<Service>) {
+    fn node_mut(&mut self, path: &str) -> &mut Node {
         match split_path(path) {
-            None => {
-                self.service = Some(service);
-            }
+            None => self,
             Some((first, rem)) => {
                 let node = match self.children.entry(first.to_owned()) {
                     hash_map::Entry::Occupied(e) => e.into_mut(),
-                    hash_map::Entry::Vacant(e) => e.insert(Node {
-                        service: None,
-                        children: HashMap::new(),
-                    }),
+                    hash_map::Entry::Vacant(e) => e.insert(Node::default()),
                 };
-                node.add_service(rem, service);
+                node.node_mut(rem)
             }
         }
     }
 
+    fn add_service(&mut self, path: &str, service: Arc<Service>) {
+        self.node_mut(path).service = Some(service);
+    }
+
+    fn add_method_service(&mut self, method: &str, path: &str, service: Arc<Service>) {
+        self.node_mut(path)
+            .methods
+            .insert(method.to_owned(), service);
+    }
+
     fn remove_service(&mut self, path: &str) -> Option<Arc<Service>> {
         match split_path(path) {
             None => self.service.take(),
@@ -42,19 +53,43 @@ impl Node {
         }
     }
 
-    fn find_service(&self, path: &str) -> Option<&Service> {
+    /// Deepest node matching the path which has any service registered
+    fn find_node(&self, path: &str) -> Option<&Node> {
         if let Some((first, rem)) = split_path(path) {
             if let Some(node) = self.children.get(first) {
-                if let Some(service) = node.find_service(rem) {
-                    return Some(service);
+                if let Some(node) = node.find_node(rem) {
+                    return Some(node);
                 }
             }
         }
 
-        self.service.as_ref().map(|a| a.as_ref())
+        if self.service.is_some() || !self.methods.is_empty() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Value of `allow` header: methods registered for this node
+    fn allow(&self) -> String {
+        self.methods
+            .keys()
+            .map(|m| &m[..])
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 }
 
+/// Add `allow` header unless service already set it
+fn add_allow(response: Response, allow: String) -> Response {
+    Response::new(response.0.map(move |(mut headers, rem)| {
+        if headers.get_opt("allow").is_none() {
+            headers.add("allow", &allow);
+        }
+        (headers, rem)
+    }))
+}
+
 fn split_path<'a>(mut path: &'a str) -> Option<(&'a str, &'a str)> {
     path = path.trim_left_matches('/');
 
@@ -94,6 +129,7 @@ fn test_split_path() {
 #[derive(Default)]
 pub struct ServicePaths {
     root: Node,
+    method_not_allowed: Option<Arc<Service>>,
 }
 
 impl ServicePaths {
@@ -146,24 +182,92 @@ impl ServicePaths {
         self.set_service(path, Arc::new(service))
     }
 
+    /// Register a service for given method and path.
+    ///
+    /// When path has services only for other methods,
+    /// request is answered with `405 Method Not Allowed`
+    /// and `allow` header listing the registered methods.
+    pub fn set_method_service(&mut self, method: &str, path: &str, service: Arc<Service>) {
+        assert!(path.starts_with("/"));
+        self.root.add_method_service(method, path, service);
+    }
+
+    pub fn set_method_service_fn<F>(&mut self, method: &str, path: &str, service: F)
+    where
+        F: Fn(Headers, HttpStreamAfterHeaders) -> Response + Send + Sync + 'static,
+    {
+        self.set_method_service(method, path, Arc::new(service))
+    }
+
+    /// Register a service invoked when path matches but method does not,
+    /// instead of default empty `405` response.
+    ///
+    /// `allow` header is added to the response unless service sets it.
+    pub fn set_method_not_allowed_service(&mut self, service: Arc<Service>) {
+        self.method_not_allowed = Some(service);
+    }
+
     pub fn remove_service(&mut self, path: &str) -> Option<Arc<Service>> {
         assert!(path.starts_with("/"));
         self.root.remove_service(path)
     }
+}
+
+impl ServicePaths {
+    fn dispatch(
+        &self,
+        headers: Headers,
+        req: HttpStreamAfterHeaders,
+        push: Option<ServerPush>,
+    ) -> Response {
+        let node = match self.root.find_node(headers.path()) {
+            Some(node) => node,
+            None => {
+                debug!("serving 404 for path {}", headers.path());
+                return Response::not_found_404();
+            }
+        };
 
-    fn find_service(&self, path: &str) -> Option<&Service> {
-        self.root.find_service(path)
+        if let Some(service) = node
+            .methods
+            .get(headers.method())
+            .or(node.service.as_ref())
+        {
+            debug!("invoking user callback for path {}", headers.path());
+            return match push {
+                Some(push) => service.start_request_with_push(headers, req, push),
+                None => service.start_request(headers, req),
+            };
+        }
+
+        debug!(
+            "serving 405 for method {} path {}",
+            headers.method(),
+            headers.path()
+        );
+        let allow = node.allow();
+        match self.method_not_allowed {
+            Some(ref service) => add_allow(service.start_request(headers, req), allow),
+            None => {
+                let mut headers = Headers::from_status(405);
+                headers.add("allow", &allow);
+                Response::headers(headers)
+            }
+        }
     }
 }
 
 impl Service for ServicePaths {
     fn start_request(&self, headers: Headers, req: HttpStreamAfterHeaders) -> Response {
-        if let Some(service) = self.find_service(headers.path()) {
-            debug!("invoking user callback for path {}", headers.path());
-            service.start_request(headers, req)
-        } else {
-            debug!("serving 404 for path {}", headers.path());
-            Response::not_found_404()
-        }
+        self.dispatch(headers, req, None)
+    }
+
+    fn start_request_with_push(
+        &self,
+        headers: Headers,
+        req: HttpStreamAfterHeaders,
+        push: ServerPush,
+    ) -> Response {
+        self.dispatch(headers, req, Some(push))
     }
 }
@@ -1,6 +1,8 @@
 use std::any::Any;
+use std::fmt;
 use std::io;
 use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
 
 use tokio_core::net::TcpListener;
 use tokio_core::net::TcpStream;
@@ -8,6 +10,7 @@ use tokio_core::reactor;
 
 use futures::stream::Stream;
 use futures::Future;
+use futures_cpupool::CpuPool;
 
 use net2;
 
@@ -97,6 +100,57 @@ impl ToClientStream for SocketAddr {
     }
 }
 
+/// Host name and port resolved on each connect.
+///
+/// Resolution is done with blocking `getaddrinfo`, so it is executed
+/// in `resolver` pool rather than in the event loop.
+#[derive(Clone)]
+pub struct HostPort {
+    /// Host name or IP address, IPv6 address without brackets
+    pub host: String,
+    pub port: u16,
+    pub resolver: CpuPool,
+}
+
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl ToClientStream for HostPort {
+    fn connect(
+        &self,
+        handle: &reactor::Handle,
+    ) -> Box<Future<Item = Box<StreamItem>, Error = io::Error> + Send> {
+        let host = self.host.clone();
+        let port = self.port;
+        let addr = self.resolver.spawn_fn(move || {
+            (&host[..], port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| io::Error::other("addr is resolved to empty list"))
+        });
+
+        // `Handle` is not `Send`, the future is polled in the event loop thread though
+        let remote = handle.remote().clone();
+        let stream = addr.and_then(move |addr| {
+            let handle = remote
+                .handle()
+                .ok_or_else(|| io::Error::other("connect polled outside of event loop"))?;
+            Ok(TcpStream::connect(&addr, &handle))
+        });
+        let stream = stream
+            .flatten()
+            .map(|stream| Box::new(stream) as Box<StreamItem>);
+        Box::new(stream)
+    }
+}
+
 impl StreamItem for TcpStream {
     fn is_tcp(&self) -> bool {
         true
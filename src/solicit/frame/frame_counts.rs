@@ -0,0 +1,87 @@
+//! Number of frames by frame type
+
+use solicit::frame::continuation::CONTINUATION_FRAME_TYPE;
+use solicit::frame::data::DATA_FRAME_TYPE;
+use solicit::frame::goaway::GOAWAY_FRAME_TYPE;
+use solicit::frame::headers::HEADERS_FRAME_TYPE;
+use solicit::frame::ping::PING_FRAME_TYPE;
+use solicit::frame::priority::PRIORITY_FRAME_TYPE;
+use solicit::frame::push_promise::PUSH_PROMISE_FRAME_TYPE;
+use solicit::frame::rst_stream::RST_STREAM_FRAME_TYPE;
+use solicit::frame::settings::SETTINGS_FRAME_TYPE;
+use solicit::frame::window_update::WINDOW_UPDATE_FRAME_TYPE;
+
+/// Number of frames of each type read from or written to the socket.
+///
+/// CONTINUATION frames are counted separately from HEADERS and PUSH_PROMISE.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub data: u64,
+    pub headers: u64,
+    pub priority: u64,
+    pub rst_stream: u64,
+    pub settings: u64,
+    pub push_promise: u64,
+    pub ping: u64,
+    pub goaway: u64,
+    pub window_update: u64,
+    pub continuation: u64,
+    /// Frames of unknown types
+    pub unknown: u64,
+}
+
+impl FrameCounts {
+    /// Count a frame of given type
+    pub fn add(&mut self, frame_type: u8) {
+        let count = match frame_type {
+            DATA_FRAME_TYPE => &mut self.data,
+            HEADERS_FRAME_TYPE => &mut self.headers,
+            PRIORITY_FRAME_TYPE => &mut self.priority,
+            RST_STREAM_FRAME_TYPE => &mut self.rst_stream,
+            SETTINGS_FRAME_TYPE => &mut self.settings,
+            PUSH_PROMISE_FRAME_TYPE => &mut self.push_promise,
+            PING_FRAME_TYPE => &mut self.ping,
+            GOAWAY_FRAME_TYPE => &mut self.goaway,
+            WINDOW_UPDATE_FRAME_TYPE => &mut self.window_update,
+            CONTINUATION_FRAME_TYPE => &mut self.continuation,
+            _ => &mut self.unknown,
+        };
+        *count += 1;
+    }
+
+    /// Counts with frame type names as in RFC 7540, `UNKNOWN` is last
+    pub fn by_name(&self) -> [(&'static str, u64); 11] {
+        [
+            ("DATA", self.data),
+            ("HEADERS", self.headers),
+            ("PRIORITY", self.priority),
+            ("RST_STREAM", self.rst_stream),
+            ("SETTINGS", self.settings),
+            ("PUSH_PROMISE", self.push_promise),
+            ("PING", self.ping),
+            ("GOAWAY", self.goaway),
+            ("WINDOW_UPDATE", self.window_update),
+            ("CONTINUATION", self.continuation),
+            ("UNKNOWN", self.unknown),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add() {
+        let mut counts = FrameCounts::default();
+        counts.add(DATA_FRAME_TYPE);
+        counts.add(DATA_FRAME_TYPE);
+        counts.add(CONTINUATION_FRAME_TYPE);
+        counts.add(0xfa);
+        assert_eq!(2, counts.data);
+        assert_eq!(1, counts.continuation);
+        assert_eq!(1, counts.unknown);
+        assert_eq!(("DATA", 2), counts.by_name()[0]);
+        assert_eq!(("UNKNOWN", 1), counts.by_name()[10]);
+    }
+}
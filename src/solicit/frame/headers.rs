@@ -275,6 +275,9 @@ impl Frame for HeadersFrame {
         // the appropriate flag is set.
         let priority = flags.is_set(HeadersFlag::Priority);
         let (data, stream_dep) = if priority {
+            if actual.len() < 5 {
+                return Err(ParseFrameError::IncorrectPayloadLen);
+            }
             let dep = StreamDependency::parse(&actual[..5]);
             if dep.stream_id == stream_id {
                 // 5.3.1
@@ -390,7 +393,7 @@ impl<'a> fmt::Debug for HeadersMultiFrame<'a> {
             .field("flags", &self.flags)
             .field("stream_id", &self.stream_id)
             .field("headers", &self.headers)
-            .field("stream_id", &self.stream_id)
+            .field("stream_dep", &self.stream_dep)
             .field("padding_len", &self.padding_len)
             .field("max_frame_size", &self.max_frame_size)
             .finish()
@@ -503,8 +506,13 @@ impl<'a> FrameIR for HeadersMultiFrame<'a> {
     fn serialize_into(self, builder: &mut WriteBuffer) {
         assert!(!self.flags.is_set(HeadersFlag::EndHeaders));
 
+        let flags = match self.stream_dep {
+            Some(..) => self.flags.with(HeadersFlag::Priority),
+            None => self.flags,
+        };
+
         let mut buf = EncodeBufForHeadersMultiFrame {
-            flags: self.flags,
+            flags,
             stream_id: self.stream_id,
             current_frame_type: HeadersFrameType::Headers,
             current_frame_offset: builder.remaining(),
@@ -514,6 +522,10 @@ impl<'a> FrameIR for HeadersMultiFrame<'a> {
 
         buf.open_frame();
 
+        if let Some(ref stream_dep) = self.stream_dep {
+            buf.builder.extend_from_slice(&stream_dep.serialize());
+        }
+
         let headers = self.headers.0.iter().map(|h| (h.name(), h.value()));
 
         self.encoder.encode_into(headers, &mut buf);
@@ -534,6 +546,7 @@ mod tests {
     use solicit::frame::FrameHeader;
     use solicit::frame::FrameIR;
     use solicit::frame::HttpFrame;
+    use solicit::frame::ParseFrameError;
     use solicit::frame::{pack_header, Frame};
     use solicit::tests::common::raw_frame_from_parts;
     use Headers;
@@ -704,6 +717,22 @@ mod tests {
         assert_eq!(4, frame.padding_len);
     }
 
+    /// Tests that a HEADERS frame with the priority flag but too short
+    /// for stream dependency fields is considered invalid.
+    #[test]
+    fn test_headers_frame_parse_priority_too_short() {
+        let payload = b"123".to_vec();
+        let header = FrameHeader::new(payload.len() as u32, 0x1, 0x20, 1);
+
+        let raw = raw_frame_from_parts(header, payload);
+        let frame = HeadersFrame::from_raw(&raw);
+
+        match frame {
+            Err(ParseFrameError::IncorrectPayloadLen) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
     /// Tests that a HEADERS with stream ID 0 is considered invalid.
     #[test]
     fn test_headers_frame_parse_invalid_stream_id() {
@@ -889,4 +918,34 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_headers_multi_frame_with_priority() {
+        let mut encoder = hpack::Encoder::new();
+
+        let dep = StreamDependency::new(0, 200, false);
+
+        let serialized = HeadersMultiFrame {
+            flags: Flags::new(0),
+            stream_id: 3,
+            headers: Headers::ok_200(),
+            stream_dep: Some(dep.clone()),
+            padding_len: 0,
+            encoder: &mut encoder,
+            max_frame_size: 16384,
+        }.serialize_into_vec();
+
+        let frames = unpack_frames_for_test(&serialized);
+        assert_eq!(1, frames.len());
+        match &frames[0] {
+            &HttpFrame::Headers(ref h) => {
+                assert!(h.flags.is_set(HeadersFlag::Priority));
+                assert!(h.flags.is_set(HeadersFlag::EndHeaders));
+                assert_eq!(Some(dep), h.stream_dep);
+                let decoded = hpack::Decoder::new().decode(&h.header_fragment).unwrap();
+                assert_eq!(1, decoded.len());
+            }
+            _ => panic!("wrong frame type"),
+        }
+    }
 }
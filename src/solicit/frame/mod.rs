@@ -41,6 +41,7 @@ pub mod builder;
 pub mod continuation;
 pub mod data;
 pub mod flags;
+pub mod frame_counts;
 pub mod goaway;
 pub mod headers;
 pub mod ping;
@@ -54,6 +55,7 @@ pub use self::builder::FrameBuilder;
 
 pub use self::continuation::ContinuationFrame;
 pub use self::data::{DataFlag, DataFrame};
+pub use self::frame_counts::FrameCounts;
 pub use self::goaway::GoawayFrame;
 pub use self::headers::{HeadersFlag, HeadersFrame};
 pub use self::ping::PingFrame;
@@ -68,6 +70,7 @@ use solicit::frame::continuation::CONTINUATION_FRAME_TYPE;
 use solicit::frame::data::DATA_FRAME_TYPE;
 use solicit::frame::goaway::GOAWAY_FRAME_TYPE;
 use solicit::frame::headers::HeadersDecodedFrame;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
 use solicit::frame::headers::HEADERS_FRAME_TYPE;
 use solicit::frame::ping::PING_FRAME_TYPE;
 use solicit::frame::priority::PRIORITY_FRAME_TYPE;
@@ -817,7 +820,7 @@ pub enum HttpFrameDecoded {
     Priority(PriorityFrame),
     RstStream(RstStreamFrame),
     Settings(SettingsFrame),
-    PushPromise(PushPromiseFrame),
+    PushPromise(PushPromiseDecodedFrame),
     Ping(PingFrame),
     Goaway(GoawayFrame),
     WindowUpdate(WindowUpdateFrame),
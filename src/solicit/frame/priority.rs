@@ -5,6 +5,7 @@ use codec::write_buffer::WriteBuffer;
 use solicit::frame::flags::Flags;
 use solicit::frame::flags::NoFlag;
 use solicit::frame::Frame;
+use solicit::frame::FrameBuilder;
 use solicit::frame::FrameHeader;
 use solicit::frame::FrameIR;
 use solicit::frame::ParseFrameError;
@@ -23,6 +24,18 @@ pub struct PriorityFrame {
 
 pub const PRIORITY_FRAME_TYPE: u8 = 0x2;
 
+impl PriorityFrame {
+    pub fn new(stream_id: StreamId, stream_dep: StreamId, weight: u8, exclusive: bool) -> Self {
+        PriorityFrame {
+            flags: Flags::default(),
+            stream_id,
+            exclusive,
+            stream_dep,
+            weight,
+        }
+    }
+}
+
 impl Frame for PriorityFrame {
     type FlagType = NoFlag;
 
@@ -85,7 +98,10 @@ impl Frame for PriorityFrame {
 }
 
 impl FrameIR for PriorityFrame {
-    fn serialize_into(self, _builder: &mut WriteBuffer) {
-        unimplemented!()
+    fn serialize_into(self, builder: &mut WriteBuffer) {
+        builder.write_header(self.get_header());
+        let exclusive = if self.exclusive { 0x80000000 } else { 0 };
+        builder.write_u32(self.stream_dep | exclusive);
+        builder.write_slice(&[self.weight]);
     }
 }
@@ -15,6 +15,7 @@ use solicit::StreamId;
 use super::flags::Flag;
 use super::flags::Flags;
 use codec::write_buffer::WriteBuffer;
+use Headers;
 
 pub const PUSH_PROMISE_FRAME_TYPE: u8 = 0x5;
 
@@ -105,12 +106,13 @@ impl Frame for PushPromiseFrame {
 
         let (payload, padding_len) = parse_padded_payload(raw_frame.payload(), padded)?;
 
-        let mut buf = (&payload).into_buf();
+        if payload.len() < 4 {
+            return Err(ParseFrameError::IncorrectPayloadLen);
+        }
 
-        let promised_stream_id = buf.get_u32_be();
+        let promised_stream_id = (&payload).into_buf().get_u32_be();
 
-        let header_fragment =
-            payload.slice((payload_len as usize) - buf.remaining(), payload.len());
+        let header_fragment = payload.slice_from(4);
 
         Ok(PushPromiseFrame {
             header_fragment,
@@ -146,6 +148,7 @@ impl FrameIR for PushPromiseFrame {
         if padded {
             b.extend_from_slice(&[self.padding_len]);
         }
+        b.write_u32(self.promised_stream_id);
         // Now the actual headers fragment
         b.extend_from_bytes(self.header_fragment);
         // Finally, add the trailing padding, if required
@@ -154,3 +157,22 @@ impl FrameIR for PushPromiseFrame {
         }
     }
 }
+
+/// PUSH_PROMISE with decoded header block
+#[derive(Debug, Clone)]
+pub struct PushPromiseDecodedFrame {
+    /// The set of flags for the frame, packed into a single byte.
+    pub flags: Flags<PushPromiseFlag>,
+    /// The ID of the stream with which this frame is associated
+    pub stream_id: StreamId,
+    /// Promised Stream ID
+    pub promised_stream_id: StreamId,
+    /// Promised request headers
+    pub headers: Headers,
+}
+
+impl PushPromiseDecodedFrame {
+    pub fn get_stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
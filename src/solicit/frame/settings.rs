@@ -264,7 +264,7 @@ impl SettingsFrame {
     ///
     /// If the frame is invalid (i.e. the length of the payload is not a
     /// multiple of 6) it returns `None`.
-    fn parse_payload(payload: &[u8]) -> ParseFrameResult<Vec<HttpSetting>> {
+    pub fn parse_payload(payload: &[u8]) -> ParseFrameResult<Vec<HttpSetting>> {
         if payload.len() % 6 != 0 {
             return Err(ParseFrameError::ProtocolError);
         }
@@ -376,8 +376,74 @@ impl FrameIR for SettingsFrame {
     }
 }
 
+/// SETTINGS frame with parameters encoded exactly as given, for interop testing.
+///
+/// Unlike `SettingsFrame`, parameters are not validated: unknown identifiers,
+/// out of range values and duplicate identifiers are sent in the given order.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RawSettingsFrame {
+    /// `(identifier, value)` pairs in wire order
+    pub params: Vec<(u16, u32)>,
+}
+
+impl RawSettingsFrame {
+    pub fn new() -> RawSettingsFrame {
+        Default::default()
+    }
+
+    /// Append parameter, possibly unknown or duplicate
+    pub fn param(mut self, id: u16, value: u32) -> RawSettingsFrame {
+        self.params.push((id, value));
+        self
+    }
+
+    /// Append known setting
+    pub fn setting(self, setting: HttpSetting) -> RawSettingsFrame {
+        self.param(setting.get_id(), setting.get_val())
+    }
+
+    pub fn from_frame(frame: &SettingsFrame) -> RawSettingsFrame {
+        RawSettingsFrame {
+            params: frame.settings.iter().map(|s| (s.get_id(), s.get_val())).collect(),
+        }
+    }
+
+    /// Settings as peer would interpret them:
+    /// unknown and invalid parameters are skipped, duplicates are kept in order.
+    pub fn to_settings_frame(&self) -> SettingsFrame {
+        SettingsFrame::from_settings(
+            self.params
+                .iter()
+                .filter_map(|&(id, value)| HttpSetting::from_id(id, value).ok().and_then(|s| s))
+                .collect(),
+        )
+    }
+}
+
+impl FrameIR for RawSettingsFrame {
+    fn serialize_into(self, b: &mut WriteBuffer) {
+        b.write_header(FrameHeader {
+            payload_len: 6 * self.params.len() as u32,
+            frame_type: SETTINGS_FRAME_TYPE,
+            flags: 0,
+            stream_id: 0,
+        });
+        for (id, value) in self.params {
+            b.extend_from_slice(&[
+                (id >> 8) as u8,
+                id as u8,
+                (value >> 24) as u8,
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+            ]);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::RawSettingsFrame;
     use super::{HttpSetting, SettingsFrame};
     use solicit::frame::FrameIR;
     use solicit::frame::{pack_header, Frame, FrameHeader};
@@ -459,6 +525,32 @@ mod tests {
         assert!(!frame.is_ack());
     }
 
+    /// Tests that a `RawSettingsFrame` keeps order, duplicates and unknown
+    /// parameters on the wire.
+    #[test]
+    fn test_raw_settings_frame_serialize() {
+        let frame = RawSettingsFrame::new()
+            .setting(HttpSetting::MaxFrameSize(0x8000))
+            .param(0xf0f0, 7)
+            .setting(HttpSetting::MaxFrameSize(0x4000));
+
+        let header = FrameHeader::new(18, 4, 0, 0);
+        let mut expected: Vec<u8> = pack_header(&header).to_vec();
+        expected.extend(&[0, 5, 0, 0, 0x80, 0, 0xf0, 0xf0, 0, 0, 0, 7, 0, 5, 0, 0, 0x40, 0]);
+        assert_eq!(expected, frame.clone().serialize_into_vec());
+
+        let parsed: SettingsFrame =
+            Frame::from_raw(&raw_frame_from_parts(header, expected[9..].to_vec())).unwrap();
+        assert_eq!(parsed, frame.to_settings_frame());
+        assert_eq!(
+            vec![
+                HttpSetting::MaxFrameSize(0x8000),
+                HttpSetting::MaxFrameSize(0x4000),
+            ],
+            parsed.settings
+        );
+    }
+
     /// Tests that a `SettingsFrame` correctly handles a SETTING frame with no
     /// ACK and an unknown setting within the frame. The unknown setting is
     /// simply ignored.
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::iter::FromIterator;
 use std::result;
 use std::str;
 use std::str::FromStr;
 
+use byte_range::RangeRequest;
 use headers_place::HeadersPlace;
 use req_resp::RequestOrResponse;
 
@@ -165,12 +167,14 @@ fn _assert_header_sync_send() {
     assert_send::<Header>();
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum HeaderError {
     UnknownPseudoHeader,
     EmptyName,
     EmptyValue(PseudoHeaderName),
     IncorrectCharInName,
+    /// NUL, CR or LF in header value
+    IncorrectCharInValue,
     UnexpectedPseudoHeader(PseudoHeaderName),
     PseudoHeadersInTrailers,
     PseudoHeadersAfterRegularHeaders,
@@ -178,10 +182,77 @@ pub enum HeaderError {
     MissingPseudoHeader(PseudoHeaderName),
     ConnectionSpecificHeader(&'static str),
     TeCanOnlyContainTrailer,
+    /// Control character or whitespace in pseudo-header value
+    IncorrectCharInPseudoHeaderValue(PseudoHeaderName),
+    /// `:authority` is not `host[:port]`
+    InvalidAuthority,
+    /// Cookie name is not a token (RFC 6265 section 4.1.1)
+    InvalidCookieName,
+    /// Cookie value contains whitespace, `"`, `,`, `;`, `\` or control character
+    InvalidCookieValue,
 }
 
 pub type HeaderResult<T> = result::Result<T, HeaderError>;
 
+// token (RFC 7230 section 3.2.6)
+fn is_cookie_name_char(b: u8) -> bool {
+    matches!(
+        b,
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+        | b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~'
+    )
+}
+
+// cookie-octet (RFC 6265 section 4.1.1)
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+/// `host[:port]` where host is a reg-name or an IP literal (RFC 3986 section 3.2.2),
+/// userinfo is not allowed in HTTP/2 (8.1.2.3).
+pub(crate) fn is_valid_authority(authority: &[u8]) -> bool {
+    let (host_ok, rem) = if authority.first() == Some(&b'[') {
+        match authority.iter().position(|&b| b == b']') {
+            Some(end) => {
+                let ip = &authority[1..end];
+                let ip_ok = !ip.is_empty()
+                    && ip
+                        .iter()
+                        .all(|&b| (b as char).is_ascii_hexdigit() || b == b':' || b == b'.');
+                (ip_ok, &authority[end + 1..])
+            }
+            None => return false,
+        }
+    } else {
+        let end = authority
+            .iter()
+            .position(|&b| b == b':')
+            .unwrap_or(authority.len());
+        let host = &authority[..end];
+        let host_ok = !host.is_empty()
+            && host.iter().all(|&b| {
+                (b as char).is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b)
+            });
+        (host_ok, &authority[end..])
+    };
+
+    if !host_ok {
+        return false;
+    }
+
+    // RFC 3986 allows empty port after colon
+    match rem.split_first() {
+        None => true,
+        Some((&b':', &[])) => true,
+        Some((&b':', port)) => {
+            port.iter().all(|&b| (b as char).is_ascii_digit())
+                && str::from_utf8(port).ok().and_then(|p| p.parse::<u16>().ok()).is_some()
+        }
+        Some(..) => false,
+    }
+}
+
 impl Header {
     /// Creates a new `Header` with the given name and value.
     ///
@@ -224,28 +295,66 @@ impl Header {
     }
 
     fn validate_header_name_char(b: u8) -> HeaderResult<()> {
-        // TODO: restrict more
-        if b >= b'A' && b <= b'Z' {
-            return Err(HeaderError::IncorrectCharInName);
+        // token characters (RFC 7230 section 3.2.6), uppercase is not allowed in HTTP/2
+        match b {
+            b'a'..=b'z' | b'0'..=b'9' => Ok(()),
+            b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => Ok(()),
+            _ => Err(HeaderError::IncorrectCharInName),
         }
-        Ok(())
     }
 
-    pub fn validate(&self, req_or_resp: RequestOrResponse) -> HeaderResult<()> {
-        if self.name.len() == 0 {
-            return Err(HeaderError::EmptyName);
+    /// Pseudo-header values are copied into HTTP/1.1 request line and `Host`
+    /// by proxies, so reject values which could be used for request smuggling.
+    fn validate_pseudo_header_value(&self, name: PseudoHeaderName) -> HeaderResult<()> {
+        if self.value.iter().any(|&b| b <= b' ' || b == 0x7f) {
+            return Err(HeaderError::IncorrectCharInPseudoHeaderValue(name));
         }
 
+        match name {
+            PseudoHeaderName::Path if self.value.is_empty() => {
+                Err(HeaderError::EmptyValue(name))
+            }
+            PseudoHeaderName::Authority if !is_valid_authority(&self.value) => {
+                Err(HeaderError::InvalidAuthority)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate(&self, req_or_resp: RequestOrResponse) -> HeaderResult<()> {
         if let Some(h) = self.pseudo_header_name()? {
             if h.req_or_resp() != req_or_resp {
                 return Err(HeaderError::UnexpectedPseudoHeader(h));
             }
         }
 
-        for c in &self.name {
+        self.validate_field()
+    }
+
+    /// Validate header name and value regardless of message type
+    pub fn validate_field(&self) -> HeaderResult<()> {
+        if self.name.len() == 0 {
+            return Err(HeaderError::EmptyName);
+        }
+
+        // pseudo-header name is checked to be known
+        let name = match self.pseudo_header_name()? {
+            Some(h) => {
+                self.validate_pseudo_header_value(h)?;
+                &self.name[1..]
+            }
+            None => &self.name[..],
+        };
+        for &c in name {
             Header::validate_header_name_char(c)?;
         }
 
+        // 10.3: NUL, CR and LF must not be present in value
+        if self.value.iter().any(|&b| b == 0 || b == b'\r' || b == b'\n') {
+            return Err(HeaderError::IncorrectCharInValue);
+        }
+
         // HTTP/2 does not use the Connection header field to indicate
         // connection-specific header fields; in this protocol, connection-
         // specific metadata is conveyed by other means.  An endpoint MUST NOT
@@ -320,6 +429,10 @@ impl Headers {
         Headers(vec![Header::new(":status", format!("{}", code))])
     }
 
+    pub fn from_status_code(status: StatusCode) -> Headers {
+        Headers::from_status(status.as_u16() as u32)
+    }
+
     pub fn ok_200() -> Headers {
         Headers::from_status(200)
     }
@@ -342,6 +455,7 @@ impl Headers {
         headers_place: HeadersPlace,
     ) -> HeaderResult<()> {
         let mut saw_regular_header = false;
+        let mut connect = false;
 
         let mut pseudo_headers_met = PseudoHeaderNameSet::new();
 
@@ -366,10 +480,8 @@ impl Headers {
                     return Err(HeaderError::MoreThanOnePseudoHeader(header_name));
                 }
 
-                if header_name == PseudoHeaderName::Path {
-                    if header.value.is_empty() {
-                        return Err(HeaderError::EmptyValue(header_name));
-                    }
+                if header_name == PseudoHeaderName::Method {
+                    connect = header.value.as_ref() == b"CONNECT";
                 }
             } else {
                 saw_regular_header = true;
@@ -382,6 +494,18 @@ impl Headers {
                 // ":method", ":scheme", and ":path" pseudo-header fields, unless it is
                 // a CONNECT request (Section 8.3).  An HTTP request that omits
                 // mandatory pseudo-header fields is malformed (Section 8.1.2.6).
+                // 8.3.  The CONNECT Method
+                // The ":scheme" and ":path" pseudo-header fields MUST be omitted.
+                // The ":authority" pseudo-header field contains the host and port
+                // to connect to.
+                RequestOrResponse::Request if connect => {
+                    for &forbidden in &[PseudoHeaderName::Scheme, PseudoHeaderName::Path] {
+                        if pseudo_headers_met.contains(forbidden) {
+                            return Err(HeaderError::UnexpectedPseudoHeader(forbidden));
+                        }
+                    }
+                    &[PseudoHeaderName::Method, PseudoHeaderName::Authority][..]
+                }
                 RequestOrResponse::Request => {
                     &[
                         PseudoHeaderName::Method,
@@ -406,6 +530,16 @@ impl Headers {
         Ok(())
     }
 
+    /// Header fields as (name, value) pairs, including duplicates.
+    ///
+    /// For received headers the order is the order of the header block
+    /// produced by HPACK decoder, e. g. for forwarding by a proxy.
+    /// Fields added by the library (like generated request id)
+    /// follow the received ones.
+    pub fn iter_raw<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+        self.0.iter().map(|h| (h.name(), h.value()))
+    }
+
     pub fn get_opt<'a>(&'a self, name: &str) -> Option<&'a str> {
         self.0
             .iter()
@@ -425,6 +559,11 @@ impl Headers {
         self.get_opt_parse(":status").unwrap()
     }
 
+    /// `None` if `:status` is absent or is not a three-digit code
+    pub fn status_code(&self) -> Option<StatusCode> {
+        self.get_opt_parse(":status")
+    }
+
     pub fn path(&self) -> &str {
         self.get(":path")
     }
@@ -433,6 +572,21 @@ impl Headers {
         self.get(":method")
     }
 
+    pub fn scheme(&self) -> &str {
+        self.get(":scheme")
+    }
+
+    /// `:authority` pseudo-header, or `host` header if `:authority` is not specified
+    /// (request may contain `host` instead of `:authority`, 8.1.2.3).
+    pub fn authority(&self) -> Option<&str> {
+        self.get_opt(":authority").or_else(|| self.get_opt("host"))
+    }
+
+    /// Split `:path` into path and decoded query parameters.
+    pub fn query(&self) -> PathQuery {
+        PathQuery::parse(self.path())
+    }
+
     pub fn content_length(&self) -> Option<u64> {
         match self.get_opt("content-length") {
             Some(v) => v.parse().ok(),
@@ -444,11 +598,550 @@ impl Headers {
         self.0.push(Header::new(name, value));
     }
 
+    /// Build headers from untrusted names and values,
+    /// fail on the first field with invalid name or value.
+    ///
+    /// Only individual fields are validated, use `validate`
+    /// to check pseudo-headers of complete request or response.
+    pub fn try_from_iter<I, N, V>(iter: I) -> HeaderResult<Headers>
+    where
+        I: IntoIterator<Item = (N, V)>,
+        N: Into<HeaderPart>,
+        V: Into<HeaderPart>,
+    {
+        let mut headers = Headers::new();
+        for (name, value) in iter {
+            let header = Header::new(name, value);
+            header.validate_field()?;
+            headers.0.push(header);
+        }
+        Ok(headers)
+    }
+
+    /// Add a cookie to request headers.
+    ///
+    /// Each cookie is sent as a separate `cookie` header field,
+    /// which is allowed by HTTP/2 and improves HPACK compression (8.1.2.5).
+    /// Name must be a token and value must consist of cookie-octets,
+    /// optionally in double quotes (RFC 6265 section 4.1.1).
+    pub fn set_cookie(&mut self, name: &str, value: &str) -> HeaderResult<()> {
+        if name.is_empty() || !name.bytes().all(is_cookie_name_char) {
+            return Err(HeaderError::InvalidCookieName);
+        }
+        let unquoted = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+        if !unquoted.bytes().all(is_cookie_octet) {
+            return Err(HeaderError::InvalidCookieValue);
+        }
+        self.add("cookie", &format!("{}={}", name, value));
+        Ok(())
+    }
+
+    /// Parse cookies from request `cookie` headers.
+    ///
+    /// HTTP/2 allows `cookie` header to be split into multiple header fields,
+    /// so all of them are concatenated before parsing (8.1.2.5).
+    /// If cookie is specified more than once, last value wins.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let joined = self
+            .0
+            .iter()
+            .filter(|h| h.name() == b"cookie")
+            .filter_map(|h| str::from_utf8(h.value()).ok())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        joined
+            .split(';')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .filter_map(|c| {
+                let eq = c.find('=')?;
+                Some((c[..eq].trim().to_owned(), c[eq + 1..].trim().to_owned()))
+            }).collect()
+    }
+
+    /// Parse all `cache-control` headers
+    pub fn cache_directives(&self) -> CacheDirectives {
+        let mut directives = CacheDirectives::default();
+        for h in self.0.iter().filter(|h| h.name() == b"cache-control") {
+            if let Ok(value) = str::from_utf8(h.value()) {
+                directives.parse_add(value);
+            }
+        }
+        directives
+    }
+
+    /// Whether response may be stored by a private (client side) cache
+    /// according to RFC 7234 section 3.
+    ///
+    /// Response is cacheable if it does not contain `no-store`
+    /// and has explicit expiration time, `public` directive,
+    /// or status code cacheable by default (RFC 7231 section 6.1).
+    /// Shared caches should also check `CacheDirectives::private`.
+    pub fn is_cacheable(&self) -> bool {
+        let status = match self.get_opt_parse::<u32>(":status") {
+            Some(status) => status,
+            None => return false,
+        };
+        if status < 200 {
+            return false;
+        }
+
+        let directives = self.cache_directives();
+        if directives.no_store {
+            return false;
+        }
+
+        let cacheable_by_default = matches!(
+            status,
+            200 | 203 | 204 | 206 | 300 | 301 | 404 | 405 | 410 | 414 | 501
+        );
+
+        cacheable_by_default
+            || directives.public
+            || directives.max_age.is_some()
+            || directives.s_maxage.is_some()
+            || self.get_opt("expires").is_some()
+    }
+
+    /// Parse `etag` header
+    pub fn etag(&self) -> Option<EntityTag> {
+        self.get_opt("etag").and_then(EntityTag::parse)
+    }
+
+    /// Whether `if-none-match` headers match the entity tag
+    /// using weak comparison, `*` matches any tag (RFC 7232 section 3.2).
+    /// `None` if request has no `if-none-match`.
+    pub fn if_none_match(&self, etag: &EntityTag) -> Option<bool> {
+        let mut present = false;
+        for h in self.0.iter().filter(|h| h.name() == b"if-none-match") {
+            present = true;
+            let value = match str::from_utf8(h.value()) {
+                Ok(value) => value,
+                Err(..) => continue,
+            };
+            if value.trim() == "*" || EntityTag::parse_list(value).iter().any(|t| t.weak_eq(etag)) {
+                return Some(true);
+            }
+        }
+        if present {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Parse `range` header for content of given length,
+    /// `RangeRequest::Full` if header is absent
+    pub fn range(&self, len: u64) -> RangeRequest {
+        match self.get_opt("range") {
+            Some(range) => RangeRequest::parse(range, len),
+            None => RangeRequest::Full,
+        }
+    }
+
     pub fn extend(&mut self, headers: Headers) {
         self.0.extend(headers.0);
     }
 }
 
+/// HTTP status code, value of `:status` pseudo-header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+macro_rules! status_codes {
+    ($(($code:expr, $name:ident, $reason:expr),)+) => {
+        impl StatusCode {
+            $(pub const $name: StatusCode = StatusCode($code);)+
+
+            /// Reason phrase of well-known status code (RFC 7231 section 6.1),
+            /// HTTP/2 does not transmit reason phrases
+            pub fn canonical_reason(&self) -> Option<&'static str> {
+                match self.0 {
+                    $($code => Some($reason),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    (100, CONTINUE, "Continue"),
+    (200, OK, "OK"),
+    (201, CREATED, "Created"),
+    (202, ACCEPTED, "Accepted"),
+    (204, NO_CONTENT, "No Content"),
+    (206, PARTIAL_CONTENT, "Partial Content"),
+    (301, MOVED_PERMANENTLY, "Moved Permanently"),
+    (302, FOUND, "Found"),
+    (303, SEE_OTHER, "See Other"),
+    (304, NOT_MODIFIED, "Not Modified"),
+    (307, TEMPORARY_REDIRECT, "Temporary Redirect"),
+    (308, PERMANENT_REDIRECT, "Permanent Redirect"),
+    (400, BAD_REQUEST, "Bad Request"),
+    (401, UNAUTHORIZED, "Unauthorized"),
+    (403, FORBIDDEN, "Forbidden"),
+    (404, NOT_FOUND, "Not Found"),
+    (405, METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    (408, REQUEST_TIMEOUT, "Request Timeout"),
+    (409, CONFLICT, "Conflict"),
+    (410, GONE, "Gone"),
+    (412, PRECONDITION_FAILED, "Precondition Failed"),
+    (413, PAYLOAD_TOO_LARGE, "Payload Too Large"),
+    (416, RANGE_NOT_SATISFIABLE, "Range Not Satisfiable"),
+    (429, TOO_MANY_REQUESTS, "Too Many Requests"),
+    (500, INTERNAL_SERVER_ERROR, "Internal Server Error"),
+    (501, NOT_IMPLEMENTED, "Not Implemented"),
+    (502, BAD_GATEWAY, "Bad Gateway"),
+    (503, SERVICE_UNAVAILABLE, "Service Unavailable"),
+    (504, GATEWAY_TIMEOUT, "Gateway Timeout"),
+}
+
+impl StatusCode {
+    /// Status code must be a three-digit integer (RFC 7231 section 6)
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        if (100..=999).contains(&code) {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// 1xx
+    pub fn is_informational(&self) -> bool {
+        self.0 >= 100 && self.0 < 200
+    }
+
+    /// 2xx
+    pub fn is_success(&self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// 3xx
+    pub fn is_redirect(&self) -> bool {
+        self.0 >= 300 && self.0 < 400
+    }
+
+    /// 4xx
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
+
+    /// 5xx
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500 && self.0 < 600
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(status: StatusCode) -> u16 {
+        status.0
+    }
+}
+
+impl FromStr for StatusCode {
+    type Err = ();
+
+    /// Parse `:status` header value, exactly three digits
+    fn from_str(s: &str) -> result::Result<StatusCode, ()> {
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        StatusCode::from_u16(s.parse().map_err(|_| ())?).ok_or(())
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.canonical_reason() {
+            Some(reason) => write!(f, "{} {}", self.0, reason),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Entity tag, value of `etag` header (RFC 7232 section 2.3)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    pub weak: bool,
+    /// Opaque tag without quotes
+    pub tag: String,
+}
+
+impl EntityTag {
+    pub fn strong<S: Into<String>>(tag: S) -> EntityTag {
+        EntityTag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    pub fn weak<S: Into<String>>(tag: S) -> EntityTag {
+        EntityTag {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    /// Strong tag computed from content length and FNV-1a hash of content,
+    /// stable across processes, but not cryptographically secure
+    pub fn from_content(content: &[u8]) -> EntityTag {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &b in content {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+        EntityTag::strong(format!("{:x}-{:016x}", content.len(), hash))
+    }
+
+    /// Parse single tag, `None` if value is not a quoted tag
+    pub fn parse(value: &str) -> Option<EntityTag> {
+        match EntityTag::parse_prefix(value.trim()) {
+            Some((tag, "")) => Some(tag),
+            _ => None,
+        }
+    }
+
+    /// Parse comma separated tags, e. g. `if-none-match`, skipping malformed tags
+    pub fn parse_list(value: &str) -> Vec<EntityTag> {
+        let mut tags = Vec::new();
+        let mut rem = value;
+        loop {
+            rem = rem.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if rem.is_empty() {
+                return tags;
+            }
+            match EntityTag::parse_prefix(rem) {
+                Some((tag, r)) => {
+                    tags.push(tag);
+                    rem = r;
+                }
+                // skip to the next comma
+                None => rem = rem.find(',').map(|c| &rem[c..]).unwrap_or(""),
+            }
+        }
+    }
+
+    /// Parse tag at the start of string, tags may contain commas
+    fn parse_prefix(value: &str) -> Option<(EntityTag, &str)> {
+        let (weak, rem) = match value.strip_prefix("W/") {
+            Some(rem) => (true, rem),
+            None => (false, value),
+        };
+        if !rem.starts_with('"') {
+            return None;
+        }
+        let end = rem[1..].find('"')? + 1;
+        let tag = EntityTag {
+            weak,
+            tag: rem[1..end].to_owned(),
+        };
+        Some((tag, &rem[end + 1..]))
+    }
+
+    /// Strong comparison function, weak tags never match
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison function, tags match regardless of weakness
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+/// Parsed `cache-control` header (RFC 7234 section 5.2)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CacheDirectives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub no_transform: bool,
+    pub public: bool,
+    pub private: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub immutable: bool,
+    /// `max-age` in seconds
+    pub max_age: Option<u64>,
+    /// `s-maxage` in seconds
+    pub s_maxage: Option<u64>,
+    /// Unrecognized directives with optional unquoted values,
+    /// names are lowercased
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl CacheDirectives {
+    pub fn parse(value: &str) -> CacheDirectives {
+        let mut directives = CacheDirectives::default();
+        directives.parse_add(value);
+        directives
+    }
+
+    fn parse_add(&mut self, value: &str) {
+        for (name, arg) in split_directives(value) {
+            // invalid delta-seconds are treated as zero, i. e. stale
+            let seconds = || arg.as_ref().map(|a| a.parse().unwrap_or(0));
+            match &name[..] {
+                "no-store" => self.no_store = true,
+                "no-cache" => self.no_cache = true,
+                "no-transform" => self.no_transform = true,
+                "public" => self.public = true,
+                "private" => self.private = true,
+                "must-revalidate" => self.must_revalidate = true,
+                "proxy-revalidate" => self.proxy_revalidate = true,
+                "immutable" => self.immutable = true,
+                "max-age" => self.max_age = seconds(),
+                "s-maxage" => self.s_maxage = seconds(),
+                _ => self.extensions.push((name, arg)),
+            }
+        }
+    }
+}
+
+/// Split comma separated directives into lowercase name and unquoted argument,
+/// commas inside quoted strings (e. g. `private="a, b"`) do not split.
+fn split_directives(value: &str) -> Vec<(String, Option<String>)> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                item.push(c);
+            }
+            ',' if !quoted => items.push(item.split_off(0)),
+            c => item.push(c),
+        }
+    }
+    items.push(item);
+
+    items
+        .iter()
+        .map(|i| i.trim())
+        .filter(|i| !i.is_empty())
+        .map(|i| match i.find('=') {
+            Some(eq) => {
+                let arg = i[eq + 1..].trim().trim_matches('"').to_owned();
+                (i[..eq].trim().to_ascii_lowercase(), Some(arg))
+            }
+            None => (i.to_ascii_lowercase(), None),
+        }).collect()
+}
+
+/// Request path split into path and query parameters
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PathQuery {
+    /// Path without query, not decoded
+    pub path: String,
+    /// Percent-decoded query parameters in request order,
+    /// repeated keys are preserved
+    pub params: Vec<(String, String)>,
+}
+
+impl PathQuery {
+    pub fn parse(path: &str) -> PathQuery {
+        let (path, query) = match path.find('?') {
+            Some(q) => (&path[..q], &path[q + 1..]),
+            None => (path, ""),
+        };
+
+        let params = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| match p.find('=') {
+                Some(eq) => (percent_decode(&p[..eq]), percent_decode(&p[eq + 1..])),
+                None => (percent_decode(p), String::new()),
+            }).collect();
+
+        PathQuery {
+            path: path.to_owned(),
+            params,
+        }
+    }
+
+    /// First value of the parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| &v[..])
+    }
+
+    /// All values of the parameter
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.params
+            .iter()
+            .filter(|(k, _)| k == name)
+            .map(|(_, v)| &v[..])
+            .collect()
+    }
+}
+
+/// Decode `%XX` sequences and `+` as space, invalid sequences are kept as is
+fn percent_decode(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(s, true)).into_owned()
+}
+
+/// Decode `%XX` sequences in URL path, `+` is kept as is
+pub(crate) fn percent_decode_path(s: &str) -> Vec<u8> {
+    percent_decode_bytes(s, false)
+}
+
+fn percent_decode_bytes(s: &str, plus_as_space: bool) -> Vec<u8> {
+    fn hex(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            b'A'..=b'F' => Some(b - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    let bytes = s.as_bytes();
+    let mut r = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => r.push(b' '),
+            b'%' => {
+                let h = bytes.get(i + 1).cloned().and_then(hex);
+                let l = bytes.get(i + 2).cloned().and_then(hex);
+                match (h, l) {
+                    (Some(h), Some(l)) => {
+                        r.push(h * 16 + l);
+                        i += 3;
+                        continue;
+                    }
+                    _ => r.push(b'%'),
+                }
+            }
+            b => r.push(b),
+        }
+        i += 1;
+    }
+    r
+}
+
 impl FromIterator<Header> for Headers {
     fn from_iter<T: IntoIterator<Item = Header>>(iter: T) -> Headers {
         Headers(iter.into_iter().collect())
@@ -457,7 +1150,18 @@ impl FromIterator<Header> for Headers {
 
 #[cfg(test)]
 mod test {
+    use headers_place::HeadersPlace;
+    use req_resp::RequestOrResponse;
+    use solicit::header::CacheDirectives;
+    use solicit::header::EntityTag;
     use solicit::header::Header;
+    use solicit::header::HeaderError;
+    use solicit::header::Headers;
+    use solicit::header::PathQuery;
+    use solicit::header::PseudoHeaderName;
+    use solicit::header::StatusCode;
+
+    use hpack;
 
     #[test]
     fn test_partial_eq_of_headers() {
@@ -481,4 +1185,379 @@ mod test {
             format!("{:?}", Header::new(&b":method"[..], &b"\xcd"[..]))
         );
     }
+
+    fn request(headers: &[(&str, &str)]) -> Headers {
+        Headers(
+            headers
+                .iter()
+                .map(|&(n, v)| Header::new(n.as_bytes(), v.as_bytes()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_validate_request_missing_pseudo_headers() {
+        let all = [(":method", "GET"), (":scheme", "http"), (":path", "/")];
+        assert_eq!(
+            Ok(()),
+            request(&all).validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+
+        for (i, &missing) in [
+            PseudoHeaderName::Method,
+            PseudoHeaderName::Scheme,
+            PseudoHeaderName::Path,
+        ].iter()
+            .enumerate()
+        {
+            let mut headers = all.to_vec();
+            headers.remove(i);
+            assert_eq!(
+                Err(HeaderError::MissingPseudoHeader(missing)),
+                request(&headers).validate(RequestOrResponse::Request, HeadersPlace::Initial)
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_connect_request() {
+        assert_eq!(
+            Ok(()),
+            request(&[(":method", "CONNECT"), (":authority", "example.com:443")])
+                .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Err(HeaderError::MissingPseudoHeader(PseudoHeaderName::Authority)),
+            request(&[(":method", "CONNECT")])
+                .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Err(HeaderError::UnexpectedPseudoHeader(PseudoHeaderName::Scheme)),
+            request(&[
+                (":method", "CONNECT"),
+                (":scheme", "https"),
+                (":authority", "example.com:443"),
+            ]).validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Err(HeaderError::UnexpectedPseudoHeader(PseudoHeaderName::Path)),
+            request(&[
+                (":method", "CONNECT"),
+                (":authority", "example.com:443"),
+                (":path", "/"),
+            ]).validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+    }
+
+    #[test]
+    fn test_validate_pseudo_header_value_chars() {
+        for &value in &["/a\r\nhost: evil", "/a\nb", "/a\0", "/a b", "/a\tb", "/\x7f"] {
+            assert_eq!(
+                Err(HeaderError::IncorrectCharInPseudoHeaderValue(PseudoHeaderName::Path)),
+                request(&[(":method", "GET"), (":scheme", "http"), (":path", value)])
+                    .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+            );
+        }
+        assert_eq!(
+            Err(HeaderError::IncorrectCharInPseudoHeaderValue(PseudoHeaderName::Method)),
+            request(&[(":method", "GET /x"), (":scheme", "http"), (":path", "/")])
+                .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Err(HeaderError::IncorrectCharInPseudoHeaderValue(PseudoHeaderName::Status)),
+            request(&[(":status", "200\r\n")])
+                .validate(RequestOrResponse::Response, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Err(HeaderError::EmptyValue(PseudoHeaderName::Path)),
+            request(&[(":method", "GET"), (":scheme", "http"), (":path", "")])
+                .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+        assert_eq!(
+            Ok(()),
+            request(&[(":method", "OPTIONS"), (":scheme", "http"), (":path", "*")])
+                .validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        );
+    }
+
+    #[test]
+    fn test_validate_authority() {
+        let validate = |authority| {
+            request(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":authority", authority),
+                (":path", "/"),
+            ]).validate(RequestOrResponse::Request, HeadersPlace::Initial)
+        };
+
+        for &authority in &[
+            "example.com",
+            "example.com:8080",
+            "127.0.0.1:80",
+            "[::1]",
+            "[::1]:443",
+            "xn--bcher-kva.example",
+            "example.com:",
+            "[::1]:",
+        ] {
+            assert_eq!(Ok(()), validate(authority), "{}", authority);
+        }
+
+        for &authority in &[
+            "",
+            ":80",
+            "user@example.com",
+            ":",
+            "example.com:port",
+            "example.com:65536",
+            "example.com:80:80",
+            "example.com/path",
+            "[::1",
+            "[]:80",
+            "[::1]x",
+        ] {
+            assert_eq!(Err(HeaderError::InvalidAuthority), validate(authority), "{}", authority);
+        }
+
+        for &authority in &["example.com\r\nx: y", "exa mple.com", "example.com\0"] {
+            assert_eq!(
+                Err(HeaderError::IncorrectCharInPseudoHeaderValue(PseudoHeaderName::Authority)),
+                validate(authority),
+                "{}",
+                authority
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_from_iter() {
+        let headers = Headers::try_from_iter(vec![
+            (":status", "200"),
+            ("content-type", "text/plain"),
+            ("x-custom_header.1~", "a b\tc"),
+        ]).unwrap();
+        assert_eq!(3, headers.0.len());
+        assert_eq!("a b\tc", headers.get("x-custom_header.1~"));
+
+        for &(name, value, ref error) in &[
+            ("", "v", HeaderError::EmptyName),
+            ("Content-Type", "v", HeaderError::IncorrectCharInName),
+            ("x header", "v", HeaderError::IncorrectCharInName),
+            ("x:header", "v", HeaderError::IncorrectCharInName),
+            ("x-header\r", "v", HeaderError::IncorrectCharInName),
+            (":unknown", "v", HeaderError::UnknownPseudoHeader),
+            (
+                ":path",
+                "/\r\n",
+                HeaderError::IncorrectCharInPseudoHeaderValue(PseudoHeaderName::Path),
+            ),
+            ("x-header", "a\r\nb", HeaderError::IncorrectCharInValue),
+            ("x-header", "a\nb", HeaderError::IncorrectCharInValue),
+            ("x-header", "a\0b", HeaderError::IncorrectCharInValue),
+            ("connection", "close", HeaderError::ConnectionSpecificHeader("connection")),
+            ("te", "gzip", HeaderError::TeCanOnlyContainTrailer),
+        ] {
+            assert_eq!(
+                Err(error.clone()),
+                Headers::try_from_iter(vec![("x-ok", "ok"), (name, value)]),
+                "{:?}: {:?}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_cookies() {
+        let mut headers = Headers::new_get("/");
+        headers.set_cookie("a", "b").unwrap();
+        headers.add("cookie", "c=d; e=f");
+        headers.add("cookie", " g = h ;");
+
+        let cookies = headers.cookies();
+        assert_eq!(4, cookies.len());
+        assert_eq!("b", cookies["a"]);
+        assert_eq!("d", cookies["c"]);
+        assert_eq!("f", cookies["e"]);
+        assert_eq!("h", cookies["g"]);
+    }
+
+    #[test]
+    fn test_set_cookie_invalid() {
+        let mut headers = Headers::new_get("/");
+        assert_eq!(Ok(()), headers.set_cookie("sid", "\"quoted\""));
+        assert_eq!(Err(HeaderError::InvalidCookieName), headers.set_cookie("", "b"));
+        assert_eq!(Err(HeaderError::InvalidCookieName), headers.set_cookie("a=b", "c"));
+        assert_eq!(Err(HeaderError::InvalidCookieName), headers.set_cookie("a;", "c"));
+        assert_eq!(Err(HeaderError::InvalidCookieValue), headers.set_cookie("a", "b; c=d"));
+        assert_eq!(Err(HeaderError::InvalidCookieValue), headers.set_cookie("a", "b,c"));
+        assert_eq!(Err(HeaderError::InvalidCookieValue), headers.set_cookie("a", "b\r\n"));
+        assert_eq!(1, headers.cookies().len());
+    }
+
+    #[test]
+    fn test_cookies_empty() {
+        assert!(Headers::new_get("/").cookies().is_empty());
+    }
+
+    #[test]
+    fn test_authority_scheme() {
+        let mut headers = Headers::new_get("/");
+        headers.add(":scheme", "https");
+        assert_eq!(None, headers.authority());
+        headers.add("host", "example.com");
+        assert_eq!(Some("example.com"), headers.authority());
+        headers.add(":authority", "example.org");
+        assert_eq!(Some("example.org"), headers.authority());
+        assert_eq!("https", headers.scheme());
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Headers::new_get("/search?q=a+b%20c&tag=x&tag=y%2Fz&empty&bad=%zz%4").query();
+        assert_eq!("/search", query.path);
+        assert_eq!(Some("a b c"), query.get("q"));
+        assert_eq!(vec!["x", "y/z"], query.get_all("tag"));
+        assert_eq!(Some(""), query.get("empty"));
+        assert_eq!(Some("%zz%4"), query.get("bad"));
+        assert_eq!(None, query.get("missing"));
+        assert_eq!(5, query.params.len());
+
+        let query = Headers::new_get("/p%20ath").query();
+        assert_eq!("/p%20ath", query.path);
+        assert!(query.params.is_empty());
+
+        let query = PathQuery::parse("/?%E2%9C%93=1");
+        assert_eq!(Some("1"), query.get("\u{2713}"));
+    }
+
+    #[test]
+    fn test_cache_directives() {
+        let directives = CacheDirectives::parse(
+            "Max-Age=60, private=\"set-cookie, x-a\", s-maxage=\"10\", x-ext",
+        );
+        assert_eq!(Some(60), directives.max_age);
+        assert_eq!(Some(10), directives.s_maxage);
+        assert!(directives.private);
+        assert!(!directives.no_store);
+        assert_eq!(vec![("x-ext".to_owned(), None)], directives.extensions);
+
+        let mut headers = Headers::ok_200();
+        headers.add("cache-control", "no-cache");
+        headers.add("cache-control", "max-age=bad");
+        let directives = headers.cache_directives();
+        assert!(directives.no_cache);
+        assert_eq!(Some(0), directives.max_age);
+    }
+
+    #[test]
+    fn test_is_cacheable() {
+        assert!(Headers::ok_200().is_cacheable());
+        assert!(Headers::not_found_404().is_cacheable());
+        assert!(!Headers::internal_error_500().is_cacheable());
+        assert!(!Headers::from_status(100).is_cacheable());
+        assert!(!Headers::new_get("/").is_cacheable());
+
+        let mut headers = Headers::ok_200();
+        headers.add("cache-control", "public, no-store");
+        assert!(!headers.is_cacheable());
+
+        let mut headers = Headers::from_status(302);
+        assert!(!headers.is_cacheable());
+        headers.add("cache-control", "max-age=60");
+        assert!(headers.is_cacheable());
+
+        let mut headers = Headers::from_status(307);
+        headers.add("expires", "Thu, 01 Dec 2094 16:00:00 GMT");
+        assert!(headers.is_cacheable());
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        assert_eq!(Some(EntityTag::strong("abc")), EntityTag::parse(" \"abc\" "));
+        assert_eq!(Some(EntityTag::weak("a,b")), EntityTag::parse("W/\"a,b\""));
+        assert_eq!(None, EntityTag::parse("abc"));
+        assert_eq!(None, EntityTag::parse("\"abc"));
+        assert_eq!("W/\"abc\"", EntityTag::weak("abc").to_string());
+
+        assert_eq!(
+            vec![EntityTag::strong("a,b"), EntityTag::weak("c"), EntityTag::strong("d")],
+            EntityTag::parse_list("\"a,b\", W/\"c\", bad, \"d\"")
+        );
+
+        assert!(EntityTag::weak("a").weak_eq(&EntityTag::strong("a")));
+        assert!(!EntityTag::weak("a").strong_eq(&EntityTag::strong("a")));
+        assert!(EntityTag::strong("a").strong_eq(&EntityTag::strong("a")));
+
+        let etag = EntityTag::from_content(b"hello");
+        assert_eq!(etag, EntityTag::from_content(b"hello"));
+        assert_ne!(etag, EntityTag::from_content(b"hellp"));
+        assert!(!etag.weak);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = EntityTag::strong("b");
+        assert_eq!(None, Headers::new_get("/").if_none_match(&etag));
+
+        let mut headers = Headers::new_get("/");
+        headers.add("if-none-match", "\"a\", W/\"b\"");
+        assert_eq!(Some(true), headers.if_none_match(&etag));
+        assert_eq!(Some(false), headers.if_none_match(&EntityTag::strong("c")));
+
+        let mut headers = Headers::new_get("/");
+        headers.add("if-none-match", "\"a\"");
+        headers.add("if-none-match", "*");
+        assert_eq!(Some(true), headers.if_none_match(&EntityTag::strong("c")));
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(Some(StatusCode::NOT_FOUND), "404".parse().ok());
+        assert_eq!(Err(()), "99".parse::<StatusCode>());
+        assert_eq!(Err(()), "+200".parse::<StatusCode>());
+        assert_eq!(Err(()), "2000".parse::<StatusCode>());
+        assert_eq!(None, StatusCode::from_u16(1000));
+
+        assert!(StatusCode::CONTINUE.is_informational());
+        assert!(StatusCode::NO_CONTENT.is_success());
+        assert!(StatusCode::NOT_MODIFIED.is_redirect());
+        assert!(StatusCode::TOO_MANY_REQUESTS.is_client_error());
+        assert!(StatusCode::BAD_GATEWAY.is_server_error());
+        assert!(!StatusCode::OK.is_client_error());
+
+        assert_eq!("404 Not Found", StatusCode::NOT_FOUND.to_string());
+        assert_eq!("299", StatusCode::from_u16(299).unwrap().to_string());
+
+        assert_eq!(Some(StatusCode::OK), Headers::ok_200().status_code());
+        assert_eq!(
+            "413",
+            Headers::from_status_code(StatusCode::PAYLOAD_TOO_LARGE).get(":status")
+        );
+        assert_eq!(None, Headers::new_get("/").status_code());
+    }
+
+    #[test]
+    fn iter_raw_hpack_order() {
+        let fields: Vec<(&[u8], &[u8])> = vec![
+            (b":status", b"200"),
+            (b"set-cookie", b"b=2"),
+            (b"via", b"proxy-b"),
+            (b"set-cookie", b"a=1"),
+            (b"via", b"proxy-a"),
+        ];
+
+        let encoded = hpack::Encoder::new().encode(fields.iter().cloned());
+        let decoded = hpack::Decoder::new().decode(&encoded).unwrap();
+        let headers = Headers(
+            decoded
+                .into_iter()
+                .map(|h| Header::new(h.0, h.1))
+                .collect(),
+        );
+
+        assert_eq!(fields, headers.iter_raw().collect::<Vec<_>>());
+        assert_eq!(200, headers.status());
+    }
 }
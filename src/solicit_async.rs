@@ -18,7 +18,7 @@ use error;
 use error::Error;
 use result::Result;
 
-use solicit::frame::settings::SettingsFrame;
+use solicit::frame::settings::RawSettingsFrame;
 use solicit::frame::unpack_header;
 use solicit::frame::FrameIR;
 use solicit::frame::RawFrame;
@@ -27,6 +27,9 @@ use solicit::frame::FRAME_HEADER_LEN;
 
 use misc::BsDebug;
 
+use server::h2c;
+use server::h2c::H2cUpgrade;
+
 pub type HttpFuture<T> = Box<Future<Item = T, Error = Error>>;
 
 pub type HttpFutureSend<T> = Box<Future<Item = T, Error = Error> + Send>;
@@ -76,14 +79,14 @@ static PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
 fn send_settings<W: AsyncWrite + Send + 'static>(
     conn: W,
-    settings: SettingsFrame,
+    settings: RawSettingsFrame,
 ) -> HttpFuture<W> {
     Box::new(send_frame(conn, settings))
 }
 
 pub fn client_handshake<I: AsyncWrite + AsyncRead + Send + 'static>(
     conn: I,
-    settings: SettingsFrame,
+    settings: RawSettingsFrame,
 ) -> HttpFuture<I> {
     debug!("send PREFACE");
     let send_preface = write_all(conn, PREFACE)
@@ -108,21 +111,39 @@ fn looks_like_http_1(buf: &[u8]) -> bool {
     buf.starts_with(b"GET ") || buf.starts_with(b"POST ") || buf.starts_with(b"HEAD ")
 }
 
-/// Recv HTTP/2 preface, or sent HTTP/1 500 and return error is input looks like HTTP/1 request
-fn recv_preface_or_handle_http_1<I>(conn: I) -> HttpFuture<I>
+fn send_http_1_500<I>(conn: I) -> HttpFuture<(I, Option<H2cUpgrade>)>
+where
+    I: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let w = write_all(conn, HTTP_1_500_RESPONSE);
+    let write = w.map_err(Error::from);
+    Box::new(write.then(|_| Err(Error::Other("request is made using HTTP/1"))))
+}
+
+/// Recv HTTP/2 preface, or sent HTTP/1 500 and return error is input looks like HTTP/1 request.
+///
+/// If `allow_h2c_upgrade`, HTTP/1.1 request with `Upgrade: h2c` is answered
+/// with `101 Switching Protocols`, and then HTTP/2 preface is expected.
+fn recv_preface_or_handle_http_1<I>(
+    conn: I,
+    allow_h2c_upgrade: bool,
+) -> HttpFuture<(I, Option<H2cUpgrade>)>
 where
     I: AsyncRead + AsyncWrite + Send + 'static,
 {
     struct Intermediate<I: AsyncRead> {
         collected: Vec<u8>,
         conn: Option<I>,
+        allow_h2c_upgrade: bool,
+        // reading HTTP/1.1 request head
+        http_1: bool,
     }
 
     impl<I: AsyncRead> Future for Intermediate<I>
     where
         I: AsyncRead + AsyncWrite + Send + 'static,
     {
-        type Item = HttpFuture<I>;
+        type Item = HttpFuture<(I, Option<H2cUpgrade>)>;
         type Error = Error;
 
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -156,20 +177,44 @@ where
 
                 self.collected.push(c);
 
+                if self.http_1 {
+                    if h2c::is_request_head_complete(&self.collected) {
+                        let conn = self.conn.take().unwrap();
+                        let upgrade = match h2c::parse_upgrade_request(&self.collected) {
+                            Some(upgrade) => upgrade,
+                            None => return Ok(Async::Ready(send_http_1_500(conn))),
+                        };
+                        debug!("upgrading HTTP/1.1 connection to h2c");
+                        let write = write_all(conn, h2c::H2C_101_RESPONSE).map_err(Error::from);
+                        let preface = write.and_then(|(conn, _)| {
+                            recv_preface_or_handle_http_1(conn, false)
+                                .map(|(conn, _)| (conn, Some(upgrade)))
+                        });
+                        return Ok(Async::Ready(Box::new(preface)));
+                    }
+                    if self.collected.len() > h2c::MAX_REQUEST_HEAD_SIZE {
+                        return Ok(Async::Ready(send_http_1_500(self.conn.take().unwrap())));
+                    }
+                    continue;
+                }
+
                 if self.collected == PREFACE {
-                    return Ok(Async::Ready(Box::new(future::ok(
+                    return Ok(Async::Ready(Box::new(future::ok((
                         self.conn.take().unwrap(),
-                    ))));
+                        None,
+                    )))));
+                }
+
+                // detect by method, because request line may be longer than preface
+                if self.allow_h2c_upgrade && looks_like_http_1(&self.collected) {
+                    self.http_1 = true;
+                    continue;
                 }
 
                 // TODO: check only for first \n
                 if c == b'\n' {
                     if looks_like_http_1(&self.collected) {
-                        let w = write_all(self.conn.take().unwrap(), HTTP_1_500_RESPONSE);
-                        let write = w.map_err(Error::from);
-                        let write =
-                            write.then(|_| Err(Error::Other("request is made using HTTP/1")));
-                        return Ok(Async::Ready(Box::new(write)));
+                        return Ok(Async::Ready(send_http_1_500(self.conn.take().unwrap())));
                     }
                 }
 
@@ -187,19 +232,28 @@ where
         Intermediate {
             conn: Some(conn),
             collected: Vec::new(),
+            allow_h2c_upgrade,
+            http_1: false,
         }.flatten(),
     )
 }
 
-pub fn server_handshake<I>(conn: I, settings: SettingsFrame) -> HttpFuture<I>
+/// Server handshake, returns upgrade request if connection was upgraded from HTTP/1.1
+pub fn server_handshake<I>(
+    conn: I,
+    settings: RawSettingsFrame,
+    allow_h2c_upgrade: bool,
+) -> HttpFuture<(I, Option<H2cUpgrade>)>
 where
     I: AsyncRead + AsyncWrite + Send + 'static,
 {
     let mut preface_buf = Vec::with_capacity(PREFACE.len());
     preface_buf.resize(PREFACE.len(), 0);
 
-    let recv_preface = recv_preface_or_handle_http_1(conn);
-    let send_settings = recv_preface.and_then(|conn| send_settings(conn, settings));
+    let recv_preface = recv_preface_or_handle_http_1(conn, allow_h2c_upgrade);
+    let send_settings = recv_preface.and_then(|(conn, upgrade)| {
+        send_settings(conn, settings).map(|conn| (conn, upgrade))
+    });
 
     Box::new(send_settings)
 }
@@ -1,4 +1,5 @@
 use solicit::frame::headers::HeadersDecodedFrame;
+use solicit::frame::push_promise::PushPromiseDecodedFrame;
 use solicit::frame::*;
 use solicit::StreamId;
 
@@ -9,7 +10,7 @@ pub enum HttpFrameStream {
     Headers(HeadersDecodedFrame),
     Priority(PriorityFrame),
     RstStream(RstStreamFrame),
-    PushPromise(PushPromiseFrame),
+    PushPromise(PushPromiseDecodedFrame),
     WindowUpdate(WindowUpdateFrame),
 }
 
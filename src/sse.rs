@@ -0,0 +1,93 @@
+//! Server-sent events (`text/event-stream`) support
+
+use bytes::Bytes;
+
+/// Single server-sent event.
+///
+/// See [the spec](https://html.spec.whatwg.org/multipage/server-sent-events.html)
+/// for fields description.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    /// Event payload, may contain multiple lines
+    pub data: String,
+    /// Reconnection time in milliseconds
+    pub retry: Option<u64>,
+}
+
+impl SseEvent {
+    /// Event with only `data` field
+    pub fn data<S: Into<String>>(data: S) -> SseEvent {
+        SseEvent {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Serialize event into `text/event-stream` wire format,
+    /// including terminating empty line.
+    ///
+    /// Line breaks in `id` and `event` are stripped, because they would
+    /// start a new field (e. g. inject `data` into the event).
+    pub fn to_bytes(&self) -> Bytes {
+        let mut r = String::new();
+        if let Some(ref id) = self.id {
+            push_single_line_field(&mut r, "id", id);
+        }
+        if let Some(ref event) = self.event {
+            push_single_line_field(&mut r, "event", event);
+        }
+        if let Some(retry) = self.retry {
+            r.push_str(&format!("retry: {}\n", retry));
+        }
+        // each line of data is sent in separate `data` field
+        for line in self.data.replace("\r\n", "\n").split(['\n', '\r']) {
+            r.push_str("data: ");
+            r.push_str(line);
+            r.push('\n');
+        }
+        r.push('\n');
+        Bytes::from(r)
+    }
+}
+
+fn push_single_line_field(r: &mut String, name: &str, value: &str) {
+    r.push_str(name);
+    r.push_str(": ");
+    r.extend(value.chars().filter(|&c| c != '\r' && c != '\n'));
+    r.push('\n');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_bytes() {
+        let event = SseEvent {
+            id: Some("1".to_owned()),
+            event: Some("update".to_owned()),
+            data: "a\r\nb".to_owned(),
+            retry: Some(10),
+        };
+        assert_eq!(
+            &b"id: 1\nevent: update\nretry: 10\ndata: a\ndata: b\n\n"[..],
+            &event.to_bytes()[..]
+        );
+    }
+
+    #[test]
+    fn to_bytes_strips_line_breaks_in_id_and_event() {
+        let event = SseEvent {
+            id: Some("1\ndata: injected".to_owned()),
+            event: Some("up\r\ndate\r".to_owned()),
+            data: "x".to_owned(),
+            retry: None,
+        };
+        assert_eq!(
+            &b"id: 1data: injected\nevent: update\ndata: x\n\n"[..],
+            &event.to_bytes()[..]
+        );
+    }
+}
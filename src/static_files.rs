@@ -0,0 +1,342 @@
+//! Serving files from a directory
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use byte_range;
+use byte_range::RangeRequest;
+use data_or_trailers::HttpStreamAfterHeaders;
+use resp::Response;
+use service::Service;
+use solicit::header::percent_decode_path;
+use solicit::header::EntityTag;
+use solicit::header::Headers;
+
+/// `Service` implementation serving files under a root directory.
+///
+/// Request `:path` is mapped to a file under the root,
+/// `index.html` is served for directories.
+/// Paths escaping the root (with `..` or symlinks) are rejected with `403`.
+///
+/// `GET` and `HEAD` requests are supported, as well as
+/// conditional requests (`If-None-Match`, `If-Modified-Since`)
+/// and single range requests (`Range`, `If-Range`).
+///
+/// ```
+/// use std::sync::Arc;
+/// use httpbis::*;
+///
+/// let mut server = ServerBuilder::new_plain();
+/// server.service.set_service("/", Arc::new(StaticFiles::new("/var/www")));
+/// ```
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new<P: AsRef<Path>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.as_ref().to_owned(),
+        }
+    }
+
+    /// Map request path to file under root, `None` if path is not allowed
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = match path.find('?') {
+            Some(q) => &path[..q],
+            None => path,
+        };
+
+        let mut r = self.root.clone();
+        for segment in path.split('/') {
+            let segment = match String::from_utf8(percent_decode_path(segment)) {
+                Ok(segment) => segment,
+                Err(_) => return None,
+            };
+            match &segment[..] {
+                "" | "." => {}
+                ".." => return None,
+                s if s.contains(['/', '\\', '\0']) => return None,
+                s if cfg!(windows) && s.contains(':') => return None,
+                s => r.push(s),
+            }
+        }
+        Some(r)
+    }
+
+    fn serve(&self, req: &Headers) -> io::Result<Response> {
+        let head = match req.method() {
+            "GET" => false,
+            "HEAD" => true,
+            _ => {
+                let mut headers = Headers::from_status(405);
+                headers.add("allow", "GET, HEAD");
+                return Ok(Response::headers(headers));
+            }
+        };
+
+        let path = match self.resolve(req.path()) {
+            Some(path) => path,
+            None => return Ok(status_response(403)),
+        };
+
+        // symlinks must not point outside of root
+        let (path, metadata) = match canonicalize_file(&path) {
+            Ok(r) => r,
+            Err(e) => return Ok(io_error_response(&e)),
+        };
+        match fs::canonicalize(&self.root) {
+            Ok(ref root) if path.starts_with(root) => {}
+            Ok(..) => return Ok(status_response(403)),
+            Err(e) => return Ok(io_error_response(&e)),
+        }
+
+        let len = metadata.len();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        let etag = format!("\"{:x}-{:x}\"", len, modified.unwrap_or(0));
+
+        let mut validators = Headers::new();
+        validators.add("etag", &etag);
+        if let Some(modified) = modified {
+            validators.add("last-modified", &format_http_date(modified));
+        }
+
+        if not_modified(req, &etag, modified) {
+            let mut headers = Headers::from_status(304);
+            headers.extend(validators);
+            return Ok(Response::headers(headers));
+        }
+
+        let range = if if_range_matches(req, &etag, modified) {
+            req.range(len)
+        } else {
+            RangeRequest::Full
+        };
+
+        let mut headers = Headers::new();
+        headers.add("content-type", content_type(&path));
+        headers.extend(validators);
+
+        let file = fs::File::open(&path)?;
+        let response = Response::byte_ranges(headers, file, len, range);
+        Ok(if head {
+            byte_range::without_body(response)
+        } else {
+            response
+        })
+    }
+}
+
+impl Service for StaticFiles {
+    fn start_request(&self, headers: Headers, _req: HttpStreamAfterHeaders) -> Response {
+        match self.serve(&headers) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("failed to serve file for path {}: {}", headers.path(), e);
+                Response::headers(Headers::internal_error_500())
+            }
+        }
+    }
+}
+
+/// Canonical path of regular file, `index.html` for directories
+fn canonicalize_file(path: &Path) -> io::Result<(PathBuf, fs::Metadata)> {
+    let mut path = fs::canonicalize(path)?;
+    let mut metadata = fs::metadata(&path)?;
+    if metadata.is_dir() {
+        path = fs::canonicalize(path.join("index.html"))?;
+        metadata = fs::metadata(&path)?;
+    }
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a regular file"));
+    }
+    Ok((path, metadata))
+}
+
+fn status_response(status: u32) -> Response {
+    Response::headers(Headers::from_status(status))
+}
+
+fn io_error_response(e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => status_response(403),
+        _ => Response::not_found_404(),
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match &ext[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Should `304 Not Modified` be returned (RFC 7232 section 6)
+fn not_modified(req: &Headers, etag: &str, modified: Option<u64>) -> bool {
+    // If-Modified-Since is ignored when If-None-Match is present
+    if let Some(matches) = EntityTag::parse(etag).and_then(|etag| req.if_none_match(&etag)) {
+        return matches;
+    }
+    match (req.get_opt("if-modified-since").and_then(parse_http_date), modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// Is `Range` header applicable according to `If-Range` (RFC 7233 section 3.2)
+fn if_range_matches(req: &Headers, etag: &str, modified: Option<u64>) -> bool {
+    match req.get_opt("if-range").map(|v| v.trim()) {
+        None => true,
+        // strong comparison, weak tags never match
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == etag,
+        Some(date) => parse_http_date(date).is_some() && parse_http_date(date) == modified,
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Year, month (1-12) and day (1-31) of days since epoch
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Days since epoch, `None` for dates before epoch
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe).checked_sub(719_468)
+}
+
+/// Format seconds since epoch as IMF-fixdate, e. g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn format_http_date(secs: u64) -> String {
+    let days = secs / 86400;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+    )
+}
+
+/// Parse IMF-fixdate into seconds since epoch, obsolete formats are not supported
+fn parse_http_date(value: &str) -> Option<u64> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+    if !parts[0].ends_with(',') || !WEEKDAYS.contains(&&parts[0][..parts[0].len() - 1]) {
+        return None;
+    }
+    let day: u64 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|&m| m == parts[2])? as u64 + 1;
+    let year: u64 = parts[3].parse().ok()?;
+    let time: Vec<u64> = parts[4]
+        .split(':')
+        .map(|t| t.parse().ok())
+        .collect::<Option<_>>()?;
+    if !(1..=31).contains(&day) || year < 1970 || time.len() != 3 {
+        return None;
+    }
+    if time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day)?;
+    Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", format_http_date(0));
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_http_date(784111777));
+        assert_eq!("Tue, 29 Feb 2000 12:00:00 GMT", format_http_date(951825600));
+        assert_eq!(Some(784111777), parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!(Some(951825600), parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"));
+        assert_eq!(None, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+        assert_eq!(None, parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"));
+        assert_eq!(None, parse_http_date("Sun, 06 Nov 1960 08:49:37 GMT"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let files = StaticFiles::new("/srv");
+        assert_eq!(Some(PathBuf::from("/srv")), files.resolve("/"));
+        assert_eq!(Some(PathBuf::from("/srv/a/b.txt")), files.resolve("/a/./b.txt?x=1"));
+        assert_eq!(Some(PathBuf::from("/srv/a b+c")), files.resolve("/a%20b+c"));
+        assert_eq!(None, files.resolve("/a/../../etc/passwd"));
+        assert_eq!(None, files.resolve("/%2e%2e/etc/passwd"));
+        assert_eq!(None, files.resolve("/a%2f..%2f..%2fetc"));
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut req = Headers::new_get("/");
+        req.add("if-none-match", "\"other\", W/\"1-2\"");
+        assert!(not_modified(&req, "\"1-2\"", Some(10)));
+
+        let mut req = Headers::new_get("/");
+        req.add("if-modified-since", "Thu, 01 Jan 1970 00:00:10 GMT");
+        assert!(not_modified(&req, "\"1-2\"", Some(10)));
+        assert!(!not_modified(&req, "\"1-2\"", Some(11)));
+
+        let mut req = Headers::new_get("/");
+        req.add("if-range", "\"1-2\"");
+        assert!(if_range_matches(&req, "\"1-2\"", Some(10)));
+        assert!(!if_range_matches(&req, "\"1-3\"", Some(10)));
+    }
+}
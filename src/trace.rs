@@ -0,0 +1,302 @@
+//! Request spans and W3C Trace Context (`traceparent`) propagation,
+//! integration point for `tracing`, OpenTelemetry and similar libraries.
+//!
+//! `TracingRequestTracer` implementation for `tracing` crate
+//! is enabled by `tracing` cargo feature.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::Async;
+use futures::Poll;
+
+use error::Error;
+use resp::Response;
+use server::request_id::random_u64;
+use solicit::header::Headers;
+use solicit::StreamId;
+
+use data_or_trailers::DataOrTrailers;
+use data_or_trailers::HttpStreamAfterHeaders;
+
+/// Name of W3C Trace Context header
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Parsed `traceparent` header (version `00`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    /// Id of the span which sent the request
+    pub parent_id: u64,
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Start a new sampled trace
+    pub fn new_root() -> TraceParent {
+        let hi = random_u64(0);
+        let lo = random_u64(hi);
+        TraceParent {
+            trace_id: ((hi as u128) << 64) | lo as u128,
+            parent_id: random_u64(lo),
+            flags: 1,
+        }
+    }
+
+    /// Context of a new span in the same trace
+    pub fn child(&self) -> TraceParent {
+        TraceParent {
+            parent_id: random_u64(self.parent_id),
+            ..*self
+        }
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// Parse header value, `None` if it is malformed or ids are all zeros
+    pub fn parse(value: &str) -> Option<TraceParent> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        if parts.len() != 4 || parts[0] != "00" {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(lower_hex(parts[1], 32)?, 16).ok()?;
+        let parent_id = u64::from_str_radix(lower_hex(parts[2], 16)?, 16).ok()?;
+        let flags = u8::from_str_radix(lower_hex(parts[3], 2)?, 16).ok()?;
+        if trace_id == 0 || parent_id == 0 {
+            return None;
+        }
+        Some(TraceParent {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+
+    /// Extract context from request headers
+    pub fn from_headers(headers: &Headers) -> Option<TraceParent> {
+        headers.get_opt(TRACEPARENT).and_then(TraceParent::parse)
+    }
+
+    /// Replace `traceparent` header with this context
+    pub fn inject(&self, headers: &mut Headers) {
+        headers.0.retain(|h| h.name() != TRACEPARENT.as_bytes());
+        headers.add(TRACEPARENT, &self.to_string());
+    }
+}
+
+/// Spec allows only lowercase hex digits
+fn lower_hex(s: &str, len: usize) -> Option<&str> {
+    if s.len() == len
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        Some(s)
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+/// Span of a single request: from request start (including waiting
+/// for connection on client) until response body is complete.
+pub trait RequestSpan: Send {
+    /// Context sent in `traceparent` header of client request,
+    /// request is sent with its own header if `None`.
+    fn trace_parent(&self) -> Option<TraceParent> {
+        None
+    }
+
+    /// Stream is opened for the request
+    fn stream_id(&mut self, _stream_id: StreamId) {}
+
+    /// Response headers are received by client or returned by server handler,
+    /// e. g. to record `:status`
+    fn response_headers(&mut self, _headers: &Headers) {}
+
+    /// Called once when response body is complete or failed,
+    /// or the response is dropped before completion
+    fn end(&mut self, _error: Option<&Error>) {}
+}
+
+/// Creates spans of requests
+pub trait RequestTracer: Send + Sync {
+    /// Start span of client request, `parent` is taken from request `traceparent` header
+    fn start_client_span(&self, headers: &Headers, parent: Option<TraceParent>)
+        -> Box<RequestSpan>;
+
+    /// Start span of request received by server,
+    /// `parent` is taken from request `traceparent` header
+    fn start_server_span(&self, headers: &Headers, parent: Option<TraceParent>)
+        -> Box<RequestSpan>;
+}
+
+/// `RequestTracer` in client and server configuration
+#[derive(Clone)]
+pub struct RequestTracing(pub Arc<RequestTracer>);
+
+impl RequestTracing {
+    pub fn new<T: RequestTracer + 'static>(tracer: T) -> RequestTracing {
+        RequestTracing(Arc::new(tracer))
+    }
+
+    /// Start client span and inject its context into request headers
+    pub(crate) fn start_client_span(&self, headers: &mut Headers) -> SpanHandle {
+        let span = self
+            .0
+            .start_client_span(headers, TraceParent::from_headers(headers));
+        if let Some(trace_parent) = span.trace_parent() {
+            trace_parent.inject(headers);
+        }
+        SpanHandle(Arc::new(Mutex::new(Some(span))))
+    }
+
+    pub(crate) fn start_server_span(&self, headers: &Headers, stream_id: StreamId) -> SpanHandle {
+        let mut span = self
+            .0
+            .start_server_span(headers, TraceParent::from_headers(headers));
+        span.stream_id(stream_id);
+        SpanHandle(Arc::new(Mutex::new(Some(span))))
+    }
+}
+
+impl fmt::Debug for RequestTracing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RequestTracing")
+    }
+}
+
+/// Span shared by request sender and response stream, span is removed when ended
+#[derive(Clone)]
+pub(crate) struct SpanHandle(Arc<Mutex<Option<Box<RequestSpan>>>>);
+
+impl SpanHandle {
+    pub fn stream_id(&self, stream_id: StreamId) {
+        if let Some(ref mut span) = *self.0.lock().unwrap() {
+            span.stream_id(stream_id);
+        }
+    }
+
+    fn response_headers(&self, headers: &Headers) {
+        if let Some(ref mut span) = *self.0.lock().unwrap() {
+            span.response_headers(headers);
+        }
+    }
+
+    fn end(&self, error: Option<&Error>) {
+        let span = self.0.lock().unwrap().take();
+        if let Some(mut span) = span {
+            span.end(error);
+        }
+    }
+
+    /// Report response headers and completion to the span
+    pub fn trace_response(self, response: Response) -> Response {
+        let guard = SpanGuard(self);
+        Response::new(response.0.then(move |r| match r {
+            Ok((headers, rem)) => {
+                guard.0.response_headers(&headers);
+                let rem = TracedStream { stream: rem, guard };
+                Ok((headers, HttpStreamAfterHeaders::new(rem)))
+            }
+            Err(e) => {
+                guard.0.end(Some(&e));
+                Err(e)
+            }
+        }))
+    }
+}
+
+/// End span when response is dropped before completion
+struct SpanGuard(SpanHandle);
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        // no-op if span is already ended
+        self.0
+            .end(Some(&Error::Other("response is dropped before completion")));
+    }
+}
+
+struct TracedStream {
+    stream: HttpStreamAfterHeaders,
+    guard: SpanGuard,
+}
+
+impl Stream for TracedStream {
+    type Item = DataOrTrailers;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<DataOrTrailers>, Error> {
+        match self.stream.poll() {
+            Ok(Async::Ready(None)) => {
+                self.guard.0.end(None);
+                Ok(Async::Ready(None))
+            }
+            Err(e) => {
+                self.guard.0.end(Some(&e));
+                Err(e)
+            }
+            r => r,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        let value = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let p = TraceParent::parse(value).expect("parse");
+        assert_eq!(0x0af7651916cd43dd8448eb211c80319c, p.trace_id);
+        assert_eq!(0xb7ad6b7169203331, p.parent_id);
+        assert!(p.sampled());
+        assert_eq!(value, p.to_string());
+
+        assert_eq!(
+            None,
+            TraceParent::parse("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceParent::parse("00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceParent::parse("00-00000000000000000000000000000000-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceParent::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b716920333-01")
+        );
+    }
+
+    #[test]
+    fn child() {
+        let root = TraceParent::new_root();
+        let child = root.child();
+        assert_eq!(root.trace_id, child.trace_id);
+        assert_ne!(root.parent_id, child.parent_id);
+
+        let mut headers = Headers::new();
+        headers.add(TRACEPARENT, "garbage");
+        child.inject(&mut headers);
+        assert_eq!(Some(child), TraceParent::from_headers(&headers));
+        assert_eq!(1, headers.0.len());
+    }
+}
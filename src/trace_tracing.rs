@@ -0,0 +1,136 @@
+//! `RequestTracer` recording requests as spans of `tracing` crate,
+//! enabled by `tracing` cargo feature.
+
+use tracing;
+use tracing::field;
+use tracing::Level;
+use tracing::Span;
+
+use error::Error;
+use solicit::header::Headers;
+use solicit::StreamId;
+use trace::RequestSpan;
+use trace::RequestTracer;
+use trace::TraceParent;
+
+/// Record each request as `http2.client` or `http2.server` span
+/// of `INFO` level with `method`, `path`, `authority`, `trace_id`,
+/// `stream_id`, `status` and `error` fields.
+///
+/// Span is closed when response body is complete, failed or dropped.
+/// Client requests are sent with `traceparent` of a new trace,
+/// or a child of `traceparent` header already added to the request.
+///
+/// ```ignore
+/// conf.request_tracing = Some(RequestTracing::new(TracingRequestTracer));
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TracingRequestTracer;
+
+struct TracingSpan {
+    span: Span,
+    trace_parent: Option<TraceParent>,
+}
+
+// span name must be a literal
+macro_rules! request_span {
+    ($name:expr, $headers:expr) => {
+        tracing::span!(
+            Level::INFO,
+            $name,
+            method = $headers.get_opt(":method").unwrap_or(""),
+            path = $headers.get_opt(":path").unwrap_or(""),
+            authority = $headers.authority().unwrap_or(""),
+            trace_id = field::Empty,
+            stream_id = field::Empty,
+            status = field::Empty,
+            error = field::Empty,
+        )
+    };
+}
+
+fn record_trace_id(span: &Span, trace_parent: Option<TraceParent>) {
+    if let Some(trace_parent) = trace_parent {
+        span.record(
+            "trace_id",
+            field::display(format!("{:032x}", trace_parent.trace_id)),
+        );
+    }
+}
+
+impl RequestTracer for TracingRequestTracer {
+    fn start_client_span(
+        &self,
+        headers: &Headers,
+        parent: Option<TraceParent>,
+    ) -> Box<RequestSpan> {
+        let trace_parent = match parent {
+            Some(parent) => parent.child(),
+            None => TraceParent::new_root(),
+        };
+        let span = request_span!("http2.client", headers);
+        record_trace_id(&span, Some(trace_parent));
+        Box::new(TracingSpan {
+            span,
+            trace_parent: Some(trace_parent),
+        })
+    }
+
+    fn start_server_span(
+        &self,
+        headers: &Headers,
+        parent: Option<TraceParent>,
+    ) -> Box<RequestSpan> {
+        let span = request_span!("http2.server", headers);
+        record_trace_id(&span, parent);
+        Box::new(TracingSpan {
+            span,
+            trace_parent: None,
+        })
+    }
+}
+
+impl RequestSpan for TracingSpan {
+    fn trace_parent(&self) -> Option<TraceParent> {
+        self.trace_parent
+    }
+
+    fn stream_id(&mut self, stream_id: StreamId) {
+        self.span.record("stream_id", stream_id);
+    }
+
+    fn response_headers(&mut self, headers: &Headers) {
+        if let Some(status) = headers.get_opt(":status") {
+            self.span.record("status", status);
+        }
+    }
+
+    fn end(&mut self, error: Option<&Error>) {
+        if let Some(error) = error {
+            self.span.record("error", field::display(error));
+        }
+        // span is closed when the last reference is dropped
+        self.span = Span::none();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_trace_parent() {
+        let mut headers = Headers::new_get("/");
+        let span = TracingRequestTracer.start_client_span(&headers, None);
+        let root = span.trace_parent().expect("trace_parent");
+
+        root.inject(&mut headers);
+        let span = TracingRequestTracer.start_client_span(&headers, Some(root));
+        let child = span.trace_parent().expect("trace_parent");
+        assert_eq!(root.trace_id, child.trace_id);
+        assert_ne!(root.parent_id, child.parent_id);
+
+        let span = TracingRequestTracer.start_server_span(&headers, Some(root));
+        assert_eq!(None, span.trace_parent());
+    }
+}